@@ -1,9 +1,9 @@
 use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple};
 use std::collections::HashMap;
 use std::fs;
 use std::io::{self, Write};
 use std::path::PathBuf;
-use xurdf;
 
 #[pyclass(from_py_object)]
 #[derive(Clone, Debug)]
@@ -283,6 +283,27 @@ impl JointLimit {
     }
 }
 
+#[pyclass(from_py_object)]
+#[derive(Clone, Debug)]
+struct Mimic {
+    #[pyo3(get, set)]
+    joint: String,
+    #[pyo3(get, set)]
+    multiplier: f64,
+    #[pyo3(get, set)]
+    offset: f64,
+}
+
+#[pymethods]
+impl Mimic {
+    fn __repr__(&self) -> String {
+        format!(
+            "Mimic(joint: {:?}, multiplier: {:?}, offset: {:?})",
+            self.joint, self.multiplier, self.offset
+        )
+    }
+}
+
 #[pyclass(from_py_object)]
 #[derive(Clone, Debug)]
 struct Joint {
@@ -300,13 +321,102 @@ struct Joint {
     axis: [f64; 3],
     #[pyo3(get, set)]
     limit: JointLimit,
+    #[pyo3(get, set)]
+    mimic: Option<Mimic>,
 }
 
 #[pymethods]
 impl Joint {
     fn __repr__(&self) -> String {
-        format!("Joint(name: {:?}, joint_type: {:?}, origin: {:?}, parent: {:?}, child: {:?}, axis: {:?}, limit: {:?})",
-                self.name, self.joint_type, self.origin, self.parent, self.child, self.axis, self.limit)
+        format!("Joint(name: {:?}, joint_type: {:?}, origin: {:?}, parent: {:?}, child: {:?}, axis: {:?}, limit: {:?}, mimic: {:?})",
+                self.name, self.joint_type, self.origin, self.parent, self.child, self.axis, self.limit, self.mimic)
+    }
+}
+
+#[pyclass(from_py_object)]
+#[derive(Clone, Debug)]
+struct TransmissionJoint {
+    #[pyo3(get, set)]
+    name: String,
+    #[pyo3(get, set)]
+    hardware_interfaces: Vec<String>,
+}
+
+#[pymethods]
+impl TransmissionJoint {
+    fn __repr__(&self) -> String {
+        format!(
+            "TransmissionJoint(name: {:?}, hardware_interfaces: {:?})",
+            self.name, self.hardware_interfaces
+        )
+    }
+}
+
+#[pyclass(from_py_object)]
+#[derive(Clone, Debug)]
+struct Actuator {
+    #[pyo3(get, set)]
+    name: String,
+    #[pyo3(get, set)]
+    mechanical_reduction: Option<f64>,
+    #[pyo3(get, set)]
+    hardware_interfaces: Vec<String>,
+}
+
+#[pymethods]
+impl Actuator {
+    fn __repr__(&self) -> String {
+        format!(
+            "Actuator(name: {:?}, mechanical_reduction: {:?}, hardware_interfaces: {:?})",
+            self.name, self.mechanical_reduction, self.hardware_interfaces
+        )
+    }
+}
+
+#[pyclass(from_py_object)]
+#[derive(Clone, Debug)]
+struct Transmission {
+    #[pyo3(get, set)]
+    name: String,
+    #[pyo3(get, set)]
+    transmission_type: String,
+    #[pyo3(get, set)]
+    joints: Vec<TransmissionJoint>,
+    #[pyo3(get, set)]
+    actuators: Vec<Actuator>,
+}
+
+#[pymethods]
+impl Transmission {
+    fn __repr__(&self) -> String {
+        format!(
+            "Transmission(name: {:?}, transmission_type: {:?}, joints: {:?}, actuators: {:?})",
+            self.name, self.transmission_type, self.joints, self.actuators
+        )
+    }
+}
+
+#[pyclass(from_py_object)]
+#[derive(Clone, Debug)]
+struct Extension {
+    #[pyo3(get, set)]
+    reference: Option<String>,
+    #[pyo3(get, set)]
+    xml: String,
+}
+
+#[pymethods]
+impl Extension {
+    #[new]
+    #[pyo3(signature = (reference = None, xml = String::new()))]
+    fn new(reference: Option<String>, xml: String) -> Self {
+        Extension { reference, xml }
+    }
+    fn __repr__(&self) -> String {
+        format!(
+            "Extension(reference: {:?}, xml: {:?})",
+            self.reference, self.xml
+        )
     }
 }
 
@@ -321,16 +431,36 @@ struct Robot {
     links: Vec<Link>,
     #[pyo3(get, set)]
     joints: Vec<Joint>,
+    #[pyo3(get, set)]
+    transmissions: Vec<Transmission>,
+    #[pyo3(get, set)]
+    extensions: Vec<Extension>,
 }
 
 #[pymethods]
 impl Robot {
     fn __repr__(&self) -> String {
         format!(
-            "Robot(name: {:?}, materials: {:?}, links: {:?}, joints: {:?})",
-            self.name, self.materials, self.links, self.joints
+            "Robot(name: {:?}, materials: {:?}, links: {:?}, joints: {:?}, transmissions: {:?}, extensions: {:?})",
+            self.name, self.materials, self.links, self.joints, self.transmissions, self.extensions
         )
     }
+
+    /// Plain dicts and lists in the JSON schema of the Rust `serde` feature.
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let json = serde_json::to_string(&model_robot(self)).map_err(py_exception)?;
+        py.import("json")?.call_method1("loads", (json,))
+    }
+
+    #[staticmethod]
+    fn from_dict(py: Python<'_>, data: &Bound<'_, PyAny>) -> PyResult<Robot> {
+        let json = py
+            .import("json")?
+            .call_method1("dumps", (data,))?
+            .extract::<String>()?;
+        let robot = serde_json::from_str::<xurdf::Robot>(&json).map_err(py_exception)?;
+        Ok(convert_robot(robot))
+    }
 }
 
 fn convert_material(material: &xurdf::Material) -> Material {
@@ -459,13 +589,181 @@ fn convert_robot(robot: xurdf::Robot) -> Robot {
                 effort: joint.limit.effort,
                 velocity: joint.limit.velocity,
             },
+            mimic: joint.mimic.as_ref().map(|mimic| Mimic {
+                joint: mimic.joint.clone(),
+                multiplier: mimic.multiplier,
+                offset: mimic.offset,
+            }),
+        })
+        .collect();
+    let transmissions = robot
+        .transmissions
+        .into_iter()
+        .map(|transmission| Transmission {
+            name: transmission.name,
+            transmission_type: transmission.transmission_type,
+            joints: transmission
+                .joints
+                .into_iter()
+                .map(|joint| TransmissionJoint {
+                    name: joint.name,
+                    hardware_interfaces: joint.hardware_interfaces,
+                })
+                .collect(),
+            actuators: transmission
+                .actuators
+                .into_iter()
+                .map(|actuator| Actuator {
+                    name: actuator.name,
+                    mechanical_reduction: actuator.mechanical_reduction,
+                    hardware_interfaces: actuator.hardware_interfaces,
+                })
+                .collect(),
+        })
+        .collect();
+    let extensions = robot
+        .extensions
+        .into_iter()
+        .map(|extension| Extension {
+            reference: extension.reference,
+            xml: extension.xml,
         })
         .collect();
     Robot {
         name: robot.name,
-        materials: materials,
-        links: links,
-        joints: joints,
+        materials,
+        links,
+        joints,
+        transmissions,
+        extensions,
+    }
+}
+
+fn model_pose(pose: &Pose) -> xurdf::Pose {
+    xurdf::Pose {
+        xyz: pose.xyz.into(),
+        rpy: pose.rpy.into(),
+    }
+}
+
+fn model_material(material: &Material) -> xurdf::Material {
+    xurdf::Material {
+        name: material.name.clone(),
+        color: material.color.map(Into::into),
+    }
+}
+
+fn model_geometry(geometry: &Geometry) -> xurdf::Geometry {
+    match geometry {
+        Geometry::Box(value) => xurdf::Geometry::Box {
+            size: value.size.into(),
+        },
+        Geometry::Cylinder(value) => xurdf::Geometry::Cylinder {
+            radius: value.radius,
+            length: value.length,
+        },
+        Geometry::Sphere(value) => xurdf::Geometry::Sphere {
+            radius: value.radius,
+        },
+        Geometry::Mesh(value) => xurdf::Geometry::Mesh {
+            filename: value.filename.clone(),
+            scale: value.scale.map(Into::into),
+        },
+    }
+}
+
+// The inverse of `convert_robot`, for passing Python-side edits back to the Rust model.
+fn model_robot(robot: &Robot) -> xurdf::Robot {
+    xurdf::Robot {
+        name: robot.name.clone(),
+        materials: robot.materials.iter().map(model_material).collect(),
+        links: robot
+            .links
+            .iter()
+            .map(|link| xurdf::Link {
+                name: link.name.clone(),
+                inertial: xurdf::Inertial {
+                    origin: model_pose(&link.inertial.origin),
+                    mass: link.inertial.mass,
+                    inertia: nalgebra::Matrix3::from_row_slice(&link.inertial.inertia),
+                },
+                visuals: link
+                    .visuals
+                    .iter()
+                    .map(|visual| xurdf::Visual {
+                        name: visual.name.clone(),
+                        origin: model_pose(&visual.origin),
+                        geometry: model_geometry(&visual.geometry),
+                        material: visual.material.as_ref().map(model_material),
+                    })
+                    .collect(),
+                collisions: link
+                    .collisions
+                    .iter()
+                    .map(|collision| xurdf::Collision {
+                        name: collision.name.clone(),
+                        origin: model_pose(&collision.origin),
+                        geometry: model_geometry(&collision.geometry),
+                    })
+                    .collect(),
+            })
+            .collect(),
+        joints: robot
+            .joints
+            .iter()
+            .map(|joint| xurdf::Joint {
+                name: joint.name.clone(),
+                joint_type: joint.joint_type.clone(),
+                origin: model_pose(&joint.origin),
+                parent: joint.parent.clone(),
+                child: joint.child.clone(),
+                axis: joint.axis.into(),
+                limit: xurdf::JointLimit {
+                    lower: joint.limit.lower,
+                    upper: joint.limit.upper,
+                    effort: joint.limit.effort,
+                    velocity: joint.limit.velocity,
+                },
+                mimic: joint.mimic.as_ref().map(|mimic| xurdf::Mimic {
+                    joint: mimic.joint.clone(),
+                    multiplier: mimic.multiplier,
+                    offset: mimic.offset,
+                }),
+            })
+            .collect(),
+        transmissions: robot
+            .transmissions
+            .iter()
+            .map(|transmission| xurdf::Transmission {
+                name: transmission.name.clone(),
+                transmission_type: transmission.transmission_type.clone(),
+                joints: transmission
+                    .joints
+                    .iter()
+                    .map(|joint| xurdf::TransmissionJoint {
+                        name: joint.name.clone(),
+                        hardware_interfaces: joint.hardware_interfaces.clone(),
+                    })
+                    .collect(),
+                actuators: transmission
+                    .actuators
+                    .iter()
+                    .map(|actuator| xurdf::Actuator {
+                        name: actuator.name.clone(),
+                        mechanical_reduction: actuator.mechanical_reduction,
+                        hardware_interfaces: actuator.hardware_interfaces.clone(),
+                    })
+                    .collect(),
+            })
+            .collect(),
+        extensions: robot
+            .extensions
+            .iter()
+            .map(|extension| xurdf::Extension {
+                reference: extension.reference.clone(),
+                xml: extension.xml.clone(),
+            })
+            .collect(),
     }
 }
 
@@ -481,69 +779,362 @@ fn parse_urdf_file(filename: &str) -> PyResult<Robot> {
 
 #[pyfunction]
 fn parse_urdf_string(contents: &str) -> PyResult<Robot> {
-    let robot = xurdf::parse_urdf_from_string(&contents.to_owned()).map_err(py_exception)?;
+    let robot = xurdf::parse_urdf_from_string(contents).map_err(py_exception)?;
     Ok(convert_robot(robot))
 }
 
+// Reports lossy conversions through Python's `warnings` module so callers can filter them.
+fn convert_imported_robot(py: Python<'_>, imported: xurdf::ImportedRobot) -> PyResult<Robot> {
+    let category = py.get_type::<pyo3::exceptions::PyUserWarning>();
+    for warning in imported.warnings {
+        let message = std::ffi::CString::new(warning).map_err(py_exception)?;
+        PyErr::warn(py, category.as_any(), &message, 2)?;
+    }
+    Ok(convert_robot(imported.robot))
+}
+
+#[pyfunction]
+fn parse_mjcf_file(py: Python<'_>, filename: &str) -> PyResult<Robot> {
+    let imported = xurdf::parse_mjcf_from_file(filename).map_err(py_exception)?;
+    convert_imported_robot(py, imported)
+}
+
+#[pyfunction]
+fn parse_mjcf_string(py: Python<'_>, contents: &str) -> PyResult<Robot> {
+    let imported = xurdf::parse_mjcf_from_string(contents).map_err(py_exception)?;
+    convert_imported_robot(py, imported)
+}
+
+#[pyfunction]
+fn parse_sdf_file(py: Python<'_>, filename: &str) -> PyResult<Robot> {
+    let imported = xurdf::parse_sdf_from_file(filename).map_err(py_exception)?;
+    convert_imported_robot(py, imported)
+}
+
+#[pyfunction]
+fn parse_sdf_string(py: Python<'_>, contents: &str) -> PyResult<Robot> {
+    let imported = xurdf::parse_sdf_from_string(contents).map_err(py_exception)?;
+    convert_imported_robot(py, imported)
+}
+
+fn parse_number_format(name: &str) -> Result<xurdf::NumberFormat, String> {
+    match name {
+        "display" => Ok(xurdf::NumberFormat::Display),
+        "python" => Ok(xurdf::NumberFormat::PythonRepr),
+        _ => Err(format!(
+            "number format must be `display` or `python`: {}",
+            name
+        )),
+    }
+}
+
+fn xacro_value_from_py(value: &Bound<'_, PyAny>) -> PyResult<xurdf::XacroValue> {
+    if value.is_none() {
+        Ok(xurdf::XacroValue::Null)
+    } else if value.is_instance_of::<PyBool>() {
+        Ok(xurdf::XacroValue::Bool(value.extract()?))
+    } else if value.is_instance_of::<PyInt>() {
+        Ok(xurdf::XacroValue::Integer(value.extract()?))
+    } else if value.is_instance_of::<PyFloat>() {
+        Ok(xurdf::XacroValue::Number(value.extract()?))
+    } else if value.is_instance_of::<PyString>() {
+        Ok(xurdf::XacroValue::String(value.extract()?))
+    } else if let Ok(dict) = value.cast::<PyDict>() {
+        let values = dict
+            .iter()
+            .map(|(key, value)| Ok((key.str()?.to_string(), xacro_value_from_py(&value)?)))
+            .collect::<PyResult<_>>()?;
+        Ok(xurdf::XacroValue::Map(values))
+    } else if value.is_instance_of::<PyList>() || value.is_instance_of::<PyTuple>() {
+        let values = value
+            .try_iter()?
+            .map(|item| xacro_value_from_py(&item?))
+            .collect::<PyResult<_>>()?;
+        Ok(xurdf::XacroValue::List(values))
+    } else {
+        Err(PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+            "unsupported xacro value type: {}",
+            value.get_type().name()?
+        )))
+    }
+}
+
+fn xacro_value_to_py<'py>(
+    py: Python<'py>,
+    value: &xurdf::XacroValue,
+) -> PyResult<Bound<'py, PyAny>> {
+    Ok(match value {
+        xurdf::XacroValue::Null => py.None().into_bound(py),
+        xurdf::XacroValue::Bool(value) => value.into_pyobject(py)?.to_owned().into_any(),
+        xurdf::XacroValue::Integer(value) => value.into_pyobject(py)?.into_any(),
+        xurdf::XacroValue::Number(value) => value.into_pyobject(py)?.into_any(),
+        xurdf::XacroValue::String(value) => value.into_pyobject(py)?.into_any(),
+        xurdf::XacroValue::List(values) => PyList::new(
+            py,
+            values
+                .iter()
+                .map(|value| xacro_value_to_py(py, value))
+                .collect::<PyResult<Vec<_>>>()?,
+        )?
+        .into_any(),
+        xurdf::XacroValue::Map(values) => {
+            let dict = PyDict::new(py);
+            for (key, value) in values.iter() {
+                dict.set_item(key, xacro_value_to_py(py, value)?)?;
+            }
+            dict.into_any()
+        }
+        // Kinds added to `XacroValue` later reach Python as their raw text.
+        value => value.raw_value().into_pyobject(py)?.into_any(),
+    })
+}
+
+fn call_python_function(
+    function: &Py<PyAny>,
+    args: &[xurdf::XacroValue],
+) -> anyhow::Result<xurdf::XacroValue> {
+    Python::attach(|py| {
+        let args = args
+            .iter()
+            .map(|value| xacro_value_to_py(py, value))
+            .collect::<PyResult<Vec<_>>>()?;
+        let result = function.bind(py).call1(PyTuple::new(py, args)?)?;
+        xacro_value_from_py(&result)
+    })
+    .map_err(|err| anyhow::anyhow!("{}", err))
+}
+
 fn xacro_options(
     package_paths: HashMap<String, String>,
-    args: HashMap<String, String>,
-) -> xurdf::XacroOptions {
+    args: HashMap<String, Bound<'_, PyAny>>,
+    number_format: Option<&str>,
+    strict: bool,
+    symbols: HashMap<String, Bound<'_, PyAny>>,
+) -> PyResult<xurdf::XacroOptions> {
     let options = package_paths.into_iter().fold(
         xurdf::XacroOptions::default(),
         |options, (package, path)| options.with_package_path(package, PathBuf::from(path)),
     );
-    args.into_iter().fold(options, |options, (name, value)| {
-        options.with_arg(name, value)
-    })
+    let options = args
+        .into_iter()
+        .try_fold(options, |options, (name, value)| {
+            if value.is_instance_of::<PyString>() {
+                Ok::<_, PyErr>(options.with_arg(name, value.extract::<String>()?))
+            } else {
+                Ok(options.with_typed_arg(name, xacro_value_from_py(&value)?))
+            }
+        })?;
+    let number_format = number_format
+        .map(parse_number_format)
+        .transpose()
+        .map_err(PyErr::new::<pyo3::exceptions::PyValueError, _>)?
+        .unwrap_or_default();
+    let options = symbols
+        .into_iter()
+        .try_fold(options, |options, (name, value)| {
+            if value.is_callable() {
+                let function = value.unbind();
+                Ok::<_, PyErr>(
+                    options.with_function(name, move |args| call_python_function(&function, args)),
+                )
+            } else {
+                Ok(options.with_constant(name, xacro_value_from_py(&value)?))
+            }
+        })?;
+    Ok(options
+        .with_number_format(number_format)
+        .with_strict(strict))
 }
 
 #[pyfunction]
-#[pyo3(signature = (filename, package_paths = None, args = None))]
+#[pyo3(signature = (filename, package_paths = None, args = None, number_format = None, strict = false, pretty_print = false, banner = false, symbols = None))]
+#[allow(clippy::too_many_arguments)]
 fn parse_xacro_file(
     filename: &str,
     package_paths: Option<HashMap<String, String>>,
-    args: Option<HashMap<String, String>>,
+    args: Option<HashMap<String, Bound<'_, PyAny>>>,
+    number_format: Option<&str>,
+    strict: bool,
+    pretty_print: bool,
+    banner: bool,
+    symbols: Option<HashMap<String, Bound<'_, PyAny>>>,
 ) -> PyResult<String> {
     let xacro = xurdf::parse_xacro_from_file_with_options(
         filename,
-        xacro_options(package_paths.unwrap_or_default(), args.unwrap_or_default()),
+        xacro_options(
+            package_paths.unwrap_or_default(),
+            args.unwrap_or_default(),
+            number_format,
+            strict,
+            symbols.unwrap_or_default(),
+        )?
+        .with_pretty_print(pretty_print)
+        .with_generated_banner(banner),
     )
-    .map_err(py_exception)?;
+    .map_err(|err| py_exception(xurdf::render_xacro_error(&err).trim_end()))?;
     Ok(xacro)
 }
 
 #[pyfunction]
-#[pyo3(signature = (contents, package_paths = None, args = None))]
+#[pyo3(signature = (contents, package_paths = None, args = None, number_format = None, strict = false, pretty_print = false, banner = false, symbols = None))]
+#[allow(clippy::too_many_arguments)]
 fn parse_xacro_string(
     contents: &str,
     package_paths: Option<HashMap<String, String>>,
-    args: Option<HashMap<String, String>>,
+    args: Option<HashMap<String, Bound<'_, PyAny>>>,
+    number_format: Option<&str>,
+    strict: bool,
+    pretty_print: bool,
+    banner: bool,
+    symbols: Option<HashMap<String, Bound<'_, PyAny>>>,
 ) -> PyResult<String> {
     let xacro = xurdf::parse_xacro_from_string_with_options(
-        &contents.to_owned(),
-        xacro_options(package_paths.unwrap_or_default(), args.unwrap_or_default()),
+        contents,
+        xacro_options(
+            package_paths.unwrap_or_default(),
+            args.unwrap_or_default(),
+            number_format,
+            strict,
+            symbols.unwrap_or_default(),
+        )?
+        .with_pretty_print(pretty_print)
+        .with_generated_banner(banner),
     )
-    .map_err(py_exception)?;
+    .map_err(|err| py_exception(xurdf::render_xacro_error(&err).trim_end()))?;
     Ok(xacro)
 }
 
+#[pyfunction]
+#[pyo3(signature = (filename, package_paths = None, args = None, number_format = None, strict = false, symbols = None))]
+fn parse_xacro_file_to_robot(
+    filename: &str,
+    package_paths: Option<HashMap<String, String>>,
+    args: Option<HashMap<String, Bound<'_, PyAny>>>,
+    number_format: Option<&str>,
+    strict: bool,
+    symbols: Option<HashMap<String, Bound<'_, PyAny>>>,
+) -> PyResult<Robot> {
+    let robot = xurdf::parse_xacro_to_robot_with_options(
+        filename,
+        xacro_options(
+            package_paths.unwrap_or_default(),
+            args.unwrap_or_default(),
+            number_format,
+            strict,
+            symbols.unwrap_or_default(),
+        )?,
+    )
+    .map_err(|err| py_exception(xurdf::render_xacro_error(&err).trim_end()))?;
+    Ok(convert_robot(robot))
+}
+
+#[pyfunction]
+#[pyo3(signature = (contents, package_paths = None, args = None, number_format = None, strict = false, symbols = None))]
+fn parse_xacro_string_to_robot(
+    contents: &str,
+    package_paths: Option<HashMap<String, String>>,
+    args: Option<HashMap<String, Bound<'_, PyAny>>>,
+    number_format: Option<&str>,
+    strict: bool,
+    symbols: Option<HashMap<String, Bound<'_, PyAny>>>,
+) -> PyResult<Robot> {
+    let robot = xurdf::XacroProcessor::with_options(xacro_options(
+        package_paths.unwrap_or_default(),
+        args.unwrap_or_default(),
+        number_format,
+        strict,
+        symbols.unwrap_or_default(),
+    )?)
+    .process_string_to_robot(contents)
+    .map_err(|err| py_exception(xurdf::render_xacro_error(&err).trim_end()))?;
+    Ok(convert_robot(robot))
+}
+
 const XACRO_CLI_USAGE: &str = r#"Usage: xurdf-xacro [OPTIONS] <INPUT> [name:=value ...]
+       xurdf-xacro graph [--format dot|mermaid] [OPTIONS] <INPUT> [name:=value ...]
 
-Expand a Xacro file and write the expanded XML.
+Expand a Xacro file and write the expanded XML. The `graph` subcommand writes the
+link/joint tree of the expanded robot as a Graphviz DOT or Mermaid graph instead.
 
 Options:
   -o, --output <PATH>             Write expanded XML to PATH instead of stdout
   -p, --package-path <NAME=PATH>  Resolve $(find NAME) and $(find-pkg-share NAME) to PATH; repeatable
+      --number-format <FORMAT>    Format substituted numbers as `display` (default) or `python`
+      --json-arg <NAME:=JSON>     Pass a typed xacro argument parsed as JSON; repeatable
+      --yaml-arg <NAME:=YAML>     Pass a typed xacro argument parsed as YAML; repeatable
+      --strict                    Fail on unresolved ${...} expressions instead of keeping them
+      --pretty                    Indent the expanded XML
+      --banner                    Start the output with a comment listing source files and args
+      --deps                      Print the included Xacro and YAML files instead of the expanded XML
+      --print-args                Print the declared xacro arguments with their defaults instead of the expanded XML
   -h, --help                      Show this help
 "#;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+fn parse_graph_format(name: &str) -> Result<GraphFormat, String> {
+    match name {
+        "dot" => Ok(GraphFormat::Dot),
+        "mermaid" => Ok(GraphFormat::Mermaid),
+        _ => Err(format!(
+            "unknown graph format `{}`; expected `dot` or `mermaid`",
+            name
+        )),
+    }
+}
+
+// Splits the graph-only `--format` option off and leaves the rest to the xacro parser.
+fn parse_graph_cli_args<I, S>(args: I) -> Result<Option<(GraphFormat, XacroCliArgs)>, String>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    let mut iter = args.into_iter().map(Into::into);
+    let mut format = GraphFormat::Dot;
+    let mut rest = Vec::new();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--format" => {
+                let name = iter
+                    .next()
+                    .ok_or_else(|| format!("{} requires a format", arg))?;
+                format = parse_graph_format(&name)?;
+            }
+            "--" => {
+                rest.push(arg);
+                rest.extend(iter.by_ref());
+            }
+            _ if arg.starts_with("--format=") => {
+                format = parse_graph_format(arg.trim_start_matches("--format="))?;
+            }
+            _ => rest.push(arg),
+        }
+    }
+    let Some(cli) = parse_xacro_cli_args(rest)? else {
+        return Ok(None);
+    };
+    if cli.deps || cli.print_args {
+        return Err("--deps and --print-args cannot be used with graph".to_string());
+    }
+    Ok(Some((format, cli)))
+}
+
 #[derive(Debug)]
 struct XacroCliArgs {
     input: PathBuf,
     output: Option<PathBuf>,
     package_paths: HashMap<String, PathBuf>,
-    args: HashMap<String, String>,
+    args: HashMap<String, xurdf::XacroValue>,
+    number_format: xurdf::NumberFormat,
+    strict: bool,
+    pretty: bool,
+    banner: bool,
+    deps: bool,
+    print_args: bool,
 }
 
 fn parse_package_path(spec: &str) -> Result<(String, PathBuf), String> {
@@ -566,6 +1157,19 @@ fn parse_xacro_arg(spec: &str) -> Result<(String, String), String> {
     Ok((name.to_string(), value.to_string()))
 }
 
+fn parse_json_value(text: &str) -> anyhow::Result<xurdf::XacroValue> {
+    Ok(serde_json::from_str(text)?)
+}
+
+fn parse_typed_xacro_arg(
+    spec: &str,
+    parse: fn(&str) -> anyhow::Result<xurdf::XacroValue>,
+) -> Result<(String, xurdf::XacroValue), String> {
+    let (name, value) = parse_xacro_arg(spec)?;
+    let value = parse(&value).map_err(|err| format!("invalid value for `{}`: {:#}", name, err))?;
+    Ok((name, value))
+}
+
 fn parse_xacro_cli_args<I, S>(args: I) -> Result<Option<XacroCliArgs>, String>
 where
     I: IntoIterator<Item = S>,
@@ -576,6 +1180,12 @@ where
     let mut output = None;
     let mut package_paths = HashMap::new();
     let mut xacro_args = HashMap::new();
+    let mut number_format = xurdf::NumberFormat::default();
+    let mut strict = false;
+    let mut pretty = false;
+    let mut banner = false;
+    let mut deps = false;
+    let mut print_args = false;
 
     while let Some(arg) = iter.next() {
         match arg.as_str() {
@@ -593,13 +1203,36 @@ where
                 let (package, path) = parse_package_path(&spec)?;
                 package_paths.insert(package, path);
             }
+            "--json-arg" | "--yaml-arg" => {
+                let spec = iter
+                    .next()
+                    .ok_or_else(|| format!("{} requires NAME:=VALUE", arg))?;
+                let parse = if arg == "--json-arg" {
+                    parse_json_value
+                } else {
+                    xurdf::parse_yaml_value
+                };
+                let (name, value) = parse_typed_xacro_arg(&spec, parse)?;
+                xacro_args.insert(name, value);
+            }
+            "--strict" => strict = true,
+            "--pretty" => pretty = true,
+            "--banner" => banner = true,
+            "--deps" => deps = true,
+            "--print-args" => print_args = true,
+            "--number-format" => {
+                let name = iter
+                    .next()
+                    .ok_or_else(|| format!("{} requires a format", arg))?;
+                number_format = parse_number_format(&name)?;
+            }
             "--" => {
                 for value in iter.by_ref() {
                     if input.is_none() {
                         input = Some(PathBuf::from(value));
                     } else {
                         let (name, value) = parse_xacro_arg(&value)?;
-                        xacro_args.insert(name, value);
+                        xacro_args.insert(name, xurdf::XacroValue::String(value));
                     }
                 }
                 break;
@@ -616,11 +1249,24 @@ where
                 let (package, path) = parse_package_path(spec)?;
                 package_paths.insert(package, path);
             }
+            _ if arg.starts_with("--json-arg=") => {
+                let spec = arg.trim_start_matches("--json-arg=");
+                let (name, value) = parse_typed_xacro_arg(spec, parse_json_value)?;
+                xacro_args.insert(name, value);
+            }
+            _ if arg.starts_with("--yaml-arg=") => {
+                let spec = arg.trim_start_matches("--yaml-arg=");
+                let (name, value) = parse_typed_xacro_arg(spec, xurdf::parse_yaml_value)?;
+                xacro_args.insert(name, value);
+            }
+            _ if arg.starts_with("--number-format=") => {
+                number_format = parse_number_format(arg.trim_start_matches("--number-format="))?;
+            }
             _ if arg.starts_with("-") => return Err(format!("unknown option: {}", arg)),
             _ if input.is_none() => input = Some(PathBuf::from(arg)),
             _ => {
                 let (name, value) = parse_xacro_arg(&arg)?;
-                xacro_args.insert(name, value);
+                xacro_args.insert(name, xurdf::XacroValue::String(value));
             }
         }
     }
@@ -631,6 +1277,12 @@ where
         output,
         package_paths,
         args: xacro_args,
+        number_format,
+        strict,
+        pretty,
+        banner,
+        deps,
+        print_args,
     }))
 }
 
@@ -639,9 +1291,66 @@ fn xacro_options_for_cli(cli: &XacroCliArgs) -> xurdf::XacroOptions {
         xurdf::XacroOptions::default(),
         |options, (package, path)| options.with_package_path(package, path),
     );
-    cli.args.iter().fold(options, |options, (name, value)| {
-        options.with_arg(name, value)
-    })
+    cli.args
+        .iter()
+        .fold(options, |options, (name, value)| {
+            options.with_typed_arg(name, value.clone())
+        })
+        .with_number_format(cli.number_format)
+        .with_strict(cli.strict)
+        .with_pretty_print(cli.pretty)
+        .with_generated_banner(cli.banner)
+}
+
+fn run_graph_cli(args: Vec<String>, stdout: &mut dyn Write, stderr: &mut dyn Write) -> i32 {
+    let (format, cli) = match parse_graph_cli_args(args) {
+        Ok(Some(parsed)) => parsed,
+        Ok(None) => {
+            let _ = stdout
+                .write_all(XACRO_CLI_USAGE.as_bytes())
+                .and_then(|_| stdout.flush());
+            return 0;
+        }
+        Err(err) => {
+            let _ = writeln!(stderr, "xurdf-xacro: error: {}", err);
+            let _ = writeln!(stderr, "Try `xurdf-xacro --help` for usage.");
+            return 2;
+        }
+    };
+
+    let mut processor = xurdf::XacroProcessor::with_options(xacro_options_for_cli(&cli));
+    let xml = match processor.process_file(&cli.input) {
+        Ok(xml) => xml,
+        Err(err) => {
+            let _ = write!(stderr, "xurdf-xacro: {}", xurdf::render_xacro_error(&err));
+            return 1;
+        }
+    };
+    let robot = match xurdf::parse_urdf_from_string(&xml) {
+        Ok(robot) => robot,
+        Err(err) => {
+            let _ = writeln!(stderr, "xurdf-xacro: error: {:#}", err);
+            return 1;
+        }
+    };
+    let graph = match format {
+        GraphFormat::Dot => xurdf::write_dot_to_string(&robot),
+        GraphFormat::Mermaid => xurdf::write_mermaid_to_string(&robot),
+    };
+
+    let result = if let Some(output) = &cli.output {
+        fs::write(output, graph.as_bytes()).map_err(|err| err.to_string())
+    } else {
+        stdout
+            .write_all(graph.as_bytes())
+            .and_then(|_| stdout.flush())
+            .map_err(|err| err.to_string())
+    };
+    if let Err(err) = result {
+        let _ = writeln!(stderr, "xurdf-xacro: error: {}", err);
+        return 1;
+    }
+    0
 }
 
 fn run_xacro_cli<I, S>(args: I, stdout: &mut dyn Write, stderr: &mut dyn Write) -> i32
@@ -649,6 +1358,12 @@ where
     I: IntoIterator<Item = S>,
     S: Into<String>,
 {
+    let mut args = args.into_iter().map(Into::into).collect::<Vec<String>>();
+    if args.first().map(String::as_str) == Some("graph") {
+        args.remove(0);
+        return run_graph_cli(args, stdout, stderr);
+    }
+
     let cli = match parse_xacro_cli_args(args) {
         Ok(Some(cli)) => cli,
         Ok(None) => {
@@ -664,14 +1379,57 @@ where
         }
     };
 
-    let xml =
-        match xurdf::parse_xacro_from_file_with_options(&cli.input, xacro_options_for_cli(&cli)) {
-            Ok(xml) => xml,
-            Err(err) => {
-                let _ = writeln!(stderr, "xurdf-xacro: error: {:#}", err);
-                return 1;
-            }
-        };
+    let mut processor = xurdf::XacroProcessor::with_options(xacro_options_for_cli(&cli));
+    let xml = match processor.process_file(&cli.input) {
+        Ok(xml) => xml,
+        Err(err) => {
+            let _ = write!(stderr, "xurdf-xacro: {}", xurdf::render_xacro_error(&err));
+            return 1;
+        }
+    };
+
+    let report = processor.arg_report();
+    for name in report.undeclared() {
+        let _ = writeln!(
+            stderr,
+            "xurdf-xacro: warning: argument `{}` was provided but is not declared with xacro:arg",
+            name
+        );
+    }
+    for arg in report.unused() {
+        let _ = writeln!(
+            stderr,
+            "xurdf-xacro: warning: argument `{}` is declared with xacro:arg but never used",
+            arg.name
+        );
+    }
+
+    if cli.print_args {
+        let result = report
+            .declared
+            .iter()
+            .try_for_each(|arg| writeln!(stdout, "{}:={}", arg.name, arg.default))
+            .and_then(|_| stdout.flush());
+        if let Err(err) = result {
+            let _ = writeln!(stderr, "xurdf-xacro: error: {}", err);
+            return 1;
+        }
+        return 0;
+    }
+
+    if cli.deps {
+        let deps = processor
+            .dependencies()
+            .all()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>();
+        let result = writeln!(stdout, "{}", deps.join(" ")).and_then(|_| stdout.flush());
+        if let Err(err) = result {
+            let _ = writeln!(stderr, "xurdf-xacro: error: {}", err);
+            return 1;
+        }
+        return 0;
+    }
 
     let result = if let Some(output) = &cli.output {
         fs::write(output, xml.as_bytes()).map_err(|err| err.to_string())
@@ -715,13 +1473,24 @@ fn xurdfpy(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<Collision>()?;
     m.add_class::<Visual>()?;
     m.add_class::<Link>()?;
+    m.add_class::<Mimic>()?;
     m.add_class::<Joint>()?;
     m.add_class::<JointLimit>()?;
+    m.add_class::<TransmissionJoint>()?;
+    m.add_class::<Actuator>()?;
+    m.add_class::<Transmission>()?;
+    m.add_class::<Extension>()?;
     m.add_class::<Robot>()?;
     m.add_function(wrap_pyfunction!(parse_urdf_file, m)?)?;
     m.add_function(wrap_pyfunction!(parse_urdf_string, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_mjcf_file, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_mjcf_string, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_sdf_file, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_sdf_string, m)?)?;
     m.add_function(wrap_pyfunction!(parse_xacro_file, m)?)?;
     m.add_function(wrap_pyfunction!(parse_xacro_string, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_xacro_file_to_robot, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_xacro_string_to_robot, m)?)?;
     m.add_function(wrap_pyfunction!(xacro_main, m)?)?;
     Ok(())
 }
@@ -1,4 +1,4 @@
-from typing import List, Mapping, Optional, Sequence, Tuple, Union
+from typing import Any, List, Literal, Mapping, Optional, Sequence, Tuple, Union
 
 Vector3 = Tuple[float, float, float]
 Vector4 = Tuple[float, float, float, float]
@@ -55,6 +55,7 @@ class Material:
     def __repr__(self) -> str: ...
 
 Geometry = Union[Box, Cylinder, Sphere, Mesh]
+NumberFormat = Literal["display", "python"]
 
 class Visual:
     name: Optional[str]
@@ -83,6 +84,12 @@ class JointLimit:
     velocity: float
     def __repr__(self) -> str: ...
 
+class Mimic:
+    joint: str
+    multiplier: float
+    offset: float
+    def __repr__(self) -> str: ...
+
 class Joint:
     name: str
     joint_type: str
@@ -91,6 +98,31 @@ class Joint:
     child: str
     axis: Vector3
     limit: JointLimit
+    mimic: Optional[Mimic]
+    def __repr__(self) -> str: ...
+
+class TransmissionJoint:
+    name: str
+    hardware_interfaces: List[str]
+    def __repr__(self) -> str: ...
+
+class Actuator:
+    name: str
+    mechanical_reduction: Optional[float]
+    hardware_interfaces: List[str]
+    def __repr__(self) -> str: ...
+
+class Transmission:
+    name: str
+    transmission_type: str
+    joints: List[TransmissionJoint]
+    actuators: List[Actuator]
+    def __repr__(self) -> str: ...
+
+class Extension:
+    reference: Optional[str]
+    xml: str
+    def __init__(self, reference: Optional[str] = ..., xml: str = ...) -> None: ...
     def __repr__(self) -> str: ...
 
 class Robot:
@@ -98,18 +130,53 @@ class Robot:
     materials: List[Material]
     links: List[Link]
     joints: List[Joint]
+    transmissions: List[Transmission]
+    extensions: List[Extension]
     def __repr__(self) -> str: ...
+    def to_dict(self) -> dict[str, Any]: ...
+    @staticmethod
+    def from_dict(data: Mapping[str, Any]) -> Robot: ...
 
 def parse_urdf_file(filename: str) -> Robot: ...
 def parse_urdf_string(contents: str) -> Robot: ...
+def parse_mjcf_file(filename: str) -> Robot: ...
+def parse_mjcf_string(contents: str) -> Robot: ...
+def parse_sdf_file(filename: str) -> Robot: ...
+def parse_sdf_string(contents: str) -> Robot: ...
 def parse_xacro_file(
     filename: str,
     package_paths: Optional[Mapping[str, str]] = ...,
-    args: Optional[Mapping[str, str]] = ...,
+    args: Optional[Mapping[str, Any]] = ...,
+    number_format: Optional[NumberFormat] = ...,
+    strict: bool = ...,
+    pretty_print: bool = ...,
+    banner: bool = ...,
+    symbols: Optional[Mapping[str, Any]] = ...,
 ) -> str: ...
 def parse_xacro_string(
     contents: str,
     package_paths: Optional[Mapping[str, str]] = ...,
-    args: Optional[Mapping[str, str]] = ...,
+    args: Optional[Mapping[str, Any]] = ...,
+    number_format: Optional[NumberFormat] = ...,
+    strict: bool = ...,
+    pretty_print: bool = ...,
+    banner: bool = ...,
+    symbols: Optional[Mapping[str, Any]] = ...,
 ) -> str: ...
+def parse_xacro_file_to_robot(
+    filename: str,
+    package_paths: Optional[Mapping[str, str]] = ...,
+    args: Optional[Mapping[str, Any]] = ...,
+    number_format: Optional[NumberFormat] = ...,
+    strict: bool = ...,
+    symbols: Optional[Mapping[str, Any]] = ...,
+) -> Robot: ...
+def parse_xacro_string_to_robot(
+    contents: str,
+    package_paths: Optional[Mapping[str, str]] = ...,
+    args: Optional[Mapping[str, Any]] = ...,
+    number_format: Optional[NumberFormat] = ...,
+    strict: bool = ...,
+    symbols: Optional[Mapping[str, Any]] = ...,
+) -> Robot: ...
 def xacro_main() -> int: ...
@@ -0,0 +1,885 @@
+extern crate nalgebra as na;
+
+use super::kinematics::forward_kinematics;
+use super::model::*;
+use anyhow::{anyhow, bail, Result};
+use na::{Isometry3, Matrix3, Vector3};
+use std::collections::{HashMap, HashSet};
+
+// URDF convention for a link that only anchors the model to the world.
+pub(crate) const WORLD_LINK: &str = "world";
+
+/// Where a link removed by [`lump_fixed_joints`] ended up: `offset` is its pose in the frame of
+/// the remaining link `link`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LumpedFrame {
+    pub link: String,
+    pub offset: Isometry3<f64>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct LumpedRobot {
+    pub robot: Robot,
+    /// Removed links and the joints that fixed them, by name.
+    pub frames: HashMap<String, LumpedFrame>,
+}
+
+impl LumpedRobot {
+    /// The frame `name` as a remaining link plus offset, whether or not it was removed.
+    pub fn frame(&self, name: &str) -> Option<LumpedFrame> {
+        if let Some(frame) = self.frames.get(name) {
+            return Some(frame.clone());
+        }
+        self.robot.link(name).map(|link| LumpedFrame {
+            link: link.name.clone(),
+            offset: Isometry3::identity(),
+        })
+    }
+}
+
+/// Merges the child of every fixed joint into its parent link. Inertials are combined about
+/// their common center of mass, and visuals, collisions and child joints are re-expressed in
+/// the parent frame. `<gazebo>` blocks of a merged link move to the parent. Joints fixing a link
+/// to `world` are kept so the model stays anchored.
+pub fn lump_fixed_joints(robot: &Robot) -> Result<LumpedRobot> {
+    lump_fixed_joints_except(robot, &HashSet::new())
+}
+
+// `lump_fixed_joints`, keeping the fixed joints named in `kept`.
+pub(crate) fn lump_fixed_joints_except(robot: &Robot, kept: &HashSet<&str>) -> Result<LumpedRobot> {
+    let mut robot = robot.clone();
+    let mut frames = HashMap::<String, LumpedFrame>::new();
+    while let Some(index) = robot.joints.iter().position(|joint| {
+        joint.joint_type == "fixed"
+            && joint.parent != WORLD_LINK
+            && !kept.contains(joint.name.as_str())
+    }) {
+        let joint = robot.joints.remove(index);
+        let offset = joint.origin.to_isometry();
+        let child_index = robot
+            .links
+            .iter()
+            .position(|link| link.name == joint.child)
+            .ok_or_else(|| anyhow!("joint `{}` has unknown child `{}`", joint.name, joint.child))?;
+        let child = robot.links.remove(child_index);
+        let parent = robot
+            .links
+            .iter_mut()
+            .find(|link| link.name == joint.parent)
+            .ok_or_else(|| {
+                anyhow!(
+                    "joint `{}` has unknown parent `{}`",
+                    joint.name,
+                    joint.parent
+                )
+            })?;
+
+        parent.inertial = combine_inertials(&[
+            (&parent.inertial, Isometry3::identity()),
+            (&child.inertial, offset),
+        ]);
+        parent
+            .visuals
+            .extend(child.visuals.into_iter().map(|mut visual| {
+                visual.origin = Pose::from_isometry(&(offset * visual.origin.to_isometry()));
+                visual
+            }));
+        parent
+            .collisions
+            .extend(child.collisions.into_iter().map(|mut collision| {
+                collision.origin = Pose::from_isometry(&(offset * collision.origin.to_isometry()));
+                collision
+            }));
+
+        for other in robot
+            .joints
+            .iter_mut()
+            .filter(|other| other.parent == child.name)
+        {
+            other.parent = joint.parent.clone();
+            other.origin = Pose::from_isometry(&(offset * other.origin.to_isometry()));
+        }
+        for extension in robot
+            .extensions
+            .iter_mut()
+            .filter(|extension| extension.reference.as_ref() == Some(&child.name))
+        {
+            extension.reference = Some(joint.parent.clone());
+        }
+        // Frames lumped into the child earlier move along with it.
+        for frame in frames.values_mut().filter(|frame| frame.link == child.name) {
+            frame.link = joint.parent.clone();
+            frame.offset = offset * frame.offset;
+        }
+        let frame = LumpedFrame {
+            link: joint.parent.clone(),
+            offset,
+        };
+        frames.insert(joint.name, frame.clone());
+        frames.insert(child.name, frame);
+    }
+    Ok(LumpedRobot { robot, frames })
+}
+
+// Combines inertials given in frames placed at the accompanying poses. The result sits at
+// the combined center of mass with axes aligned to the common frame.
+pub(crate) fn combine_inertials(parts: &[(&Inertial, Isometry3<f64>)]) -> Inertial {
+    let mass = parts.iter().map(|(inertial, _)| inertial.mass).sum::<f64>();
+    if mass <= 0.0 {
+        return parts
+            .first()
+            .map(|(inertial, _)| (*inertial).clone())
+            .unwrap_or_default();
+    }
+
+    let placed = parts
+        .iter()
+        .map(|(inertial, pose)| {
+            let frame = pose * inertial.origin.to_isometry();
+            let rotation = frame.rotation.to_rotation_matrix();
+            let inertia = rotation.matrix() * inertial.inertia * rotation.matrix().transpose();
+            (inertial.mass, frame.translation.vector, inertia)
+        })
+        .collect::<Vec<_>>();
+    let center = placed
+        .iter()
+        .map(|(mass, com, _)| com * *mass)
+        .sum::<Vector3<f64>>()
+        / mass;
+    let inertia = placed
+        .iter()
+        .map(|(mass, com, inertia)| {
+            let d = com - center;
+            inertia + (Matrix3::identity() * d.dot(&d) - d * d.transpose()) * *mass
+        })
+        .sum::<Matrix3<f64>>();
+
+    Inertial {
+        origin: Pose {
+            xyz: center,
+            rpy: Vector3::zeros(),
+        },
+        mass,
+        inertia,
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct AttachOptions {
+    /// Pose of the attached root link in the parent link frame.
+    pub origin: Pose,
+    /// Prepended to every link, joint, transmission, actuator and conflicting material name of the
+    /// attached robot. Defaults to the attached robot's name followed by `_`.
+    pub prefix: Option<String>,
+    /// Name of the new fixed joint; defaults to `<parent>_to_<attached root>`.
+    pub joint_name: Option<String>,
+}
+
+impl AttachOptions {
+    pub fn with_origin(mut self, origin: Pose) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn with_joint_name(mut self, joint_name: impl Into<String>) -> Self {
+        self.joint_name = Some(joint_name.into());
+        self
+    }
+}
+
+pub fn attach_robot(robot: &Robot, attached: &Robot, parent_link: &str) -> Result<Robot> {
+    attach_robot_with_options(robot, attached, parent_link, AttachOptions::default())
+}
+
+/// Grafts `attached` onto `parent_link` of `robot` with a new fixed joint. The attached links,
+/// joints and transmissions are prefixed as by [`prefix_names`], its `<gazebo>` blocks are
+/// carried over with their references renamed, and its materials are merged into
+/// `robot.materials`, keeping shared definitions and renaming ones whose color differs. A `world`
+/// root of the attached robot is dropped together with its joint and the `<gazebo>` blocks
+/// referring to either.
+pub fn attach_robot_with_options(
+    robot: &Robot,
+    attached: &Robot,
+    parent_link: &str,
+    options: AttachOptions,
+) -> Result<Robot> {
+    if robot.link(parent_link).is_none() {
+        bail!("robot `{}` has no link `{}`", robot.name, parent_link);
+    }
+    let mut attached = attached.clone();
+    let mut origin = options.origin.to_isometry();
+    let roots = attached
+        .root_links()
+        .map(|link| link.name.clone())
+        .collect::<Vec<_>>();
+    let mut root = match roots.as_slice() {
+        [root] => root.clone(),
+        [] => bail!("robot `{}` has no root link", attached.name),
+        _ => bail!("robot `{}` has more than one root link", attached.name),
+    };
+    if root == WORLD_LINK {
+        let joints = attached.child_joints(WORLD_LINK).collect::<Vec<_>>();
+        let [joint] = joints[..] else {
+            bail!(
+                "`{}` of robot `{}` must have exactly one child joint to be attached",
+                WORLD_LINK,
+                attached.name
+            );
+        };
+        if joint.joint_type != "fixed" {
+            bail!(
+                "robot `{}` is attached to `{}` by non-fixed joint `{}`",
+                attached.name,
+                WORLD_LINK,
+                joint.name
+            );
+        }
+        let joint_name = joint.name.clone();
+        origin *= joint.origin.to_isometry();
+        root = joint.child.clone();
+        attached.joints.retain(|joint| joint.name != joint_name);
+        attached.links.retain(|link| link.name != WORLD_LINK);
+        attached.extensions.retain(|extension| {
+            extension.reference.as_deref() != Some(WORLD_LINK)
+                && extension.reference.as_ref() != Some(&joint_name)
+        });
+    }
+
+    let prefix = options
+        .prefix
+        .unwrap_or_else(|| format!("{}_", attached.name));
+    let mut attached = prefix_names(&attached, &prefix)?;
+    let root = format!("{}{}", prefix, root);
+
+    let mut result = robot.clone();
+    let renamed = merge_materials(&mut result.materials, attached.materials, &prefix);
+    for visual in attached
+        .links
+        .iter_mut()
+        .flat_map(|link| link.visuals.iter_mut())
+    {
+        if let Some(name) = visual
+            .material
+            .as_mut()
+            .and_then(|material| material.name.as_mut())
+        {
+            if let Some(new_name) = renamed.get(name) {
+                *name = new_name.clone();
+            }
+        }
+    }
+
+    let joint_name = options
+        .joint_name
+        .unwrap_or_else(|| format!("{}_to_{}", parent_link, root));
+    result.joints.push(Joint {
+        name: joint_name,
+        joint_type: "fixed".to_string(),
+        origin: Pose::from_isometry(&origin),
+        parent: parent_link.to_string(),
+        child: root,
+        ..Default::default()
+    });
+    result.links.extend(attached.links);
+    result.joints.extend(attached.joints);
+    result.transmissions.extend(attached.transmissions);
+    result.extensions.extend(attached.extensions);
+    if let Some(name) = duplicates(
+        result
+            .transmissions
+            .iter()
+            .map(|transmission| transmission.name.as_str()),
+    )
+    .first()
+    {
+        bail!("attaching produces duplicate transmission name {}", name);
+    }
+    check_tree(&result)?;
+    Ok(result)
+}
+
+pub fn prefix_names(robot: &Robot, prefix: &str) -> Result<Robot> {
+    rename_with(robot, |name| format!("{}{}", prefix, name))
+}
+
+/// Renames every link, joint, transmission and actuator of `robot` with `rename` and updates the
+/// references to them: joint parents and children, mimicked joints, transmission joints and
+/// `<gazebo reference>` attributes. The content of `<gazebo>` blocks is left as written. Fails,
+/// listing the names, if two links, joints or transmissions end up with the same name.
+///
+/// `rename` is called once per distinct name, whatever it names: a link and a joint that share a
+/// name are both given the name returned for it.
+pub fn rename_with(robot: &Robot, mut rename: impl FnMut(&str) -> String) -> Result<Robot> {
+    let mut robot = robot.clone();
+    // One map for every kind of name, so references stay consistent even if `rename` is not
+    // pure.
+    let mut names = HashMap::new();
+    let mut rename = |name: &mut String| {
+        let renamed = names
+            .entry(name.clone())
+            .or_insert_with(|| rename(name))
+            .clone();
+        *name = renamed;
+    };
+    for link in robot.links.iter_mut() {
+        rename(&mut link.name);
+    }
+    for joint in robot.joints.iter_mut() {
+        rename(&mut joint.parent);
+        rename(&mut joint.child);
+    }
+    for joint in robot.joints.iter_mut() {
+        rename(&mut joint.name);
+        if let Some(mimic) = joint.mimic.as_mut() {
+            rename(&mut mimic.joint);
+        }
+    }
+    for transmission in robot.transmissions.iter_mut() {
+        for joint in transmission.joints.iter_mut() {
+            rename(&mut joint.name);
+        }
+    }
+    for extension in robot.extensions.iter_mut() {
+        if let Some(reference) = extension.reference.as_mut() {
+            rename(reference);
+        }
+    }
+    for transmission in robot.transmissions.iter_mut() {
+        rename(&mut transmission.name);
+        for actuator in transmission.actuators.iter_mut() {
+            rename(&mut actuator.name);
+        }
+    }
+
+    let collisions = [
+        (
+            "link",
+            duplicates(robot.links.iter().map(|link| link.name.as_str())),
+        ),
+        (
+            "joint",
+            duplicates(robot.joints.iter().map(|joint| joint.name.as_str())),
+        ),
+        (
+            "transmission",
+            duplicates(
+                robot
+                    .transmissions
+                    .iter()
+                    .map(|transmission| transmission.name.as_str()),
+            ),
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, names)| !names.is_empty())
+    .map(|(kind, names)| format!("{} names {}", kind, names.join(", ")))
+    .collect::<Vec<_>>();
+    if !collisions.is_empty() {
+        bail!("renaming produces duplicate {}", collisions.join(" and "));
+    }
+    Ok(robot)
+}
+
+fn duplicates<'a>(names: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut seen = HashSet::new();
+    let mut duplicates = Vec::new();
+    for name in names {
+        if !seen.insert(name) && !duplicates.contains(&name) {
+            duplicates.push(name);
+        }
+    }
+    duplicates
+}
+
+/// Re-roots `robot` at `link`. The joints on the path from the old root are reversed: each
+/// reversed joint moves along its negated axis, so joint positions keep their meaning, and each
+/// link on the path takes the frame of the joint that used to lead away from it. Inertials,
+/// visuals, collisions and other joints of those links are re-expressed in the new frames, so
+/// the model describes the same geometry.
+pub fn reroot(robot: &Robot, link: &str) -> Result<Robot> {
+    check_tree(robot)?;
+    if robot.link(link).is_none() {
+        bail!("robot `{}` has no link `{}`", robot.name, link);
+    }
+
+    let mut path = Vec::new();
+    let mut current = link;
+    while let Some(index) = robot.joints.iter().position(|joint| joint.child == current) {
+        path.push(index);
+        current = &robot.joints[index].parent;
+    }
+    // New frame of every link on the path, except the new root, in its old frame.
+    let shifts = path
+        .iter()
+        .map(|index| {
+            let joint = &robot.joints[*index];
+            (joint.parent.clone(), joint.origin.to_isometry())
+        })
+        .collect::<HashMap<_, _>>();
+    let reexpress = |shift: &Isometry3<f64>, pose: &mut Pose| {
+        *pose = Pose::from_isometry(&(shift.inverse() * pose.to_isometry()));
+    };
+
+    let mut robot = robot.clone();
+    for (index, joint) in robot.joints.iter_mut().enumerate() {
+        if path.contains(&index) {
+            std::mem::swap(&mut joint.parent, &mut joint.child);
+            let shift = shifts
+                .get(&joint.parent)
+                .copied()
+                .unwrap_or_else(Isometry3::identity);
+            joint.origin = Pose::from_isometry(&shift.inverse());
+            joint.axis = -joint.axis;
+        } else if let Some(shift) = shifts.get(&joint.parent) {
+            reexpress(shift, &mut joint.origin);
+        }
+    }
+    for link in robot.links.iter_mut() {
+        if let Some(shift) = shifts.get(&link.name) {
+            reexpress(shift, &mut link.inertial.origin);
+            for visual in link.visuals.iter_mut() {
+                reexpress(shift, &mut visual.origin);
+            }
+            for collision in link.collisions.iter_mut() {
+                reexpress(shift, &mut collision.origin);
+            }
+        }
+    }
+    Ok(robot)
+}
+
+// Adds `added` to `materials`. A material whose name is taken by a different color gets the
+// first free `prefix`ed name; the returned map holds those renames.
+fn merge_materials(
+    materials: &mut Vec<Material>,
+    added: Vec<Material>,
+    prefix: &str,
+) -> HashMap<String, String> {
+    let mut renamed = HashMap::new();
+    for mut material in added {
+        let Some(name) = material.name.clone() else {
+            materials.push(material);
+            continue;
+        };
+        let existing = materials
+            .iter()
+            .find(|other| other.name.as_deref() == Some(name.as_str()));
+        match existing {
+            None => materials.push(material),
+            Some(existing) if existing.color == material.color => {}
+            Some(_) => {
+                let taken = |candidate: &str| {
+                    materials
+                        .iter()
+                        .any(|other| other.name.as_deref() == Some(candidate))
+                };
+                let mut new_name = format!("{}{}", prefix, name);
+                let mut suffix = 2;
+                while taken(&new_name) {
+                    new_name = format!("{}{}_{}", prefix, name, suffix);
+                    suffix += 1;
+                }
+                material.name = Some(new_name.clone());
+                materials.push(material);
+                renamed.insert(name, new_name);
+            }
+        }
+    }
+    renamed
+}
+
+// Checks that `robot` is a single tree: unique names, joints between known links and exactly
+// one root from which every link is reached once.
+pub(crate) fn check_tree(robot: &Robot) -> Result<()> {
+    let mut links = HashSet::new();
+    for link in robot.links.iter() {
+        if !links.insert(link.name.as_str()) {
+            bail!("duplicate link `{}`", link.name);
+        }
+    }
+    let mut joints = HashSet::new();
+    for joint in robot.joints.iter() {
+        if !joints.insert(joint.name.as_str()) {
+            bail!("duplicate joint `{}`", joint.name);
+        }
+        for link in [&joint.parent, &joint.child] {
+            if !links.contains(link.as_str()) {
+                bail!("joint `{}` refers to unknown link `{}`", joint.name, link);
+            }
+        }
+    }
+    let roots = robot
+        .root_links()
+        .map(|link| link.name.as_str())
+        .collect::<Vec<_>>();
+    if roots.len() != 1 {
+        bail!(
+            "expected one root link, found {}: {}",
+            roots.len(),
+            roots.join(", ")
+        );
+    }
+    forward_kinematics(robot, &HashMap::new())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_urdf_from_string;
+
+    #[test]
+    fn attaches_prefixed_robot_with_merged_materials() {
+        let arm = parse_urdf_from_string(
+            r#"
+            <robot name="arm">
+              <material name="grey"><color rgba="0.5 0.5 0.5 1"/></material>
+              <link name="base"/>
+              <link name="flange"/>
+              <joint name="wrist" type="revolute">
+                <parent link="base"/>
+                <child link="flange"/>
+                <axis xyz="0 0 1"/>
+              </joint>
+            </robot>
+            "#,
+        )
+        .unwrap();
+        let gripper = parse_urdf_from_string(
+            r#"
+            <robot name="gripper">
+              <material name="grey"><color rgba="0.2 0.2 0.2 1"/></material>
+              <link name="world"/>
+              <link name="base">
+                <visual><geometry><box size="1 1 1"/></geometry><material name="grey"/></visual>
+              </link>
+              <link name="finger"/>
+              <joint name="mount" type="fixed">
+                <parent link="world"/>
+                <child link="base"/>
+                <origin xyz="0 0 0.1"/>
+              </joint>
+              <joint name="slide" type="prismatic">
+                <parent link="base"/>
+                <child link="finger"/>
+                <axis xyz="1 0 0"/>
+              </joint>
+              <transmission name="slide_trans">
+                <type>transmission_interface/SimpleTransmission</type>
+                <joint name="slide"/>
+                <actuator name="motor"/>
+              </transmission>
+              <gazebo reference="world"><static>true</static></gazebo>
+              <gazebo reference="finger"><mu1>1</mu1></gazebo>
+              <gazebo><plugin filename="libgrip.so"/></gazebo>
+            </robot>
+            "#,
+        )
+        .unwrap();
+
+        let robot = attach_robot_with_options(
+            &arm,
+            &gripper,
+            "flange",
+            AttachOptions::default().with_origin(Pose {
+                xyz: Vector3::new(0.0, 0.0, 0.5),
+                ..Default::default()
+            }),
+        )
+        .unwrap();
+
+        let links = robot
+            .links
+            .iter()
+            .map(|link| link.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(links, ["base", "flange", "gripper_base", "gripper_finger"]);
+        let mount = robot.joint("flange_to_gripper_base").unwrap();
+        assert_eq!(mount.joint_type, "fixed");
+        assert_eq!(mount.parent, "flange");
+        assert!((mount.origin.xyz - Vector3::new(0.0, 0.0, 0.6)).norm() < 1e-12);
+        let slide = robot.joint("gripper_slide").unwrap();
+        assert_eq!(
+            (slide.parent.as_str(), slide.child.as_str()),
+            ("gripper_base", "gripper_finger")
+        );
+
+        let materials = robot
+            .materials
+            .iter()
+            .map(|material| material.name.as_deref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(materials, ["grey", "gripper_grey"]);
+        let material = robot.link("gripper_base").unwrap().visuals[0]
+            .material
+            .as_ref();
+        assert_eq!(material.unwrap().name.as_deref(), Some("gripper_grey"));
+
+        let transmission = &robot.transmissions[0];
+        assert_eq!(transmission.name, "gripper_slide_trans");
+        assert_eq!(transmission.joints[0].name, "gripper_slide");
+        assert_eq!(transmission.actuators[0].name, "gripper_motor");
+        let references = robot
+            .extensions
+            .iter()
+            .map(|extension| extension.reference.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(references, [Some("gripper_finger"), None]);
+    }
+
+    #[test]
+    fn lumps_fixed_chains_into_parent() {
+        let robot = parse_urdf_from_string(
+            r#"
+            <robot name="arm">
+              <link name="base">
+                <inertial><mass value="1"/><inertia ixx="1" iyy="1" izz="1" ixy="0" ixz="0" iyz="0"/></inertial>
+              </link>
+              <link name="plate">
+                <inertial>
+                  <origin rpy="0 0 1.5707963267948966"/>
+                  <mass value="2"/>
+                  <inertia ixx="1" iyy="2" izz="3" ixy="0" ixz="0" iyz="0"/>
+                </inertial>
+                <collision><geometry><sphere radius="0.1"/></geometry></collision>
+              </link>
+              <link name="tool"/>
+              <link name="finger"/>
+              <joint name="plate_joint" type="fixed">
+                <parent link="base"/>
+                <child link="plate"/>
+                <origin xyz="1 0 0"/>
+              </joint>
+              <joint name="tool_joint" type="fixed">
+                <parent link="plate"/>
+                <child link="tool"/>
+                <origin xyz="0 0 1" rpy="0 0 1.5707963267948966"/>
+              </joint>
+              <joint name="finger_joint" type="prismatic">
+                <parent link="tool"/>
+                <child link="finger"/>
+                <origin xyz="0.5 0 0"/>
+                <axis xyz="1 0 0"/>
+              </joint>
+              <gazebo reference="tool"><mu1>0.5</mu1></gazebo>
+            </robot>
+            "#,
+        )
+        .unwrap();
+
+        let lumped = lump_fixed_joints(&robot).unwrap();
+        let links = lumped
+            .robot
+            .links
+            .iter()
+            .map(|link| link.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(links, ["base", "finger"]);
+
+        let inertial = &lumped.robot.links[0].inertial;
+        assert_eq!(inertial.mass, 3.0);
+        assert!((inertial.origin.xyz - Vector3::new(2.0 / 3.0, 0.0, 0.0)).norm() < 1e-12);
+        let expected = Matrix3::from_diagonal(&Vector3::new(3.0, 8.0 / 3.0, 14.0 / 3.0));
+        assert!((inertial.inertia - expected).norm() < 1e-12);
+        let collision = &lumped.robot.links[0].collisions[0];
+        assert!((collision.origin.xyz - Vector3::x()).norm() < 1e-12);
+
+        let finger_joint = lumped.robot.joint("finger_joint").unwrap();
+        assert_eq!(finger_joint.parent, "base");
+        assert!((finger_joint.origin.xyz - Vector3::new(1.0, 0.5, 1.0)).norm() < 1e-12);
+
+        let tool = lumped.frame("tool").unwrap();
+        assert_eq!(tool.link, "base");
+        assert!((tool.offset.translation.vector - Vector3::new(1.0, 0.0, 1.0)).norm() < 1e-12);
+        assert_eq!(lumped.frame("tool_joint"), Some(tool));
+        assert_eq!(lumped.frame("base").unwrap().offset, Isometry3::identity());
+        assert!(lumped.frame("missing").is_none());
+        assert_eq!(
+            lumped.robot.extensions[0].reference.as_deref(),
+            Some("base")
+        );
+    }
+
+    #[test]
+    fn rerooting_keeps_geometry_in_place() {
+        let robot = parse_urdf_from_string(
+            r#"
+            <robot name="arm">
+              <link name="base"><visual><origin xyz="0 0 0.1"/><geometry><sphere radius="1"/></geometry></visual></link>
+              <link name="upper"><visual><origin rpy="0.3 0 0"/><geometry><sphere radius="1"/></geometry></visual></link>
+              <link name="fore"><visual><origin xyz="0.2 0 0"/><geometry><sphere radius="1"/></geometry></visual></link>
+              <link name="tool">
+                <inertial><origin xyz="0 0 0.05"/><mass value="1"/></inertial>
+                <visual><geometry><sphere radius="1"/></geometry></visual>
+              </link>
+              <link name="side"><visual><geometry><sphere radius="1"/></geometry></visual></link>
+              <joint name="shoulder" type="revolute">
+                <parent link="base"/>
+                <child link="upper"/>
+                <origin xyz="0 0 1" rpy="0 0.4 0"/>
+                <axis xyz="0 0 1"/>
+              </joint>
+              <joint name="slide" type="prismatic">
+                <parent link="upper"/>
+                <child link="fore"/>
+                <origin xyz="0.5 0 0" rpy="0.1 0 0.7"/>
+                <axis xyz="1 0 0"/>
+              </joint>
+              <joint name="flange" type="fixed">
+                <parent link="fore"/>
+                <child link="tool"/>
+                <origin xyz="0 0.3 0" rpy="0 0 1.2"/>
+              </joint>
+              <joint name="side_joint" type="continuous">
+                <parent link="upper"/>
+                <child link="side"/>
+                <origin xyz="0 0.2 0.1"/>
+                <axis xyz="0 1 0"/>
+              </joint>
+            </robot>
+            "#,
+        )
+        .unwrap();
+
+        let rerooted = reroot(&robot, "tool").unwrap();
+        assert_eq!(rerooted.root_links().next().unwrap().name, "tool");
+        let shoulder = rerooted.joint("shoulder").unwrap();
+        assert_eq!(
+            (shoulder.parent.as_str(), shoulder.child.as_str()),
+            ("upper", "base")
+        );
+        assert_eq!(shoulder.axis, -Vector3::z());
+        let tool = rerooted.link("tool").unwrap();
+        assert!((tool.inertial.origin.xyz - Vector3::new(0.0, 0.0, 0.05)).norm() < 1e-12);
+
+        let positions = HashMap::from([
+            ("shoulder".to_string(), 0.8),
+            ("slide".to_string(), 0.25),
+            ("side_joint".to_string(), -1.1),
+        ]);
+        let before = forward_kinematics(&robot, &positions).unwrap();
+        let after = forward_kinematics(&rerooted, &positions).unwrap();
+        let tool_pose = before["tool"].inverse();
+        for link in robot.links.iter() {
+            let expected = tool_pose * before[&link.name] * link.visuals[0].origin.to_isometry();
+            let actual = after[&link.name]
+                * rerooted.link(&link.name).unwrap().visuals[0]
+                    .origin
+                    .to_isometry();
+            assert!(
+                (expected.to_homogeneous() - actual.to_homogeneous()).norm() < 1e-9,
+                "{} moved",
+                link.name
+            );
+        }
+        let side = tool_pose * before["side"];
+        assert!((after["side"].to_homogeneous() - side.to_homogeneous()).norm() < 1e-9);
+    }
+
+    #[test]
+    fn renames_links_and_joint_references() {
+        let robot = parse_urdf_from_string(
+            r#"
+            <robot name="arm">
+              <link name="base"/>
+              <link name="tip"/>
+              <link name="finger"/>
+              <joint name="tip_joint" type="revolute">
+                <parent link="base"/>
+                <child link="tip"/>
+              </joint>
+              <joint name="finger_joint" type="revolute">
+                <parent link="tip"/>
+                <child link="finger"/>
+                <mimic joint="tip_joint" multiplier="2"/>
+              </joint>
+              <transmission name="tip_trans">
+                <joint name="tip_joint"/>
+                <actuator name="tip_motor"/>
+              </transmission>
+              <gazebo reference="finger"><mu1>1</mu1></gazebo>
+              <gazebo reference="tip_joint"><provideFeedback>true</provideFeedback></gazebo>
+              <gazebo><static>false</static></gazebo>
+            </robot>
+            "#,
+        )
+        .unwrap();
+
+        let left = prefix_names(&robot, "left_").unwrap();
+        assert_eq!(left.links[1].name, "left_tip");
+        let joint = &left.joints[0];
+        assert_eq!(
+            (
+                joint.name.as_str(),
+                joint.parent.as_str(),
+                joint.child.as_str()
+            ),
+            ("left_tip_joint", "left_base", "left_tip")
+        );
+        let mimic = left.joints[1].mimic.as_ref().unwrap();
+        assert_eq!(mimic.joint, "left_tip_joint");
+        assert_eq!(mimic.multiplier, 2.0);
+        let transmission = &left.transmissions[0];
+        assert_eq!(
+            (
+                transmission.name.as_str(),
+                transmission.joints[0].name.as_str(),
+                transmission.actuators[0].name.as_str()
+            ),
+            ("left_tip_trans", "left_tip_joint", "left_tip_motor")
+        );
+        let references = left
+            .extensions
+            .iter()
+            .map(|extension| extension.reference.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            references,
+            [Some("left_finger"), Some("left_tip_joint"), None]
+        );
+
+        let mut calls = Vec::new();
+        let numbered = rename_with(&robot, |name| {
+            calls.push(name.to_string());
+            format!("{}_{}", name, calls.len())
+        })
+        .unwrap();
+        assert_eq!(
+            numbered.joints[1].mimic.as_ref().unwrap().joint,
+            numbered.joints[0].name
+        );
+        assert_eq!(
+            numbered.transmissions[0].joints[0].name,
+            numbered.joints[0].name
+        );
+        assert_eq!(calls.iter().collect::<HashSet<_>>().len(), calls.len());
+
+        let err = rename_with(&robot, |name| name.replace("tip", "base")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "renaming produces duplicate link names base"
+        );
+    }
+
+    #[test]
+    fn rejects_colliding_names() {
+        let robot =
+            parse_urdf_from_string(r#"<robot name="a"><link name="base"/></robot>"#).unwrap();
+        let err = attach_robot_with_options(
+            &robot,
+            &robot,
+            "base",
+            AttachOptions::default().with_prefix(""),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "duplicate link `base`");
+        assert!(attach_robot(&robot, &robot, "missing").is_err());
+        assert!(attach_robot(&robot, &robot, "base").is_ok());
+    }
+}
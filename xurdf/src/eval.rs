@@ -1,18 +1,29 @@
 use super::lexer::*;
-use anyhow::{anyhow, Result};
-use indexmap::IndexMap;
-use pyisheval::{EvalError, Interpreter, Value};
+use super::pyexpr::{self, Builtin, EvalError, Value};
+use anyhow::{anyhow, Context, Result};
+use once_cell::sync::Lazy;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
 
 #[derive(Clone, Debug)]
 pub struct PropertyValue {
     pub raw_value: String,
 }
 
+/// With the `serde` feature, values map onto plain JSON-like data: `null`, booleans, numbers,
+/// strings, arrays and objects.
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+#[non_exhaustive]
 pub enum XacroValue {
     Null,
     Bool(bool),
+    Integer(i64),
     Number(f64),
     String(String),
     List(Vec<XacroValue>),
@@ -25,6 +36,8 @@ impl XacroValue {
             Self::Bool(true)
         } else if raw_value.eq_ignore_ascii_case("false") {
             Self::Bool(false)
+        } else if let Ok(value) = raw_value.parse::<i64>() {
+            Self::Integer(value)
         } else if let Ok(value) = raw_value.parse::<f64>() {
             Self::Number(value)
         } else {
@@ -33,19 +46,24 @@ impl XacroValue {
     }
 
     pub fn raw_value(&self) -> String {
+        self.raw_value_with_format(NumberFormat::default())
+    }
+
+    pub fn raw_value_with_format(&self, format: NumberFormat) -> String {
         match self {
             Self::Null => String::new(),
-            Self::Bool(value) => value.to_string(),
-            Self::Number(value) => value.to_string(),
+            Self::Bool(value) => format.format_bool(*value),
+            Self::Integer(value) => value.to_string(),
+            Self::Number(value) => format.format_number(*value),
             Self::String(value) => value.clone(),
             Self::List(values) => values
                 .iter()
-                .map(Self::raw_value)
+                .map(|value| value.raw_value_with_format(format))
                 .collect::<Vec<_>>()
                 .join(" "),
             Self::Map(values) => values
                 .iter()
-                .map(|(key, value)| format!("{}: {}", key, value.raw_value()))
+                .map(|(key, value)| format!("{}: {}", key, value.raw_value_with_format(format)))
                 .collect::<Vec<_>>()
                 .join(", "),
         }
@@ -55,6 +73,7 @@ impl XacroValue {
         match self {
             Self::Null => false,
             Self::Bool(value) => *value,
+            Self::Integer(value) => *value != 0,
             Self::Number(value) => *value != 0.0,
             Self::String(value) => !value.is_empty() && !value.eq_ignore_ascii_case("false"),
             Self::List(values) => !values.is_empty(),
@@ -64,22 +83,196 @@ impl XacroValue {
 
     fn to_eval_value(&self) -> Value {
         match self {
-            Self::Null => Value::StringLit(String::new()),
-            Self::Bool(value) => Value::Number(if *value { 1.0 } else { 0.0 }),
-            Self::Number(value) => Value::Number(*value),
-            Self::String(value) => Value::StringLit(value.clone()),
+            Self::Null => Value::None,
+            Self::Bool(value) => Value::Bool(*value),
+            Self::Integer(value) => Value::Int(*value),
+            Self::Number(value) => Value::Float(*value),
+            Self::String(value) => Value::Str(value.clone()),
             Self::List(values) => Value::List(values.iter().map(Self::to_eval_value).collect()),
-            Self::Map(values) => {
-                let mut map = IndexMap::new();
-                for (key, value) in values.iter() {
-                    map.insert(key.clone(), value.to_eval_value());
-                }
-                Value::Dict(map)
-            }
+            Self::Map(values) => Value::Dict(
+                values
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.to_eval_value()))
+                    .collect(),
+            ),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NumberFormat {
+    /// Rust `f64` display, e.g. `180` and `0.00001`.
+    #[default]
+    Display,
+    /// Python `repr`, e.g. `180.0`, `1e-05` and `True`, as printed by upstream xacro.
+    PythonRepr,
+}
+
+impl NumberFormat {
+    pub fn format_bool(&self, value: bool) -> String {
+        match (self, value) {
+            (Self::Display, _) => value.to_string(),
+            (Self::PythonRepr, true) => "True".to_string(),
+            (Self::PythonRepr, false) => "False".to_string(),
+        }
+    }
+
+    pub fn format_number(&self, value: f64) -> String {
+        match self {
+            Self::Display => value.to_string(),
+            Self::PythonRepr => pyexpr::float_repr(value),
+        }
+    }
+}
+
+pub type XacroFunction = dyn Fn(&[XacroValue]) -> Result<XacroValue> + Send + Sync;
+
+#[derive(Clone, Default)]
+pub struct XacroFunctions {
+    functions: Arc<HashMap<String, Arc<XacroFunction>>>,
+}
+
+impl XacroFunctions {
+    pub fn insert<F>(&mut self, name: impl Into<String>, function: F)
+    where
+        F: Fn(&[XacroValue]) -> Result<XacroValue> + Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.functions).insert(name.into(), Arc::new(function));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&XacroFunction> {
+        self.functions.get(name).map(Arc::as_ref)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.functions.keys().map(String::as_str)
+    }
+}
+
+impl fmt::Debug for XacroFunctions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = self.names().collect::<Vec<_>>();
+        names.sort_unstable();
+        f.debug_set().entries(names).finish()
+    }
+}
+
+/// Names visible to expressions, looked up one at a time so nested scopes never need to be
+/// flattened into a single map.
+pub trait XacroSymbols {
+    fn get(&self, name: &str) -> Option<&XacroValue>;
+
+    fn names(&self) -> Vec<&str>;
+
+    fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+}
+
+impl XacroSymbols for HashMap<String, XacroValue> {
+    fn get(&self, name: &str) -> Option<&XacroValue> {
+        HashMap::get(self, name)
+    }
+
+    fn names(&self) -> Vec<&str> {
+        self.keys().map(String::as_str).collect()
+    }
+}
+
+struct SymbolsWithCalls<'a> {
+    symbols: &'a dyn XacroSymbols,
+    calls: &'a HashMap<String, XacroValue>,
+}
+
+impl XacroSymbols for SymbolsWithCalls<'_> {
+    fn get(&self, name: &str) -> Option<&XacroValue> {
+        self.calls.get(name).or_else(|| self.symbols.get(name))
+    }
+
+    fn names(&self) -> Vec<&str> {
+        let mut names = self.symbols.names();
+        names.extend(self.calls.keys().map(String::as_str));
+        names
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct EvalOptions {
+    pub number_format: NumberFormat,
+    pub strict: bool,
+    pub functions: XacroFunctions,
+    pub(crate) step_hook: Option<StepHook>,
+}
+
+/// Called before each sub-expression is evaluated; an error stops the evaluation and is
+/// returned as is, even when unresolved expressions are otherwise kept as text.
+#[derive(Clone)]
+pub(crate) struct StepHook(pub(crate) Arc<dyn Fn() -> Result<()> + Send + Sync>);
+
+impl fmt::Debug for StepHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StepHook")
+    }
+}
+
+impl EvalOptions {
+    pub fn with_functions(mut self, functions: XacroFunctions) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    pub fn with_function<F>(mut self, name: impl Into<String>, function: F) -> Self
+    where
+        F: Fn(&[XacroValue]) -> Result<XacroValue> + Send + Sync + 'static,
+    {
+        self.functions.insert(name, function);
+        self
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn with_number_format(mut self, number_format: NumberFormat) -> Self {
+        self.number_format = number_format;
+        self
+    }
+
+    pub(crate) fn with_step_hook(mut self, hook: StepHook) -> Self {
+        self.step_hook = Some(hook);
+        self
+    }
+}
+
+// The outer error comes from the step hook and must always be reported; the inner one is an
+// ordinary evaluation failure.
+fn eval_python(
+    expr: &str,
+    names: &dyn Fn(&str) -> Option<Value>,
+    options: &EvalOptions,
+) -> Result<Result<Value, EvalError>> {
+    let Some(StepHook(hook)) = &options.step_hook else {
+        return Ok(pyexpr::eval(expr, names));
+    };
+    let interrupted = RefCell::new(None);
+    let result = pyexpr::eval_with_steps(expr, names, &|| {
+        hook().map_err(|err| {
+            *interrupted.borrow_mut() = Some(err);
+            EvalError::Interrupted
+        })
+    });
+    match interrupted.into_inner() {
+        Some(err) => Err(err),
+        None => Ok(result),
+    }
+}
+
 impl From<&PropertyValue> for XacroValue {
     fn from(value: &PropertyValue) -> Self {
         Self::from_raw(&value.raw_value)
@@ -123,7 +316,27 @@ where
 
 pub fn try_eval_text_with_values<F, G>(
     s: &str,
-    symbol_map: &HashMap<String, XacroValue>,
+    symbol_map: &dyn XacroSymbols,
+    resolve_extension: &F,
+    resolve_value: &G,
+) -> Result<String>
+where
+    F: Fn(&str) -> Result<String>,
+    G: Fn(&str) -> Result<Option<XacroValue>>,
+{
+    try_eval_text_with_options(
+        s,
+        symbol_map,
+        &EvalOptions::default(),
+        resolve_extension,
+        resolve_value,
+    )
+}
+
+pub fn try_eval_text_with_options<F, G>(
+    s: &str,
+    symbol_map: &dyn XacroSymbols,
+    options: &EvalOptions,
     resolve_extension: &F,
     resolve_value: &G,
 ) -> Result<String>
@@ -137,22 +350,27 @@ where
         match token.0 {
             TokenType::Text => result.push(token.1),
             TokenType::Expr => {
-                let expr_in = try_eval_text_with_values(
+                let expr_in = try_eval_text_with_options(
                     token.1.as_str(),
                     symbol_map,
+                    options,
                     resolve_extension,
                     resolve_value,
                 )?;
-                if let Some(value) = try_eval_expression(&expr_in, symbol_map, resolve_value)? {
-                    result.push(remove_quotation_marks(&value.raw_value()).to_owned());
+                if let Some(value) =
+                    try_eval_expression(&expr_in, symbol_map, options, resolve_value)?
+                {
+                    let value = value.raw_value_with_format(options.number_format);
+                    result.push(remove_quotation_marks(&value).to_owned());
                 } else {
                     result.push(format!("${{{}}}", token.1));
                 }
             }
             TokenType::Extension => {
-                let expr_in = try_eval_text_with_values(
+                let expr_in = try_eval_text_with_options(
                     token.1.as_str(),
                     symbol_map,
+                    options,
                     resolve_extension,
                     resolve_value,
                 )?;
@@ -166,7 +384,27 @@ where
 
 pub fn try_eval_value_with_values<F, G>(
     s: &str,
-    symbol_map: &HashMap<String, XacroValue>,
+    symbol_map: &dyn XacroSymbols,
+    resolve_extension: &F,
+    resolve_value: &G,
+) -> Result<XacroValue>
+where
+    F: Fn(&str) -> Result<String>,
+    G: Fn(&str) -> Result<Option<XacroValue>>,
+{
+    try_eval_value_with_options(
+        s,
+        symbol_map,
+        &EvalOptions::default(),
+        resolve_extension,
+        resolve_value,
+    )
+}
+
+pub fn try_eval_value_with_options<F, G>(
+    s: &str,
+    symbol_map: &dyn XacroSymbols,
+    options: &EvalOptions,
     resolve_extension: &F,
     resolve_value: &G,
 ) -> Result<XacroValue>
@@ -175,14 +413,20 @@ where
     G: Fn(&str) -> Result<Option<XacroValue>>,
 {
     if let Some(expr) = single_expression(s) {
-        let expr_in =
-            try_eval_text_with_values(&expr, symbol_map, resolve_extension, resolve_value)?;
-        if let Some(value) = try_eval_expression(&expr_in, symbol_map, resolve_value)? {
+        let expr_in = try_eval_text_with_options(
+            &expr,
+            symbol_map,
+            options,
+            resolve_extension,
+            resolve_value,
+        )?;
+        if let Some(value) = try_eval_expression(&expr_in, symbol_map, options, resolve_value)? {
             return Ok(value);
         }
     }
 
-    let value = try_eval_text_with_values(s, symbol_map, resolve_extension, resolve_value)?;
+    let value =
+        try_eval_text_with_options(s, symbol_map, options, resolve_extension, resolve_value)?;
     Ok(XacroValue::from_raw(&value))
 }
 
@@ -197,7 +441,27 @@ pub fn try_get_boolean_value(s: &str, symbol_map: &HashMap<String, PropertyValue
 
 pub fn try_get_boolean_value_with_values<F, G>(
     s: &str,
-    symbol_map: &HashMap<String, XacroValue>,
+    symbol_map: &dyn XacroSymbols,
+    resolve_extension: &F,
+    resolve_value: &G,
+) -> Result<bool>
+where
+    F: Fn(&str) -> Result<String>,
+    G: Fn(&str) -> Result<Option<XacroValue>>,
+{
+    try_get_boolean_value_with_options(
+        s,
+        symbol_map,
+        &EvalOptions::default(),
+        resolve_extension,
+        resolve_value,
+    )
+}
+
+pub fn try_get_boolean_value_with_options<F, G>(
+    s: &str,
+    symbol_map: &dyn XacroSymbols,
+    options: &EvalOptions,
     resolve_extension: &F,
     resolve_value: &G,
 ) -> Result<bool>
@@ -206,14 +470,20 @@ where
     G: Fn(&str) -> Result<Option<XacroValue>>,
 {
     if let Some(expr) = single_expression(s) {
-        let expr_in =
-            try_eval_text_with_values(&expr, symbol_map, resolve_extension, resolve_value)?;
-        if let Some(value) = try_eval_expression(&expr_in, symbol_map, resolve_value)? {
+        let expr_in = try_eval_text_with_options(
+            &expr,
+            symbol_map,
+            options,
+            resolve_extension,
+            resolve_value,
+        )?;
+        if let Some(value) = try_eval_expression(&expr_in, symbol_map, options, resolve_value)? {
             return Ok(value.is_truthy());
         }
     }
 
-    let res_text = try_eval_text_with_values(s, symbol_map, resolve_extension, resolve_value)?;
+    let res_text =
+        try_eval_text_with_options(s, symbol_map, options, resolve_extension, resolve_value)?;
     if res_text.eq_ignore_ascii_case("true") {
         return Ok(true);
     }
@@ -224,15 +494,16 @@ where
         return Ok(value != 0.0);
     }
 
-    let interp = Interpreter::new();
-    interp.eval_boolean(res_text.as_str()).map_err(|e| {
-        anyhow!(
-            "failed to evaluate boolean expression `{}` from `{}`: {}",
-            res_text,
-            s,
-            e
-        )
-    })
+    eval_python(&res_text, &|_| None, options)?
+        .map(|value| value.is_truthy())
+        .map_err(|e| {
+            anyhow!(
+                "failed to evaluate boolean expression `{}` from `{}`: {}",
+                res_text,
+                s,
+                e
+            )
+        })
 }
 
 fn typed_context_from_properties(
@@ -244,49 +515,525 @@ fn typed_context_from_properties(
         .collect()
 }
 
-fn xacro_builtin_radians(args: &[f64]) -> std::result::Result<f64, EvalError> {
-    if args.len() != 1 {
-        return Err(EvalError::ArgError("radians".to_string()));
+static MATH_CONSTANTS: Lazy<HashMap<&str, f64>> = Lazy::new(|| {
+    HashMap::from([
+        ("pi", std::f64::consts::PI),
+        ("e", std::f64::consts::E),
+        ("tau", std::f64::consts::TAU),
+        ("inf", f64::INFINITY),
+        ("nan", f64::NAN),
+    ])
+});
+
+static MATH_FUNCTIONS: Lazy<HashMap<&str, Builtin>> =
+    Lazy::new(|| pyexpr::builtins_by_name(MATH_BUILTINS));
+
+// The functions of Python's `math` module; like Python, domain and range errors are reported
+// instead of producing nan or inf from finite arguments, and `ceil`, `floor`, `trunc`, `comb`,
+// `perm`, `isqrt`, `gcd` and `lcm` return ints.
+const MATH_BUILTINS: &[Builtin] = &[
+    Builtin::Function("acos", |args| math_unary("acos", args, f64::acos)),
+    Builtin::Function("acosh", |args| math_unary("acosh", args, f64::acosh)),
+    Builtin::Function("asin", |args| math_unary("asin", args, f64::asin)),
+    Builtin::Function("asinh", |args| math_unary("asinh", args, f64::asinh)),
+    Builtin::Function("atan", |args| math_unary("atan", args, f64::atan)),
+    Builtin::Function("atan2", |args| math_binary("atan2", args, f64::atan2)),
+    Builtin::Function("atanh", |args| math_unary("atanh", args, f64::atanh)),
+    Builtin::Function("cbrt", |args| math_unary("cbrt", args, f64::cbrt)),
+    Builtin::Function("ceil", |args| math_integral("ceil", args, f64::ceil)),
+    Builtin::Function("comb", math_comb),
+    Builtin::Function("copysign", |args| {
+        math_binary("copysign", args, f64::copysign)
+    }),
+    Builtin::Function("cos", |args| math_unary("cos", args, f64::cos)),
+    Builtin::Function("cosh", |args| math_unary("cosh", args, f64::cosh)),
+    Builtin::Function("degrees", |args| {
+        math_unary("degrees", args, f64::to_degrees)
+    }),
+    Builtin::Function("erf", |args| math_unary("erf", args, libm::erf)),
+    Builtin::Function("exp", |args| math_unary("exp", args, f64::exp)),
+    Builtin::Function("exp2", |args| math_unary("exp2", args, f64::exp2)),
+    Builtin::Function("expm1", |args| math_unary("expm1", args, f64::exp_m1)),
+    Builtin::Function("fabs", |args| math_unary("fabs", args, f64::abs)),
+    Builtin::Function("factorial", math_factorial),
+    Builtin::Function("floor", |args| math_integral("floor", args, f64::floor)),
+    Builtin::Function("fmod", |args| math_binary("fmod", args, |x, y| x % y)),
+    Builtin::Function("frexp", |args| match math_args(args)?[..] {
+        [x] => {
+            let (mantissa, exponent) = if x == 0.0 || !x.is_finite() {
+                (x, 0)
+            } else {
+                libm::frexp(x)
+            };
+            Ok(Value::Tuple(vec![
+                Value::Float(mantissa),
+                Value::Int(exponent.into()),
+            ]))
+        }
+        _ => Err(math_arity("frexp", 1, args)),
+    }),
+    Builtin::Function("fsum", math_fsum),
+    Builtin::Function("gamma", |args| math_gamma("gamma", args, libm::tgamma)),
+    Builtin::Function("gcd", math_gcd),
+    Builtin::Function("hypot", |args| {
+        let args = math_args(args)?;
+        math_result(
+            "hypot",
+            &args,
+            args.iter().fold(0.0, |acc, x| acc.hypot(*x)),
+        )
+    }),
+    Builtin::Interpreter("isclose", math_isclose),
+    Builtin::Function("isfinite", |args| {
+        math_predicate("isfinite", args, f64::is_finite)
+    }),
+    Builtin::Function("isinf", |args| {
+        math_predicate("isinf", args, f64::is_infinite)
+    }),
+    Builtin::Function("isnan", |args| math_predicate("isnan", args, f64::is_nan)),
+    Builtin::Function("isqrt", math_isqrt),
+    Builtin::Function("lcm", math_lcm),
+    Builtin::Function("ldexp", |args| match args {
+        [x, i] => {
+            let x = pyexpr::float_arg(x)?;
+            let i = pyexpr::int_arg(i)?.clamp(-2000, 2000) as i32;
+            math_result("ldexp", &[x], x * 2f64.powi(i))
+        }
+        _ => Err(math_arity("ldexp", 2, args)),
+    }),
+    Builtin::Function("lgamma", |args| math_gamma("lgamma", args, libm::lgamma)),
+    Builtin::Function("log", |args| match math_args(args)?[..] {
+        [x] => math_result("log", &[x], x.ln()),
+        [x, base] => math_result("log", &[x, base], x.ln() / base.ln()),
+        _ => Err(EvalError::Type(format!(
+            "log expected 1 or 2 arguments, got {}",
+            args.len()
+        ))),
+    }),
+    Builtin::Function("log10", |args| math_unary("log10", args, f64::log10)),
+    Builtin::Function("log1p", |args| math_unary("log1p", args, f64::ln_1p)),
+    Builtin::Function("log2", |args| math_unary("log2", args, f64::log2)),
+    Builtin::Function("modf", |args| match math_args(args)?[..] {
+        [x] => {
+            let integral = x.trunc();
+            let fraction = if x.is_infinite() {
+                0.0f64.copysign(x)
+            } else {
+                (x - integral).copysign(x)
+            };
+            Ok(Value::Tuple(vec![
+                Value::Float(fraction),
+                Value::Float(integral),
+            ]))
+        }
+        _ => Err(math_arity("modf", 1, args)),
+    }),
+    Builtin::Function("perm", math_perm),
+    Builtin::Function("pow", |args| math_binary("pow", args, f64::powf)),
+    Builtin::Interpreter("prod", math_prod),
+    Builtin::Function("radians", |args| {
+        math_unary("radians", args, f64::to_radians)
+    }),
+    Builtin::Function("remainder", |args| {
+        math_binary("remainder", args, libm::remainder)
+    }),
+    Builtin::Function("sin", |args| math_unary("sin", args, f64::sin)),
+    Builtin::Function("sinh", |args| math_unary("sinh", args, f64::sinh)),
+    Builtin::Function("sqrt", |args| math_unary("sqrt", args, f64::sqrt)),
+    Builtin::Function("tan", |args| math_unary("tan", args, f64::tan)),
+    Builtin::Function("tanh", |args| math_unary("tanh", args, f64::tanh)),
+    Builtin::Function("trunc", |args| math_integral("trunc", args, f64::trunc)),
+];
+
+fn math_args(args: &[Value]) -> std::result::Result<Vec<f64>, EvalError> {
+    args.iter().map(pyexpr::float_arg).collect()
+}
+
+fn math_arity(name: &str, expected: usize, args: &[Value]) -> EvalError {
+    EvalError::Type(format!(
+        "{}() takes exactly {} argument{} ({} given)",
+        name,
+        expected,
+        if expected == 1 { "" } else { "s" },
+        args.len()
+    ))
+}
+
+fn math_unary(
+    name: &str,
+    args: &[Value],
+    func: fn(f64) -> f64,
+) -> std::result::Result<Value, EvalError> {
+    match math_args(args)?[..] {
+        [x] => math_result(name, &[x], func(x)),
+        _ => Err(math_arity(name, 1, args)),
     }
-    Ok(args[0].to_radians())
 }
 
-fn xacro_builtin_degrees(args: &[f64]) -> std::result::Result<f64, EvalError> {
-    if args.len() != 1 {
-        return Err(EvalError::ArgError("degrees".to_string()));
+fn math_binary(
+    name: &str,
+    args: &[Value],
+    func: fn(f64, f64) -> f64,
+) -> std::result::Result<Value, EvalError> {
+    match math_args(args)?[..] {
+        [x, y] => math_result(name, &[x, y], func(x, y)),
+        _ => Err(math_arity(name, 2, args)),
     }
-    Ok(args[0].to_degrees())
 }
 
-fn eval_context(symbol_map: &HashMap<String, XacroValue>) -> HashMap<String, Value> {
-    let mut context = HashMap::from([
-        ("pi".to_string(), Value::Number(std::f64::consts::PI)),
-        (
-            "radians".to_string(),
-            Value::Builtin {
-                name: "radians".to_string(),
-                func: xacro_builtin_radians,
-            },
-        ),
-        (
-            "degrees".to_string(),
-            Value::Builtin {
-                name: "degrees".to_string(),
-                func: xacro_builtin_degrees,
-            },
-        ),
-    ]);
-    context.extend(
-        symbol_map
-            .iter()
-            .map(|(name, value)| (name.clone(), value.to_eval_value())),
-    );
-    context
+// `ceil`, `floor` and `trunc` return ints, and leave ints untouched.
+fn math_integral(
+    name: &str,
+    args: &[Value],
+    func: fn(f64) -> f64,
+) -> std::result::Result<Value, EvalError> {
+    match args {
+        [Value::Int(value)] => Ok(Value::Int(*value)),
+        [value] => pyexpr::float_to_int(func(pyexpr::float_arg(value)?)).map(Value::Int),
+        _ => Err(math_arity(name, 1, args)),
+    }
+}
+
+fn math_result(name: &str, args: &[f64], value: f64) -> std::result::Result<Value, EvalError> {
+    if value.is_nan() && !args.iter().any(|arg| arg.is_nan()) {
+        return Err(EvalError::Value(format!("{}: math domain error", name)));
+    }
+    if value.is_infinite() && args.iter().all(|arg| arg.is_finite()) {
+        return Err(EvalError::Overflow(format!("{}: math range error", name)));
+    }
+    Ok(Value::Float(value))
+}
+
+fn math_predicate(
+    name: &str,
+    args: &[Value],
+    func: fn(f64) -> bool,
+) -> std::result::Result<Value, EvalError> {
+    match math_args(args)?[..] {
+        [x] => Ok(Value::Bool(func(x))),
+        _ => Err(math_arity(name, 1, args)),
+    }
+}
+
+// `gamma` and `lgamma` have poles at zero and the negative integers.
+fn math_gamma(
+    name: &str,
+    args: &[Value],
+    func: fn(f64) -> f64,
+) -> std::result::Result<Value, EvalError> {
+    match math_args(args)?[..] {
+        [x] if x <= 0.0 && x.fract() == 0.0 => {
+            Err(EvalError::Value(format!("{}: math domain error", name)))
+        }
+        [x] => math_result(name, &[x], func(x)),
+        _ => Err(math_arity(name, 1, args)),
+    }
+}
+
+fn math_isclose(
+    _: &mut pyexpr::Interpreter<'_>,
+    args: &[Value],
+    keywords: &[(String, Value)],
+) -> std::result::Result<Value, EvalError> {
+    let [a, b] = math_args(args)?[..] else {
+        return Err(math_arity("isclose", 2, args));
+    };
+    let [rel_tol, abs_tol] = pyexpr::keyword_args("isclose", keywords, ["rel_tol", "abs_tol"])?;
+    let rel_tol = rel_tol.map(pyexpr::float_arg).transpose()?.unwrap_or(1e-9);
+    let abs_tol = abs_tol.map(pyexpr::float_arg).transpose()?.unwrap_or(0.0);
+    if rel_tol < 0.0 || abs_tol < 0.0 {
+        return Err(EvalError::Value(
+            "tolerances must be non-negative".to_string(),
+        ));
+    }
+    if a == b {
+        return Ok(Value::Bool(true));
+    }
+    if a.is_infinite() || b.is_infinite() {
+        return Ok(Value::Bool(false));
+    }
+    let diff = (b - a).abs();
+    Ok(Value::Bool(
+        diff <= (rel_tol * b).abs() || diff <= (rel_tol * a).abs() || diff <= abs_tol,
+    ))
+}
+
+fn math_prod(
+    _: &mut pyexpr::Interpreter<'_>,
+    args: &[Value],
+    keywords: &[(String, Value)],
+) -> std::result::Result<Value, EvalError> {
+    let [items] = args else {
+        return Err(math_arity("prod", 1, args));
+    };
+    let [start] = pyexpr::keyword_args("prod", keywords, ["start"])?;
+    let mut product = start.cloned().unwrap_or(Value::Int(1));
+    for item in pyexpr::iterate(items)? {
+        product = pyexpr::multiply(product, item)?;
+    }
+    Ok(product)
+}
+
+// Shewchuk's exact summation, as in CPython: `partials` holds non-overlapping floats whose
+// exact sum is the running total, and the last step rounds that total once.
+fn math_fsum(args: &[Value]) -> std::result::Result<Value, EvalError> {
+    let [items] = args else {
+        return Err(math_arity("fsum", 1, args));
+    };
+    let mut partials: Vec<f64> = Vec::new();
+    let mut special_sum = 0.0;
+    let mut inf_sum = 0.0;
+    for item in pyexpr::iterate(items)? {
+        let mut x = pyexpr::float_arg(&item)?;
+        if !x.is_finite() {
+            if x.is_infinite() {
+                inf_sum += x;
+            }
+            special_sum += x;
+            continue;
+        }
+        let mut kept = 0;
+        for i in 0..partials.len() {
+            let mut y = partials[i];
+            if x.abs() < y.abs() {
+                std::mem::swap(&mut x, &mut y);
+            }
+            let hi = x + y;
+            let lo = y - (hi - x);
+            if lo != 0.0 {
+                partials[kept] = lo;
+                kept += 1;
+            }
+            x = hi;
+        }
+        if !x.is_finite() {
+            return Err(EvalError::Overflow(
+                "intermediate overflow in fsum".to_string(),
+            ));
+        }
+        partials.truncate(kept);
+        partials.push(x);
+    }
+    if special_sum != 0.0 || special_sum.is_nan() {
+        if inf_sum.is_nan() {
+            return Err(EvalError::Value("-inf + inf in fsum".to_string()));
+        }
+        return Ok(Value::Float(special_sum));
+    }
+
+    let Some((&last, rest)) = partials.split_last() else {
+        return Ok(Value::Float(0.0));
+    };
+    let mut hi = last;
+    let mut lo = 0.0;
+    let mut n = rest.len();
+    while n > 0 {
+        n -= 1;
+        let (x, y) = (hi, rest[n]);
+        hi = x + y;
+        lo = y - (hi - x);
+        if lo != 0.0 {
+            break;
+        }
+    }
+    // Round half to even when the remaining partials break a tie in `lo`.
+    if n > 0 && ((lo < 0.0 && rest[n - 1] < 0.0) || (lo > 0.0 && rest[n - 1] > 0.0)) {
+        let y = lo * 2.0;
+        let x = hi + y;
+        if y == x - hi {
+            hi = x;
+        }
+    }
+    Ok(Value::Float(hi))
+}
+
+fn math_factorial(args: &[Value]) -> std::result::Result<Value, EvalError> {
+    // 171! is past the largest float; checking up front also keeps huge arguments from
+    // looping.
+    const MAX_FACTORIAL: f64 = 170.0;
+    let n = match args {
+        [value] => pyexpr::float_arg(value)?,
+        _ => return Err(math_arity("factorial", 1, args)),
+    };
+    if n.fract() != 0.0 || n < 0.0 {
+        return Err(EvalError::Value(
+            "factorial() only accepts non-negative integral values".to_string(),
+        ));
+    }
+    if n > MAX_FACTORIAL {
+        return Err(EvalError::Overflow(
+            "factorial: math range error".to_string(),
+        ));
+    }
+    // Computed exactly in 32-bit limbs, least significant first.
+    let mut limbs = vec![1u32];
+    for k in 2..=n as u64 {
+        let mut carry = 0;
+        for limb in limbs.iter_mut() {
+            let value = u64::from(*limb) * k + carry;
+            *limb = value as u32;
+            carry = value >> 32;
+        }
+        if carry > 0 {
+            limbs.push(carry as u32);
+        }
+    }
+    Ok(integer_from_limbs(&limbs))
+}
+
+// Ints are 64-bit, so a larger result becomes the float nearest to it.
+fn integer_from_limbs(limbs: &[u32]) -> Value {
+    let bit = |i: usize| (limbs[i / 32] >> (i % 32)) & 1 == 1;
+    let bits = limbs.len() * 32
+        - limbs
+            .last()
+            .map_or(32, |limb| limb.leading_zeros() as usize);
+    let shift = bits.saturating_sub(64);
+    let top = (shift..bits)
+        .rev()
+        .fold(0u64, |top, i| (top << 1) | u64::from(bit(i)));
+    if shift == 0 {
+        return match i64::try_from(top) {
+            Ok(value) => Value::Int(value),
+            Err(_) => Value::Float(top as f64),
+        };
+    }
+    // Folding the dropped bits into the lowest kept one keeps the rounding to 53 bits exact.
+    let sticky = (0..shift).any(bit);
+    Value::Float((top | u64::from(sticky)) as f64 * 2f64.powi(shift as i32))
+}
+
+fn math_int_args<const N: usize>(
+    name: &str,
+    args: &[Value],
+) -> std::result::Result<[i64; N], EvalError> {
+    let values = args
+        .iter()
+        .map(pyexpr::int_arg)
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    values.try_into().map_err(|_| math_arity(name, N, args))
+}
+
+fn math_comb(args: &[Value]) -> std::result::Result<Value, EvalError> {
+    let [n, k] = math_int_args("comb", args)?;
+    let (n, k) = non_negative_n_k(n, k)?;
+    if k > n {
+        return Ok(Value::Int(0));
+    }
+    // Each partial product is itself a binomial coefficient no larger than the result.
+    let k = k.min(n - k);
+    let mut value = 1i128;
+    for i in 0..k {
+        value = value * i128::from(n - i) / i128::from(i + 1);
+        if value > i128::from(i64::MAX) {
+            return Err(pyexpr::integer_overflow());
+        }
+    }
+    Ok(Value::Int(value as i64))
+}
+
+fn math_perm(args: &[Value]) -> std::result::Result<Value, EvalError> {
+    let (n, k) = match args {
+        [n] => {
+            let n = pyexpr::int_arg(n)?;
+            (n, n)
+        }
+        _ => {
+            let [n, k] = math_int_args("perm", args)?;
+            (n, k)
+        }
+    };
+    let (n, k) = non_negative_n_k(n, k)?;
+    if k > n {
+        return Ok(Value::Int(0));
+    }
+    (n - k + 1..=n)
+        .try_fold(1i64, |value, i| value.checked_mul(i))
+        .map(Value::Int)
+        .ok_or_else(pyexpr::integer_overflow)
+}
+
+fn non_negative_n_k(n: i64, k: i64) -> std::result::Result<(i64, i64), EvalError> {
+    if n < 0 {
+        return Err(EvalError::Value(
+            "n must be a non-negative integer".to_string(),
+        ));
+    }
+    if k < 0 {
+        return Err(EvalError::Value(
+            "k must be a non-negative integer".to_string(),
+        ));
+    }
+    Ok((n, k))
+}
+
+fn math_isqrt(args: &[Value]) -> std::result::Result<Value, EvalError> {
+    let [n] = math_int_args("isqrt", args)?;
+    if n < 0 {
+        return Err(EvalError::Value(
+            "isqrt() argument must be nonnegative".to_string(),
+        ));
+    }
+    // The float square root is within one of the answer; correct it exactly.
+    let n = i128::from(n);
+    let mut root = (n as f64).sqrt() as i128;
+    while root * root > n {
+        root -= 1;
+    }
+    while (root + 1) * (root + 1) <= n {
+        root += 1;
+    }
+    Ok(Value::Int(root as i64))
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn math_int_list(args: &[Value]) -> std::result::Result<Vec<u64>, EvalError> {
+    args.iter()
+        .map(|arg| pyexpr::int_arg(arg).map(i64::unsigned_abs))
+        .collect()
+}
+
+fn math_gcd(args: &[Value]) -> std::result::Result<Value, EvalError> {
+    let gcd = math_int_list(args)?.into_iter().fold(0, gcd);
+    i64::try_from(gcd)
+        .map(Value::Int)
+        .map_err(|_| EvalError::Overflow("gcd: integer overflow".to_string()))
+}
+
+fn math_lcm(args: &[Value]) -> std::result::Result<Value, EvalError> {
+    math_int_list(args)?
+        .into_iter()
+        .try_fold(1u64, |lcm, value| match (lcm, value) {
+            (0, _) | (_, 0) => Some(0),
+            _ => (lcm / gcd(lcm, value)).checked_mul(value),
+        })
+        .and_then(|lcm| i64::try_from(lcm).ok())
+        .map(Value::Int)
+        .ok_or_else(pyexpr::integer_overflow)
+}
+
+// Names the expression does not define itself: properties first, as upstream xacro lets them
+// shadow the `math` members, then the `math` constants and functions.
+fn lookup_name(name: &str, symbol_map: &dyn XacroSymbols) -> Option<Value> {
+    if let Some(value) = symbol_map.get(name) {
+        return Some(value.to_eval_value());
+    }
+    if let Some(value) = MATH_CONSTANTS.get(name) {
+        return Some(Value::Float(*value));
+    }
+    MATH_FUNCTIONS.get(name).copied().map(Value::Builtin)
 }
 
 fn try_eval_expression<G>(
     expr: &str,
-    symbol_map: &HashMap<String, XacroValue>,
+    symbol_map: &dyn XacroSymbols,
+    options: &EvalOptions,
     resolve_value: &G,
 ) -> Result<Option<XacroValue>>
 where
@@ -299,18 +1046,250 @@ where
         return Ok(Some(value));
     }
 
-    let interp = Interpreter::new();
-    match interp.eval_with_context(expr, &eval_context(symbol_map)) {
+    let expr = strip_math_module_prefix(expr, symbol_map)?;
+    let Some((expr, calls)) = substitute_function_calls(&expr, symbol_map, options, resolve_value)?
+    else {
+        return Ok(None);
+    };
+    let symbol_map = &SymbolsWithCalls {
+        symbols: symbol_map,
+        calls: &calls,
+    };
+    if let Some(value) = lookup_path_expression(&expr, symbol_map) {
+        return Ok(Some(value));
+    }
+
+    let expr = expr.as_str();
+    match eval_python(expr, &|name| lookup_name(name, symbol_map), options)? {
         Ok(value) => Ok(Some(xacro_value_from_eval(value))),
+        Err(err) if options.strict => Err(unresolved_expression_error(expr, symbol_map, err)),
         Err(_) => Ok(None),
     }
 }
 
+fn unresolved_expression_error(
+    expr: &str,
+    symbol_map: &dyn XacroSymbols,
+    err: EvalError,
+) -> anyhow::Error {
+    let EvalError::UndefinedName(name) = &err else {
+        return anyhow!(
+            "failed to evaluate xacro expression `${{{}}}`: {}",
+            expr,
+            err
+        );
+    };
+    if take_identifier(name) != Some((name.as_str(), "")) {
+        return anyhow!(
+            "failed to evaluate xacro expression `${{{}}}`: {}",
+            expr,
+            err
+        );
+    }
+
+    let suggestions = similar_names(name, symbol_map.names().into_iter());
+    if suggestions.is_empty() {
+        anyhow!(
+            "undefined name `{}` in xacro expression `${{{}}}`",
+            name,
+            expr
+        )
+    } else {
+        anyhow!(
+            "undefined name `{}` in xacro expression `${{{}}}`; did you mean {}?",
+            name,
+            expr,
+            suggestions
+                .iter()
+                .map(|name| format!("`{}`", name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+fn similar_names<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    let mut matches = candidates
+        .filter_map(|candidate| {
+            let distance = edit_distance(name, candidate);
+            (distance <= max_distance).then_some((distance, candidate))
+        })
+        .collect::<Vec<_>>();
+    matches.sort();
+    matches
+        .into_iter()
+        .take(3)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_ch) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_ch) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_ch != *b_ch);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// Upstream xacro exposes both `math` and its members, so `math.sqrt(2)` and `sqrt(2)`
+// are the same call.
+fn strip_math_module_prefix(expr: &str, symbol_map: &dyn XacroSymbols) -> Result<String> {
+    if symbol_map.contains_key("math") || !expr.contains("math.") {
+        return Ok(expr.to_string());
+    }
+    rewrite_identifiers(expr, |name, after, is_attribute| {
+        if name != "math" || is_attribute {
+            return Ok(None);
+        }
+        let is_math_member = after
+            .strip_prefix('.')
+            .and_then(take_identifier)
+            .is_some_and(|(member, _)| {
+                MATH_FUNCTIONS.contains_key(member) || MATH_CONSTANTS.contains_key(member)
+            });
+        Ok(is_math_member.then(|| (String::new(), &after[1..])))
+    })
+}
+
+// Registered functions are Rust closures, which the interpreter cannot call, so each call is
+// evaluated up front and replaced by a placeholder name bound to its result. Calls that
+// `resolve_value` understands, like `xacro.arg(...)`, are substituted the same way so they can
+// be part of a larger expression.
+fn substitute_function_calls<G>(
+    expr: &str,
+    symbol_map: &dyn XacroSymbols,
+    options: &EvalOptions,
+    resolve_value: &G,
+) -> Result<Option<(String, HashMap<String, XacroValue>)>>
+where
+    G: Fn(&str) -> Result<Option<XacroValue>>,
+{
+    let mut calls = HashMap::new();
+    let mut unresolved = false;
+    let expr = rewrite_identifiers(expr, |name, after, is_attribute| {
+        if is_attribute || unresolved || symbol_map.contains_key(name) {
+            return Ok(None);
+        }
+        let (name, after) = match after.strip_prefix('.').and_then(take_identifier) {
+            Some((member, rest)) if name == "xacro" => (format!("{}.{}", name, member), rest),
+            _ => (name.to_string(), after),
+        };
+        let Some((args, rest)) = split_call_args(after) else {
+            return Ok(None);
+        };
+
+        let value = if let Some(function) = options.functions.get(&name) {
+            let mut values = Vec::with_capacity(args.len());
+            for arg in args {
+                match try_eval_expression(arg, symbol_map, options, resolve_value)? {
+                    Some(value) => values.push(value),
+                    None => {
+                        unresolved = true;
+                        return Ok(None);
+                    }
+                }
+            }
+            function(&values).with_context(|| format!("xacro function `{}` failed", name))?
+        } else if let Some(value) = resolve_value(&format!("{}({})", name, args.join(", ")))? {
+            value
+        } else {
+            return Ok(None);
+        };
+        let placeholder = format!("__xacro_call_{}", calls.len());
+        calls.insert(placeholder.clone(), value);
+        Ok(Some((placeholder, rest)))
+    })?;
+    Ok((!unresolved).then_some((expr, calls)))
+}
+
+// Calls `rewrite` with every identifier outside string literals, the text following it and
+// whether it is an attribute (`x.name`); `rewrite` can replace the identifier and any of the
+// text after it by returning the replacement and the remaining input.
+fn rewrite_identifiers<'a, F>(expr: &'a str, mut rewrite: F) -> Result<String>
+where
+    F: FnMut(&'a str, &'a str, bool) -> Result<Option<(String, &'a str)>>,
+{
+    let mut result = String::with_capacity(expr.len());
+    let mut quote = None;
+    let mut rest = expr;
+    while let Some(ch) = rest.chars().next() {
+        if let Some(q) = quote {
+            if ch == q {
+                quote = None;
+            }
+        } else if ch == '\'' || ch == '"' {
+            quote = Some(ch);
+        } else if ch == '_' || ch.is_ascii_alphabetic() {
+            let (name, after) = take_identifier(rest).unwrap_or((rest, ""));
+            let is_attribute = result.trim_end().ends_with('.');
+            match rewrite(name, after, is_attribute)? {
+                Some((replacement, after)) => {
+                    result.push_str(&replacement);
+                    rest = after;
+                }
+                None => {
+                    result.push_str(name);
+                    rest = after;
+                }
+            }
+            continue;
+        }
+        result.push(ch);
+        rest = &rest[ch.len_utf8()..];
+    }
+    Ok(result)
+}
+
+// Splits `(a, f(b, c), 'd,e')...` into its top-level arguments and the text after `)`.
+pub(crate) fn split_call_args(s: &str) -> Option<(Vec<&str>, &str)> {
+    let s = s.trim_start().strip_prefix('(')?;
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, ch) in s.char_indices() {
+        if let Some(q) = quote {
+            if ch == q {
+                quote = None;
+            }
+            continue;
+        }
+        match ch {
+            '\'' | '"' => quote = Some(ch),
+            '(' | '[' | '{' => depth += 1,
+            ')' if depth == 0 => {
+                let last = s[start..i].trim();
+                if !last.is_empty() || !args.is_empty() {
+                    args.push(last);
+                }
+                return Some((args, &s[i + 1..]));
+            }
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
 fn xacro_value_from_eval(value: Value) -> XacroValue {
     match value {
-        Value::Number(value) => XacroValue::Number(value),
-        Value::StringLit(value) | Value::Var(value) => XacroValue::String(value),
-        Value::List(values) | Value::Tuple(values) | Value::Set(values) => {
+        Value::None => XacroValue::Null,
+        Value::Bool(value) => XacroValue::Bool(value),
+        Value::Int(value) => XacroValue::Integer(value),
+        Value::Float(value) => XacroValue::Number(value),
+        Value::Str(value) => XacroValue::String(value),
+        Value::List(values) | Value::Tuple(values) => {
             XacroValue::List(values.into_iter().map(xacro_value_from_eval).collect())
         }
         Value::Dict(values) => XacroValue::Map(
@@ -319,25 +1298,20 @@ fn xacro_value_from_eval(value: Value) -> XacroValue {
                 .map(|(key, value)| (key, xacro_value_from_eval(value)))
                 .collect(),
         ),
-        value => XacroValue::String(value.to_string()),
+        value => XacroValue::String(value.repr()),
     }
 }
 
 fn single_expression(s: &str) -> Option<String> {
     let mut lexer = Lexer::new(s);
-    let Some(token) = lexer.next() else {
-        return None;
-    };
+    let token = lexer.next()?;
     if token.0 != TokenType::Expr || lexer.next().is_some() {
         return None;
     }
     Some(token.1)
 }
 
-fn lookup_path_expression(
-    expr: &str,
-    symbol_map: &HashMap<String, XacroValue>,
-) -> Option<XacroValue> {
+fn lookup_path_expression(expr: &str, symbol_map: &dyn XacroSymbols) -> Option<XacroValue> {
     let mut cursor = expr.trim();
     let (name, rest) = take_identifier(cursor)?;
     let mut value = symbol_map.get(name)?.clone();
@@ -503,6 +1477,41 @@ mod tests {
         assert_eq!(result, "prefix ${missing}");
     }
 
+    #[test]
+    fn strict_mode_rejects_unresolved_expr_and_suggests_names() {
+        use super::*;
+        let context = HashMap::from([
+            ("wheel_radius".to_string(), XacroValue::Number(0.1)),
+            ("wheel_width".to_string(), XacroValue::Number(0.05)),
+        ]);
+        let options = EvalOptions::default().with_strict(true);
+
+        let err = try_eval_text_with_options(
+            "${wheel_radus * 2}",
+            &context,
+            &options,
+            &|_| Ok(String::new()),
+            &|_| Ok(None),
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(
+            err,
+            "undefined name `wheel_radus` in xacro expression `${wheel_radus * 2}`; did you mean `wheel_radius`?"
+        );
+
+        let err = try_eval_text_with_options(
+            "${1 / 0}",
+            &context,
+            &options,
+            &|_| Ok(String::new()),
+            &|_| Ok(None),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("failed to evaluate xacro expression `${1 / 0}`"));
+    }
+
     #[test]
     fn evaluates_xacro_angle_helpers() {
         use super::*;
@@ -530,6 +1539,291 @@ mod tests {
         assert!((value - std::f64::consts::PI).abs() < 1e-12);
     }
 
+    #[test]
+    fn formats_numbers_like_python_repr() {
+        use super::*;
+        let cases = [
+            (180.0, "180.0"),
+            (0.1, "0.1"),
+            (-0.0, "-0.0"),
+            (std::f64::consts::FRAC_PI_2, "1.5707963267948966"),
+            (0.0001, "0.0001"),
+            (0.00001, "1e-05"),
+            (-1.25e-7, "-1.25e-07"),
+            (1e15, "1000000000000000.0"),
+            (1e16, "1e+16"),
+            (1.5e300, "1.5e+300"),
+            (f64::INFINITY, "inf"),
+            (f64::NAN, "nan"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(NumberFormat::PythonRepr.format_number(value), expected);
+        }
+    }
+
+    #[test]
+    fn infers_python_integer_results() {
+        use super::*;
+        let context = HashMap::from([
+            ("count".to_string(), XacroValue::Integer(3)),
+            ("width".to_string(), XacroValue::Number(2.0)),
+        ]);
+        let options = EvalOptions::default().with_number_format(NumberFormat::PythonRepr);
+
+        let result = try_eval_text_with_options(
+            "${count * 2} ${count - 5} ${count / 3} ${width * 2} ${2.0 * count} ${radians(0)}",
+            &context,
+            &options,
+            &|_| Ok(String::new()),
+            &|_| Ok(None),
+        )
+        .unwrap();
+        assert_eq!(result, "6 -2 1.0 4.0 6.0 0.0");
+    }
+
+    #[test]
+    fn types_subexpressions_like_python() {
+        use super::*;
+        let context = HashMap::from([
+            ("n".to_string(), XacroValue::Integer(3)),
+            ("x".to_string(), XacroValue::Number(1.0)),
+            (
+                "xs".to_string(),
+                XacroValue::List(vec![XacroValue::Number(1.0), XacroValue::Number(2.0)]),
+            ),
+        ]);
+        let options = EvalOptions::default().with_number_format(NumberFormat::PythonRepr);
+        // Expected values are `repr(eval(expr))` in CPython.
+        let cases = [
+            ("1 if x > 0.5 else 2", "1"),
+            ("1 if x < 0.5 else 2.0", "2.0"),
+            ("1.0 if x > 0.5 else 2", "1.0"),
+            ("int(x) + 1", "2"),
+            ("int(x * 2.5) * 2", "4"),
+            ("(n + 1) * 2", "8"),
+            ("n ** 2", "9"),
+            ("abs(n - 5)", "2"),
+            ("max(n, 2)", "3"),
+            ("max(n, 2.0)", "3"),
+            ("max(x, 2)", "2"),
+            ("min(x, 2.0)", "1.0"),
+            ("float(n)", "3.0"),
+            ("round(x)", "1"),
+            ("xs[1] * 2", "4.0"),
+            ("sum(xs)", "3.0"),
+            ("len(xs) * 2", "4"),
+            ("[1, 2][0]", "1"),
+            ("n // 2", "1"),
+            ("-n // 2", "-2"),
+            ("n % 2 + x", "2.0"),
+            ("sorted([n, x, 2])[1]", "2"),
+            ("[v * 2 for v in xs]", "2.0 4.0"),
+            ("'%s_%d' % ('arm', n)", "arm_3"),
+            ("'%.2f' % x", "1.00"),
+        ];
+        for (expr, expected) in cases {
+            let result = try_eval_text_with_options(
+                &format!("${{{}}}", expr),
+                &context,
+                &options,
+                &|_| Ok(String::new()),
+                &|_| Ok(None),
+            )
+            .unwrap();
+            assert_eq!(result, expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn math_builtins_match_python() {
+        use super::*;
+        let context = HashMap::from([
+            ("a".to_string(), XacroValue::Integer(3)),
+            ("b".to_string(), XacroValue::Integer(4)),
+            ("y".to_string(), XacroValue::Number(1.0)),
+            ("x".to_string(), XacroValue::Number(-2.0)),
+        ]);
+        let options = EvalOptions::default().with_number_format(NumberFormat::PythonRepr);
+        // Expected values are `repr(eval(expr))` under `from math import *` in CPython.
+        let cases = [
+            ("sqrt(a**2 + b**2)", "5.0"),
+            ("atan2(y, x)", "2.677945044588987"),
+            ("sin(pi/6)", "0.49999999999999994"),
+            ("cos(1)", "0.5403023058681398"),
+            ("tan(0.5)", "0.5463024898437905"),
+            ("asin(0.5)", "0.5235987755982989"),
+            ("acos(-1)", "3.141592653589793"),
+            ("atan(1)", "0.7853981633974483"),
+            ("pow(2, 10)", "1024.0"),
+            ("floor(2.7)", "2"),
+            ("ceil(-2.5)", "-2"),
+            ("trunc(-2.5)", "-2"),
+            ("fabs(-3)", "3.0"),
+            ("log(e)", "1.0"),
+            ("log(8, 2)", "3.0"),
+            ("log10(1000)", "3.0"),
+            ("log2(10)", "3.321928094887362"),
+            ("exp(1)", "2.718281828459045"),
+            ("cosh(1)", "1.5430806348152437"),
+            ("math.hypot(3, 4)", "5.0"),
+            ("degrees(math.pi / 4)", "45.0"),
+            ("fmod(7, 3)", "1.0"),
+            ("copysign(2, -1)", "-2.0"),
+            ("factorial(5)", "120"),
+            ("gcd(12, 18)", "6"),
+            ("factorial(20)", "2432902008176640000"),
+            ("factorial(170) > 1e306", "True"),
+            ("a > 2", "True"),
+            ("isclose(0.1 + 0.2, 0.3)", "True"),
+            ("isclose(1, 1.1, rel_tol=0.2)", "True"),
+            ("isclose(1, 1.1, abs_tol=0.05)", "False"),
+            ("isnan(nan)", "True"),
+            ("isinf(-inf)", "True"),
+            ("isfinite(a / b)", "True"),
+            ("erf(0.5)", "0.5204998778130465"),
+            ("gamma(5)", "24.0"),
+            ("gamma(0.5)", "1.7724538509055159"),
+            // CPython computes `lgamma` itself, so only agree to a few ulps.
+            ("round(lgamma(10), 9)", "12.80182748"),
+            ("round(lgamma(0.5), 9)", "0.572364943"),
+            ("comb(10, 3)", "120"),
+            ("comb(3, 10)", "0"),
+            ("comb(a, 2) * b", "12"),
+            ("perm(5, 2)", "20"),
+            ("perm(5)", "120"),
+            ("isqrt(a * 10)", "5"),
+            ("lcm(4, 6, 10)", "60"),
+            ("fsum([0.1] * 10)", "1.0"),
+            ("sum([0.1] * 10)", "0.9999999999999999"),
+            ("prod([a, b, 2])", "24"),
+            ("prod([a, y], start=2)", "6.0"),
+            ("modf(-3.5)[0]", "-0.5"),
+            ("frexp(8.0)[1]", "4"),
+            ("remainder(7, 4)", "-1.0"),
+            ("cbrt(-8)", "-2.0"),
+            ("exp2(10)", "1024.0"),
+            ("round(2.5)", "2"),
+            ("round(-3.5)", "-4"),
+            ("round(2.567, 2)", "2.57"),
+            ("round(2.675, 2)", "2.67"),
+            ("round(0.125, 2)", "0.12"),
+            ("round(1234.5, -2)", "1200.0"),
+            ("round(b * 4, -1)", "20"),
+            ("a**2", "9"),
+            ("e", "2.718281828459045"),
+            ("tau", "6.283185307179586"),
+            ("inf", "inf"),
+        ];
+        for (expr, expected) in cases {
+            let result = try_eval_text_with_options(
+                &format!("${{{}}}", expr),
+                &context,
+                &options,
+                &|_| Ok(String::new()),
+                &|_| Ok(None),
+            )
+            .unwrap();
+            assert_eq!(result, expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn math_domain_errors_are_not_silently_evaluated() {
+        use super::*;
+        let context = HashMap::new();
+
+        let result = try_eval_text_with_options(
+            "${sqrt(-1)} ${log(0)}",
+            &context,
+            &EvalOptions::default(),
+            &|_| Ok(String::new()),
+            &|_| Ok(None),
+        )
+        .unwrap();
+        assert_eq!(result, "${sqrt(-1)} ${log(0)}");
+
+        let err = try_eval_text_with_options(
+            "${sqrt(-1)}",
+            &context,
+            &EvalOptions::default().with_strict(true),
+            &|_| Ok(String::new()),
+            &|_| Ok(None),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("sqrt: math domain error"), "{}", err);
+
+        let err = try_eval_text_with_options(
+            "${factorial(3e9)}",
+            &context,
+            &EvalOptions::default().with_strict(true),
+            &|_| Ok(String::new()),
+            &|_| Ok(None),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("factorial: math range error"), "{}", err);
+
+        for (expr, message) in [
+            ("factorial(171)", "factorial: math range error"),
+            ("gamma(0)", "gamma: math domain error"),
+            ("comb(-1, 2)", "n must be a non-negative integer"),
+            ("perm(40, 20)", "integer result does not fit in 64 bits"),
+            ("fsum([inf, -inf])", "-inf + inf in fsum"),
+        ] {
+            let err = try_eval_text_with_options(
+                &format!("${{{}}}", expr),
+                &context,
+                &EvalOptions::default().with_strict(true),
+                &|_| Ok(String::new()),
+                &|_| Ok(None),
+            )
+            .unwrap_err()
+            .to_string();
+            assert!(err.contains(message), "{}: {}", expr, err);
+        }
+    }
+
+    #[test]
+    fn calls_registered_functions_inside_expressions() {
+        use super::*;
+        let context = HashMap::from([("length".to_string(), XacroValue::Integer(20))]);
+        let options = EvalOptions::default()
+            .with_number_format(NumberFormat::PythonRepr)
+            .with_function("mm", |args| match args {
+                [XacroValue::Integer(value)] => Ok(XacroValue::Number(*value as f64 / 1000.0)),
+                [XacroValue::Number(value)] => Ok(XacroValue::Number(value / 1000.0)),
+                _ => Err(anyhow!("mm() takes one number")),
+            })
+            .with_function("twice", |args| match args {
+                [XacroValue::Integer(value)] => Ok(XacroValue::Integer(value * 2)),
+                _ => Err(anyhow!("twice() takes one integer")),
+            });
+
+        let result = try_eval_text_with_options(
+            "${mm(12.5)} ${mm(length) * 2} ${twice(twice(length))} ${max(twice(1), 3)} ${'mm(1)'}",
+            &context,
+            &options,
+            &|_| Ok(String::new()),
+            &|_| Ok(None),
+        )
+        .unwrap();
+        assert_eq!(result, "0.0125 0.04 80 3 mm(1)");
+
+        let err = try_eval_text_with_options(
+            "${mm('a')}",
+            &context,
+            &options,
+            &|_| Ok(String::new()),
+            &|_| Ok(None),
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "xacro function `mm` failed: mm() takes one number"
+        );
+    }
+
     #[test]
     fn evaluates_typed_path_values() {
         use super::*;
@@ -611,3 +1905,38 @@ mod tests {
         .unwrap());
     }
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod serde_tests {
+    use super::*;
+
+    #[test]
+    fn deserializes_json_values() {
+        let value: XacroValue = serde_json::from_str(
+            r#"{"name": "armé\u007f", "dims": [1, 2.5, -3e2], "ok": true, "none": null}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            XacroValue::Map(BTreeMap::from([
+                (
+                    "name".to_string(),
+                    XacroValue::String("armé\u{7f}".to_string())
+                ),
+                (
+                    "dims".to_string(),
+                    XacroValue::List(vec![
+                        XacroValue::Integer(1),
+                        XacroValue::Number(2.5),
+                        XacroValue::Number(-300.0),
+                    ])
+                ),
+                ("ok".to_string(), XacroValue::Bool(true)),
+                ("none".to_string(), XacroValue::Null),
+            ]))
+        );
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(serde_json::from_str::<XacroValue>(&json).unwrap(), value);
+        assert!(serde_json::from_str::<XacroValue>("[1, 2").is_err());
+    }
+}
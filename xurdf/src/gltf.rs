@@ -0,0 +1,737 @@
+extern crate nalgebra as na;
+
+use super::json_writer::{json_numbers, json_string};
+use super::kinematics::forward_kinematics;
+use super::model::*;
+use anyhow::Result;
+use na::{Isometry3, Point3, UnitQuaternion, Vector3, Vector4};
+use std::collections::HashMap;
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+use std::path::PathBuf;
+
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct GltfOptions {
+    pub joint_positions: HashMap<String, f64>,
+    pub package_paths: HashMap<String, PathBuf>,
+    /// Segments around the circumference of tessellated cylinders and spheres.
+    pub segments: usize,
+}
+
+impl Default for GltfOptions {
+    fn default() -> Self {
+        Self {
+            joint_positions: HashMap::new(),
+            package_paths: HashMap::new(),
+            segments: 32,
+        }
+    }
+}
+
+impl GltfOptions {
+    pub fn with_joint_position(mut self, joint: impl Into<String>, position: f64) -> Self {
+        self.joint_positions.insert(joint.into(), position);
+        self
+    }
+
+    pub fn with_package_path(
+        mut self,
+        package: impl Into<String>,
+        path: impl Into<PathBuf>,
+    ) -> Self {
+        self.package_paths.insert(package.into(), path.into());
+        self
+    }
+
+    pub fn with_segments(mut self, segments: usize) -> Self {
+        self.segments = segments;
+        self
+    }
+}
+
+pub fn write_glb(robot: &Robot) -> Result<Vec<u8>> {
+    write_glb_with_options(robot, GltfOptions::default())
+}
+
+/// Writes the visuals of `robot` as a binary glTF scene with one node per link, posed at
+/// `options.joint_positions`. Box, cylinder and sphere geometry is tessellated and STL meshes
+/// that can be found are embedded; other meshes become empty nodes whose `extras.uri` holds the
+/// original filename. The scene root turns URDF's z-up frame into glTF's y-up frame. Poses and
+/// colors must be finite, as JSON cannot hold NaN or infinities.
+pub fn write_glb_with_options(robot: &Robot, options: GltfOptions) -> Result<Vec<u8>> {
+    let poses = forward_kinematics(robot, &options.joint_positions)?;
+    let mut builder = GlbBuilder {
+        robot,
+        options: &options,
+        poses: &poses,
+        binary: Vec::new(),
+        buffer_views: Vec::new(),
+        accessors: Vec::new(),
+        meshes: Vec::new(),
+        materials: Vec::new(),
+        material_indices: HashMap::new(),
+        nodes: Vec::new(),
+    };
+    let root = builder.add_node(Node {
+        name: robot.name.clone(),
+        rotation: Some([-FRAC_1_SQRT_2, 0.0, 0.0, FRAC_1_SQRT_2]),
+        ..Default::default()
+    });
+    for link in robot.root_links() {
+        let child = builder.add_link(link, &Isometry3::identity())?;
+        builder.nodes[root].children.push(child);
+    }
+    builder.finish()
+}
+
+#[derive(Default)]
+struct Node {
+    name: String,
+    translation: Option<[f64; 3]>,
+    rotation: Option<[f64; 4]>,
+    scale: Option<[f64; 3]>,
+    mesh: Option<usize>,
+    children: Vec<usize>,
+    uri: Option<String>,
+}
+
+impl Node {
+    fn with_transform(mut self, transform: &Isometry3<f64>) -> Self {
+        let translation = transform.translation.vector;
+        if translation != Vector3::zeros() {
+            self.translation = Some(translation.into());
+        }
+        let rotation = transform.rotation;
+        if rotation != UnitQuaternion::identity() {
+            self.rotation = Some([rotation.i, rotation.j, rotation.k, rotation.w]);
+        }
+        self
+    }
+
+    fn to_json(&self) -> Result<String> {
+        let mut fields = vec![format!("\"name\":{}", json_string(&self.name))];
+        if let Some(translation) = self.translation {
+            fields.push(format!("\"translation\":{}", json_numbers(&translation)?));
+        }
+        if let Some(rotation) = self.rotation {
+            fields.push(format!("\"rotation\":{}", json_numbers(&rotation)?));
+        }
+        if let Some(scale) = self.scale {
+            fields.push(format!("\"scale\":{}", json_numbers(&scale)?));
+        }
+        if let Some(mesh) = self.mesh {
+            fields.push(format!("\"mesh\":{}", mesh));
+        }
+        if !self.children.is_empty() {
+            let children = self
+                .children
+                .iter()
+                .map(|child| *child as f64)
+                .collect::<Vec<_>>();
+            fields.push(format!("\"children\":{}", json_numbers(&children)?));
+        }
+        if let Some(uri) = &self.uri {
+            fields.push(format!("\"extras\":{{\"uri\":{}}}", json_string(uri)));
+        }
+        Ok(format!("{{{}}}", fields.join(",")))
+    }
+}
+
+#[derive(Default)]
+struct TriangleMesh {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+}
+
+impl TriangleMesh {
+    fn push_vertex(&mut self, position: Vector3<f64>, normal: Vector3<f64>) -> u32 {
+        self.positions.push(position.cast::<f32>().into());
+        self.normals.push(normal.cast::<f32>().into());
+        (self.positions.len() - 1) as u32
+    }
+
+    // A flat-shaded triangle, counter-clockwise when seen from outside.
+    fn push_triangle(&mut self, corners: [Vector3<f64>; 3]) {
+        let normal = (corners[1] - corners[0])
+            .cross(&(corners[2] - corners[0]))
+            .try_normalize(0.0)
+            .unwrap_or_else(Vector3::z);
+        for corner in corners {
+            let index = self.push_vertex(corner, normal);
+            self.indices.push(index);
+        }
+    }
+
+    fn cuboid(size: &Vector3<f64>) -> Self {
+        let half = size / 2.0;
+        let (x, y, z) = (
+            Vector3::x() * half.x,
+            Vector3::y() * half.y,
+            Vector3::z() * half.z,
+        );
+        let mut mesh = TriangleMesh::default();
+        // Each face as its outward offset and two edges whose cross product points outward.
+        for (offset, u, v) in [
+            (x, y, z),
+            (-x, z, y),
+            (y, z, x),
+            (-y, x, z),
+            (z, x, y),
+            (-z, y, x),
+        ] {
+            let normal = offset.normalize();
+            let corners = [
+                offset - u - v,
+                offset + u - v,
+                offset + u + v,
+                offset - u + v,
+            ]
+            .map(|corner| mesh.push_vertex(corner, normal));
+            mesh.indices.extend([
+                corners[0], corners[1], corners[2], corners[0], corners[2], corners[3],
+            ]);
+        }
+        mesh
+    }
+
+    fn cylinder(radius: f64, length: f64, segments: usize) -> Self {
+        let half = length / 2.0;
+        let ring = (0..=segments)
+            .map(|i| {
+                let angle = 2.0 * PI * i as f64 / segments as f64;
+                Vector3::new(angle.cos(), angle.sin(), 0.0)
+            })
+            .collect::<Vec<_>>();
+        let mut mesh = TriangleMesh::default();
+        for pair in ring.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let bottom_a = mesh.push_vertex(a * radius - Vector3::z() * half, a);
+            let bottom_b = mesh.push_vertex(b * radius - Vector3::z() * half, b);
+            let top_a = mesh.push_vertex(a * radius + Vector3::z() * half, a);
+            let top_b = mesh.push_vertex(b * radius + Vector3::z() * half, b);
+            mesh.indices
+                .extend([bottom_a, bottom_b, top_b, bottom_a, top_b, top_a]);
+        }
+        for side in [1.0, -1.0] {
+            let center = Vector3::z() * half * side;
+            for pair in ring.windows(2) {
+                let (a, b) = (center + pair[0] * radius, center + pair[1] * radius);
+                if side > 0.0 {
+                    mesh.push_triangle([center, a, b]);
+                } else {
+                    mesh.push_triangle([center, b, a]);
+                }
+            }
+        }
+        mesh
+    }
+
+    fn sphere(radius: f64, segments: usize) -> Self {
+        let stacks = (segments / 2).max(2);
+        let mut mesh = TriangleMesh::default();
+        for stack in 0..=stacks {
+            let polar = PI * stack as f64 / stacks as f64;
+            for segment in 0..=segments {
+                let azimuth = 2.0 * PI * segment as f64 / segments as f64;
+                let normal = Vector3::new(
+                    polar.sin() * azimuth.cos(),
+                    polar.sin() * azimuth.sin(),
+                    polar.cos(),
+                );
+                mesh.push_vertex(normal * radius, normal);
+            }
+        }
+        let row = segments as u32 + 1;
+        for stack in 0..stacks as u32 {
+            for segment in 0..segments as u32 {
+                let a = stack * row + segment;
+                let b = a + row;
+                mesh.indices.extend([a, b, a + 1, a + 1, b, b + 1]);
+            }
+        }
+        mesh
+    }
+
+    fn from_stl(bytes: &[u8]) -> Option<Self> {
+        let mut mesh = TriangleMesh::default();
+        let binary_count = bytes
+            .get(80..84)
+            .map(|count| u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize);
+        match binary_count {
+            Some(count) if bytes.len() == 84 + 50 * count => {
+                for triangle in bytes[84..].chunks_exact(50) {
+                    let corner = |index: usize| {
+                        let start = 12 + index * 12;
+                        let value = |offset: usize| {
+                            let bytes = &triangle[start + offset..start + offset + 4];
+                            f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+                        };
+                        Vector3::new(value(0), value(4), value(8))
+                    };
+                    mesh.push_triangle([corner(0), corner(1), corner(2)]);
+                }
+            }
+            _ => {
+                let text = std::str::from_utf8(bytes).ok()?;
+                let vertices = text
+                    .lines()
+                    .filter_map(|line| line.trim().strip_prefix("vertex"))
+                    .map(|values| {
+                        let values = values
+                            .split_whitespace()
+                            .map(|value| value.parse::<f64>().ok())
+                            .collect::<Option<Vec<_>>>()?;
+                        <[f64; 3]>::try_from(values).ok().map(Vector3::from)
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                for triangle in vertices.chunks_exact(3) {
+                    mesh.push_triangle([triangle[0], triangle[1], triangle[2]]);
+                }
+            }
+        }
+        (!mesh.indices.is_empty()).then_some(mesh)
+    }
+}
+
+struct GlbBuilder<'a> {
+    robot: &'a Robot,
+    options: &'a GltfOptions,
+    poses: &'a HashMap<String, Isometry3<f64>>,
+    binary: Vec<u8>,
+    buffer_views: Vec<String>,
+    accessors: Vec<String>,
+    meshes: Vec<String>,
+    materials: Vec<String>,
+    material_indices: HashMap<String, usize>,
+    nodes: Vec<Node>,
+}
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+impl GlbBuilder<'_> {
+    fn add_node(&mut self, node: Node) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn add_link(&mut self, link: &Link, parent_pose: &Isometry3<f64>) -> Result<usize> {
+        let pose = self.poses[&link.name];
+        let index = self.add_node(
+            Node {
+                name: link.name.clone(),
+                ..Default::default()
+            }
+            .with_transform(&(parent_pose.inverse() * pose)),
+        );
+        for (i, visual) in link.visuals.iter().enumerate() {
+            let name = visual
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("{}_visual_{}", link.name, i));
+            let child = self.add_visual(name, visual)?;
+            self.nodes[index].children.push(child);
+        }
+        let robot = self.robot;
+        for joint in robot.child_joints(&link.name) {
+            if let Some(child_link) = robot.link(&joint.child) {
+                let child = self.add_link(child_link, &pose)?;
+                self.nodes[index].children.push(child);
+            }
+        }
+        Ok(index)
+    }
+
+    fn add_visual(&mut self, name: String, visual: &Visual) -> Result<usize> {
+        let segments = self.options.segments.max(3);
+        let mut node = Node {
+            name,
+            ..Default::default()
+        }
+        .with_transform(&visual.origin.to_isometry());
+        let mesh = match &visual.geometry {
+            Geometry::Box { size } => Some(TriangleMesh::cuboid(size)),
+            Geometry::Cylinder { radius, length } => {
+                Some(TriangleMesh::cylinder(*radius, *length, segments))
+            }
+            Geometry::Sphere { radius } => Some(TriangleMesh::sphere(*radius, segments)),
+            Geometry::Mesh { filename, scale } => {
+                node.scale = scale.map(Into::into);
+                let mesh = self.load_mesh(filename);
+                if mesh.is_none() {
+                    node.uri = Some(filename.clone());
+                }
+                mesh
+            }
+        };
+        if let Some(mesh) = mesh {
+            let color = visual.material.as_ref().and_then(|material| {
+                material
+                    .color
+                    .map(|color| (material.name.as_deref(), color))
+            });
+            node.mesh = Some(self.add_mesh(&mesh, color)?);
+        }
+        Ok(self.add_node(node))
+    }
+
+    fn load_mesh(&self, filename: &str) -> Option<TriangleMesh> {
+        let path = if let Some(rest) = filename.strip_prefix("package://") {
+            let (package, path) = rest.split_once('/')?;
+            self.options.package_paths.get(package)?.join(path)
+        } else if let Some(path) = filename.strip_prefix("file://") {
+            PathBuf::from(path)
+        } else if filename.contains("://") {
+            return None;
+        } else {
+            PathBuf::from(filename)
+        };
+        let is_stl = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("stl"));
+        if !is_stl {
+            return None;
+        }
+        TriangleMesh::from_stl(&std::fs::read(path).ok()?)
+    }
+
+    fn add_mesh(
+        &mut self,
+        mesh: &TriangleMesh,
+        color: Option<(Option<&str>, Vector4<f64>)>,
+    ) -> Result<usize> {
+        let (min, max) = mesh.positions.iter().fold(
+            (Point3::from([f32::MAX; 3]), Point3::from([f32::MIN; 3])),
+            |(min, max), position| {
+                let position = Point3::from(*position);
+                (min.inf(&position), max.sup(&position))
+            },
+        );
+        let positions = self.add_view(
+            mesh.positions
+                .iter()
+                .flatten()
+                .flat_map(|v| v.to_le_bytes())
+                .collect(),
+            ARRAY_BUFFER,
+        );
+        let positions = self.add_accessor(format!(
+            "{{\"bufferView\":{},\"componentType\":{},\"count\":{},\"type\":\"VEC3\",\"min\":{},\"max\":{}}}",
+            positions,
+            FLOAT,
+            mesh.positions.len(),
+            json_numbers(min.coords.cast::<f64>().as_slice())?,
+            json_numbers(max.coords.cast::<f64>().as_slice())?,
+        ));
+        let normals = self.add_view(
+            mesh.normals
+                .iter()
+                .flatten()
+                .flat_map(|v| v.to_le_bytes())
+                .collect(),
+            ARRAY_BUFFER,
+        );
+        let normals = self.add_accessor(format!(
+            "{{\"bufferView\":{},\"componentType\":{},\"count\":{},\"type\":\"VEC3\"}}",
+            normals,
+            FLOAT,
+            mesh.normals.len()
+        ));
+        let indices = self.add_view(
+            mesh.indices.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            ELEMENT_ARRAY_BUFFER,
+        );
+        let indices = self.add_accessor(format!(
+            "{{\"bufferView\":{},\"componentType\":{},\"count\":{},\"type\":\"SCALAR\"}}",
+            indices,
+            UNSIGNED_INT,
+            mesh.indices.len()
+        ));
+        let material = match color {
+            Some((name, color)) => format!(",\"material\":{}", self.add_material(name, color)?),
+            None => String::new(),
+        };
+        self.meshes.push(format!(
+            "{{\"primitives\":[{{\"attributes\":{{\"POSITION\":{},\"NORMAL\":{}}},\"indices\":{}{}}}]}}",
+            positions, normals, indices, material
+        ));
+        Ok(self.meshes.len() - 1)
+    }
+
+    fn add_view(&mut self, bytes: Vec<u8>, target: u32) -> usize {
+        let offset = self.binary.len();
+        self.binary.extend(bytes);
+        self.buffer_views.push(format!(
+            "{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{},\"target\":{}}}",
+            offset,
+            self.binary.len() - offset,
+            target
+        ));
+        // Every component is four bytes wide, so views stay aligned without padding.
+        self.buffer_views.len() - 1
+    }
+
+    fn add_accessor(&mut self, accessor: String) -> usize {
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn add_material(&mut self, name: Option<&str>, color: Vector4<f64>) -> Result<usize> {
+        let key = format!("{:?}{:?}", name, color.as_slice());
+        if let Some(index) = self.material_indices.get(&key) {
+            return Ok(*index);
+        }
+        let mut fields = Vec::new();
+        if let Some(name) = name {
+            fields.push(format!("\"name\":{}", json_string(name)));
+        }
+        fields.push(format!(
+            "\"pbrMetallicRoughness\":{{\"baseColorFactor\":{},\"metallicFactor\":0,\"roughnessFactor\":1}}",
+            json_numbers(color.as_slice())?
+        ));
+        if color.w < 1.0 {
+            fields.push("\"alphaMode\":\"BLEND\"".to_string());
+        }
+        self.materials.push(format!("{{{}}}", fields.join(",")));
+        self.material_indices.insert(key, self.materials.len() - 1);
+        Ok(self.materials.len() - 1)
+    }
+
+    fn finish(self) -> Result<Vec<u8>> {
+        let mut fields = vec![
+            "\"asset\":{\"version\":\"2.0\",\"generator\":\"xurdf\"}".to_string(),
+            "\"scene\":0".to_string(),
+            "\"scenes\":[{\"nodes\":[0]}]".to_string(),
+            format!(
+                "\"nodes\":[{}]",
+                self.nodes
+                    .iter()
+                    .map(Node::to_json)
+                    .collect::<Result<Vec<_>>>()?
+                    .join(",")
+            ),
+        ];
+        // glTF forbids empty arrays, so sections without entries are left out.
+        for (name, entries) in [
+            ("meshes", &self.meshes),
+            ("materials", &self.materials),
+            ("accessors", &self.accessors),
+            ("bufferViews", &self.buffer_views),
+        ] {
+            if !entries.is_empty() {
+                fields.push(format!("\"{}\":[{}]", name, entries.join(",")));
+            }
+        }
+        if !self.binary.is_empty() {
+            fields.push(format!(
+                "\"buffers\":[{{\"byteLength\":{}}}]",
+                self.binary.len()
+            ));
+        }
+
+        let mut json = format!("{{{}}}", fields.join(",")).into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut binary = self.binary;
+        binary.resize(binary.len().next_multiple_of(4), 0);
+        let mut length = 12 + 8 + json.len();
+        if !binary.is_empty() {
+            length += 8 + binary.len();
+        }
+
+        let mut glb = Vec::with_capacity(length);
+        glb.extend(b"glTF");
+        glb.extend(2u32.to_le_bytes());
+        glb.extend((length as u32).to_le_bytes());
+        glb.extend((json.len() as u32).to_le_bytes());
+        glb.extend(b"JSON");
+        glb.extend(json);
+        if !binary.is_empty() {
+            glb.extend((binary.len() as u32).to_le_bytes());
+            glb.extend(b"BIN\0");
+            glb.extend(binary);
+        }
+        Ok(glb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_urdf_from_string;
+    use std::fs;
+
+    fn glb_json(glb: &[u8]) -> serde_json::Value {
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(
+            u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize,
+            glb.len()
+        );
+        let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        assert_eq!(&glb[16..20], b"JSON");
+        serde_json::from_slice(&glb[20..20 + json_length]).unwrap()
+    }
+
+    #[test]
+    fn writes_posed_link_tree_with_tessellated_visuals() {
+        let robot = parse_urdf_from_string(
+            r#"
+            <robot name="arm">
+              <material name="red"><color rgba="1 0 0 0.5"/></material>
+              <link name="base">
+                <visual><geometry><box size="1 1 1"/></geometry><material name="red"/></visual>
+              </link>
+              <link name="upper">
+                <visual name="shell">
+                  <origin xyz="0 0 0.5"/>
+                  <geometry><cylinder radius="0.1" length="1"/></geometry>
+                </visual>
+                <visual><geometry><sphere radius="0.2"/></geometry></visual>
+              </link>
+              <joint name="shoulder" type="revolute">
+                <parent link="base"/>
+                <child link="upper"/>
+                <origin xyz="0 0 1"/>
+                <axis xyz="0 0 1"/>
+              </joint>
+            </robot>
+            "#,
+        )
+        .unwrap();
+
+        let glb = write_glb_with_options(
+            &robot,
+            GltfOptions::default()
+                .with_joint_position("shoulder", PI)
+                .with_segments(8),
+        )
+        .unwrap();
+
+        let json = glb_json(&glb);
+        let nodes = json["nodes"].as_array().unwrap();
+        let names = nodes
+            .iter()
+            .map(|node| node["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "arm",
+                "base",
+                "base_visual_0",
+                "upper",
+                "shell",
+                "upper_visual_1"
+            ]
+        );
+        assert_eq!(nodes[0]["children"], serde_json::json!([1]));
+        assert_eq!(nodes[1]["children"], serde_json::json!([2, 3]));
+        assert_eq!(nodes[3]["children"], serde_json::json!([4, 5]));
+        assert_eq!(nodes[3]["translation"], serde_json::json!([0, 0, 1]));
+        let rotation = nodes[3]["rotation"].as_array().unwrap();
+        assert!((rotation[2].as_f64().unwrap().abs() - 1.0).abs() < 1e-12);
+        assert_eq!(nodes[4]["translation"], serde_json::json!([0, 0, 0.5]));
+
+        let box_primitive = &json["meshes"][0]["primitives"][0];
+        assert_eq!(
+            json["accessors"][box_primitive["attributes"]["POSITION"].as_u64().unwrap() as usize]
+                ["count"],
+            24
+        );
+        assert_eq!(
+            json["accessors"][box_primitive["indices"].as_u64().unwrap() as usize]["count"],
+            36
+        );
+        assert_eq!(
+            json["accessors"][0]["min"],
+            serde_json::json!([-0.5, -0.5, -0.5])
+        );
+        assert_eq!(
+            json["materials"],
+            serde_json::json!([{
+                "name": "red",
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": [1, 0, 0, 0.5],
+                    "metallicFactor": 0,
+                    "roughnessFactor": 1
+                },
+                "alphaMode": "BLEND"
+            }])
+        );
+        assert!(json["meshes"][1]["primitives"][0].get("material").is_none());
+    }
+
+    #[test]
+    fn rejects_numbers_json_cannot_represent() {
+        let mut robot = parse_urdf_from_string(
+            r#"
+            <robot name="arm">
+              <link name="base"/>
+              <link name="upper"/>
+              <joint name="shoulder" type="fixed">
+                <parent link="base"/>
+                <child link="upper"/>
+                <origin xyz="-0 0 1"/>
+              </joint>
+            </robot>
+            "#,
+        )
+        .unwrap();
+
+        let json = glb_json(&write_glb(&robot).unwrap());
+        assert_eq!(
+            json["nodes"][2]["translation"],
+            serde_json::json!([0, 0, 1])
+        );
+
+        robot.joints[0].origin.xyz.x = f64::NAN;
+        let err = write_glb(&robot).unwrap_err();
+        assert_eq!(err.to_string(), "cannot write NaN as a JSON number");
+        let err = json_numbers(&[1.0, f64::NEG_INFINITY]).unwrap_err();
+        assert_eq!(err.to_string(), "cannot write -inf as a JSON number");
+    }
+
+    #[test]
+    fn embeds_found_stl_meshes_and_references_others() {
+        let dir = std::env::temp_dir().join(format!("xurdf-gltf-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("meshes")).unwrap();
+        fs::write(
+            dir.join("meshes/part.stl"),
+            "solid part\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nvertex 0 1 0\nendloop\nendfacet\nendsolid part\n",
+        )
+        .unwrap();
+        let robot = parse_urdf_from_string(
+            r#"
+            <robot name="parts">
+              <link name="base">
+                <visual><geometry><mesh filename="package://parts/meshes/part.stl" scale="2 2 2"/></geometry></visual>
+                <visual><geometry><mesh filename="package://parts/meshes/shell.dae"/></geometry></visual>
+              </link>
+            </robot>
+            "#,
+        )
+        .unwrap();
+
+        let glb = write_glb_with_options(
+            &robot,
+            GltfOptions::default().with_package_path("parts", &dir),
+        )
+        .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let json = glb_json(&glb);
+        let nodes = &json["nodes"];
+        assert_eq!(nodes[2]["mesh"], 0);
+        assert_eq!(nodes[2]["scale"], serde_json::json!([2, 2, 2]));
+        assert_eq!(json["accessors"][0]["count"], 3);
+        assert_eq!(json["accessors"][0]["max"], serde_json::json!([1, 1, 0]));
+        assert!(nodes[3].get("mesh").is_none());
+        assert_eq!(
+            nodes[3]["extras"]["uri"],
+            "package://parts/meshes/shell.dae"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use super::model::*;
+use super::xml_writer::format_vector;
+use indexmap::IndexSet;
+
+/// Writes the link/joint tree of `robot` as a Graphviz DOT digraph. Links are boxes, leaf links
+/// are filled, and each joint is an edge labelled with its type, origin and axis; fixed joints
+/// are drawn dashed.
+pub fn write_dot_to_string(robot: &Robot) -> String {
+    let mut output = format!("digraph {} {{\n", dot_string(&robot.name));
+    output.push_str("  rankdir=TB;\n");
+    output.push_str("  node [shape=box, style=rounded, fontname=\"Helvetica\"];\n");
+    output.push_str("  edge [fontname=\"Helvetica\", fontsize=10];\n");
+    for link in link_names(robot) {
+        if is_leaf(robot, link) {
+            output.push_str(&format!(
+                "  {} [style=\"rounded,filled\", fillcolor=\"#d9ead3\"];\n",
+                dot_string(link)
+            ));
+        } else {
+            output.push_str(&format!("  {};\n", dot_string(link)));
+        }
+    }
+    for joint in robot.joints.iter() {
+        let style = if joint.joint_type == "fixed" {
+            ", style=dashed, color=\"#808080\""
+        } else {
+            ""
+        };
+        output.push_str(&format!(
+            "  {} -> {} [label={}{}];\n",
+            dot_string(&joint.parent),
+            dot_string(&joint.child),
+            dot_string(&joint_label(joint).join("\n")),
+            style
+        ));
+    }
+    output.push_str("}\n");
+    output
+}
+
+/// Writes the link/joint tree of `robot` as a Mermaid flowchart, styled like
+/// [`write_dot_to_string`].
+pub fn write_mermaid_to_string(robot: &Robot) -> String {
+    let links = link_names(robot);
+    // Mermaid identifiers cannot hold arbitrary link names, so nodes are numbered.
+    let id = |name: &str| format!("n{}", links.get_index_of(name).unwrap_or_default());
+    let mut output = String::from("flowchart TD\n");
+    for link in links.iter() {
+        output.push_str(&format!("  {}[\"{}\"]\n", id(link), mermaid_text(link)));
+    }
+    for joint in robot.joints.iter() {
+        let arrow = if joint.joint_type == "fixed" {
+            "-.->"
+        } else {
+            "-->"
+        };
+        let label = joint_label(joint)
+            .iter()
+            .map(|line| mermaid_text(line))
+            .collect::<Vec<_>>()
+            .join("<br/>");
+        output.push_str(&format!(
+            "  {} {}|\"{}\"| {}\n",
+            id(&joint.parent),
+            arrow,
+            label,
+            id(&joint.child)
+        ));
+    }
+    let leaves = links
+        .iter()
+        .filter(|link| is_leaf(robot, link))
+        .map(|link| id(link))
+        .collect::<Vec<_>>();
+    if !leaves.is_empty() {
+        output.push_str("  classDef leaf fill:#d9ead3,stroke:#6a9f5b\n");
+        output.push_str(&format!("  class {} leaf\n", leaves.join(",")));
+    }
+    output
+}
+
+// Links in model order followed by names that joints reference but the model lacks.
+fn link_names(robot: &Robot) -> IndexSet<&str> {
+    robot
+        .links
+        .iter()
+        .map(|link| link.name.as_str())
+        .chain(
+            robot
+                .joints
+                .iter()
+                .flat_map(|joint| [joint.parent.as_str(), joint.child.as_str()]),
+        )
+        .collect()
+}
+
+fn is_leaf(robot: &Robot, link: &str) -> bool {
+    robot.child_joints(link).next().is_none()
+}
+
+fn joint_label(joint: &Joint) -> Vec<String> {
+    let mut lines = vec![
+        format!("{} ({})", joint.name, joint.joint_type),
+        format!("xyz: {}", format_vector(&joint.origin.xyz)),
+        format!("rpy: {}", format_vector(&joint.origin.rpy)),
+    ];
+    if matches!(
+        joint.joint_type.as_str(),
+        "revolute" | "continuous" | "prismatic" | "planar"
+    ) {
+        lines.push(format!("axis: {}", format_vector(&joint.axis)));
+    }
+    lines
+}
+
+fn dot_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn mermaid_text(text: &str) -> String {
+    text.replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_urdf_from_string;
+
+    const ARM: &str = r#"
+        <robot name="arm">
+          <link name="base"/>
+          <link name="upper"/>
+          <link name="tool"/>
+          <joint name="shoulder" type="revolute">
+            <parent link="base"/>
+            <child link="upper"/>
+            <origin xyz="0 0 0.5" rpy="0 0 1.5"/>
+            <axis xyz="0 1 0"/>
+          </joint>
+          <joint name="flange" type="fixed">
+            <parent link="upper"/>
+            <child link="tool"/>
+            <origin xyz="0 0 1"/>
+          </joint>
+        </robot>
+    "#;
+
+    #[test]
+    fn writes_dot_graph() {
+        let robot = parse_urdf_from_string(ARM).unwrap();
+        assert_eq!(
+            write_dot_to_string(&robot),
+            r##"digraph "arm" {
+  rankdir=TB;
+  node [shape=box, style=rounded, fontname="Helvetica"];
+  edge [fontname="Helvetica", fontsize=10];
+  "base";
+  "upper";
+  "tool" [style="rounded,filled", fillcolor="#d9ead3"];
+  "base" -> "upper" [label="shoulder (revolute)\nxyz: 0 0 0.5\nrpy: 0 0 1.5\naxis: 0 1 0"];
+  "upper" -> "tool" [label="flange (fixed)\nxyz: 0 0 1\nrpy: 0 0 0", style=dashed, color="#808080"];
+}
+"##
+        );
+    }
+
+    #[test]
+    fn writes_mermaid_flowchart() {
+        let robot = parse_urdf_from_string(ARM).unwrap();
+        assert_eq!(
+            write_mermaid_to_string(&robot),
+            r#"flowchart TD
+  n0["base"]
+  n1["upper"]
+  n2["tool"]
+  n0 -->|"shoulder (revolute)<br/>xyz: 0 0 0.5<br/>rpy: 0 0 1.5<br/>axis: 0 1 0"| n1
+  n1 -.->|"flange (fixed)<br/>xyz: 0 0 1<br/>rpy: 0 0 0"| n2
+  classDef leaf fill:#d9ead3,stroke:#6a9f5b
+  class n2 leaf
+"#
+        );
+    }
+}
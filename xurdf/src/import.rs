@@ -0,0 +1,55 @@
+extern crate nalgebra as na;
+
+use super::model::*;
+use anyhow::{anyhow, bail, Result};
+use indexmap::IndexMap;
+use na::{UnitQuaternion, Vector3};
+
+// Collects lossy-conversion warnings, folding repeats of the same message into one line.
+#[derive(Default)]
+pub(crate) struct ImportNotes {
+    counts: IndexMap<String, usize>,
+}
+
+impl ImportNotes {
+    pub(crate) fn note(&mut self, message: String) {
+        *self.counts.entry(message).or_insert(0) += 1;
+    }
+
+    pub(crate) fn finish(self, robot: Robot) -> ImportedRobot {
+        let warnings = self
+            .counts
+            .into_iter()
+            .map(|(message, count)| match count {
+                1 => message,
+                _ => format!("{} ({} times)", message, count),
+            })
+            .collect();
+        ImportedRobot { robot, warnings }
+    }
+}
+
+pub(crate) fn parse_floats(text: &str, what: &str) -> Result<Vec<f64>> {
+    text.split_whitespace()
+        .map(|value| {
+            value
+                .parse::<f64>()
+                .map_err(|_| anyhow!("invalid number `{}` in {}", value, what))
+        })
+        .collect()
+}
+
+pub(crate) fn parse_exact_floats<const N: usize>(text: &str, what: &str) -> Result<[f64; N]> {
+    let values = parse_floats(text, what)?;
+    match <[f64; N]>::try_from(values.as_slice()) {
+        Ok(values) => Ok(values),
+        Err(_) => bail!("expected {} numbers in {}, found {}", N, what, values.len()),
+    }
+}
+
+// The rotation taking +z onto `direction`, as MJCF's `zaxis` and `fromto` define it.
+pub(crate) fn rotation_from_z(direction: &Vector3<f64>) -> UnitQuaternion<f64> {
+    UnitQuaternion::rotation_between(&Vector3::z(), direction).unwrap_or_else(|| {
+        UnitQuaternion::from_axis_angle(&Vector3::x_axis(), std::f64::consts::PI)
+    })
+}
@@ -0,0 +1,30 @@
+// Helpers for the hand-written JSON in glTF files and the banner's typed args.
+
+use super::xml_writer::format_float;
+use anyhow::{bail, Result};
+
+pub(crate) fn json_string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// JSON has no literal for NaN or the infinities, so they are rejected.
+pub(crate) fn json_numbers(values: &[f64]) -> Result<String> {
+    let mut numbers = Vec::with_capacity(values.len());
+    for value in values {
+        if !value.is_finite() {
+            bail!("cannot write {} as a JSON number", value);
+        }
+        numbers.push(format_float(*value));
+    }
+    Ok(format!("[{}]", numbers.join(",")))
+}
@@ -0,0 +1,79 @@
+extern crate nalgebra as na;
+
+use super::model::*;
+use anyhow::{bail, Result};
+use na::Isometry3;
+use std::collections::HashMap;
+
+/// Poses of all links relative to the root link, with each joint at the position given in
+/// `joint_positions` or at zero. Every root link is placed at the origin.
+pub fn forward_kinematics(
+    robot: &Robot,
+    joint_positions: &HashMap<String, f64>,
+) -> Result<HashMap<String, Isometry3<f64>>> {
+    let mut poses = HashMap::new();
+    let mut pending = robot
+        .root_links()
+        .map(|link| (link.name.as_str(), Isometry3::identity()))
+        .collect::<Vec<_>>();
+    while let Some((link, pose)) = pending.pop() {
+        if poses.insert(link.to_string(), pose).is_some() {
+            bail!("link `{}` is reached by more than one joint", link);
+        }
+        for joint in robot.child_joints(link) {
+            let position = joint_positions.get(&joint.name).copied().unwrap_or(0.0);
+            pending.push((joint.child.as_str(), pose * joint.transform(position)));
+        }
+    }
+    if let Some(link) = robot
+        .links
+        .iter()
+        .find(|link| !poses.contains_key(&link.name))
+    {
+        bail!("link `{}` is not connected to a root link", link.name);
+    }
+    Ok(poses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_urdf_from_string;
+    use na::Vector3;
+
+    #[test]
+    fn places_links_at_joint_positions() {
+        let robot = parse_urdf_from_string(
+            r#"
+            <robot name="arm">
+              <link name="base"/>
+              <link name="upper"/>
+              <link name="slider"/>
+              <joint name="shoulder" type="revolute">
+                <parent link="base"/>
+                <child link="upper"/>
+                <origin xyz="0 0 1"/>
+                <axis xyz="0 0 1"/>
+              </joint>
+              <joint name="extend" type="prismatic">
+                <parent link="upper"/>
+                <child link="slider"/>
+                <origin xyz="1 0 0"/>
+                <axis xyz="1 0 0"/>
+              </joint>
+            </robot>
+            "#,
+        )
+        .unwrap();
+        let positions = HashMap::from([
+            ("shoulder".to_string(), std::f64::consts::FRAC_PI_2),
+            ("extend".to_string(), 0.5),
+        ]);
+
+        let poses = forward_kinematics(&robot, &positions).unwrap();
+
+        assert_eq!(poses["base"], Isometry3::identity());
+        let slider = poses["slider"].translation.vector;
+        assert!((slider - Vector3::new(0.0, 1.5, 1.0)).norm() < 1e-12);
+    }
+}
@@ -16,7 +16,7 @@ pub enum TokenType {
 
 pub struct Lexer {
     input_str: String,
-    regexes: Vec<(TokenType, Regex)>,
+    regexes: [(TokenType, &'static Regex); 4],
     position: usize,
 }
 
@@ -24,18 +24,17 @@ impl Lexer {
     pub fn new(input_str: &str) -> Lexer {
         Lexer {
             input_str: input_str.to_string(),
-            regexes: vec![
-                (
-                    TokenType::DollarDollarBrace,
-                    DOLLAR_DOLLAR_BRACE_REGEX.clone(),
-                ),
-                (TokenType::Expr, EXPR_REGEX.clone()),
-                (TokenType::Extension, EXTENSION_REGEX.clone()),
-                (TokenType::Text, TEXT_REGEX.clone()),
+            // Cloning a `Regex` gives it a fresh match cache, so share the statics.
+            regexes: [
+                (TokenType::DollarDollarBrace, &DOLLAR_DOLLAR_BRACE_REGEX),
+                (TokenType::Expr, &EXPR_REGEX),
+                (TokenType::Extension, &EXTENSION_REGEX),
+                (TokenType::Text, &TEXT_REGEX),
             ],
             position: 0,
         }
     }
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<(TokenType, String)> {
         for (token_type, regex) in self.regexes.iter() {
             if let Some(m) = regex.captures(&self.input_str[self.position..]) {
@@ -48,7 +47,7 @@ impl Lexer {
                         TokenType::Extension => &m[2..m.len() - 1],
                         TokenType::Text => m,
                     };
-                    return Some((token_type.clone(), m.to_string()));
+                    return Some((*token_type, m.to_string()));
                 }
             }
         }
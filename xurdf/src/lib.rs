@@ -1,11 +1,27 @@
+mod edit;
 mod eval;
+mod gltf;
+mod graph;
+mod import;
+mod json_writer;
+mod kinematics;
 mod lexer;
+mod mjcf;
 mod model;
+mod pyexpr;
+mod sdf;
 mod urdf;
 mod xacro;
+mod xml_writer;
 
+pub use self::edit::*;
 pub use self::eval::*;
+pub use self::gltf::*;
+pub use self::graph::*;
+pub use self::kinematics::*;
 pub use self::lexer::*;
+pub use self::mjcf::*;
 pub use self::model::*;
+pub use self::sdf::*;
 pub use self::urdf::*;
 pub use self::xacro::*;
@@ -0,0 +1,1351 @@
+extern crate nalgebra as na;
+
+use super::edit::WORLD_LINK;
+use super::import::*;
+use super::model::*;
+use super::xml_writer::*;
+use anyhow::{anyhow, bail, Result};
+use na::{
+    Isometry3, Matrix3, Quaternion, Rotation3, Translation3, Unit, UnitQuaternion, Vector3, Vector4,
+};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// The actuator generated for each revolute, continuous and prismatic joint.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum MjcfActuator {
+    None,
+    /// Direct torque or force control, limited by the joint effort.
+    #[default]
+    Motor,
+    /// Position servo with the given proportional gain, limited by the joint range.
+    Position {
+        kp: f64,
+    },
+    /// Velocity servo with the given velocity gain, limited by the joint velocity.
+    Velocity {
+        kv: f64,
+    },
+}
+
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct MjcfOptions {
+    pub actuator: MjcfActuator,
+    /// Where `package://` mesh URIs are resolved, as MuJoCo cannot read them itself. URIs of
+    /// other packages are written unchanged.
+    pub package_paths: HashMap<String, PathBuf>,
+}
+
+impl MjcfOptions {
+    pub fn with_actuator(mut self, actuator: MjcfActuator) -> Self {
+        self.actuator = actuator;
+        self
+    }
+
+    pub fn with_package_path(
+        mut self,
+        package: impl Into<String>,
+        path: impl Into<PathBuf>,
+    ) -> Self {
+        self.package_paths.insert(package.into(), path.into());
+        self
+    }
+}
+
+pub fn write_mjcf_to_string(robot: &Robot) -> Result<String> {
+    write_mjcf_to_string_with_options(robot, MjcfOptions::default())
+}
+
+/// Writes `robot` as a MuJoCo model. Bodies are nested along the joint tree and placed at
+/// the joint origins; visuals become non-colliding geoms in group 1 and collisions geoms in
+/// group 3. Mimic joints are coupled to the joints they follow by `<equality>` constraints
+/// instead of getting actuators.
+pub fn write_mjcf_to_string_with_options(robot: &Robot, options: MjcfOptions) -> Result<String> {
+    let meshes = MeshAssets::collect(robot, &options.package_paths);
+    let mut writer = XmlWriter::new();
+    writer.start("mujoco", &[("model", &robot.name)]);
+    writer.empty("compiler", &[("angle", "radian"), ("autolimits", "true")]);
+
+    if !meshes.entries.is_empty() {
+        writer.start("asset", &[]);
+        for (name, file, scale) in meshes.entries.iter() {
+            let mut attributes = vec![("name", name.clone()), ("file", file.clone())];
+            if let Some(scale) = scale {
+                attributes.push(("scale", scale.clone()));
+            }
+            writer.empty("mesh", &borrowed(&attributes));
+        }
+        writer.end("asset");
+    }
+
+    let mut body_writer = BodyWriter {
+        robot,
+        meshes: &meshes,
+        writer: &mut writer,
+        geom_names: HashSet::new(),
+    };
+    body_writer.writer.start("worldbody", &[]);
+    for root in robot.root_links() {
+        if root.name == WORLD_LINK {
+            body_writer.write_geoms(root);
+            for joint in robot.child_joints(&root.name) {
+                body_writer.write_body(
+                    robot.link(&joint.child).ok_or_else(|| {
+                        anyhow!("joint `{}` has unknown child `{}`", joint.name, joint.child)
+                    })?,
+                    Some(joint),
+                    true,
+                )?;
+            }
+        } else {
+            body_writer.write_body(root, None, true)?;
+        }
+    }
+    writer.end("worldbody");
+
+    let (mimics, actuated): (Vec<_>, Vec<_>) = robot
+        .joints
+        .iter()
+        .filter(|joint| is_movable(joint))
+        .partition(|joint| joint.mimic.is_some());
+    if !mimics.is_empty() {
+        writer.start("equality", &[]);
+        for joint in mimics {
+            write_mimic(&mut writer, robot, joint)?;
+        }
+        writer.end("equality");
+    }
+    if options.actuator != MjcfActuator::None && !actuated.is_empty() {
+        writer.start("actuator", &[]);
+        for joint in actuated {
+            write_actuator(&mut writer, joint, &options.actuator);
+        }
+        writer.end("actuator");
+    }
+
+    writer.end("mujoco");
+    Ok(writer.finish())
+}
+
+struct BodyWriter<'a> {
+    robot: &'a Robot,
+    meshes: &'a MeshAssets,
+    writer: &'a mut XmlWriter,
+    // Geom names share one namespace across the model, unlike URDF visual names.
+    geom_names: HashSet<String>,
+}
+
+impl BodyWriter<'_> {
+    fn write_body(&mut self, link: &Link, joint: Option<&Joint>, top_level: bool) -> Result<()> {
+        let mut attributes = vec![("name", link.name.clone())];
+        if let Some(joint) = joint {
+            attributes.extend(pose_attributes(&joint.origin));
+        }
+        self.writer.start("body", &borrowed(&attributes));
+        if let Some(joint) = joint {
+            self.write_joint(joint, top_level)?;
+        }
+
+        let inertial = &link.inertial;
+        if inertial.mass > 0.0 {
+            let mut attributes = pose_attributes(&inertial.origin);
+            attributes.push(("mass", format_float(inertial.mass)));
+            let inertia = &inertial.inertia;
+            attributes.push((
+                "fullinertia",
+                format_floats(&[
+                    inertia[(0, 0)],
+                    inertia[(1, 1)],
+                    inertia[(2, 2)],
+                    inertia[(0, 1)],
+                    inertia[(0, 2)],
+                    inertia[(1, 2)],
+                ]),
+            ));
+            self.writer.empty("inertial", &borrowed(&attributes));
+        }
+
+        self.write_geoms(link);
+        let robot = self.robot;
+        for joint in robot.child_joints(&link.name) {
+            let child = robot.link(&joint.child).ok_or_else(|| {
+                anyhow!("joint `{}` has unknown child `{}`", joint.name, joint.child)
+            })?;
+            self.write_body(child, Some(joint), false)?;
+        }
+        self.writer.end("body");
+        Ok(())
+    }
+
+    fn write_joint(&mut self, joint: &Joint, top_level: bool) -> Result<()> {
+        let joint_type = match joint.joint_type.as_str() {
+            "revolute" | "continuous" => "hinge",
+            "prismatic" => "slide",
+            "fixed" => return Ok(()),
+            "floating" if top_level => {
+                self.writer.empty("freejoint", &[("name", &joint.name)]);
+                return Ok(());
+            }
+            "floating" => bail!(
+                "joint `{}` is floating, but MuJoCo only allows free joints on bodies attached to the world",
+                joint.name
+            ),
+            other => bail!(
+                "joint `{}` has type `{}`, which MuJoCo cannot represent",
+                joint.name,
+                other
+            ),
+        };
+        let mut attributes = vec![
+            ("name", joint.name.clone()),
+            ("type", joint_type.to_string()),
+            ("axis", format_vector(&joint.axis)),
+        ];
+        if joint.joint_type != "continuous" && joint.limit.lower < joint.limit.upper {
+            attributes.push((
+                "range",
+                format_floats(&[joint.limit.lower, joint.limit.upper]),
+            ));
+        }
+        self.writer.empty("joint", &borrowed(&attributes));
+        Ok(())
+    }
+
+    fn write_geoms(&mut self, link: &Link) {
+        for collision in link.collisions.iter() {
+            let mut attributes =
+                self.geom_attributes(&collision.name, &collision.geometry, &collision.origin);
+            attributes.push(("group", "3".to_string()));
+            self.writer.empty("geom", &borrowed(&attributes));
+        }
+        for visual in link.visuals.iter() {
+            let mut attributes =
+                self.geom_attributes(&visual.name, &visual.geometry, &visual.origin);
+            if let Some(color) = visual.material.as_ref().and_then(|material| material.color) {
+                attributes.push(("rgba", format_floats(color.as_slice())));
+            }
+            attributes.push(("contype", "0".to_string()));
+            attributes.push(("conaffinity", "0".to_string()));
+            attributes.push(("group", "1".to_string()));
+            self.writer.empty("geom", &borrowed(&attributes));
+        }
+    }
+
+    fn geom_attributes(
+        &mut self,
+        name: &Option<String>,
+        geometry: &Geometry,
+        origin: &Pose,
+    ) -> Vec<(&'static str, String)> {
+        let mut attributes = Vec::new();
+        if let Some(name) = name {
+            if self.geom_names.insert(name.clone()) {
+                attributes.push(("name", name.clone()));
+            }
+        }
+        match geometry {
+            Geometry::Box { size } => {
+                attributes.push(("type", "box".to_string()));
+                attributes.push(("size", format_vector(&(size / 2.0))));
+            }
+            Geometry::Cylinder { radius, length } => {
+                attributes.push(("type", "cylinder".to_string()));
+                attributes.push(("size", format_floats(&[*radius, length / 2.0])));
+            }
+            Geometry::Sphere { radius } => {
+                attributes.push(("type", "sphere".to_string()));
+                attributes.push(("size", format_float(*radius)));
+            }
+            Geometry::Mesh { filename, scale } => {
+                attributes.push(("type", "mesh".to_string()));
+                attributes.push(("mesh", self.meshes.name(filename, scale).to_string()));
+            }
+        }
+        attributes.extend(pose_attributes(origin));
+        attributes
+    }
+}
+
+fn is_movable(joint: &Joint) -> bool {
+    matches!(
+        joint.joint_type.as_str(),
+        "revolute" | "continuous" | "prismatic"
+    )
+}
+
+fn write_mimic(writer: &mut XmlWriter, robot: &Robot, joint: &Joint) -> Result<()> {
+    let Some(mimic) = &joint.mimic else {
+        return Ok(());
+    };
+    match robot.joints.iter().find(|other| other.name == mimic.joint) {
+        Some(leader) if is_movable(leader) => {}
+        Some(_) => bail!(
+            "joint `{}` mimics `{}`, which MuJoCo does not represent as a joint",
+            joint.name,
+            mimic.joint
+        ),
+        None => bail!(
+            "joint `{}` mimics unknown joint `{}`",
+            joint.name,
+            mimic.joint
+        ),
+    }
+    let polycoef = format_floats(&[mimic.offset, mimic.multiplier, 0.0, 0.0, 0.0]);
+    writer.empty(
+        "joint",
+        &[
+            ("joint1", joint.name.as_str()),
+            ("joint2", mimic.joint.as_str()),
+            ("polycoef", &polycoef),
+        ],
+    );
+    Ok(())
+}
+
+fn write_actuator(writer: &mut XmlWriter, joint: &Joint, actuator: &MjcfActuator) {
+    let limit = &joint.limit;
+    let mut attributes = vec![("name", joint.name.clone()), ("joint", joint.name.clone())];
+    let tag = match actuator {
+        MjcfActuator::None => return,
+        MjcfActuator::Motor => {
+            if limit.effort > 0.0 {
+                attributes.push(("ctrlrange", format_floats(&[-limit.effort, limit.effort])));
+            }
+            "motor"
+        }
+        MjcfActuator::Position { kp } => {
+            attributes.push(("kp", format_float(*kp)));
+            if joint.joint_type != "continuous" && limit.lower < limit.upper {
+                attributes.push(("ctrlrange", format_floats(&[limit.lower, limit.upper])));
+            }
+            "position"
+        }
+        MjcfActuator::Velocity { kv } => {
+            attributes.push(("kv", format_float(*kv)));
+            if limit.velocity > 0.0 {
+                attributes.push((
+                    "ctrlrange",
+                    format_floats(&[-limit.velocity, limit.velocity]),
+                ));
+            }
+            "velocity"
+        }
+    };
+    if *actuator != MjcfActuator::Motor && limit.effort > 0.0 {
+        attributes.push(("forcerange", format_floats(&[-limit.effort, limit.effort])));
+    }
+    writer.empty(tag, &borrowed(&attributes));
+}
+
+// One asset per distinct filename and scale, named after the file.
+struct MeshAssets {
+    names: HashMap<(String, Option<String>), String>,
+    entries: Vec<(String, String, Option<String>)>,
+}
+
+impl MeshAssets {
+    fn collect(robot: &Robot, package_paths: &HashMap<String, PathBuf>) -> MeshAssets {
+        let mut assets = MeshAssets {
+            names: HashMap::new(),
+            entries: Vec::new(),
+        };
+        let mut used = HashSet::new();
+        let geometries = robot.links.iter().flat_map(|link| {
+            link.collisions
+                .iter()
+                .map(|collision| &collision.geometry)
+                .chain(link.visuals.iter().map(|visual| &visual.geometry))
+        });
+        for geometry in geometries {
+            let Geometry::Mesh { filename, scale } = geometry else {
+                continue;
+            };
+            let key = (filename.clone(), scale.as_ref().map(format_vector));
+            if assets.names.contains_key(&key) {
+                continue;
+            }
+            let stem = Path::new(filename)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "mesh".to_string());
+            let mut name = stem.clone();
+            let mut suffix = 1;
+            while !used.insert(name.clone()) {
+                name = format!("{}_{}", stem, suffix);
+                suffix += 1;
+            }
+            let package_file = filename
+                .strip_prefix("package://")
+                .and_then(|rest| rest.split_once('/'))
+                .and_then(|(package, path)| Some(package_paths.get(package)?.join(path)));
+            let file = match package_file {
+                Some(path) => path.display().to_string(),
+                None => filename
+                    .strip_prefix("file://")
+                    .unwrap_or(filename)
+                    .to_string(),
+            };
+            assets.entries.push((name.clone(), file, key.1.clone()));
+            assets.names.insert(key, name);
+        }
+        assets
+    }
+
+    fn name(&self, filename: &str, scale: &Option<na::Vector3<f64>>) -> &str {
+        &self.names[&(filename.to_string(), scale.as_ref().map(format_vector))]
+    }
+}
+
+fn pose_attributes(pose: &Pose) -> Vec<(&'static str, String)> {
+    let mut attributes = Vec::new();
+    if pose.xyz.iter().any(|v| *v != 0.0) {
+        attributes.push(("pos", format_vector(&pose.xyz)));
+    }
+    if pose.rpy.iter().any(|v| *v != 0.0) {
+        let quat = UnitQuaternion::from_euler_angles(pose.rpy.x, pose.rpy.y, pose.rpy.z);
+        attributes.push(("quat", format_floats(&[quat.w, quat.i, quat.j, quat.k])));
+    }
+    attributes
+}
+
+fn borrowed<'a>(attributes: &'a [(&'a str, String)]) -> Vec<(&'a str, &'a str)> {
+    attributes
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect()
+}
+
+pub fn parse_mjcf_from_string(xml: &str) -> Result<ImportedRobot> {
+    let doc = roxmltree::Document::parse(xml)?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "mujoco" {
+        bail!(
+            "expected a `<mujoco>` element, found `<{}>`",
+            root.tag_name().name()
+        );
+    }
+    let mut importer = MjcfImporter {
+        radians: false,
+        eulerseq: ['x', 'y', 'z'],
+        meshdir: None,
+        meshes: HashMap::new(),
+        materials: HashMap::new(),
+        robot: Robot {
+            name: root.attribute("model").unwrap_or("mujoco").to_string(),
+            ..Default::default()
+        },
+        notes: ImportNotes::default(),
+    };
+    for compiler in elements(root, "compiler") {
+        importer.read_compiler(compiler)?;
+    }
+    for asset in elements(root, "asset") {
+        importer.read_assets(asset)?;
+    }
+    if let Some(worldbody) = elements(root, "worldbody").next() {
+        importer.read_worldbody(worldbody)?;
+    }
+    for actuator in elements(root, "actuator") {
+        importer.read_actuators(actuator)?;
+    }
+    for child in root.children().filter(roxmltree::Node::is_element) {
+        match child.tag_name().name() {
+            "compiler" | "asset" | "worldbody" | "actuator" | "option" | "size" | "visual"
+            | "statistic" => {}
+            "default" => {
+                if child.children().any(|node| node.is_element()) {
+                    importer.notes.note(
+                        "`<default>` classes are not applied; attributes inherited from them are lost"
+                            .to_string(),
+                    );
+                }
+            }
+            other => importer.notes.note(format!(
+                "`<{}>` sections are not represented and were dropped",
+                other
+            )),
+        }
+    }
+    Ok(importer.notes.finish(importer.robot))
+}
+
+pub fn parse_mjcf_from_file<P: AsRef<Path>>(path: P) -> Result<ImportedRobot> {
+    parse_mjcf_from_string(&std::fs::read_to_string(path)?)
+}
+
+struct MjcfImporter {
+    radians: bool,
+    eulerseq: [char; 3],
+    meshdir: Option<String>,
+    meshes: HashMap<String, Geometry>,
+    materials: HashMap<String, Vector4<f64>>,
+    robot: Robot,
+    notes: ImportNotes,
+}
+
+type MjcfNode<'a, 'input> = roxmltree::Node<'a, 'input>;
+
+fn elements<'a, 'input: 'a>(
+    node: MjcfNode<'a, 'input>,
+    tag: &'a str,
+) -> impl Iterator<Item = MjcfNode<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == tag)
+}
+
+fn describe(node: MjcfNode, attribute: &str) -> String {
+    format!("`{}` of `<{}>`", attribute, node.tag_name().name())
+}
+
+fn read_numbers<const N: usize>(node: MjcfNode, attribute: &str) -> Result<Option<[f64; N]>> {
+    node.attribute(attribute)
+        .map(|text| parse_exact_floats::<N>(text, &describe(node, attribute)))
+        .transpose()
+}
+
+fn read_vector(node: MjcfNode, attribute: &str) -> Result<Option<Vector3<f64>>> {
+    Ok(read_numbers::<3>(node, attribute)?.map(Vector3::from))
+}
+
+impl MjcfImporter {
+    fn angle(&self, value: f64) -> f64 {
+        if self.radians {
+            value
+        } else {
+            value.to_radians()
+        }
+    }
+
+    fn read_compiler(&mut self, node: MjcfNode) -> Result<()> {
+        match node.attribute("angle") {
+            Some("radian") => self.radians = true,
+            Some("degree") => self.radians = false,
+            Some(other) => bail!("unknown compiler angle unit `{}`", other),
+            None => {}
+        }
+        if let Some(sequence) = node.attribute("eulerseq") {
+            let axes = sequence.chars().collect::<Vec<_>>();
+            match <[char; 3]>::try_from(axes.as_slice()) {
+                Ok(axes) if axes.iter().all(|axis| "xyzXYZ".contains(*axis)) => {
+                    self.eulerseq = axes
+                }
+                _ => bail!("invalid compiler eulerseq `{}`", sequence),
+            }
+        }
+        if let Some(dir) = node.attribute("meshdir").or(node.attribute("assetdir")) {
+            self.meshdir = Some(dir.to_string());
+        }
+        Ok(())
+    }
+
+    fn read_assets(&mut self, node: MjcfNode) -> Result<()> {
+        for child in node.children().filter(roxmltree::Node::is_element) {
+            match child.tag_name().name() {
+                "mesh" => {
+                    let Some(file) = child.attribute("file") else {
+                        self.notes
+                            .note("meshes defined by inline vertex data were dropped".to_string());
+                        continue;
+                    };
+                    let name = child
+                        .attribute("name")
+                        .map(String::from)
+                        .unwrap_or_else(|| {
+                            Path::new(file)
+                                .file_stem()
+                                .map(|stem| stem.to_string_lossy().into_owned())
+                                .unwrap_or_default()
+                        });
+                    let filename = match &self.meshdir {
+                        Some(dir) if Path::new(file).is_relative() => {
+                            Path::new(dir).join(file).to_string_lossy().into_owned()
+                        }
+                        _ => file.to_string(),
+                    };
+                    let scale = read_vector(child, "scale")?;
+                    self.meshes.insert(name, Geometry::Mesh { filename, scale });
+                }
+                "material" => {
+                    let name = child
+                        .attribute("name")
+                        .ok_or_else(|| anyhow!("`<material>` asset without a name"))?;
+                    let color =
+                        Vector4::from(read_numbers::<4>(child, "rgba")?.unwrap_or([1.0; 4]));
+                    self.materials.insert(name.to_string(), color);
+                    self.robot.materials.push(Material {
+                        name: Some(name.to_string()),
+                        color: Some(color),
+                    });
+                }
+                other => self
+                    .notes
+                    .note(format!("`<{}>` assets were dropped", other)),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_worldbody(&mut self, node: MjcfNode) -> Result<()> {
+        let mut world = Link {
+            name: WORLD_LINK.to_string(),
+            ..Default::default()
+        };
+        let bodies = self.read_contents(&mut world, node, &Isometry3::identity())?;
+        let has_joints = |body: &MjcfNode| {
+            body.children()
+                .any(|child| matches!(child.tag_name().name(), "joint" | "freejoint"))
+        };
+        // A single welded body needs no world link; it becomes the root as in a plain URDF.
+        match bodies.as_slice() {
+            [(body, _)]
+                if world.visuals.is_empty() && world.collisions.is_empty() && !has_joints(body) =>
+            {
+                self.read_body(*body, None, &Isometry3::identity())?
+            }
+            _ => {
+                self.robot.links.push(world);
+                for (body, offset) in bodies {
+                    self.read_body(body, Some(WORLD_LINK), &offset)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Reads geoms and inertials into `link`, where `offset` places the enclosing MJCF frame in
+    // the link frame. Returns the child bodies along with their offsets.
+    fn read_contents<'a, 'input>(
+        &mut self,
+        link: &mut Link,
+        node: MjcfNode<'a, 'input>,
+        offset: &Isometry3<f64>,
+    ) -> Result<Vec<(MjcfNode<'a, 'input>, Isometry3<f64>)>> {
+        let mut bodies = Vec::new();
+        for child in node.children().filter(roxmltree::Node::is_element) {
+            match child.tag_name().name() {
+                "body" => bodies.push((child, *offset)),
+                "geom" => self.read_geom(link, child, offset)?,
+                "inertial" => link.inertial = self.read_inertial(child, offset)?,
+                "frame" => {
+                    let frame = offset * self.read_frame(child)?;
+                    bodies.extend(self.read_contents(link, child, &frame)?);
+                }
+                "joint" | "freejoint" => {}
+                other => self.notes.note(format!(
+                    "`<{}>` elements are not represented and were dropped",
+                    other
+                )),
+            }
+        }
+        Ok(bodies)
+    }
+
+    fn read_body(
+        &mut self,
+        node: MjcfNode,
+        parent: Option<&str>,
+        offset: &Isometry3<f64>,
+    ) -> Result<()> {
+        let name = node
+            .attribute("name")
+            .map(String::from)
+            .unwrap_or_else(|| format!("body_{}", self.robot.links.len()));
+        let body_frame = offset * self.read_frame(node)?;
+        let joints = node
+            .children()
+            .filter(|child| matches!(child.tag_name().name(), "joint" | "freejoint"))
+            .collect::<Vec<_>>();
+
+        // URDF places a link frame at its joint, so the body contents are shifted by the
+        // position of the last joint. Extra joints get intermediate massless links.
+        let mut joint_position = Vector3::zeros();
+        if let Some(parent) = parent {
+            if joints.is_empty() {
+                self.robot.joints.push(Joint {
+                    name: format!("{}_joint", name),
+                    joint_type: "fixed".to_string(),
+                    origin: Pose::from_isometry(&body_frame),
+                    parent: parent.to_string(),
+                    child: name.clone(),
+                    axis: Vector3::x(),
+                    limit: JointLimit::default(),
+                    mimic: None,
+                });
+            }
+            let mut parent = parent.to_string();
+            let mut frame = body_frame;
+            for (index, joint) in joints.iter().enumerate() {
+                let joint_name = joint
+                    .attribute("name")
+                    .map(String::from)
+                    .unwrap_or_else(|| match joints.len() {
+                        1 => format!("{}_joint", name),
+                        _ => format!("{}_joint_{}", name, index),
+                    });
+                let child = if index + 1 == joints.len() {
+                    name.clone()
+                } else {
+                    format!("{}_{}", name, joint_name)
+                };
+                let position = read_vector(*joint, "pos")?.unwrap_or_else(Vector3::zeros);
+                let origin = frame * Translation3::from(position - joint_position);
+                let (joint_type, limit) =
+                    self.read_joint_type(*joint, &joint_name, parent == WORLD_LINK)?;
+                let axis = read_vector(*joint, "axis")?
+                    .unwrap_or_else(Vector3::z)
+                    .try_normalize(0.0)
+                    .unwrap_or_else(Vector3::z);
+                self.robot.joints.push(Joint {
+                    name: joint_name,
+                    joint_type,
+                    origin: Pose::from_isometry(&origin),
+                    parent,
+                    child: child.clone(),
+                    axis,
+                    limit,
+                    mimic: None,
+                });
+                if child != name {
+                    self.robot.links.push(Link {
+                        name: child.clone(),
+                        ..Default::default()
+                    });
+                }
+                parent = child;
+                frame = Isometry3::identity();
+                joint_position = position;
+            }
+        }
+
+        let mut link = Link {
+            name: name.clone(),
+            ..Default::default()
+        };
+        let contents_offset = Isometry3::from(Translation3::from(-joint_position));
+        let bodies = self.read_contents(&mut link, node, &contents_offset)?;
+        if link.inertial.mass == 0.0 && !link.collisions.is_empty() {
+            self.notes.note(
+                "bodies without `<inertial>` were imported without mass; MuJoCo would derive it from their geoms"
+                    .to_string(),
+            );
+        }
+        self.robot.links.push(link);
+        for (body, offset) in bodies {
+            self.read_body(body, Some(&name), &offset)?;
+        }
+        Ok(())
+    }
+
+    fn read_joint_type(
+        &mut self,
+        node: MjcfNode,
+        name: &str,
+        at_world: bool,
+    ) -> Result<(String, JointLimit)> {
+        let kind = match node.tag_name().name() {
+            "freejoint" => "free",
+            _ => node.attribute("type").unwrap_or("hinge"),
+        };
+        let range = match node.attribute("limited") {
+            Some("false") => None,
+            _ => read_numbers::<2>(node, "range")?,
+        };
+        let mut limit = JointLimit::default();
+        if let Some([lower, upper]) = read_numbers::<2>(node, "actuatorfrcrange")? {
+            limit.effort = lower.abs().max(upper.abs());
+        }
+        for attribute in [
+            "damping",
+            "stiffness",
+            "armature",
+            "frictionloss",
+            "springref",
+            "ref",
+        ] {
+            if node.attribute(attribute).is_some() {
+                self.notes
+                    .note(format!("joint `{}` attributes were dropped", attribute));
+            }
+        }
+        let joint_type = match (kind, range) {
+            ("hinge", Some([lower, upper])) => {
+                limit.lower = self.angle(lower);
+                limit.upper = self.angle(upper);
+                "revolute"
+            }
+            ("hinge", None) => "continuous",
+            ("slide", range) => {
+                match range {
+                    Some([lower, upper]) => {
+                        limit.lower = lower;
+                        limit.upper = upper;
+                    }
+                    None => self.notes.note(format!(
+                        "slide joint `{}` has no range and was imported with zero limits",
+                        name
+                    )),
+                }
+                "prismatic"
+            }
+            ("ball", _) => {
+                self.notes.note(format!(
+                    "ball joint `{}` cannot be represented and was imported as fixed",
+                    name
+                ));
+                "fixed"
+            }
+            ("free", _) if at_world => "floating",
+            ("free", _) => {
+                self.notes.note(format!(
+                    "free joint `{}` is not attached to the world and was imported as fixed",
+                    name
+                ));
+                "fixed"
+            }
+            (other, _) => bail!("joint `{}` has unknown type `{}`", name, other),
+        };
+        Ok((joint_type.to_string(), limit))
+    }
+
+    fn read_frame(&self, node: MjcfNode) -> Result<Isometry3<f64>> {
+        let position = read_vector(node, "pos")?.unwrap_or_else(Vector3::zeros);
+        Ok(Isometry3::from_parts(
+            Translation3::from(position),
+            self.read_orientation(node)?,
+        ))
+    }
+
+    fn read_orientation(&self, node: MjcfNode) -> Result<UnitQuaternion<f64>> {
+        if let Some([w, x, y, z]) = read_numbers::<4>(node, "quat")? {
+            return Ok(UnitQuaternion::from_quaternion(Quaternion::new(w, x, y, z)));
+        }
+        if let Some([x, y, z, angle]) = read_numbers::<4>(node, "axisangle")? {
+            return Ok(UnitQuaternion::from_axis_angle(
+                &Unit::new_normalize(Vector3::new(x, y, z)),
+                self.angle(angle),
+            ));
+        }
+        if let Some(angles) = read_numbers::<3>(node, "euler")? {
+            // Lowercase axes rotate with the frame, uppercase axes stay fixed.
+            let mut rotation = UnitQuaternion::identity();
+            for (axis, angle) in self.eulerseq.iter().zip(angles) {
+                let unit = match axis.to_ascii_lowercase() {
+                    'x' => Vector3::x_axis(),
+                    'y' => Vector3::y_axis(),
+                    _ => Vector3::z_axis(),
+                };
+                let step = UnitQuaternion::from_axis_angle(&unit, self.angle(angle));
+                rotation = if axis.is_ascii_lowercase() {
+                    rotation * step
+                } else {
+                    step * rotation
+                };
+            }
+            return Ok(rotation);
+        }
+        if let Some([x0, x1, x2, y0, y1, y2]) = read_numbers::<6>(node, "xyaxes")? {
+            let x = Vector3::new(x0, x1, x2).normalize();
+            let y = Vector3::new(y0, y1, y2);
+            let y = (y - x * x.dot(&y)).normalize();
+            let z = x.cross(&y);
+            return Ok(UnitQuaternion::from_rotation_matrix(
+                &Rotation3::from_basis_unchecked(&[x, y, z]),
+            ));
+        }
+        if let Some(z) = read_vector(node, "zaxis")? {
+            return Ok(rotation_from_z(&z));
+        }
+        Ok(UnitQuaternion::identity())
+    }
+
+    fn read_inertial(&self, node: MjcfNode, offset: &Isometry3<f64>) -> Result<Inertial> {
+        let frame = offset * self.read_frame(node)?;
+        let [mass] = read_numbers::<1>(node, "mass")?
+            .ok_or_else(|| anyhow!("`<inertial>` without a mass"))?;
+        let inertia =
+            if let Some([ixx, iyy, izz, ixy, ixz, iyz]) = read_numbers::<6>(node, "fullinertia")? {
+                Matrix3::new(ixx, ixy, ixz, ixy, iyy, iyz, ixz, iyz, izz)
+            } else if let Some(diagonal) = read_vector(node, "diaginertia")? {
+                Matrix3::from_diagonal(&diagonal)
+            } else {
+                bail!("`<inertial>` needs `diaginertia` or `fullinertia`");
+            };
+        Ok(Inertial {
+            origin: Pose::from_isometry(&frame),
+            mass,
+            inertia,
+        })
+    }
+
+    fn read_geom(
+        &mut self,
+        link: &mut Link,
+        node: MjcfNode,
+        offset: &Isometry3<f64>,
+    ) -> Result<()> {
+        let kind = node.attribute("type").unwrap_or("sphere");
+        let size = node
+            .attribute("size")
+            .map(|text| parse_floats(text, &describe(node, "size")))
+            .transpose()?
+            .unwrap_or_default();
+        let size_at = |index: usize| size.get(index).copied().unwrap_or(0.0);
+        let mut frame = self.read_frame(node)?;
+        let mut fromto_length = None;
+        if let Some([x0, y0, z0, x1, y1, z1]) = read_numbers::<6>(node, "fromto")? {
+            let (from, to) = (Vector3::new(x0, y0, z0), Vector3::new(x1, y1, z1));
+            frame = Isometry3::from_parts(
+                Translation3::from((from + to) / 2.0),
+                rotation_from_z(&(to - from)),
+            );
+            fromto_length = Some((to - from).norm());
+        }
+        let geometry = match kind {
+            "sphere" => Geometry::Sphere { radius: size_at(0) },
+            "box" => Geometry::Box {
+                size: Vector3::new(size_at(0), size_at(1), size_at(2)) * 2.0,
+            },
+            "cylinder" | "capsule" => {
+                if kind == "capsule" {
+                    self.notes.note(
+                        "`capsule` geoms were approximated as cylinders without the end caps"
+                            .to_string(),
+                    );
+                }
+                Geometry::Cylinder {
+                    radius: size_at(0),
+                    length: fromto_length.unwrap_or(size_at(1) * 2.0),
+                }
+            }
+            "ellipsoid" => {
+                self.notes.note(
+                    "`ellipsoid` geoms were approximated as spheres of the largest radius"
+                        .to_string(),
+                );
+                Geometry::Sphere {
+                    radius: size.iter().copied().fold(0.0, f64::max),
+                }
+            }
+            "mesh" => {
+                let mesh = node
+                    .attribute("mesh")
+                    .ok_or_else(|| anyhow!("mesh `<geom>` without a `mesh` attribute"))?;
+                self.meshes
+                    .get(mesh)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("unknown mesh asset `{}`", mesh))?
+            }
+            other => {
+                self.notes.note(format!(
+                    "`{}` geoms have no URDF equivalent and were dropped",
+                    other
+                ));
+                return Ok(());
+            }
+        };
+
+        let origin = Pose::from_isometry(&(offset * frame));
+        let material = if let Some(rgba) = read_numbers::<4>(node, "rgba")? {
+            Some(Material {
+                name: None,
+                color: Some(Vector4::from(rgba)),
+            })
+        } else {
+            node.attribute("material").map(|name| Material {
+                name: Some(name.to_string()),
+                color: self.materials.get(name).copied(),
+            })
+        };
+        let name = node.attribute("name").map(String::from);
+        // Every geom is drawn; only those taking part in contacts are collisions.
+        let contype = node.attribute("contype").unwrap_or("1").trim();
+        let conaffinity = node.attribute("conaffinity").unwrap_or("1").trim();
+        if contype != "0" || conaffinity != "0" {
+            link.collisions.push(Collision {
+                name: name.clone(),
+                origin: origin.clone(),
+                geometry: geometry.clone(),
+            });
+        }
+        link.visuals.push(Visual {
+            name,
+            origin,
+            geometry,
+            material,
+        });
+        Ok(())
+    }
+
+    fn read_actuators(&mut self, node: MjcfNode) -> Result<()> {
+        for actuator in node.children().filter(roxmltree::Node::is_element) {
+            let Some(joint_name) = actuator.attribute("joint") else {
+                self.notes.note(format!(
+                    "`<{}>` actuators without a joint transmission were dropped",
+                    actuator.tag_name().name()
+                ));
+                continue;
+            };
+            let effort = if let Some([lower, upper]) = read_numbers::<2>(actuator, "forcerange")? {
+                Some(lower.abs().max(upper.abs()))
+            } else if actuator.tag_name().name() == "motor" {
+                let gear = actuator
+                    .attribute("gear")
+                    .map(|text| parse_floats(text, &describe(actuator, "gear")))
+                    .transpose()?
+                    .and_then(|gear| gear.first().copied())
+                    .unwrap_or(1.0);
+                read_numbers::<2>(actuator, "ctrlrange")?
+                    .map(|[lower, upper]| lower.abs().max(upper.abs()) * gear.abs())
+            } else {
+                None
+            };
+            if let Some(effort) = effort {
+                if let Some(joint) = self
+                    .robot
+                    .joints
+                    .iter_mut()
+                    .find(|joint| joint.name == joint_name && joint.limit.effort == 0.0)
+                {
+                    joint.limit.effort = effort;
+                }
+            }
+            self.notes
+                .note("actuators were reduced to the effort limits of their joints".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_urdf_from_string;
+
+    const URDF: &str = r#"
+        <robot name="arm">
+          <link name="world"/>
+          <link name="base">
+            <inertial>
+              <origin xyz="0 0 0.1" rpy="0 0 1.5707963267948966"/>
+              <mass value="2"/>
+              <inertia ixx="0.1" ixy="0.01" ixz="0" iyy="0.2" iyz="0" izz="0.3"/>
+            </inertial>
+            <visual>
+              <geometry><mesh filename="package://arm/meshes/base.stl"/></geometry>
+              <material name="red"><color rgba="1 0 0 1"/></material>
+            </visual>
+            <collision name="base_box">
+              <origin xyz="0 0 0.1"/>
+              <geometry><box size="0.2 0.4 0.2"/></geometry>
+            </collision>
+          </link>
+          <link name="upper">
+            <inertial>
+              <mass value="1"/>
+              <inertia ixx="0.01" ixy="0" ixz="0" iyy="0.01" iyz="0" izz="0.01"/>
+            </inertial>
+            <collision><geometry><cylinder radius="0.05" length="0.5"/></geometry></collision>
+          </link>
+          <link name="slider">
+            <visual><geometry><sphere radius="0.05"/></geometry></visual>
+          </link>
+          <joint name="anchor" type="fixed">
+            <parent link="world"/>
+            <child link="base"/>
+          </joint>
+          <joint name="shoulder" type="revolute">
+            <parent link="base"/>
+            <child link="upper"/>
+            <origin xyz="0 0 0.2"/>
+            <axis xyz="0 1 0"/>
+            <limit lower="-1" upper="1" effort="10" velocity="2"/>
+          </joint>
+          <joint name="extend" type="prismatic">
+            <parent link="upper"/>
+            <child link="slider"/>
+            <origin xyz="0 0 0.5"/>
+            <axis xyz="0 0 1"/>
+            <limit lower="0" upper="0.3" effort="50" velocity="0.5"/>
+          </joint>
+        </robot>
+    "#;
+
+    #[test]
+    fn writes_nested_bodies_assets_and_actuators() {
+        let robot = parse_urdf_from_string(URDF).unwrap();
+
+        let mjcf = write_mjcf_to_string(&robot).unwrap();
+
+        assert_eq!(
+            mjcf,
+            r#"<?xml version="1.0"?>
+<mujoco model="arm">
+  <compiler angle="radian" autolimits="true"/>
+  <asset>
+    <mesh name="base" file="package://arm/meshes/base.stl"/>
+  </asset>
+  <worldbody>
+    <body name="base">
+      <inertial pos="0 0 0.1" quat="0.7071067811865476 0 0 0.7071067811865475" mass="2" fullinertia="0.1 0.2 0.3 0.01 0 0"/>
+      <geom name="base_box" type="box" size="0.1 0.2 0.1" pos="0 0 0.1" group="3"/>
+      <geom type="mesh" mesh="base" rgba="1 0 0 1" contype="0" conaffinity="0" group="1"/>
+      <body name="upper" pos="0 0 0.2">
+        <joint name="shoulder" type="hinge" axis="0 1 0" range="-1 1"/>
+        <inertial mass="1" fullinertia="0.01 0.01 0.01 0 0 0"/>
+        <geom type="cylinder" size="0.05 0.25" group="3"/>
+        <body name="slider" pos="0 0 0.5">
+          <joint name="extend" type="slide" axis="0 0 1" range="0 0.3"/>
+          <geom type="sphere" size="0.05" contype="0" conaffinity="0" group="1"/>
+        </body>
+      </body>
+    </body>
+  </worldbody>
+  <actuator>
+    <motor name="shoulder" joint="shoulder" ctrlrange="-10 10"/>
+    <motor name="extend" joint="extend" ctrlrange="-50 50"/>
+  </actuator>
+</mujoco>
+"#
+        );
+    }
+
+    #[test]
+    fn writes_selected_actuator_kind() {
+        let robot = parse_urdf_from_string(URDF).unwrap();
+
+        let position = write_mjcf_to_string_with_options(
+            &robot,
+            MjcfOptions::default().with_actuator(MjcfActuator::Position { kp: 100.0 }),
+        )
+        .unwrap();
+        let velocity = write_mjcf_to_string_with_options(
+            &robot,
+            MjcfOptions::default().with_actuator(MjcfActuator::Velocity { kv: 5.0 }),
+        )
+        .unwrap();
+        let none = write_mjcf_to_string_with_options(
+            &robot,
+            MjcfOptions::default().with_actuator(MjcfActuator::None),
+        )
+        .unwrap();
+
+        assert!(position.contains(
+            r#"<position name="shoulder" joint="shoulder" kp="100" ctrlrange="-1 1" forcerange="-10 10"/>"#
+        ));
+        assert!(velocity.contains(
+            r#"<velocity name="extend" joint="extend" kv="5" ctrlrange="-0.5 0.5" forcerange="-50 50"/>"#
+        ));
+        assert!(!none.contains("<actuator>"));
+    }
+
+    #[test]
+    fn couples_mimic_joints_instead_of_actuating_them() {
+        let mut robot = parse_urdf_from_string(URDF).unwrap();
+        robot.joints[2].mimic = Some(Mimic {
+            joint: "shoulder".to_string(),
+            multiplier: 0.5,
+            offset: 0.1,
+        });
+
+        let mjcf = write_mjcf_to_string(&robot).unwrap();
+
+        assert!(mjcf.contains(
+            r#"  <equality>
+    <joint joint1="extend" joint2="shoulder" polycoef="0.1 0.5 0 0 0"/>
+  </equality>
+  <actuator>
+    <motor name="shoulder" joint="shoulder" ctrlrange="-10 10"/>
+  </actuator>"#
+        ));
+
+        robot.joints[2].mimic.as_mut().unwrap().joint = "anchor".to_string();
+        let err = write_mjcf_to_string(&robot).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "joint `extend` mimics `anchor`, which MuJoCo does not represent as a joint"
+        );
+    }
+
+    #[test]
+    fn resolves_package_mesh_uris() {
+        let robot = parse_urdf_from_string(URDF).unwrap();
+
+        let mjcf = write_mjcf_to_string_with_options(
+            &robot,
+            MjcfOptions::default().with_package_path("arm", "/opt/arm"),
+        )
+        .unwrap();
+
+        assert!(mjcf.contains(r#"<mesh name="base" file="/opt/arm/meshes/base.stl"/>"#));
+    }
+
+    #[test]
+    fn rejects_nested_floating_joints() {
+        let mut robot = parse_urdf_from_string(URDF).unwrap();
+        robot.joints[1].joint_type = "floating".to_string();
+
+        let err = write_mjcf_to_string(&robot).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "joint `shoulder` is floating, but MuJoCo only allows free joints on bodies attached to the world"
+        );
+    }
+
+    fn assert_pose(pose: &Pose, xyz: [f64; 3], rpy: [f64; 3]) {
+        let expected = Pose {
+            xyz: Vector3::from(xyz),
+            rpy: Vector3::from(rpy),
+        };
+        assert!(
+            (pose.to_isometry().to_homogeneous() - expected.to_isometry().to_homogeneous()).norm()
+                < 1e-9,
+            "{:?} != {:?}",
+            pose,
+            expected
+        );
+    }
+
+    #[test]
+    fn imports_exported_model() {
+        let robot = parse_urdf_from_string(URDF).unwrap();
+        let mjcf = write_mjcf_to_string(&robot).unwrap();
+
+        let imported = parse_mjcf_from_string(&mjcf).unwrap();
+
+        let robot = imported.robot;
+        assert_eq!(
+            robot
+                .links
+                .iter()
+                .map(|link| link.name.as_str())
+                .collect::<Vec<_>>(),
+            ["base", "upper", "slider"]
+        );
+        let shoulder = robot.joint("shoulder").unwrap();
+        assert_eq!(shoulder.joint_type, "revolute");
+        assert_eq!(
+            (shoulder.parent.as_str(), shoulder.child.as_str()),
+            ("base", "upper")
+        );
+        assert_eq!((shoulder.limit.lower, shoulder.limit.upper), (-1.0, 1.0));
+        assert_eq!(shoulder.limit.effort, 10.0);
+        assert_pose(&shoulder.origin, [0.0, 0.0, 0.2], [0.0, 0.0, 0.0]);
+        assert_eq!(robot.joint("extend").unwrap().joint_type, "prismatic");
+        let base = robot.link("base").unwrap();
+        assert_pose(
+            &base.inertial.origin,
+            [0.0, 0.0, 0.1],
+            [0.0, 0.0, std::f64::consts::FRAC_PI_2],
+        );
+        assert_eq!(base.inertial.inertia[(0, 1)], 0.01);
+        assert!(matches!(
+            &base.visuals[1].geometry,
+            Geometry::Mesh { filename, .. } if filename == "package://arm/meshes/base.stl"
+        ));
+        assert!(matches!(
+            base.collisions[0].geometry,
+            Geometry::Box { size } if size == Vector3::new(0.2, 0.4, 0.2)
+        ));
+        // Visual-only geoms are not collisions, but colliding geoms are also drawn.
+        assert_eq!((base.visuals.len(), base.collisions.len()), (2, 1));
+        assert_eq!(
+            imported.warnings,
+            ["actuators were reduced to the effort limits of their joints (2 times)"]
+        );
+    }
+
+    #[test]
+    fn converts_orientation_conventions() {
+        let mjcf = r#"
+            <mujoco model="frames">
+              <compiler eulerseq="XYZ"/>
+              <worldbody>
+                <body name="base">
+                  <body name="quat" quat="0.7071067811865476 0 0 0.7071067811865476"/>
+                  <body name="axisangle" axisangle="0 0 1 90"/>
+                  <body name="euler" euler="0 0 90"/>
+                  <body name="xyaxes" xyaxes="0 1 0 -1 0 0"/>
+                  <body name="zaxis" zaxis="1 0 0"/>
+                </body>
+              </worldbody>
+            </mujoco>
+        "#;
+
+        let robot = parse_mjcf_from_string(mjcf).unwrap().robot;
+
+        let half_pi = std::f64::consts::FRAC_PI_2;
+        for name in ["quat", "axisangle", "euler", "xyaxes"] {
+            assert_pose(
+                &robot.parent_joint(name).unwrap().origin,
+                [0.0; 3],
+                [0.0, 0.0, half_pi],
+            );
+        }
+        assert_pose(
+            &robot.parent_joint("zaxis").unwrap().origin,
+            [0.0; 3],
+            [0.0, half_pi, 0.0],
+        );
+    }
+
+    #[test]
+    fn moves_link_frames_to_joint_positions_and_warns_about_losses() {
+        let mjcf = r#"
+            <mujoco model="pendulum">
+              <compiler angle="radian"/>
+              <worldbody>
+                <geom type="plane" size="1 1 0.1"/>
+                <body name="arm" pos="0 0 1">
+                  <joint name="pivot" type="hinge" pos="0 0 0.5" axis="1 0 0" damping="0.1"/>
+                  <geom type="capsule" fromto="0 0 0.5 0 0 0" size="0.05"/>
+                  <site name="tip" pos="0 0 0"/>
+                  <body name="bob">
+                    <joint name="swivel" type="ball"/>
+                    <geom type="sphere" size="0.1" contype="0" conaffinity="0"/>
+                  </body>
+                </body>
+              </worldbody>
+              <equality/>
+            </mujoco>
+        "#;
+
+        let imported = parse_mjcf_from_string(mjcf).unwrap();
+
+        let robot = &imported.robot;
+        let pivot = robot.joint("pivot").unwrap();
+        assert_eq!(
+            (pivot.parent.as_str(), pivot.joint_type.as_str()),
+            ("world", "continuous")
+        );
+        assert_pose(&pivot.origin, [0.0, 0.0, 1.5], [0.0; 3]);
+        let arm = robot.link("arm").unwrap();
+        assert_pose(
+            &arm.visuals[0].origin,
+            [0.0, 0.0, -0.25],
+            [std::f64::consts::PI, 0.0, 0.0],
+        );
+        assert!(
+            matches!(arm.visuals[0].geometry, Geometry::Cylinder { length, .. } if length == 0.5)
+        );
+        assert_pose(
+            &robot.joint("swivel").unwrap().origin,
+            [0.0, 0.0, -0.5],
+            [0.0; 3],
+        );
+        assert!(robot.link("bob").unwrap().collisions.is_empty());
+        assert_eq!(
+            imported.warnings,
+            [
+                "`plane` geoms have no URDF equivalent and were dropped",
+                "joint `damping` attributes were dropped",
+                "`capsule` geoms were approximated as cylinders without the end caps",
+                "`<site>` elements are not represented and were dropped",
+                "bodies without `<inertial>` were imported without mass; MuJoCo would derive it from their geoms",
+                "ball joint `swivel` cannot be represented and was imported as fixed",
+                "`<equality>` sections are not represented and were dropped",
+            ]
+        );
+    }
+}
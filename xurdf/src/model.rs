@@ -1,13 +1,37 @@
 extern crate nalgebra as na;
 
-use na::{Matrix3, Vector3, Vector4};
+use na::{Isometry3, Matrix3, Translation3, UnitQuaternion, Vector3, Vector4};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Pose {
+    #[cfg_attr(feature = "serde", serde(with = "serde_format::vector3"))]
     pub xyz: Vector3<f64>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_format::vector3"))]
     pub rpy: Vector3<f64>,
 }
 
+impl Pose {
+    /// `rpy` are fixed-axis rotations about x, then y, then z, as in URDF.
+    pub fn to_isometry(&self) -> Isometry3<f64> {
+        Isometry3::from_parts(
+            Translation3::from(self.xyz),
+            UnitQuaternion::from_euler_angles(self.rpy.x, self.rpy.y, self.rpy.z),
+        )
+    }
+
+    pub fn from_isometry(isometry: &Isometry3<f64>) -> Pose {
+        let (roll, pitch, yaw) = isometry.rotation.euler_angles();
+        Pose {
+            xyz: isometry.translation.vector,
+            rpy: Vector3::new(roll, pitch, yaw),
+        }
+    }
+}
+
 impl Default for Pose {
     fn default() -> Pose {
         Pose {
@@ -17,16 +41,22 @@ impl Default for Pose {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Inertial {
     pub origin: Pose,
     pub mass: f64,
+    #[cfg_attr(feature = "serde", serde(with = "serde_format::inertia"))]
     pub inertia: Matrix3<f64>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
 pub enum Geometry {
     Box {
+        #[cfg_attr(feature = "serde", serde(with = "serde_format::vector3"))]
         size: Vector3<f64>,
     },
     Cylinder {
@@ -38,6 +68,10 @@ pub enum Geometry {
     },
     Mesh {
         filename: String,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, with = "serde_format::option_vector3")
+        )]
         scale: Option<Vector3<f64>>,
     },
 }
@@ -49,12 +83,17 @@ impl Default for Geometry {
 }
 
 #[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Material {
     pub name: Option<String>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_format::option_vector4"))]
     pub color: Option<Vector4<f64>>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Visual {
     pub name: Option<String>,
     pub origin: Pose,
@@ -62,14 +101,18 @@ pub struct Visual {
     pub material: Option<Material>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Collision {
     pub name: Option<String>,
     pub origin: Pose,
     pub geometry: Geometry,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Link {
     pub name: String,
     pub inertial: Inertial,
@@ -77,7 +120,9 @@ pub struct Link {
     pub collisions: Vec<Collision>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct JointLimit {
     pub lower: f64,
     pub upper: f64,
@@ -85,21 +130,368 @@ pub struct JointLimit {
     pub velocity: f64,
 }
 
-#[derive(Debug, Default)]
+/// Makes a joint follow `joint` at `multiplier * position + offset`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Mimic {
+    pub joint: String,
+    pub multiplier: f64,
+    pub offset: f64,
+}
+
+impl Default for Mimic {
+    fn default() -> Mimic {
+        Mimic {
+            joint: String::new(),
+            multiplier: 1.0,
+            offset: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Joint {
     pub name: String,
     pub joint_type: String,
     pub origin: Pose,
     pub parent: String,
     pub child: String,
+    #[cfg_attr(feature = "serde", serde(with = "serde_format::vector3"))]
     pub axis: Vector3<f64>,
     pub limit: JointLimit,
+    pub mimic: Option<Mimic>,
+}
+
+impl Joint {
+    /// The pose of the child link in the parent link frame with the joint at `position`,
+    /// in radians or meters. Fixed, floating and planar joints stay at their origin.
+    pub fn transform(&self, position: f64) -> Isometry3<f64> {
+        let motion = match self.joint_type.as_str() {
+            "revolute" | "continuous" => match na::Unit::try_new(self.axis, 0.0) {
+                Some(axis) => Isometry3::from_parts(
+                    Translation3::identity(),
+                    UnitQuaternion::from_axis_angle(&axis, position),
+                ),
+                None => Isometry3::identity(),
+            },
+            "prismatic" => Isometry3::translation(
+                self.axis.x * position,
+                self.axis.y * position,
+                self.axis.z * position,
+            ),
+            _ => Isometry3::identity(),
+        };
+        self.origin.to_isometry() * motion
+    }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct TransmissionJoint {
+    pub name: String,
+    pub hardware_interfaces: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Actuator {
+    pub name: String,
+    pub mechanical_reduction: Option<f64>,
+    pub hardware_interfaces: Vec<String>,
+}
+
+/// A `<transmission>` linking actuators to joints, as used by `ros_control`.
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Transmission {
+    pub name: String,
+    pub transmission_type: String,
+    pub joints: Vec<TransmissionJoint>,
+    pub actuators: Vec<Actuator>,
+}
+
+/// A `<gazebo>` extension block. `reference` names the link or joint it applies to, or is `None`
+/// for the whole model, and `xml` holds the content of the block as written.
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Extension {
+    pub reference: Option<String>,
+    pub xml: String,
+}
+
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Robot {
     pub name: String,
     pub materials: Vec<Material>,
     pub links: Vec<Link>,
     pub joints: Vec<Joint>,
+    pub transmissions: Vec<Transmission>,
+    pub extensions: Vec<Extension>,
+}
+
+impl Robot {
+    pub fn link(&self, name: &str) -> Option<&Link> {
+        self.links.iter().find(|link| link.name == name)
+    }
+
+    pub fn joint(&self, name: &str) -> Option<&Joint> {
+        self.joints.iter().find(|joint| joint.name == name)
+    }
+
+    /// The joint whose child is `link`, if any.
+    pub fn parent_joint(&self, link: &str) -> Option<&Joint> {
+        self.joints.iter().find(|joint| joint.child == link)
+    }
+
+    pub fn child_joints<'a>(&'a self, link: &'a str) -> impl Iterator<Item = &'a Joint> + 'a {
+        self.joints.iter().filter(move |joint| joint.parent == link)
+    }
+
+    /// Links that are not the child of any joint; a valid URDF has exactly one.
+    pub fn root_links(&self) -> impl Iterator<Item = &Link> {
+        self.links
+            .iter()
+            .filter(|link| self.parent_joint(&link.name).is_none())
+    }
+}
+
+/// A `Robot` converted from another format. `warnings` describe everything the conversion had
+/// to approximate or drop because the model cannot represent it.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ImportedRobot {
+    pub robot: Robot,
+    pub warnings: Vec<String>,
+}
+
+// The JSON shape of nalgebra fields is spelled out here rather than inherited from nalgebra, so
+// the schema stays the same across nalgebra versions: vectors are arrays and the symmetric
+// inertia tensor is its six URDF components.
+#[cfg(feature = "serde")]
+mod serde_format {
+    use super::na::{Matrix3, Vector3, Vector4};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(super) mod vector3 {
+        use super::*;
+
+        pub(crate) fn serialize<S: Serializer>(
+            value: &Vector3<f64>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            <[f64; 3]>::from(*value).serialize(serializer)
+        }
+
+        pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vector3<f64>, D::Error> {
+            <[f64; 3]>::deserialize(deserializer).map(Vector3::from)
+        }
+    }
+
+    pub(super) mod option_vector3 {
+        use super::*;
+
+        pub(crate) fn serialize<S: Serializer>(
+            value: &Option<Vector3<f64>>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            value.map(<[f64; 3]>::from).serialize(serializer)
+        }
+
+        pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Vector3<f64>>, D::Error> {
+            Ok(Option::<[f64; 3]>::deserialize(deserializer)?.map(Vector3::from))
+        }
+    }
+
+    pub(super) mod option_vector4 {
+        use super::*;
+
+        pub(crate) fn serialize<S: Serializer>(
+            value: &Option<Vector4<f64>>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            value.map(<[f64; 4]>::from).serialize(serializer)
+        }
+
+        pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Vector4<f64>>, D::Error> {
+            Ok(Option::<[f64; 4]>::deserialize(deserializer)?.map(Vector4::from))
+        }
+    }
+
+    pub(super) mod inertia {
+        use super::*;
+
+        #[derive(Serialize, Deserialize)]
+        struct Inertia {
+            ixx: f64,
+            ixy: f64,
+            ixz: f64,
+            iyy: f64,
+            iyz: f64,
+            izz: f64,
+        }
+
+        pub(crate) fn serialize<S: Serializer>(
+            value: &Matrix3<f64>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            Inertia {
+                ixx: value[(0, 0)],
+                ixy: value[(0, 1)],
+                ixz: value[(0, 2)],
+                iyy: value[(1, 1)],
+                iyz: value[(1, 2)],
+                izz: value[(2, 2)],
+            }
+            .serialize(serializer)
+        }
+
+        pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Matrix3<f64>, D::Error> {
+            let i = Inertia::deserialize(deserializer)?;
+            Ok(Matrix3::new(
+                i.ixx, i.ixy, i.ixz, i.ixy, i.iyy, i.iyz, i.ixz, i.iyz, i.izz,
+            ))
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::parse_urdf_from_string;
+
+    #[test]
+    fn serializes_to_documented_json() {
+        let robot = parse_urdf_from_string(
+            r#"
+            <robot name="bot">
+              <link name="base">
+                <inertial>
+                  <mass value="2"/>
+                  <inertia ixx="1" ixy="0.1" ixz="0" iyy="2" iyz="0" izz="3"/>
+                </inertial>
+                <visual>
+                  <geometry><mesh filename="base.stl"/></geometry>
+                  <material name="red"><color rgba="1 0 0 1"/></material>
+                </visual>
+              </link>
+              <link name="arm"/>
+              <joint name="shoulder" type="revolute">
+                <parent link="base"/>
+                <child link="arm"/>
+                <origin xyz="0 0 0.5"/>
+                <axis xyz="0 0 1"/>
+                <limit lower="-1" upper="1" effort="5" velocity="2"/>
+              </joint>
+              <transmission name="shoulder_trans">
+                <type>transmission_interface/SimpleTransmission</type>
+                <joint name="shoulder"><hardwareInterface>EffortJointInterface</hardwareInterface></joint>
+                <actuator name="shoulder_motor"><mechanicalReduction>50</mechanicalReduction></actuator>
+              </transmission>
+              <gazebo reference="arm"><mu1>0.5</mu1></gazebo>
+            </robot>
+            "#,
+        )
+        .unwrap();
+
+        let json = serde_json::to_value(&robot).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "name": "bot",
+                "materials": [],
+                "links": [
+                    {
+                        "name": "base",
+                        "inertial": {
+                            "origin": {"xyz": [0.0, 0.0, 0.0], "rpy": [0.0, 0.0, 0.0]},
+                            "mass": 2.0,
+                            "inertia": {
+                                "ixx": 1.0, "ixy": 0.1, "ixz": 0.0,
+                                "iyy": 2.0, "iyz": 0.0, "izz": 3.0
+                            }
+                        },
+                        "visuals": [{
+                            "name": null,
+                            "origin": {"xyz": [0.0, 0.0, 0.0], "rpy": [0.0, 0.0, 0.0]},
+                            "geometry": {"type": "mesh", "filename": "base.stl", "scale": null},
+                            "material": {"name": "red", "color": [1.0, 0.0, 0.0, 1.0]}
+                        }],
+                        "collisions": []
+                    },
+                    {
+                        "name": "arm",
+                        "inertial": {
+                            "origin": {"xyz": [0.0, 0.0, 0.0], "rpy": [0.0, 0.0, 0.0]},
+                            "mass": 0.0,
+                            "inertia": {
+                                "ixx": 0.0, "ixy": 0.0, "ixz": 0.0,
+                                "iyy": 0.0, "iyz": 0.0, "izz": 0.0
+                            }
+                        },
+                        "visuals": [],
+                        "collisions": []
+                    }
+                ],
+                "joints": [{
+                    "name": "shoulder",
+                    "joint_type": "revolute",
+                    "origin": {"xyz": [0.0, 0.0, 0.5], "rpy": [0.0, 0.0, 0.0]},
+                    "parent": "base",
+                    "child": "arm",
+                    "axis": [0.0, 0.0, 1.0],
+                    "limit": {"lower": -1.0, "upper": 1.0, "effort": 5.0, "velocity": 2.0},
+                    "mimic": null
+                }],
+                "transmissions": [{
+                    "name": "shoulder_trans",
+                    "transmission_type": "transmission_interface/SimpleTransmission",
+                    "joints": [{"name": "shoulder", "hardware_interfaces": ["EffortJointInterface"]}],
+                    "actuators": [{
+                        "name": "shoulder_motor",
+                        "mechanical_reduction": 50.0,
+                        "hardware_interfaces": []
+                    }]
+                }],
+                "extensions": [{"reference": "arm", "xml": "<mu1>0.5</mu1>"}]
+            })
+        );
+        let restored: Robot = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&restored).unwrap(), json);
+    }
+
+    #[test]
+    fn fills_omitted_fields_with_defaults() {
+        let robot: Robot = serde_json::from_str(
+            r#"{"name": "bot", "links": [{"name": "base", "visuals": [
+                {"geometry": {"type": "box", "size": [1, 2, 3]}}
+            ]}]}"#,
+        )
+        .unwrap();
+
+        assert!(robot.joints.is_empty());
+        let visual = &robot.links[0].visuals[0];
+        assert_eq!(visual.origin, Pose::default());
+        assert!(
+            matches!(visual.geometry, Geometry::Box { size } if size == Vector3::new(1.0, 2.0, 3.0))
+        );
+    }
 }
@@ -0,0 +1,3412 @@
+// Evaluates the Python expression subset used in xacro `${...}` blocks. Values keep Python's
+// int/float distinction, so `7 / 2` is `3.5`, `7 // 2` is `3` and `max(n, 2.0)` is `n` itself,
+// as upstream xacro prints them.
+
+use indexmap::IndexMap;
+use once_cell::sync::Lazy;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// Bounds on sequence sizes and nesting, so a short expression can exhaust neither memory nor
+// the stack.
+const MAX_LENGTH: usize = 1 << 24;
+const MAX_PARSE_DEPTH: usize = 64;
+const MAX_EVAL_DEPTH: usize = 128;
+
+#[derive(Clone, Debug)]
+pub(crate) enum Value {
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    List(Vec<Value>),
+    Tuple(Vec<Value>),
+    Dict(IndexMap<String, Value>),
+    Builtin(Builtin),
+    Lambda(Rc<Lambda>),
+    Method(Box<Value>, String),
+}
+
+pub(crate) type BuiltinFn = fn(&[Value]) -> Result<Value, EvalError>;
+pub(crate) type InterpreterFn =
+    fn(&mut Interpreter<'_>, &[Value], &[(String, Value)]) -> Result<Value, EvalError>;
+
+#[derive(Clone, Copy)]
+pub(crate) enum Builtin {
+    // Takes positional arguments only.
+    Function(&'static str, BuiltinFn),
+    // Takes keyword arguments or calls back into the interpreter, like `sorted(xs, key=f)`.
+    Interpreter(&'static str, InterpreterFn),
+}
+
+impl Builtin {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Function(name, _) | Self::Interpreter(name, _) => name,
+        }
+    }
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<built-in function {}>", self.name())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Lambda {
+    def: Rc<LambdaDef>,
+    captured: HashMap<String, Value>,
+}
+
+#[derive(Debug)]
+struct LambdaDef {
+    params: Vec<String>,
+    body: Expr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum EvalError {
+    Syntax(String),
+    UndefinedName(String),
+    Type(String),
+    Value(String),
+    ZeroDivision(String),
+    Index(String),
+    Key(String),
+    Attribute(String),
+    Overflow(String),
+    Memory,
+    Recursion,
+    /// The step callback stopped the evaluation.
+    Interrupted,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax(message) => write!(f, "invalid syntax: {}", message),
+            Self::UndefinedName(name) => write!(f, "NameError: name '{}' is not defined", name),
+            Self::Type(message) => write!(f, "TypeError: {}", message),
+            Self::Value(message) => write!(f, "ValueError: {}", message),
+            Self::ZeroDivision(message) => write!(f, "ZeroDivisionError: {}", message),
+            Self::Index(message) => write!(f, "IndexError: {}", message),
+            Self::Key(key) => write!(f, "KeyError: {}", key),
+            Self::Attribute(message) => write!(f, "AttributeError: {}", message),
+            Self::Overflow(message) => write!(f, "OverflowError: {}", message),
+            Self::Memory => write!(f, "MemoryError: result is too large"),
+            Self::Recursion => write!(f, "RecursionError: maximum recursion depth exceeded"),
+            Self::Interrupted => write!(f, "evaluation interrupted"),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+type EvalResult<T> = Result<T, EvalError>;
+
+// Evaluates `source`, looking names up in `names` before the Python builtins.
+pub(crate) fn eval(source: &str, names: &dyn Fn(&str) -> Option<Value>) -> EvalResult<Value> {
+    eval_with_steps(source, names, &|| Ok(()))
+}
+
+// Like `eval`, but calls `step` before evaluating each sub-expression, so comprehensions and
+// lambdas can be charged against a budget.
+pub(crate) fn eval_with_steps(
+    source: &str,
+    names: &dyn Fn(&str) -> Option<Value>,
+    step: &dyn Fn() -> EvalResult<()>,
+) -> EvalResult<Value> {
+    let expr = Parser::new(source)?.parse()?;
+    Interpreter {
+        names,
+        step,
+        scopes: Vec::new(),
+        depth: 0,
+    }
+    .eval(&expr)
+}
+
+impl Value {
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Self::None => "NoneType",
+            Self::Bool(_) => "bool",
+            Self::Int(_) => "int",
+            Self::Float(_) => "float",
+            Self::Str(_) => "str",
+            Self::List(_) => "list",
+            Self::Tuple(_) => "tuple",
+            Self::Dict(_) => "dict",
+            Self::Builtin(_) => "builtin_function_or_method",
+            Self::Lambda(_) => "function",
+            Self::Method(..) => "builtin_function_or_method",
+        }
+    }
+
+    pub(crate) fn is_truthy(&self) -> bool {
+        match self {
+            Self::None => false,
+            Self::Bool(value) => *value,
+            Self::Int(value) => *value != 0,
+            Self::Float(value) => *value != 0.0,
+            Self::Str(value) => !value.is_empty(),
+            Self::List(values) | Self::Tuple(values) => !values.is_empty(),
+            Self::Dict(values) => !values.is_empty(),
+            Self::Builtin(_) | Self::Lambda(_) | Self::Method(..) => true,
+        }
+    }
+
+    // Python's `str(value)`.
+    pub(crate) fn to_str(&self) -> String {
+        match self {
+            Self::Str(value) => value.clone(),
+            value => value.repr(),
+        }
+    }
+
+    // Python's `repr(value)`.
+    pub(crate) fn repr(&self) -> String {
+        fn join(values: &[Value]) -> String {
+            values
+                .iter()
+                .map(Value::repr)
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+        match self {
+            Self::None => "None".to_string(),
+            Self::Bool(true) => "True".to_string(),
+            Self::Bool(false) => "False".to_string(),
+            Self::Int(value) => value.to_string(),
+            Self::Float(value) => float_repr(*value),
+            Self::Str(value) => quote(value),
+            Self::List(values) => format!("[{}]", join(values)),
+            Self::Tuple(values) if values.len() == 1 => format!("({},)", values[0].repr()),
+            Self::Tuple(values) => format!("({})", join(values)),
+            Self::Dict(values) => format!(
+                "{{{}}}",
+                values
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", quote(key), value.repr()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Self::Builtin(builtin) => format!("{:?}", builtin),
+            Self::Lambda(_) => "<function <lambda>>".to_string(),
+            Self::Method(receiver, name) => format!(
+                "<built-in method {} of {} object>",
+                name,
+                receiver.type_name()
+            ),
+        }
+    }
+}
+
+// Python's float `repr`: the shortest digits that round-trip, e.g. `180.0` and `1e-05`.
+pub(crate) fn float_repr(value: f64) -> String {
+    if value.is_nan() {
+        return "nan".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+
+    // `{:e}` yields the shortest round-trip digits, e.g. `-1.5e16`.
+    let scientific = format!("{:e}", value);
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("scientific float formatting always has an exponent");
+    let exponent = exponent
+        .parse::<i32>()
+        .expect("scientific float exponent is an integer");
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa),
+    };
+    let digits = mantissa.replace('.', "");
+    let decimal_point = exponent + 1;
+
+    let body = if !(-4 < decimal_point && decimal_point <= 16) {
+        let (first, rest) = digits.split_at(1);
+        let mantissa = if rest.is_empty() {
+            first.to_string()
+        } else {
+            format!("{}.{}", first, rest)
+        };
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", mantissa, exponent_sign, exponent.abs())
+    } else if decimal_point <= 0 {
+        format!(
+            "0.{}{}",
+            "0".repeat(decimal_point.unsigned_abs() as usize),
+            digits
+        )
+    } else if (decimal_point as usize) < digits.len() {
+        let (integer, fraction) = digits.split_at(decimal_point as usize);
+        format!("{}.{}", integer, fraction)
+    } else {
+        format!(
+            "{}{}.0",
+            digits,
+            "0".repeat(decimal_point as usize - digits.len())
+        )
+    };
+    format!("{}{}", sign, body)
+}
+
+fn quote(value: &str) -> String {
+    let quote = if value.contains('\'') && !value.contains('"') {
+        '"'
+    } else {
+        '\''
+    };
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push(quote);
+    for ch in value.chars() {
+        match ch {
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            ch if ch == quote => {
+                result.push('\\');
+                result.push(ch);
+            }
+            ch if (ch as u32) < 0x20 || ch == '\x7f' => {
+                result.push_str(&format!("\\x{:02x}", ch as u32));
+            }
+            ch => result.push(ch),
+        }
+    }
+    result.push(quote);
+    result
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Name(String),
+    Op(&'static str),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(value) => write!(f, "`{}`", value),
+            Self::Float(value) => write!(f, "`{}`", float_repr(*value)),
+            Self::Str(value) => write!(f, "`{}`", quote(value)),
+            Self::Name(name) => write!(f, "`{}`", name),
+            Self::Op(op) => write!(f, "`{}`", op),
+            Self::End => write!(f, "end of expression"),
+        }
+    }
+}
+
+// Longer operators come first so `**` is not read as two `*`.
+const OPERATORS: &[&str] = &[
+    "**", "//", "==", "!=", "<=", ">=", "<<", ">>", "+", "-", "*", "/", "%", "<", ">", "~", "&",
+    "|", "^", "(", ")", "[", "]", "{", "}", ",", ":", ".", "=",
+];
+
+const KEYWORDS: &[&str] = &[
+    "and", "else", "for", "if", "in", "is", "lambda", "not", "or", "False", "None", "True",
+];
+
+fn tokenize(source: &str) -> EvalResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while let Some(ch) = rest.chars().next() {
+        if ch.is_ascii_digit() || (ch == '.' && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+        {
+            let (token, after) = take_number(rest)?;
+            tokens.push(token);
+            rest = after;
+        } else if ch == '_' || ch.is_alphabetic() {
+            let end = rest
+                .find(|c: char| !(c == '_' || c.is_alphanumeric()))
+                .unwrap_or(rest.len());
+            let (name, after) = rest.split_at(end);
+            if after.starts_with(['\'', '"']) {
+                let raw = match name.to_ascii_lowercase().as_str() {
+                    "r" => true,
+                    "u" => false,
+                    _ => {
+                        return Err(EvalError::Syntax(format!(
+                            "`{}` string literals are not supported",
+                            name
+                        )))
+                    }
+                };
+                let (value, after) = take_string(after, raw)?;
+                tokens.push(Token::Str(value));
+                rest = after;
+            } else {
+                tokens.push(Token::Name(name.to_string()));
+                rest = after;
+            }
+        } else if ch == '\'' || ch == '"' {
+            let (value, after) = take_string(rest, false)?;
+            tokens.push(Token::Str(value));
+            rest = after;
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(EvalError::Syntax(format!("unexpected character `{}`", ch)));
+        }
+        rest = rest.trim_start();
+    }
+    tokens.push(Token::End);
+    Ok(tokens)
+}
+
+fn take_number(s: &str) -> EvalResult<(Token, &str)> {
+    let invalid = |literal: &str| EvalError::Syntax(format!("invalid number `{}`", literal));
+    let digits_end = |from: usize| {
+        from + s[from..]
+            .find(|c: char| !(c.is_ascii_digit() || c == '_'))
+            .unwrap_or(s.len() - from)
+    };
+
+    let radix = match s.get(..2).map(str::to_ascii_lowercase).as_deref() {
+        Some("0x") => Some(16),
+        Some("0o") => Some(8),
+        Some("0b") => Some(2),
+        _ => None,
+    };
+    if let Some(radix) = radix {
+        let end = 2 + s[2..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(s.len() - 2);
+        let value = i64::from_str_radix(&s[2..end].replace('_', ""), radix)
+            .map_err(|_| invalid(&s[..end]))?;
+        return Ok((Token::Int(value), &s[end..]));
+    }
+
+    let mut end = digits_end(0);
+    let mut is_float = false;
+    if s[end..].starts_with('.') {
+        is_float = true;
+        end = digits_end(end + 1);
+    }
+    if s[end..].starts_with(['e', 'E']) {
+        let mut exponent = end + 1;
+        if s[exponent..].starts_with(['+', '-']) {
+            exponent += 1;
+        }
+        if s[exponent..].starts_with(|c: char| c.is_ascii_digit()) {
+            is_float = true;
+            end = digits_end(exponent);
+        }
+    }
+    let (literal, after) = s.split_at(end);
+    if after.starts_with(|c: char| c == '_' || c.is_alphanumeric()) {
+        return Err(invalid(s));
+    }
+
+    let digits = literal.replace('_', "");
+    let token = if is_float {
+        Token::Float(digits.parse().map_err(|_| invalid(literal))?)
+    } else {
+        Token::Int(
+            digits
+                .parse()
+                .map_err(|_| EvalError::Overflow(format!("integer `{}` is too large", literal)))?,
+        )
+    };
+    Ok((token, after))
+}
+
+fn take_string(s: &str, raw: bool) -> EvalResult<(String, &str)> {
+    let quote = &s[..1];
+    let triple = quote.repeat(3);
+    let (delimiter, mut rest) = if s.starts_with(&triple) {
+        (triple.as_str(), &s[3..])
+    } else {
+        (quote, &s[1..])
+    };
+
+    let unterminated = || EvalError::Syntax("unterminated string literal".to_string());
+    let mut value = String::new();
+    loop {
+        if let Some(after) = rest.strip_prefix(delimiter) {
+            return Ok((value, after));
+        }
+        let mut chars = rest.chars();
+        let ch = chars.next().ok_or_else(unterminated)?;
+        if ch != '\\' {
+            value.push(ch);
+            rest = chars.as_str();
+            continue;
+        }
+
+        let escape = chars.next().ok_or_else(unterminated)?;
+        if raw {
+            value.push('\\');
+            value.push(escape);
+            rest = chars.as_str();
+            continue;
+        }
+        let hex_digits = match escape {
+            'x' => 2,
+            'u' => 4,
+            'U' => 8,
+            _ => 0,
+        };
+        if hex_digits > 0 {
+            let digits = chars.as_str().get(..hex_digits).unwrap_or("");
+            let ch = u32::from_str_radix(digits, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| EvalError::Syntax(format!("invalid `\\{}` escape", escape)))?;
+            value.push(ch);
+            rest = &chars.as_str()[hex_digits..];
+            continue;
+        }
+        match escape {
+            'n' => value.push('\n'),
+            't' => value.push('\t'),
+            'r' => value.push('\r'),
+            '0' => value.push('\0'),
+            'a' => value.push('\x07'),
+            'b' => value.push('\x08'),
+            'f' => value.push('\x0c'),
+            'v' => value.push('\x0b'),
+            '\\' | '\'' | '"' => value.push(escape),
+            '\n' => {}
+            _ => {
+                value.push('\\');
+                value.push(escape);
+            }
+        }
+        rest = chars.as_str();
+    }
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Name(String),
+    Tuple(Vec<Expr>),
+    List(Vec<Expr>),
+    Dict(Vec<(Expr, Expr)>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, Vec<(CompareOp, Expr)>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    IfElse(Box<Expr>, Box<Expr>, Box<Expr>),
+    Lambda(Rc<LambdaDef>),
+    Call(Box<Expr>, Vec<Expr>, Vec<(String, Expr)>),
+    Attribute(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Slice(Box<Expr>, Box<[Option<Expr>; 3]>),
+    ListComp(Box<Expr>, Vec<Clause>),
+    DictComp(Box<(Expr, Expr)>, Vec<Clause>),
+}
+
+// Positional and keyword arguments of a call.
+type CallArgs = (Vec<Expr>, Vec<(String, Expr)>);
+
+#[derive(Debug)]
+enum Clause {
+    For(Target, Expr),
+    If(Expr),
+}
+
+#[derive(Debug)]
+enum Target {
+    Name(String),
+    Tuple(Vec<Target>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum UnaryOp {
+    Neg,
+    Pos,
+    Invert,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    FloorDiv,
+    Mod,
+    Pow,
+    LShift,
+    RShift,
+    BitAnd,
+    BitOr,
+    BitXor,
+}
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::FloorDiv => "//",
+            Self::Mod => "%",
+            Self::Pow => "** or pow()",
+            Self::LShift => "<<",
+            Self::RShift => ">>",
+            Self::BitAnd => "&",
+            Self::BitOr => "|",
+            Self::BitXor => "^",
+        }
+    }
+
+    fn is_integer_only(self) -> bool {
+        matches!(
+            self,
+            Self::LShift | Self::RShift | Self::BitAnd | Self::BitOr | Self::BitXor
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CompareOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtE,
+    Gt,
+    GtE,
+    In,
+    NotIn,
+    Is,
+    IsNot,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn new(source: &str) -> EvalResult<Self> {
+        Ok(Self {
+            tokens: tokenize(source)?,
+            position: 0,
+            depth: 0,
+        })
+    }
+
+    fn parse(mut self) -> EvalResult<Expr> {
+        let first = self.expression()?;
+        let expr = if self.is_op(",") {
+            let mut items = vec![first];
+            while self.eat_op(",") && *self.peek() != Token::End {
+                items.push(self.expression()?);
+            }
+            Expr::Tuple(items)
+        } else {
+            first
+        };
+        match self.peek() {
+            Token::End => Ok(expr),
+            token => Err(EvalError::Syntax(format!("unexpected {}", token))),
+        }
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        if token != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn is_op(&self, op: &str) -> bool {
+        matches!(self.peek(), Token::Op(o) if *o == op)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Token::Name(name) if name == keyword)
+    }
+
+    fn eat_op(&mut self, op: &str) -> bool {
+        let found = self.is_op(op);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect_op(&mut self, op: &str) -> EvalResult<()> {
+        if self.eat_op(op) {
+            Ok(())
+        } else {
+            Err(EvalError::Syntax(format!(
+                "expected `{}` but found {}",
+                op,
+                self.peek()
+            )))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> EvalResult<()> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(EvalError::Syntax(format!(
+                "expected `{}` but found {}",
+                keyword,
+                self.peek()
+            )))
+        }
+    }
+
+    fn expect_name(&mut self) -> EvalResult<String> {
+        match self.advance() {
+            Token::Name(name) if !KEYWORDS.contains(&name.as_str()) => Ok(name),
+            token => Err(EvalError::Syntax(format!(
+                "expected a name but found {}",
+                token
+            ))),
+        }
+    }
+
+    fn enter(&mut self) -> EvalResult<()> {
+        self.depth += 1;
+        if self.depth > MAX_PARSE_DEPTH {
+            return Err(EvalError::Syntax(
+                "expression is nested too deeply".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self) -> EvalResult<Expr> {
+        self.enter()?;
+        let expr = if self.eat_keyword("lambda") {
+            self.lambda()?
+        } else {
+            let body = self.or_test()?;
+            if self.eat_keyword("if") {
+                let test = self.or_test()?;
+                self.expect_keyword("else")?;
+                let orelse = self.expression()?;
+                Expr::IfElse(Box::new(test), Box::new(body), Box::new(orelse))
+            } else {
+                body
+            }
+        };
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn lambda(&mut self) -> EvalResult<Expr> {
+        let mut params = Vec::new();
+        if !self.is_op(":") {
+            loop {
+                params.push(self.expect_name()?);
+                if !self.eat_op(",") {
+                    break;
+                }
+            }
+        }
+        self.expect_op(":")?;
+        let body = self.expression()?;
+        Ok(Expr::Lambda(Rc::new(LambdaDef { params, body })))
+    }
+
+    fn or_test(&mut self) -> EvalResult<Expr> {
+        let mut left = self.and_test()?;
+        let depth = self.depth;
+        while self.eat_keyword("or") {
+            self.enter()?;
+            left = Expr::Or(Box::new(left), Box::new(self.and_test()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn and_test(&mut self) -> EvalResult<Expr> {
+        let mut left = self.not_test()?;
+        let depth = self.depth;
+        while self.eat_keyword("and") {
+            self.enter()?;
+            left = Expr::And(Box::new(left), Box::new(self.not_test()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn not_test(&mut self) -> EvalResult<Expr> {
+        if self.eat_keyword("not") {
+            self.enter()?;
+            let operand = self.not_test()?;
+            self.depth -= 1;
+            return Ok(Expr::Not(Box::new(operand)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> EvalResult<Expr> {
+        let first = self.binary(0)?;
+        let mut rest = Vec::new();
+        loop {
+            let op = match self.peek() {
+                Token::Op("==") => CompareOp::Eq,
+                Token::Op("!=") => CompareOp::NotEq,
+                Token::Op("<") => CompareOp::Lt,
+                Token::Op("<=") => CompareOp::LtE,
+                Token::Op(">") => CompareOp::Gt,
+                Token::Op(">=") => CompareOp::GtE,
+                Token::Name(name) if name == "in" => CompareOp::In,
+                Token::Name(name)
+                    if name == "not"
+                        && matches!(&self.tokens[self.position + 1], Token::Name(next) if next == "in") =>
+                {
+                    self.position += 1;
+                    CompareOp::NotIn
+                }
+                Token::Name(name) if name == "is" => {
+                    if matches!(&self.tokens[self.position + 1], Token::Name(next) if next == "not")
+                    {
+                        self.position += 1;
+                        CompareOp::IsNot
+                    } else {
+                        CompareOp::Is
+                    }
+                }
+                _ => break,
+            };
+            self.position += 1;
+            self.enter()?;
+            rest.push((op, self.binary(0)?));
+        }
+        self.depth -= rest.len();
+        if rest.is_empty() {
+            Ok(first)
+        } else {
+            Ok(Expr::Compare(Box::new(first), rest))
+        }
+    }
+
+    // Binary operators binding at least as tightly as `min_level`, by precedence climbing so
+    // that each nesting level costs one stack frame.
+    fn binary(&mut self, min_level: usize) -> EvalResult<Expr> {
+        let mut left = self.factor()?;
+        let depth = self.depth;
+        loop {
+            let (level, op) = match self.peek() {
+                Token::Op("|") => (0, BinaryOp::BitOr),
+                Token::Op("^") => (1, BinaryOp::BitXor),
+                Token::Op("&") => (2, BinaryOp::BitAnd),
+                Token::Op("<<") => (3, BinaryOp::LShift),
+                Token::Op(">>") => (3, BinaryOp::RShift),
+                Token::Op("+") => (4, BinaryOp::Add),
+                Token::Op("-") => (4, BinaryOp::Sub),
+                Token::Op("*") => (5, BinaryOp::Mul),
+                Token::Op("/") => (5, BinaryOp::Div),
+                Token::Op("//") => (5, BinaryOp::FloorDiv),
+                Token::Op("%") => (5, BinaryOp::Mod),
+                _ => break,
+            };
+            if level < min_level {
+                break;
+            }
+            self.position += 1;
+            self.enter()?;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn factor(&mut self) -> EvalResult<Expr> {
+        let op = if self.eat_op("-") {
+            UnaryOp::Neg
+        } else if self.eat_op("+") {
+            UnaryOp::Pos
+        } else if self.eat_op("~") {
+            UnaryOp::Invert
+        } else {
+            return self.power();
+        };
+        self.enter()?;
+        let operand = self.factor()?;
+        self.depth -= 1;
+        Ok(Expr::Unary(op, Box::new(operand)))
+    }
+
+    fn power(&mut self) -> EvalResult<Expr> {
+        let base = self.primary()?;
+        if !self.eat_op("**") {
+            return Ok(base);
+        }
+        self.enter()?;
+        let exponent = self.factor()?;
+        self.depth -= 1;
+        Ok(Expr::Binary(
+            BinaryOp::Pow,
+            Box::new(base),
+            Box::new(exponent),
+        ))
+    }
+
+    fn primary(&mut self) -> EvalResult<Expr> {
+        let mut expr = self.atom()?;
+        let depth = self.depth;
+        loop {
+            expr = if self.eat_op("(") {
+                let (args, keywords) = self.call_args()?;
+                Expr::Call(Box::new(expr), args, keywords)
+            } else if self.eat_op("[") {
+                self.subscript(expr)?
+            } else if self.eat_op(".") {
+                Expr::Attribute(Box::new(expr), self.expect_name()?)
+            } else {
+                break;
+            };
+            self.enter()?;
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn call_args(&mut self) -> EvalResult<CallArgs> {
+        let mut args = Vec::new();
+        let mut keywords = Vec::new();
+        while !self.is_op(")") {
+            let is_keyword = matches!(self.peek(), Token::Name(_))
+                && self.tokens[self.position + 1] == Token::Op("=");
+            if is_keyword {
+                let name = self.expect_name()?;
+                self.position += 1;
+                keywords.push((name, self.expression()?));
+            } else if !keywords.is_empty() {
+                return Err(EvalError::Syntax(
+                    "positional argument follows keyword argument".to_string(),
+                ));
+            } else {
+                let arg = self.expression()?;
+                if self.is_keyword("for") {
+                    // A generator argument, e.g. `sum(x * 2 for x in xs)`.
+                    args.push(Expr::ListComp(Box::new(arg), self.comprehension()?));
+                } else {
+                    args.push(arg);
+                }
+            }
+            if !self.eat_op(",") {
+                break;
+            }
+        }
+        self.expect_op(")")?;
+        Ok((args, keywords))
+    }
+
+    fn subscript(&mut self, target: Expr) -> EvalResult<Expr> {
+        let start = if self.is_op(":") {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        if !self.eat_op(":") {
+            self.expect_op("]")?;
+            let index = start.expect("an index without `:` is an expression");
+            return Ok(Expr::Index(Box::new(target), Box::new(index)));
+        }
+
+        let stop = if self.is_op(":") || self.is_op("]") {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        let step = if self.eat_op(":") && !self.is_op("]") {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.expect_op("]")?;
+        Ok(Expr::Slice(Box::new(target), Box::new([start, stop, step])))
+    }
+
+    fn atom(&mut self) -> EvalResult<Expr> {
+        match self.advance() {
+            Token::Int(value) => Ok(Expr::Literal(Value::Int(value))),
+            Token::Float(value) => Ok(Expr::Literal(Value::Float(value))),
+            Token::Str(mut value) => {
+                // Adjacent literals are concatenated, as in `'a' 'b'`.
+                while let Token::Str(more) = self.peek() {
+                    value.push_str(more);
+                    self.position += 1;
+                }
+                Ok(Expr::Literal(Value::Str(value)))
+            }
+            Token::Name(name) => match name.as_str() {
+                "True" => Ok(Expr::Literal(Value::Bool(true))),
+                "False" => Ok(Expr::Literal(Value::Bool(false))),
+                "None" => Ok(Expr::Literal(Value::None)),
+                name if KEYWORDS.contains(&name) => {
+                    Err(EvalError::Syntax(format!("unexpected `{}`", name)))
+                }
+                _ => Ok(Expr::Name(name)),
+            },
+            Token::Op("(") => {
+                if self.eat_op(")") {
+                    return Ok(Expr::Tuple(Vec::new()));
+                }
+                let first = self.expression()?;
+                if self.is_keyword("for") {
+                    let clauses = self.comprehension()?;
+                    self.expect_op(")")?;
+                    return Ok(Expr::ListComp(Box::new(first), clauses));
+                }
+                if self.eat_op(")") {
+                    return Ok(first);
+                }
+                let items = self.items(first, ")")?;
+                Ok(Expr::Tuple(items))
+            }
+            Token::Op("[") => {
+                if self.eat_op("]") {
+                    return Ok(Expr::List(Vec::new()));
+                }
+                let first = self.expression()?;
+                if self.is_keyword("for") {
+                    let clauses = self.comprehension()?;
+                    self.expect_op("]")?;
+                    return Ok(Expr::ListComp(Box::new(first), clauses));
+                }
+                let items = self.items(first, "]")?;
+                Ok(Expr::List(items))
+            }
+            Token::Op("{") => {
+                if self.eat_op("}") {
+                    return Ok(Expr::Dict(Vec::new()));
+                }
+                let key = self.expression()?;
+                if !self.eat_op(":") {
+                    return Err(EvalError::Syntax("sets are not supported".to_string()));
+                }
+                let value = self.expression()?;
+                if self.is_keyword("for") {
+                    let clauses = self.comprehension()?;
+                    self.expect_op("}")?;
+                    return Ok(Expr::DictComp(Box::new((key, value)), clauses));
+                }
+                let mut entries = vec![(key, value)];
+                while self.eat_op(",") && !self.is_op("}") {
+                    let key = self.expression()?;
+                    self.expect_op(":")?;
+                    entries.push((key, self.expression()?));
+                }
+                self.expect_op("}")?;
+                Ok(Expr::Dict(entries))
+            }
+            token => Err(EvalError::Syntax(format!("unexpected {}", token))),
+        }
+    }
+
+    // The rest of a comma-separated display after its first item, up to `close`.
+    fn items(&mut self, first: Expr, close: &str) -> EvalResult<Vec<Expr>> {
+        let mut items = vec![first];
+        while self.eat_op(",") && !self.is_op(close) {
+            items.push(self.expression()?);
+        }
+        self.expect_op(close)?;
+        Ok(items)
+    }
+
+    fn comprehension(&mut self) -> EvalResult<Vec<Clause>> {
+        let mut clauses = Vec::new();
+        while self.eat_keyword("for") {
+            let target = self.target()?;
+            self.expect_keyword("in")?;
+            clauses.push(Clause::For(target, self.or_test()?));
+            while self.eat_keyword("if") {
+                clauses.push(Clause::If(self.or_test()?));
+            }
+        }
+        Ok(clauses)
+    }
+
+    fn target(&mut self) -> EvalResult<Target> {
+        let first = self.target_item()?;
+        if !self.is_op(",") {
+            return Ok(first);
+        }
+        let mut items = vec![first];
+        while self.eat_op(",") && !self.is_keyword("in") && !self.is_op(")") {
+            items.push(self.target_item()?);
+        }
+        Ok(Target::Tuple(items))
+    }
+
+    fn target_item(&mut self) -> EvalResult<Target> {
+        if self.eat_op("(") {
+            self.enter()?;
+            let target = self.target()?;
+            self.expect_op(")")?;
+            self.depth -= 1;
+            Ok(target)
+        } else {
+            Ok(Target::Name(self.expect_name()?))
+        }
+    }
+}
+
+pub(crate) struct Interpreter<'a> {
+    names: &'a dyn Fn(&str) -> Option<Value>,
+    step: &'a dyn Fn() -> EvalResult<()>,
+    // Comprehension and lambda variables, innermost last.
+    scopes: Vec<HashMap<String, Value>>,
+    depth: usize,
+}
+
+impl Interpreter<'_> {
+    fn lookup(&self, name: &str) -> EvalResult<Value> {
+        if let Some(value) = self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            return Ok(value.clone());
+        }
+        if let Some(value) = (self.names)(name) {
+            return Ok(value);
+        }
+        BUILTINS_BY_NAME
+            .get(name)
+            .copied()
+            .map(Value::Builtin)
+            .ok_or_else(|| EvalError::UndefinedName(name.to_string()))
+    }
+
+    fn eval(&mut self, expr: &Expr) -> EvalResult<Value> {
+        if self.depth >= MAX_EVAL_DEPTH {
+            return Err(EvalError::Recursion);
+        }
+        (self.step)()?;
+        self.depth += 1;
+        let result = self.eval_expr(expr);
+        self.depth -= 1;
+        result
+    }
+
+    fn eval_all(&mut self, exprs: &[Expr]) -> EvalResult<Vec<Value>> {
+        exprs.iter().map(|expr| self.eval(expr)).collect()
+    }
+
+    // The larger cases live in their own methods, which keeps this frame small for
+    // deeply nested expressions.
+    fn eval_expr(&mut self, expr: &Expr) -> EvalResult<Value> {
+        match expr {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Name(name) => self.lookup(name),
+            Expr::Tuple(items) => Ok(Value::Tuple(self.eval_all(items)?)),
+            Expr::List(items) => Ok(Value::List(self.eval_all(items)?)),
+            Expr::Dict(entries) => self.eval_dict(entries),
+            Expr::Unary(op, operand) => unary(*op, self.eval(operand)?),
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                binary(*op, left, self.eval(right)?)
+            }
+            Expr::Compare(first, rest) => self.eval_compare(first, rest),
+            Expr::Not(operand) => Ok(Value::Bool(!self.eval(operand)?.is_truthy())),
+            Expr::And(left, right) => {
+                let left = self.eval(left)?;
+                if left.is_truthy() {
+                    self.eval(right)
+                } else {
+                    Ok(left)
+                }
+            }
+            Expr::Or(left, right) => {
+                let left = self.eval(left)?;
+                if left.is_truthy() {
+                    Ok(left)
+                } else {
+                    self.eval(right)
+                }
+            }
+            Expr::IfElse(test, body, orelse) => {
+                if self.eval(test)?.is_truthy() {
+                    self.eval(body)
+                } else {
+                    self.eval(orelse)
+                }
+            }
+            Expr::Lambda(def) => Ok(self.make_lambda(def)),
+            Expr::Call(func, args, keywords) => self.eval_call(func, args, keywords),
+            Expr::Attribute(target, name) => attribute(self.eval(target)?, name),
+            Expr::Index(target, index) => {
+                let target = self.eval(target)?;
+                index_value(&target, &self.eval(index)?)
+            }
+            Expr::Slice(target, bounds) => self.eval_slice(target, bounds),
+            Expr::ListComp(element, clauses) => self.eval_list_comp(element, clauses),
+            Expr::DictComp(entry, clauses) => self.eval_dict_comp(entry, clauses),
+        }
+    }
+
+    fn eval_dict(&mut self, entries: &[(Expr, Expr)]) -> EvalResult<Value> {
+        let mut values = IndexMap::new();
+        for (key, value) in entries.iter() {
+            let key = dict_key(&self.eval(key)?)?;
+            values.insert(key, self.eval(value)?);
+        }
+        Ok(Value::Dict(values))
+    }
+
+    fn eval_compare(&mut self, first: &Expr, rest: &[(CompareOp, Expr)]) -> EvalResult<Value> {
+        let mut left = self.eval(first)?;
+        for (op, right) in rest.iter() {
+            let right = self.eval(right)?;
+            if !compare(*op, &left, &right)? {
+                return Ok(Value::Bool(false));
+            }
+            left = right;
+        }
+        Ok(Value::Bool(true))
+    }
+
+    fn make_lambda(&self, def: &Rc<LambdaDef>) -> Value {
+        Value::Lambda(Rc::new(Lambda {
+            def: def.clone(),
+            captured: self
+                .scopes
+                .iter()
+                .flatten()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        }))
+    }
+
+    fn eval_call(
+        &mut self,
+        func: &Expr,
+        args: &[Expr],
+        keywords: &[(String, Expr)],
+    ) -> EvalResult<Value> {
+        let func = self.eval(func)?;
+        let args = self.eval_all(args)?;
+        let keywords = keywords
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), self.eval(value)?)))
+            .collect::<EvalResult<Vec<_>>>()?;
+        self.call(&func, &args, &keywords)
+    }
+
+    fn eval_slice(&mut self, target: &Expr, bounds: &[Option<Expr>; 3]) -> EvalResult<Value> {
+        let target = self.eval(target)?;
+        let mut values = [None, None, None];
+        for (value, bound) in values.iter_mut().zip(bounds.iter()) {
+            if let Some(bound) = bound {
+                *value = slice_bound(&self.eval(bound)?)?;
+            }
+        }
+        slice_value(&target, values)
+    }
+
+    fn eval_list_comp(&mut self, element: &Expr, clauses: &[Clause]) -> EvalResult<Value> {
+        let mut items = Vec::new();
+        self.scopes.push(HashMap::new());
+        let result = self.comprehension(clauses, &mut |this| {
+            items.push(this.eval(element)?);
+            check_length(items.len())
+        });
+        self.scopes.pop();
+        result?;
+        Ok(Value::List(items))
+    }
+
+    fn eval_dict_comp(&mut self, entry: &(Expr, Expr), clauses: &[Clause]) -> EvalResult<Value> {
+        let mut values = IndexMap::new();
+        self.scopes.push(HashMap::new());
+        let result = self.comprehension(clauses, &mut |this| {
+            let key = dict_key(&this.eval(&entry.0)?)?;
+            values.insert(key, this.eval(&entry.1)?);
+            check_length(values.len())
+        });
+        self.scopes.pop();
+        result?;
+        Ok(Value::Dict(values))
+    }
+
+    fn comprehension(
+        &mut self,
+        clauses: &[Clause],
+        emit: &mut dyn FnMut(&mut Self) -> EvalResult<()>,
+    ) -> EvalResult<()> {
+        let Some((clause, rest)) = clauses.split_first() else {
+            return emit(self);
+        };
+        match clause {
+            Clause::If(test) => {
+                if self.eval(test)?.is_truthy() {
+                    self.comprehension(rest, emit)?;
+                }
+                Ok(())
+            }
+            Clause::For(target, iterable) => {
+                for item in iterate(&self.eval(iterable)?)? {
+                    self.bind(target, item)?;
+                    self.comprehension(rest, emit)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn bind(&mut self, target: &Target, value: Value) -> EvalResult<()> {
+        match target {
+            Target::Name(name) => {
+                self.scopes
+                    .last_mut()
+                    .expect("comprehensions push a scope")
+                    .insert(name.clone(), value);
+                Ok(())
+            }
+            Target::Tuple(targets) => {
+                let items = iterate(&value)?;
+                if items.len() != targets.len() {
+                    return Err(EvalError::Value(format!(
+                        "expected {} values to unpack, got {}",
+                        targets.len(),
+                        items.len()
+                    )));
+                }
+                for (target, item) in targets.iter().zip(items) {
+                    self.bind(target, item)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn call(
+        &mut self,
+        func: &Value,
+        args: &[Value],
+        keywords: &[(String, Value)],
+    ) -> EvalResult<Value> {
+        match func {
+            Value::Builtin(Builtin::Function(name, func)) => {
+                no_keywords(name, keywords)?;
+                func(args)
+            }
+            Value::Builtin(Builtin::Interpreter(_, func)) => func(self, args, keywords),
+            Value::Lambda(lambda) => {
+                no_keywords("<lambda>", keywords)?;
+                self.call_lambda(lambda, args)
+            }
+            Value::Method(receiver, name) => call_method(receiver, name, args, keywords),
+            value => Err(EvalError::Type(format!(
+                "'{}' object is not callable",
+                value.type_name()
+            ))),
+        }
+    }
+
+    fn call_lambda(&mut self, lambda: &Lambda, args: &[Value]) -> EvalResult<Value> {
+        let params = &lambda.def.params;
+        if args.len() != params.len() {
+            return Err(EvalError::Type(format!(
+                "<lambda>() takes {} positional arguments but {} were given",
+                params.len(),
+                args.len()
+            )));
+        }
+        let mut scope = lambda.captured.clone();
+        scope.extend(params.iter().cloned().zip(args.iter().cloned()));
+        // The body sees what the lambda captured, not the caller's comprehension variables.
+        let caller_scopes = std::mem::replace(&mut self.scopes, vec![scope]);
+        let result = self.eval(&lambda.def.body);
+        self.scopes = caller_scopes;
+        result
+    }
+}
+
+fn no_keywords(name: &str, keywords: &[(String, Value)]) -> EvalResult<()> {
+    if keywords.is_empty() {
+        Ok(())
+    } else {
+        Err(EvalError::Type(format!(
+            "{}() takes no keyword arguments",
+            name
+        )))
+    }
+}
+
+fn check_length(length: usize) -> EvalResult<()> {
+    if length > MAX_LENGTH {
+        Err(EvalError::Memory)
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    fn to_f64(self) -> f64 {
+        match self {
+            Self::Int(value) => value as f64,
+            Self::Float(value) => value,
+        }
+    }
+}
+
+// `bool` is an `int` in Python, so `True + 1` is `2`.
+fn number(value: &Value) -> Option<Number> {
+    match value {
+        Value::Bool(value) => Some(Number::Int(i64::from(*value))),
+        Value::Int(value) => Some(Number::Int(*value)),
+        Value::Float(value) => Some(Number::Float(*value)),
+        _ => None,
+    }
+}
+
+fn unary(op: UnaryOp, operand: Value) -> EvalResult<Value> {
+    let symbol = match op {
+        UnaryOp::Neg => "-",
+        UnaryOp::Pos => "+",
+        UnaryOp::Invert => "~",
+    };
+    match (op, number(&operand)) {
+        (UnaryOp::Neg, Some(Number::Int(value))) => value
+            .checked_neg()
+            .map(Value::Int)
+            .ok_or_else(integer_overflow),
+        (UnaryOp::Neg, Some(Number::Float(value))) => Ok(Value::Float(-value)),
+        (UnaryOp::Pos, Some(Number::Int(value))) => Ok(Value::Int(value)),
+        (UnaryOp::Pos, Some(Number::Float(value))) => Ok(Value::Float(value)),
+        (UnaryOp::Invert, Some(Number::Int(value))) => Ok(Value::Int(!value)),
+        _ => Err(EvalError::Type(format!(
+            "bad operand type for unary {}: '{}'",
+            symbol,
+            operand.type_name()
+        ))),
+    }
+}
+
+pub(crate) fn integer_overflow() -> EvalError {
+    EvalError::Overflow("integer result does not fit in 64 bits".to_string())
+}
+
+pub(crate) fn multiply(left: Value, right: Value) -> EvalResult<Value> {
+    binary(BinaryOp::Mul, left, right)
+}
+
+fn binary(op: BinaryOp, left: Value, right: Value) -> EvalResult<Value> {
+    if let (Some(a), Some(b)) = (number(&left), number(&right)) {
+        match (a, b) {
+            (Number::Int(a), Number::Int(b)) => return int_arithmetic(op, a, b),
+            (a, b) if !op.is_integer_only() => return float_arithmetic(op, a.to_f64(), b.to_f64()),
+            _ => {}
+        }
+    }
+    match (op, left, right) {
+        (BinaryOp::Add, Value::Str(a), Value::Str(b)) => {
+            check_length(a.len() + b.len())?;
+            Ok(Value::Str(a + &b))
+        }
+        (BinaryOp::Add, Value::List(mut a), Value::List(b)) => {
+            check_length(a.len() + b.len())?;
+            a.extend(b);
+            Ok(Value::List(a))
+        }
+        (BinaryOp::Add, Value::Tuple(mut a), Value::Tuple(b)) => {
+            check_length(a.len() + b.len())?;
+            a.extend(b);
+            Ok(Value::Tuple(a))
+        }
+        (BinaryOp::Mul, Value::Str(s), Value::Int(n))
+        | (BinaryOp::Mul, Value::Int(n), Value::Str(s)) => {
+            let n = usize::try_from(n).unwrap_or(0);
+            check_length(s.len().saturating_mul(n))?;
+            Ok(Value::Str(s.repeat(n)))
+        }
+        (BinaryOp::Mul, Value::List(items), Value::Int(n))
+        | (BinaryOp::Mul, Value::Int(n), Value::List(items)) => {
+            Ok(Value::List(repeat_items(&items, n)?))
+        }
+        (BinaryOp::Mul, Value::Tuple(items), Value::Int(n))
+        | (BinaryOp::Mul, Value::Int(n), Value::Tuple(items)) => {
+            Ok(Value::Tuple(repeat_items(&items, n)?))
+        }
+        (BinaryOp::Mod, Value::Str(format), args) => Ok(Value::Str(printf_format(&format, &args)?)),
+        (op, left, right) => Err(EvalError::Type(format!(
+            "unsupported operand type(s) for {}: '{}' and '{}'",
+            op.symbol(),
+            left.type_name(),
+            right.type_name()
+        ))),
+    }
+}
+
+fn repeat_items(items: &[Value], n: i64) -> EvalResult<Vec<Value>> {
+    let n = usize::try_from(n).unwrap_or(0);
+    check_length(items.len().saturating_mul(n))?;
+    Ok(items
+        .iter()
+        .cloned()
+        .cycle()
+        .take(items.len() * n)
+        .collect())
+}
+
+fn int_arithmetic(op: BinaryOp, a: i64, b: i64) -> EvalResult<Value> {
+    let modulo_by_zero =
+        || EvalError::ZeroDivision("integer division or modulo by zero".to_string());
+    let value = match op {
+        BinaryOp::Add => a.checked_add(b),
+        BinaryOp::Sub => a.checked_sub(b),
+        BinaryOp::Mul => a.checked_mul(b),
+        BinaryOp::Div if b == 0 => {
+            return Err(EvalError::ZeroDivision("division by zero".to_string()))
+        }
+        BinaryOp::Div => return Ok(Value::Float(a as f64 / b as f64)),
+        BinaryOp::FloorDiv if b == 0 => return Err(modulo_by_zero()),
+        BinaryOp::FloorDiv => a.checked_div(b).map(|quotient| {
+            if a % b != 0 && (a < 0) != (b < 0) {
+                quotient - 1
+            } else {
+                quotient
+            }
+        }),
+        BinaryOp::Mod if b == 0 => return Err(modulo_by_zero()),
+        BinaryOp::Mod => {
+            let remainder = a.wrapping_rem(b);
+            Some(if remainder != 0 && (remainder < 0) != (b < 0) {
+                remainder + b
+            } else {
+                remainder
+            })
+        }
+        BinaryOp::Pow if b < 0 => return float_arithmetic(op, a as f64, b as f64),
+        BinaryOp::Pow => match (a, u32::try_from(b)) {
+            (_, Ok(b)) => a.checked_pow(b),
+            (0 | 1, Err(_)) => Some(a),
+            (-1, Err(_)) => Some(if b % 2 == 0 { 1 } else { -1 }),
+            _ => None,
+        },
+        BinaryOp::LShift | BinaryOp::RShift if b < 0 => {
+            return Err(EvalError::Value("negative shift count".to_string()))
+        }
+        BinaryOp::LShift if a == 0 => Some(0),
+        BinaryOp::LShift => u32::try_from(b)
+            .ok()
+            .filter(|b| *b < 64)
+            .map(|b| a << b)
+            .filter(|value| value >> b == a),
+        BinaryOp::RShift => Some(a >> b.min(63)),
+        BinaryOp::BitAnd => Some(a & b),
+        BinaryOp::BitOr => Some(a | b),
+        BinaryOp::BitXor => Some(a ^ b),
+    };
+    value.map(Value::Int).ok_or_else(integer_overflow)
+}
+
+fn float_arithmetic(op: BinaryOp, a: f64, b: f64) -> EvalResult<Value> {
+    let value = match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Sub => a - b,
+        BinaryOp::Mul => a * b,
+        BinaryOp::Div if b == 0.0 => {
+            return Err(EvalError::ZeroDivision(
+                "float division by zero".to_string(),
+            ))
+        }
+        BinaryOp::Div => a / b,
+        BinaryOp::FloorDiv if b == 0.0 => {
+            return Err(EvalError::ZeroDivision(
+                "float floor division by zero".to_string(),
+            ))
+        }
+        BinaryOp::FloorDiv => float_divmod(a, b).0,
+        BinaryOp::Mod if b == 0.0 => {
+            return Err(EvalError::ZeroDivision("float modulo".to_string()))
+        }
+        BinaryOp::Mod => float_divmod(a, b).1,
+        BinaryOp::Pow => float_pow(a, b)?,
+        _ => unreachable!("integer-only operators never reach float arithmetic"),
+    };
+    Ok(Value::Float(value))
+}
+
+// CPython's `float.__divmod__`: the remainder takes the sign of `b` and the quotient is exact
+// where plain `(a / b).floor()` would round.
+fn float_divmod(a: f64, b: f64) -> (f64, f64) {
+    let mut remainder = a % b;
+    let mut quotient = (a - remainder) / b;
+    if remainder != 0.0 {
+        if (b < 0.0) != (remainder < 0.0) {
+            remainder += b;
+            quotient -= 1.0;
+        }
+    } else {
+        remainder = 0f64.copysign(b);
+    }
+    let quotient = if quotient != 0.0 {
+        let floor = quotient.floor();
+        if quotient - floor > 0.5 {
+            floor + 1.0
+        } else {
+            floor
+        }
+    } else {
+        0f64.copysign(a / b)
+    };
+    (quotient, remainder)
+}
+
+fn float_pow(a: f64, b: f64) -> EvalResult<f64> {
+    if a == 0.0 && b < 0.0 {
+        return Err(EvalError::ZeroDivision(
+            "0.0 cannot be raised to a negative power".to_string(),
+        ));
+    }
+    if a < 0.0 && b.is_finite() && b.fract() != 0.0 {
+        return Err(EvalError::Value(
+            "a negative number cannot be raised to a fractional power".to_string(),
+        ));
+    }
+    let value = a.powf(b);
+    if value.is_infinite() && a.is_finite() && b.is_finite() {
+        return Err(EvalError::Overflow(
+            "numerical result out of range".to_string(),
+        ));
+    }
+    Ok(value)
+}
+
+fn compare(op: CompareOp, left: &Value, right: &Value) -> EvalResult<bool> {
+    let (symbol, accepts): (&str, fn(Ordering) -> bool) = match op {
+        CompareOp::Eq => return Ok(values_equal(left, right)),
+        CompareOp::NotEq => return Ok(!values_equal(left, right)),
+        CompareOp::In => return contains(right, left),
+        CompareOp::NotIn => return contains(right, left).map(|found| !found),
+        CompareOp::Is => return Ok(is_same(left, right)),
+        CompareOp::IsNot => return Ok(!is_same(left, right)),
+        CompareOp::Lt => ("<", Ordering::is_lt),
+        CompareOp::LtE => ("<=", Ordering::is_le),
+        CompareOp::Gt => (">", Ordering::is_gt),
+        CompareOp::GtE => (">=", Ordering::is_ge),
+    };
+    // `nan` is unordered, so every ordering comparison with it is false.
+    Ok(order(left, right, symbol)?.is_some_and(accepts))
+}
+
+fn order(left: &Value, right: &Value, symbol: &str) -> EvalResult<Option<Ordering>> {
+    if let (Some(a), Some(b)) = (number(left), number(right)) {
+        return Ok(match (a, b) {
+            (Number::Int(a), Number::Int(b)) => Some(a.cmp(&b)),
+            (a, b) => a.to_f64().partial_cmp(&b.to_f64()),
+        });
+    }
+    match (left, right) {
+        (Value::Str(a), Value::Str(b)) => Ok(Some(a.cmp(b))),
+        (Value::List(a), Value::List(b)) | (Value::Tuple(a), Value::Tuple(b)) => {
+            for (a, b) in a.iter().zip(b.iter()) {
+                if !values_equal(a, b) {
+                    return order(a, b, symbol);
+                }
+            }
+            Ok(Some(a.len().cmp(&b.len())))
+        }
+        _ => Err(EvalError::Type(format!(
+            "'{}' not supported between instances of '{}' and '{}'",
+            symbol,
+            left.type_name(),
+            right.type_name()
+        ))),
+    }
+}
+
+pub(crate) fn values_equal(left: &Value, right: &Value) -> bool {
+    if let (Some(a), Some(b)) = (number(left), number(right)) {
+        return match (a, b) {
+            (Number::Int(a), Number::Int(b)) => a == b,
+            (a, b) => a.to_f64() == b.to_f64(),
+        };
+    }
+    match (left, right) {
+        (Value::None, Value::None) => true,
+        (Value::Str(a), Value::Str(b)) => a == b,
+        (Value::List(a), Value::List(b)) | (Value::Tuple(a), Value::Tuple(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| values_equal(a, b))
+        }
+        (Value::Dict(a), Value::Dict(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| values_equal(a, b)))
+        }
+        (Value::Builtin(a), Value::Builtin(b)) => a.name() == b.name(),
+        (Value::Lambda(a), Value::Lambda(b)) => Rc::ptr_eq(a, b),
+        _ => false,
+    }
+}
+
+// Identity is only observable for the singletons, as in `x is None`.
+fn is_same(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::None, Value::None) => true,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Lambda(a), Value::Lambda(b)) => Rc::ptr_eq(a, b),
+        _ => false,
+    }
+}
+
+fn contains(container: &Value, item: &Value) -> EvalResult<bool> {
+    match (container, item) {
+        (Value::Str(s), Value::Str(sub)) => Ok(s.contains(sub.as_str())),
+        (Value::Str(_), item) => Err(EvalError::Type(format!(
+            "'in <string>' requires string as left operand, not {}",
+            item.type_name()
+        ))),
+        (Value::List(items) | Value::Tuple(items), item) => {
+            Ok(items.iter().any(|value| values_equal(value, item)))
+        }
+        (Value::Dict(values), item) => {
+            Ok(dict_key(item).is_ok_and(|key| values.contains_key(&key)))
+        }
+        (container, _) => Err(EvalError::Type(format!(
+            "argument of type '{}' is not iterable",
+            container.type_name()
+        ))),
+    }
+}
+
+pub(crate) fn iterate(value: &Value) -> EvalResult<Vec<Value>> {
+    match value {
+        Value::List(items) | Value::Tuple(items) => Ok(items.clone()),
+        Value::Str(s) => Ok(s.chars().map(|ch| Value::Str(ch.to_string())).collect()),
+        Value::Dict(values) => Ok(values.keys().map(|key| Value::Str(key.clone())).collect()),
+        value => Err(EvalError::Type(format!(
+            "'{}' object is not iterable",
+            value.type_name()
+        ))),
+    }
+}
+
+// Dictionary keys are strings, as in the YAML and xacro values they come from; other
+// scalars are looked up by their string form, so `d[1]` finds the key `'1'`.
+fn dict_key(value: &Value) -> EvalResult<String> {
+    match value {
+        Value::Str(key) => Ok(key.clone()),
+        Value::None | Value::Bool(_) | Value::Int(_) | Value::Float(_) => Ok(value.to_str()),
+        value => Err(EvalError::Type(format!(
+            "unhashable type: '{}'",
+            value.type_name()
+        ))),
+    }
+}
+
+fn index_value(target: &Value, index: &Value) -> EvalResult<Value> {
+    match target {
+        Value::List(items) | Value::Tuple(items) => {
+            Ok(items[sequence_index(target, index, items.len())?].clone())
+        }
+        Value::Str(s) => {
+            let chars = s.chars().collect::<Vec<_>>();
+            Ok(Value::Str(
+                chars[sequence_index(target, index, chars.len())?].to_string(),
+            ))
+        }
+        Value::Dict(values) => values
+            .get(&dict_key(index)?)
+            .cloned()
+            .ok_or_else(|| EvalError::Key(index.repr())),
+        target => Err(EvalError::Type(format!(
+            "'{}' object is not subscriptable",
+            target.type_name()
+        ))),
+    }
+}
+
+fn sequence_index(target: &Value, index: &Value, len: usize) -> EvalResult<usize> {
+    let type_name = match target {
+        Value::Str(_) => "string",
+        target => target.type_name(),
+    };
+    let Some(Number::Int(index)) = number(index) else {
+        return Err(EvalError::Type(format!(
+            "{} indices must be integers, not {}",
+            type_name,
+            index.type_name()
+        )));
+    };
+    let index = if index < 0 { index + len as i64 } else { index };
+    usize::try_from(index)
+        .ok()
+        .filter(|index| *index < len)
+        .ok_or_else(|| EvalError::Index(format!("{} index out of range", type_name)))
+}
+
+fn slice_bound(value: &Value) -> EvalResult<Option<i64>> {
+    match (value, number(value)) {
+        (Value::None, _) => Ok(None),
+        (_, Some(Number::Int(value))) => Ok(Some(value)),
+        _ => Err(EvalError::Type(
+            "slice indices must be integers or None".to_string(),
+        )),
+    }
+}
+
+fn slice_value(target: &Value, [start, stop, step]: [Option<i64>; 3]) -> EvalResult<Value> {
+    let step = step.unwrap_or(1);
+    if step == 0 {
+        return Err(EvalError::Value("slice step cannot be zero".to_string()));
+    }
+    let indices = |len: usize| {
+        let len = len as i64;
+        let clamp = |value: i64, low: i64, high: i64| {
+            let value = if value < 0 { value + len } else { value };
+            value.clamp(low, high)
+        };
+        let mut indices = Vec::new();
+        if step > 0 {
+            let mut i = start.map_or(0, |value| clamp(value, 0, len));
+            let stop = stop.map_or(len, |value| clamp(value, 0, len));
+            while i < stop {
+                indices.push(i as usize);
+                i += step;
+            }
+        } else {
+            let mut i = start.map_or(len - 1, |value| clamp(value, -1, len - 1));
+            let stop = stop.map_or(-1, |value| clamp(value, -1, len - 1));
+            while i > stop {
+                indices.push(i as usize);
+                i += step;
+            }
+        }
+        indices
+    };
+    match target {
+        Value::List(items) => Ok(Value::List(
+            indices(items.len())
+                .into_iter()
+                .map(|i| items[i].clone())
+                .collect(),
+        )),
+        Value::Tuple(items) => Ok(Value::Tuple(
+            indices(items.len())
+                .into_iter()
+                .map(|i| items[i].clone())
+                .collect(),
+        )),
+        Value::Str(s) => {
+            let chars = s.chars().collect::<Vec<_>>();
+            Ok(Value::Str(
+                indices(chars.len()).into_iter().map(|i| chars[i]).collect(),
+            ))
+        }
+        target => Err(EvalError::Type(format!(
+            "'{}' object is not subscriptable",
+            target.type_name()
+        ))),
+    }
+}
+
+const STR_METHODS: &[&str] = &[
+    "capitalize",
+    "center",
+    "count",
+    "endswith",
+    "find",
+    "format",
+    "index",
+    "isalnum",
+    "isalpha",
+    "isdigit",
+    "islower",
+    "isspace",
+    "isupper",
+    "join",
+    "ljust",
+    "lower",
+    "lstrip",
+    "replace",
+    "rfind",
+    "rjust",
+    "rsplit",
+    "rstrip",
+    "split",
+    "startswith",
+    "strip",
+    "title",
+    "upper",
+    "zfill",
+];
+const SEQUENCE_METHODS: &[&str] = &["count", "index"];
+const DICT_METHODS: &[&str] = &["get", "items", "keys", "values"];
+
+// Dictionaries also expose their keys as attributes, like upstream xacro's YAML values do,
+// so `cfg.robot.name` works as well as `cfg['robot']['name']`.
+fn attribute(target: Value, name: &str) -> EvalResult<Value> {
+    let methods = match &target {
+        Value::Dict(values) => {
+            if let Some(value) = values.get(name) {
+                return Ok(value.clone());
+            }
+            DICT_METHODS
+        }
+        Value::Str(_) => STR_METHODS,
+        Value::List(_) | Value::Tuple(_) => SEQUENCE_METHODS,
+        _ => &[],
+    };
+    if methods.contains(&name) {
+        Ok(Value::Method(Box::new(target), name.to_string()))
+    } else {
+        Err(EvalError::Attribute(format!(
+            "'{}' object has no attribute '{}'",
+            target.type_name(),
+            name
+        )))
+    }
+}
+
+fn call_method(
+    receiver: &Value,
+    name: &str,
+    args: &[Value],
+    keywords: &[(String, Value)],
+) -> EvalResult<Value> {
+    if name != "format" {
+        no_keywords(name, keywords)?;
+    }
+    match receiver {
+        Value::Str(s) => str_method(s, name, args, keywords),
+        Value::List(items) | Value::Tuple(items) => {
+            let [item] = arity(name, args, 1, 1)? else {
+                unreachable!()
+            };
+            let mut matches = items.iter().map(|value| values_equal(value, item));
+            match name {
+                "count" => Ok(Value::Int(matches.filter(|found| *found).count() as i64)),
+                _ => matches
+                    .position(|found| found)
+                    .map(|index| Value::Int(index as i64))
+                    .ok_or_else(|| {
+                        EvalError::Value(format!(
+                            "{} is not in {}",
+                            item.repr(),
+                            receiver.type_name()
+                        ))
+                    }),
+            }
+        }
+        Value::Dict(values) => match name {
+            "get" => {
+                let args = arity(name, args, 1, 2)?;
+                Ok(values
+                    .get(&dict_key(&args[0])?)
+                    .or(args.get(1))
+                    .cloned()
+                    .unwrap_or(Value::None))
+            }
+            "items" => {
+                arity(name, args, 0, 0)?;
+                Ok(Value::List(
+                    values
+                        .iter()
+                        .map(|(key, value)| {
+                            Value::Tuple(vec![Value::Str(key.clone()), value.clone()])
+                        })
+                        .collect(),
+                ))
+            }
+            "keys" => {
+                arity(name, args, 0, 0)?;
+                iterate(receiver).map(Value::List)
+            }
+            _ => {
+                arity(name, args, 0, 0)?;
+                Ok(Value::List(values.values().cloned().collect()))
+            }
+        },
+        _ => unreachable!("methods are only looked up on strings, sequences and dicts"),
+    }
+}
+
+fn str_method(
+    s: &str,
+    name: &str,
+    args: &[Value],
+    keywords: &[(String, Value)],
+) -> EvalResult<Value> {
+    let string = |value: String| Ok(Value::Str(value));
+    let boolean = |value: bool| Ok(Value::Bool(value));
+    let all_chars = |test: fn(char) -> bool| !s.is_empty() && s.chars().all(test);
+    match name {
+        "format" => string(format_string(s, args, keywords)?),
+        "join" => {
+            let [items] = arity(name, args, 1, 1)? else {
+                unreachable!()
+            };
+            let items = iterate(items)?
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| match item {
+                    Value::Str(item) => Ok(item),
+                    item => Err(EvalError::Type(format!(
+                        "sequence item {}: expected str instance, {} found",
+                        i,
+                        item.type_name()
+                    ))),
+                })
+                .collect::<EvalResult<Vec<_>>>()?;
+            string(items.join(s))
+        }
+        "split" | "rsplit" => {
+            let args = arity(name, args, 0, 2)?;
+            let separator = match args.first() {
+                None | Some(Value::None) => None,
+                Some(value) => Some(str_arg(name, value)?),
+            };
+            let max_splits = match args.get(1) {
+                Some(value) => usize::try_from(int_arg(value)?).ok(),
+                None => None,
+            };
+            let parts = split(s, separator, max_splits, name == "rsplit")?;
+            Ok(Value::List(parts.into_iter().map(Value::Str).collect()))
+        }
+        "strip" | "lstrip" | "rstrip" => {
+            let args = arity(name, args, 0, 1)?;
+            let chars = match args.first() {
+                None | Some(Value::None) => None,
+                Some(value) => Some(str_arg(name, value)?.chars().collect::<Vec<_>>()),
+            };
+            let strip = |ch: char| match &chars {
+                Some(chars) => chars.contains(&ch),
+                None => ch.is_whitespace(),
+            };
+            string(
+                match name {
+                    "strip" => s.trim_matches(strip),
+                    "lstrip" => s.trim_start_matches(strip),
+                    _ => s.trim_end_matches(strip),
+                }
+                .to_string(),
+            )
+        }
+        "replace" => {
+            let args = arity(name, args, 2, 3)?;
+            let (old, new) = (str_arg(name, &args[0])?, str_arg(name, &args[1])?);
+            match args.get(2).map(int_arg).transpose()? {
+                Some(count) if count >= 0 => string(s.replacen(old, new, count as usize)),
+                _ => string(s.replace(old, new)),
+            }
+        }
+        "startswith" | "endswith" => {
+            let [affix] = arity(name, args, 1, 1)? else {
+                unreachable!()
+            };
+            let affixes = match affix {
+                Value::Tuple(items) => items.iter().collect::<Vec<_>>(),
+                affix => vec![affix],
+            };
+            for affix in affixes {
+                let affix = str_arg(name, affix)?;
+                let found = if name == "startswith" {
+                    s.starts_with(affix)
+                } else {
+                    s.ends_with(affix)
+                };
+                if found {
+                    return boolean(true);
+                }
+            }
+            boolean(false)
+        }
+        "find" | "rfind" | "index" | "count" => {
+            let [sub] = arity(name, args, 1, 1)? else {
+                unreachable!()
+            };
+            let sub = str_arg(name, sub)?;
+            let char_index = |byte: usize| Value::Int(s[..byte].chars().count() as i64);
+            match name {
+                "count" if sub.is_empty() => Ok(Value::Int(s.chars().count() as i64 + 1)),
+                "count" => Ok(Value::Int(s.matches(sub).count() as i64)),
+                "find" => Ok(s.find(sub).map_or(Value::Int(-1), char_index)),
+                "rfind" => Ok(s.rfind(sub).map_or(Value::Int(-1), char_index)),
+                _ => s
+                    .find(sub)
+                    .map(char_index)
+                    .ok_or_else(|| EvalError::Value("substring not found".to_string())),
+            }
+        }
+        "center" | "ljust" | "rjust" | "zfill" => {
+            let args = arity(name, args, 1, if name == "zfill" { 1 } else { 2 })?;
+            let width = usize::try_from(int_arg(&args[0])?).unwrap_or(0);
+            check_length(width)?;
+            let fill = match args.get(1) {
+                Some(fill) => {
+                    let fill = str_arg(name, fill)?;
+                    let mut chars = fill.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(ch), None) => ch,
+                        _ => {
+                            return Err(EvalError::Type(
+                                "the fill character must be exactly one character long".to_string(),
+                            ))
+                        }
+                    }
+                }
+                None => ' ',
+            };
+            let align = match name {
+                "center" => '^',
+                "ljust" => '<',
+                "rjust" => '>',
+                _ => '=',
+            };
+            if name == "zfill" {
+                let (sign, digits) = match s.strip_prefix(['+', '-']) {
+                    Some(digits) => (&s[..1], digits),
+                    None => ("", s),
+                };
+                return string(pad(sign, digits, '0', align, width));
+            }
+            string(pad("", s, fill, align, width))
+        }
+        "lower" => string(s.to_lowercase()),
+        "upper" => string(s.to_uppercase()),
+        "capitalize" => {
+            let mut chars = s.chars();
+            string(match chars.next() {
+                Some(first) => first
+                    .to_uppercase()
+                    .chain(chars.flat_map(char::to_lowercase))
+                    .collect(),
+                None => String::new(),
+            })
+        }
+        "title" => {
+            let mut previous_is_letter = false;
+            string(
+                s.chars()
+                    .flat_map(|ch| {
+                        let title = if previous_is_letter {
+                            ch.to_lowercase().collect::<Vec<_>>()
+                        } else {
+                            ch.to_uppercase().collect::<Vec<_>>()
+                        };
+                        previous_is_letter = ch.is_alphabetic();
+                        title
+                    })
+                    .collect(),
+            )
+        }
+        "isalnum" => boolean(all_chars(char::is_alphanumeric)),
+        "isalpha" => boolean(all_chars(char::is_alphabetic)),
+        "isdigit" => boolean(all_chars(|ch| ch.is_ascii_digit())),
+        "isspace" => boolean(all_chars(char::is_whitespace)),
+        "islower" => {
+            boolean(s.chars().any(char::is_lowercase) && !s.chars().any(char::is_uppercase))
+        }
+        "isupper" => {
+            boolean(s.chars().any(char::is_uppercase) && !s.chars().any(char::is_lowercase))
+        }
+        _ => unreachable!("`{}` is listed in STR_METHODS", name),
+    }
+}
+
+fn split(
+    s: &str,
+    separator: Option<&str>,
+    max_splits: Option<usize>,
+    from_right: bool,
+) -> EvalResult<Vec<String>> {
+    let limit = max_splits.map_or(usize::MAX, |splits| splits.saturating_add(1));
+    let mut parts: Vec<String> = match (separator, from_right) {
+        (Some(""), _) => return Err(EvalError::Value("empty separator".to_string())),
+        (Some(separator), false) => s.splitn(limit, separator).map(str::to_string).collect(),
+        (Some(separator), true) => s.rsplitn(limit, separator).map(str::to_string).collect(),
+        (None, false) => {
+            let mut parts = Vec::new();
+            let mut rest = s.trim_start();
+            while !rest.is_empty() {
+                if parts.len() + 1 == limit {
+                    parts.push(rest.trim_end().to_string());
+                    break;
+                }
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                parts.push(rest[..end].to_string());
+                rest = rest[end..].trim_start();
+            }
+            parts
+        }
+        (None, true) => {
+            let mut parts = Vec::new();
+            let mut rest = s.trim_end();
+            while !rest.is_empty() {
+                if parts.len() + 1 == limit {
+                    parts.push(rest.trim_start().to_string());
+                    break;
+                }
+                let start = rest.rfind(char::is_whitespace).map_or(0, |i| {
+                    i + rest[i..].chars().next().map_or(1, char::len_utf8)
+                });
+                parts.push(rest[start..].to_string());
+                rest = rest[..start].trim_end();
+            }
+            parts
+        }
+    };
+    if from_right {
+        parts.reverse();
+    }
+    Ok(parts)
+}
+
+// Checks that a call got `min..=max` positional arguments.
+fn arity<'v>(name: &str, args: &'v [Value], min: usize, max: usize) -> EvalResult<&'v [Value]> {
+    if (min..=max).contains(&args.len()) {
+        return Ok(args);
+    }
+    let expected = if min == max {
+        format!("exactly {}", min)
+    } else {
+        format!("from {} to {}", min, max)
+    };
+    Err(EvalError::Type(format!(
+        "{}() takes {} argument{} ({} given)",
+        name,
+        expected,
+        if max == 1 { "" } else { "s" },
+        args.len()
+    )))
+}
+
+fn str_arg<'v>(name: &str, value: &'v Value) -> EvalResult<&'v str> {
+    match value {
+        Value::Str(value) => Ok(value),
+        value => Err(EvalError::Type(format!(
+            "{}() argument must be str, not {}",
+            name,
+            value.type_name()
+        ))),
+    }
+}
+
+pub(crate) fn int_arg(value: &Value) -> EvalResult<i64> {
+    match number(value) {
+        Some(Number::Int(value)) => Ok(value),
+        _ => Err(EvalError::Type(format!(
+            "'{}' object cannot be interpreted as an integer",
+            value.type_name()
+        ))),
+    }
+}
+
+pub(crate) fn float_arg(value: &Value) -> EvalResult<f64> {
+    match number(value) {
+        Some(number) => Ok(number.to_f64()),
+        None => Err(EvalError::Type(format!(
+            "must be real number, not {}",
+            value.type_name()
+        ))),
+    }
+}
+
+pub(crate) fn float_to_int(value: f64) -> EvalResult<i64> {
+    if value.is_nan() {
+        return Err(EvalError::Value(
+            "cannot convert float NaN to integer".to_string(),
+        ));
+    }
+    if value.is_infinite() {
+        return Err(EvalError::Overflow(
+            "cannot convert float infinity to integer".to_string(),
+        ));
+    }
+    let value = value.trunc();
+    // `i64::MAX as f64` rounds up to 2**63, which is already out of range.
+    if value < i64::MIN as f64 || value >= i64::MAX as f64 {
+        return Err(integer_overflow());
+    }
+    Ok(value as i64)
+}
+
+static BUILTINS_BY_NAME: Lazy<HashMap<&str, Builtin>> = Lazy::new(|| builtins_by_name(BUILTINS));
+
+pub(crate) fn builtins_by_name(builtins: &[Builtin]) -> HashMap<&'static str, Builtin> {
+    builtins
+        .iter()
+        .map(|builtin| (builtin.name(), *builtin))
+        .collect()
+}
+
+static BUILTINS: &[Builtin] = &[
+    Builtin::Function("abs", |args| match arity("abs", args, 1, 1)? {
+        [value] => match number(value) {
+            Some(Number::Int(value)) => value
+                .checked_abs()
+                .map(Value::Int)
+                .ok_or_else(integer_overflow),
+            Some(Number::Float(value)) => Ok(Value::Float(value.abs())),
+            None => Err(EvalError::Type(format!(
+                "bad operand type for abs(): '{}'",
+                value.type_name()
+            ))),
+        },
+        _ => unreachable!(),
+    }),
+    Builtin::Function("all", |args| {
+        let [items] = arity("all", args, 1, 1)? else {
+            unreachable!()
+        };
+        Ok(Value::Bool(iterate(items)?.iter().all(Value::is_truthy)))
+    }),
+    Builtin::Function("any", |args| {
+        let [items] = arity("any", args, 1, 1)? else {
+            unreachable!()
+        };
+        Ok(Value::Bool(iterate(items)?.iter().any(Value::is_truthy)))
+    }),
+    Builtin::Function("bool", |args| {
+        let args = arity("bool", args, 0, 1)?;
+        Ok(Value::Bool(args.first().is_some_and(Value::is_truthy)))
+    }),
+    Builtin::Interpreter("dict", builtin_dict),
+    Builtin::Function("divmod", |args| {
+        let [a, b] = arity("divmod", args, 2, 2)? else {
+            unreachable!()
+        };
+        Ok(Value::Tuple(vec![
+            binary(BinaryOp::FloorDiv, a.clone(), b.clone())?,
+            binary(BinaryOp::Mod, a.clone(), b.clone())?,
+        ]))
+    }),
+    Builtin::Interpreter("enumerate", builtin_enumerate),
+    Builtin::Interpreter("filter", builtin_filter),
+    Builtin::Function("float", builtin_float),
+    Builtin::Function("format", |args| {
+        let args = arity("format", args, 1, 2)?;
+        let spec = match args.get(1) {
+            Some(spec) => str_arg("format", spec)?,
+            None => "",
+        };
+        Ok(Value::Str(format_value(&args[0], spec)?))
+    }),
+    Builtin::Function("int", builtin_int),
+    Builtin::Function("len", |args| {
+        let [value] = arity("len", args, 1, 1)? else {
+            unreachable!()
+        };
+        let len = match value {
+            Value::Str(s) => s.chars().count(),
+            Value::List(items) | Value::Tuple(items) => items.len(),
+            Value::Dict(values) => values.len(),
+            value => {
+                return Err(EvalError::Type(format!(
+                    "object of type '{}' has no len()",
+                    value.type_name()
+                )))
+            }
+        };
+        Ok(Value::Int(len as i64))
+    }),
+    Builtin::Function("list", |args| match arity("list", args, 0, 1)? {
+        [items] => iterate(items).map(Value::List),
+        _ => Ok(Value::List(Vec::new())),
+    }),
+    Builtin::Interpreter("map", builtin_map),
+    Builtin::Interpreter("max", |interp, args, keywords| {
+        builtin_extremum(interp, "max", Ordering::Greater, args, keywords)
+    }),
+    Builtin::Interpreter("min", |interp, args, keywords| {
+        builtin_extremum(interp, "min", Ordering::Less, args, keywords)
+    }),
+    Builtin::Function("range", builtin_range),
+    Builtin::Function("repr", |args| {
+        let [value] = arity("repr", args, 1, 1)? else {
+            unreachable!()
+        };
+        Ok(Value::Str(value.repr()))
+    }),
+    Builtin::Function("reversed", |args| {
+        let [items] = arity("reversed", args, 1, 1)? else {
+            unreachable!()
+        };
+        let mut items = iterate(items)?;
+        items.reverse();
+        Ok(Value::List(items))
+    }),
+    Builtin::Function("round", builtin_round),
+    Builtin::Interpreter("sorted", builtin_sorted),
+    Builtin::Function("str", |args| {
+        let args = arity("str", args, 0, 1)?;
+        Ok(Value::Str(
+            args.first().map(Value::to_str).unwrap_or_default(),
+        ))
+    }),
+    Builtin::Function("sum", |args| {
+        let args = arity("sum", args, 1, 2)?;
+        let mut total = args.get(1).cloned().unwrap_or(Value::Int(0));
+        for item in iterate(&args[0])? {
+            total = binary(BinaryOp::Add, total, item)?;
+        }
+        Ok(total)
+    }),
+    Builtin::Function("tuple", |args| match arity("tuple", args, 0, 1)? {
+        [items] => iterate(items).map(Value::Tuple),
+        _ => Ok(Value::Tuple(Vec::new())),
+    }),
+    Builtin::Function("zip", |args| {
+        let columns = args.iter().map(iterate).collect::<EvalResult<Vec<_>>>()?;
+        let len = columns.iter().map(Vec::len).min().unwrap_or(0);
+        Ok(Value::List(
+            (0..len)
+                .map(|i| Value::Tuple(columns.iter().map(|column| column[i].clone()).collect()))
+                .collect(),
+        ))
+    }),
+];
+
+pub(crate) fn keyword_args<'v, const N: usize>(
+    name: &str,
+    keywords: &'v [(String, Value)],
+    allowed: [&str; N],
+) -> EvalResult<[Option<&'v Value>; N]> {
+    let mut values = [None; N];
+    for (keyword, value) in keywords.iter() {
+        let Some(i) = allowed.iter().position(|allowed| allowed == keyword) else {
+            return Err(EvalError::Type(format!(
+                "'{}' is an invalid keyword argument for {}()",
+                keyword, name
+            )));
+        };
+        values[i] = Some(value);
+    }
+    Ok(values)
+}
+
+fn builtin_dict(
+    _: &mut Interpreter<'_>,
+    args: &[Value],
+    keywords: &[(String, Value)],
+) -> EvalResult<Value> {
+    let mut values = IndexMap::new();
+    match arity("dict", args, 0, 1)? {
+        [Value::Dict(items)] => values.extend(items.clone()),
+        [items] => {
+            for item in iterate(items)? {
+                match iterate(&item)?.as_slice() {
+                    [key, value] => {
+                        values.insert(dict_key(key)?, value.clone());
+                    }
+                    pair => {
+                        return Err(EvalError::Value(format!(
+                            "dictionary update sequence element has length {}; 2 is required",
+                            pair.len()
+                        )))
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    values.extend(keywords.iter().cloned());
+    Ok(Value::Dict(values))
+}
+
+fn builtin_enumerate(
+    _: &mut Interpreter<'_>,
+    args: &[Value],
+    keywords: &[(String, Value)],
+) -> EvalResult<Value> {
+    let [start] = keyword_args("enumerate", keywords, ["start"])?;
+    let args = arity("enumerate", args, 1, 2)?;
+    let start = match args.get(1).or(start) {
+        Some(start) => int_arg(start)?,
+        None => 0,
+    };
+    let items = iterate(&args[0])?;
+    Ok(Value::List(
+        items
+            .into_iter()
+            .zip(start..)
+            .map(|(item, i)| Value::Tuple(vec![Value::Int(i), item]))
+            .collect(),
+    ))
+}
+
+fn builtin_filter(
+    interp: &mut Interpreter<'_>,
+    args: &[Value],
+    keywords: &[(String, Value)],
+) -> EvalResult<Value> {
+    no_keywords("filter", keywords)?;
+    let [func, items] = arity("filter", args, 2, 2)? else {
+        unreachable!()
+    };
+    let mut kept = Vec::new();
+    for item in iterate(items)? {
+        let keep = match func {
+            Value::None => item.is_truthy(),
+            func => interp
+                .call(func, std::slice::from_ref(&item), &[])?
+                .is_truthy(),
+        };
+        if keep {
+            kept.push(item);
+        }
+    }
+    Ok(Value::List(kept))
+}
+
+fn builtin_map(
+    interp: &mut Interpreter<'_>,
+    args: &[Value],
+    keywords: &[(String, Value)],
+) -> EvalResult<Value> {
+    no_keywords("map", keywords)?;
+    let Some((func, iterables)) = args.split_first().filter(|(_, rest)| !rest.is_empty()) else {
+        return Err(EvalError::Type(
+            "map() must have at least two arguments".to_string(),
+        ));
+    };
+    let columns = iterables
+        .iter()
+        .map(iterate)
+        .collect::<EvalResult<Vec<_>>>()?;
+    let len = columns.iter().map(Vec::len).min().unwrap_or(0);
+    let mut results = Vec::with_capacity(len);
+    for i in 0..len {
+        let row = columns
+            .iter()
+            .map(|column| column[i].clone())
+            .collect::<Vec<_>>();
+        results.push(interp.call(func, &row, &[])?);
+    }
+    Ok(Value::List(results))
+}
+
+// `max` and `min` return the winning argument itself, so `max(3, 2.0)` is the int `3`.
+fn builtin_extremum(
+    interp: &mut Interpreter<'_>,
+    name: &str,
+    wanted: Ordering,
+    args: &[Value],
+    keywords: &[(String, Value)],
+) -> EvalResult<Value> {
+    let [key, default] = keyword_args(name, keywords, ["key", "default"])?;
+    let items = match args {
+        [] => {
+            return Err(EvalError::Type(format!(
+                "{} expected at least 1 argument, got 0",
+                name
+            )))
+        }
+        [items] => iterate(items)?,
+        items if default.is_some() => {
+            return Err(EvalError::Type(format!(
+                "Cannot specify a default for {}() with multiple positional arguments ({} given)",
+                name,
+                items.len()
+            )))
+        }
+        items => items.to_vec(),
+    };
+
+    let mut best: Option<(Value, Value)> = None;
+    for item in items {
+        let item_key = match key {
+            Some(Value::None) | None => item.clone(),
+            Some(key) => interp.call(key, std::slice::from_ref(&item), &[])?,
+        };
+        let better = match &best {
+            Some((_, best_key)) => order(&item_key, best_key, "<")? == Some(wanted),
+            None => true,
+        };
+        if better {
+            best = Some((item, item_key));
+        }
+    }
+    best.map(|(item, _)| item)
+        .or_else(|| default.cloned())
+        .ok_or_else(|| EvalError::Value(format!("{}() arg is an empty sequence", name)))
+}
+
+fn builtin_sorted(
+    interp: &mut Interpreter<'_>,
+    args: &[Value],
+    keywords: &[(String, Value)],
+) -> EvalResult<Value> {
+    let [key, reverse] = keyword_args("sorted", keywords, ["key", "reverse"])?;
+    let [items] = arity("sorted", args, 1, 1)? else {
+        unreachable!()
+    };
+    let reverse = reverse.is_some_and(Value::is_truthy);
+    let mut keyed = Vec::new();
+    for item in iterate(items)? {
+        let item_key = match key {
+            Some(Value::None) | None => item.clone(),
+            Some(key) => interp.call(key, std::slice::from_ref(&item), &[])?,
+        };
+        keyed.push((item_key, item));
+    }
+
+    let mut error = None;
+    keyed.sort_by(|(a, _), (b, _)| {
+        let ordering = match order(a, b, "<") {
+            Ok(ordering) => ordering.unwrap_or(Ordering::Equal),
+            Err(err) => {
+                error.get_or_insert(err);
+                Ordering::Equal
+            }
+        };
+        // Reversing each comparison keeps equal items in their original order.
+        if reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+    match error {
+        Some(err) => Err(err),
+        None => Ok(Value::List(
+            keyed.into_iter().map(|(_, item)| item).collect(),
+        )),
+    }
+}
+
+fn builtin_float(args: &[Value]) -> EvalResult<Value> {
+    match arity("float", args, 0, 1)? {
+        [] => Ok(Value::Float(0.0)),
+        [Value::Str(s)] => {
+            let text = s.trim();
+            text.parse::<f64>()
+                .ok()
+                .or_else(|| {
+                    let digits = text.replace('_', "");
+                    (text.contains('_') && !text.starts_with('_') && !text.ends_with('_'))
+                        .then(|| digits.parse().ok())
+                        .flatten()
+                })
+                .map(Value::Float)
+                .ok_or_else(|| {
+                    EvalError::Value(format!("could not convert string to float: {}", quote(s)))
+                })
+        }
+        [value] => match number(value) {
+            Some(number) => Ok(Value::Float(number.to_f64())),
+            None => Err(EvalError::Type(format!(
+                "float() argument must be a string or a real number, not '{}'",
+                value.type_name()
+            ))),
+        },
+        _ => unreachable!(),
+    }
+}
+
+fn builtin_int(args: &[Value]) -> EvalResult<Value> {
+    match arity("int", args, 0, 2)? {
+        [] => Ok(Value::Int(0)),
+        [Value::Str(s)] => parse_int(s, 10).map(Value::Int),
+        [Value::Str(s), base] => parse_int(s, int_arg(base)?).map(Value::Int),
+        [_, _] => Err(EvalError::Type(
+            "int() can't convert non-string with explicit base".to_string(),
+        )),
+        [value] => match number(value) {
+            Some(Number::Int(value)) => Ok(Value::Int(value)),
+            Some(Number::Float(value)) => float_to_int(value).map(Value::Int),
+            None => Err(EvalError::Type(format!(
+                "int() argument must be a string or a real number, not '{}'",
+                value.type_name()
+            ))),
+        },
+        _ => unreachable!(),
+    }
+}
+
+fn parse_int(s: &str, base: i64) -> EvalResult<i64> {
+    if base != 0 && !(2..=36).contains(&base) {
+        return Err(EvalError::Value(
+            "int() base must be >= 2 and <= 36, or 0".to_string(),
+        ));
+    }
+    let invalid = || {
+        EvalError::Value(format!(
+            "invalid literal for int() with base {}: {}",
+            base,
+            quote(s)
+        ))
+    };
+    let text = s.trim();
+    let (sign, digits) = match text.strip_prefix(['+', '-']) {
+        Some(digits) => (&text[..1], digits),
+        None => ("", text),
+    };
+    let prefix = digits.get(..2).map(str::to_ascii_lowercase);
+    let (radix, digits) = match (base, prefix.as_deref()) {
+        (0 | 16, Some("0x")) => (16, &digits[2..]),
+        (0 | 8, Some("0o")) => (8, &digits[2..]),
+        (0 | 2, Some("0b")) => (2, &digits[2..]),
+        (0, _) => (10, digits),
+        (base, _) => (base as u32, digits),
+    };
+    if digits.is_empty()
+        || digits.starts_with('_')
+        || digits.ends_with('_')
+        || digits.contains("__")
+    {
+        return Err(invalid());
+    }
+    let digits = format!("{}{}", sign, digits.replace('_', ""));
+    i64::from_str_radix(&digits, radix).map_err(|err| match err.kind() {
+        std::num::IntErrorKind::PosOverflow | std::num::IntErrorKind::NegOverflow => {
+            integer_overflow()
+        }
+        _ => invalid(),
+    })
+}
+
+fn builtin_range(args: &[Value]) -> EvalResult<Value> {
+    let bounds = arity("range", args, 1, 3)?
+        .iter()
+        .map(int_arg)
+        .collect::<EvalResult<Vec<_>>>()?;
+    let (start, stop, step) = match bounds[..] {
+        [stop] => (0, stop, 1),
+        [start, stop] => (start, stop, 1),
+        [start, stop, step] => (start, stop, step),
+        _ => unreachable!(),
+    };
+    if step == 0 {
+        return Err(EvalError::Value(
+            "range() arg 3 must not be zero".to_string(),
+        ));
+    }
+    let (start, stop, step) = (i128::from(start), i128::from(stop), i128::from(step));
+    let len = if step > 0 {
+        (stop - start + step - 1).div_euclid(step)
+    } else {
+        (start - stop - step - 1).div_euclid(-step)
+    }
+    .max(0);
+    check_length(len as usize)?;
+    Ok(Value::List(
+        (0..len)
+            .map(|i| Value::Int((start + i * step) as i64))
+            .collect(),
+    ))
+}
+
+// `round(x)` gives an int with ties to even; `round(x, n)` keeps the type of `x` and rounds to
+// `n` decimal digits of its exact value, so `round(2.675, 2)` is `2.67`.
+fn builtin_round(args: &[Value]) -> EvalResult<Value> {
+    let args = arity("round", args, 1, 2)?;
+    let value = number(&args[0]).ok_or_else(|| {
+        EvalError::Type(format!(
+            "type {} doesn't define __round__ method",
+            args[0].type_name()
+        ))
+    })?;
+    let digits = match args.get(1) {
+        None | Some(Value::None) => None,
+        Some(digits) => Some(int_arg(digits)?),
+    };
+    match (value, digits) {
+        (Number::Int(value), None) => Ok(Value::Int(value)),
+        (Number::Float(value), None) => float_to_int(value.round_ties_even()).map(Value::Int),
+        (Number::Int(value), Some(digits)) => round_int(value, digits).map(Value::Int),
+        (Number::Float(value), Some(digits)) => Ok(Value::Float(round_float(value, digits))),
+    }
+}
+
+fn round_int(value: i64, digits: i64) -> EvalResult<i64> {
+    if digits >= 0 {
+        return Ok(value);
+    }
+    let Some(scale) = u32::try_from(-digits)
+        .ok()
+        .and_then(|exponent| 10i64.checked_pow(exponent))
+    else {
+        return Ok(0);
+    };
+    let remainder = value.rem_euclid(scale);
+    let base = value - remainder;
+    let round_up = remainder > scale - remainder
+        || (remainder == scale - remainder && (base / scale) % 2 != 0);
+    if round_up {
+        base.checked_add(scale).ok_or_else(integer_overflow)
+    } else {
+        Ok(base)
+    }
+}
+
+fn round_float(value: f64, digits: i64) -> f64 {
+    if !value.is_finite() {
+        return value;
+    }
+    if digits >= 0 {
+        // Formatting rounds the exact binary value, as CPython does.
+        return format!("{:.*}", digits.min(1100) as usize, value)
+            .parse()
+            .unwrap_or(value);
+    }
+    let scale = 10f64.powf(-(digits.max(-400) as f64));
+    if scale.is_infinite() {
+        0f64.copysign(value)
+    } else {
+        (value / scale).round_ties_even() * scale
+    }
+}
+
+// Pads `sign` followed by `body` to `width` characters; `=` puts the fill between the two.
+fn pad(sign: &str, body: &str, fill: char, align: char, width: usize) -> String {
+    let len = sign.chars().count() + body.chars().count();
+    let padding = width.saturating_sub(len);
+    let fill_with = |count: usize| fill.to_string().repeat(count);
+    match align {
+        '<' => format!("{}{}{}", sign, body, fill_with(padding)),
+        '^' => format!(
+            "{}{}{}{}",
+            fill_with(padding / 2),
+            sign,
+            body,
+            fill_with(padding - padding / 2)
+        ),
+        '=' => format!("{}{}{}", sign, fill_with(padding), body),
+        _ => format!("{}{}{}", fill_with(padding), sign, body),
+    }
+}
+
+#[derive(Default)]
+struct FormatSpec {
+    fill: Option<char>,
+    align: Option<char>,
+    sign: Option<char>,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    grouping: Option<char>,
+    precision: Option<usize>,
+    kind: Option<char>,
+}
+
+// `[[fill]align][sign][#][0][width][,|_][.precision][type]`, as in Python's `format()`.
+fn parse_format_spec(spec: &str) -> EvalResult<FormatSpec> {
+    let invalid = || EvalError::Value(format!("invalid format specifier '{}'", spec));
+    let chars = spec.chars().collect::<Vec<_>>();
+    let mut parsed = FormatSpec::default();
+    let mut i = 0;
+    let is_align = |ch: &char| "<>=^".contains(*ch);
+    if chars.get(1).is_some_and(is_align) {
+        parsed.fill = Some(chars[0]);
+        parsed.align = Some(chars[1]);
+        i = 2;
+    } else if chars.first().is_some_and(is_align) {
+        parsed.align = Some(chars[0]);
+        i = 1;
+    }
+    if let Some(sign) = chars.get(i).filter(|ch| "+- ".contains(**ch)) {
+        parsed.sign = Some(*sign);
+        i += 1;
+    }
+    if chars.get(i) == Some(&'#') {
+        parsed.alternate = true;
+        i += 1;
+    }
+    if chars.get(i) == Some(&'0') {
+        parsed.zero = true;
+        i += 1;
+    }
+    let digits = |i: &mut usize| {
+        let start = *i;
+        while chars.get(*i).is_some_and(char::is_ascii_digit) {
+            *i += 1;
+        }
+        chars[start..*i].iter().collect::<String>()
+    };
+    let width = digits(&mut i);
+    if !width.is_empty() {
+        parsed.width = width.parse().map_err(|_| invalid())?;
+        check_length(parsed.width)?;
+    }
+    if let Some(grouping) = chars.get(i).filter(|ch| ",_".contains(**ch)) {
+        parsed.grouping = Some(*grouping);
+        i += 1;
+    }
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        let precision = digits(&mut i);
+        parsed.precision = Some(precision.parse().map_err(|_| invalid())?);
+    }
+    parsed.kind = chars.get(i).copied();
+    if chars.len() > i + 1 {
+        return Err(invalid());
+    }
+    Ok(parsed)
+}
+
+pub(crate) fn format_value(value: &Value, spec: &str) -> EvalResult<String> {
+    let spec = parse_format_spec(spec)?;
+    let unknown = |kind: char| {
+        EvalError::Value(format!(
+            "Unknown format code '{}' for object of type '{}'",
+            kind,
+            value.type_name()
+        ))
+    };
+
+    let numeric = match (value, spec.kind) {
+        (Value::Bool(_), None) | (Value::Str(_), _) | (_, Some('s')) => None,
+        (value, _) => number(value),
+    };
+    let Some(numeric) = numeric else {
+        if let Some(kind) = spec.kind.filter(|kind| *kind != 's') {
+            return Err(unknown(kind));
+        }
+        let text = value.to_str();
+        let text = match spec.precision {
+            Some(precision) => text.chars().take(precision).collect(),
+            None => text,
+        };
+        let fill = spec.fill.unwrap_or(' ');
+        return Ok(pad("", &text, fill, spec.align.unwrap_or('<'), spec.width));
+    };
+
+    let (negative, body) = match (numeric, spec.kind) {
+        (Number::Int(value), None | Some('d' | 'n')) => {
+            (value < 0, value.unsigned_abs().to_string())
+        }
+        (Number::Int(value), Some(kind @ ('b' | 'o' | 'x' | 'X'))) => {
+            let magnitude = value.unsigned_abs();
+            let digits = match kind {
+                'b' => format!("{:b}", magnitude),
+                'o' => format!("{:o}", magnitude),
+                'x' => format!("{:x}", magnitude),
+                _ => format!("{:X}", magnitude),
+            };
+            let prefix = if spec.alternate {
+                match kind {
+                    'b' => "0b",
+                    'o' => "0o",
+                    'x' => "0x",
+                    _ => "0X",
+                }
+            } else {
+                ""
+            };
+            (value < 0, format!("{}{}", prefix, digits))
+        }
+        (Number::Int(value), Some('c')) => {
+            let ch = u32::try_from(value)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| EvalError::Overflow("%c arg not in range(0x110000)".to_string()))?;
+            (false, ch.to_string())
+        }
+        (number, kind @ (None | Some('e' | 'E' | 'f' | 'F' | 'g' | 'G' | '%'))) => {
+            let value = number.to_f64();
+            (
+                value.is_sign_negative() && !value.is_nan(),
+                format_float(value.abs(), kind, spec.precision, spec.alternate),
+            )
+        }
+        (_, Some(kind)) => return Err(unknown(kind)),
+    };
+
+    let body = match spec.grouping {
+        Some(separator) => group_digits(&body, separator),
+        None => body,
+    };
+    let sign = match (negative, spec.sign) {
+        (true, _) => "-",
+        (false, Some('+')) => "+",
+        (false, Some(' ')) => " ",
+        _ => "",
+    };
+    let (fill, align) = match (spec.fill, spec.align) {
+        (fill, Some(align)) => (fill.unwrap_or(' '), align),
+        (_, None) if spec.zero => ('0', '='),
+        (_, None) => (' ', '>'),
+    };
+    Ok(pad(sign, &body, fill, align, spec.width))
+}
+
+// Formats a non-negative float for the `e`, `f`, `g` and `%` presentation types; `None` is
+// `repr`, or `g` when a precision is given.
+fn format_float(
+    value: f64,
+    kind: Option<char>,
+    precision: Option<usize>,
+    alternate: bool,
+) -> String {
+    let upper = kind.is_some_and(|kind| kind.is_ascii_uppercase());
+    if !value.is_finite() {
+        let text = if value.is_nan() { "nan" } else { "inf" };
+        return if upper {
+            text.to_uppercase()
+        } else {
+            text.to_string()
+        };
+    }
+    let text = match (kind, precision) {
+        (None, None) => float_repr(value),
+        (Some('f' | 'F'), precision) => format!("{:.*}", precision.unwrap_or(6), value),
+        (Some('%'), precision) => format!("{:.*}%", precision.unwrap_or(6), value * 100.0),
+        (Some('e' | 'E'), precision) => scientific(value, precision.unwrap_or(6)),
+        (_, precision) => {
+            let precision = precision.unwrap_or(6).max(1);
+            let exponent = if value == 0.0 {
+                0
+            } else {
+                scientific(value, precision - 1)
+                    .split_once('e')
+                    .and_then(|(_, exponent)| exponent.parse::<i64>().ok())
+                    .unwrap_or(0)
+            };
+            let text = if -4 <= exponent && exponent < precision as i64 {
+                format!("{:.*}", (precision as i64 - 1 - exponent) as usize, value)
+            } else {
+                scientific(value, precision - 1)
+            };
+            if alternate {
+                text
+            } else {
+                strip_fraction_zeros(&text)
+            }
+        }
+    };
+    if upper {
+        text.to_uppercase()
+    } else {
+        text
+    }
+}
+
+// `{:e}` with Python's exponent, e.g. `1.500000e+03`.
+fn scientific(value: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = text
+        .split_once('e')
+        .expect("scientific float formatting always has an exponent");
+    let exponent = exponent
+        .parse::<i32>()
+        .expect("scientific float exponent is an integer");
+    let exponent_sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, exponent_sign, exponent.abs())
+}
+
+fn strip_fraction_zeros(text: &str) -> String {
+    let (mantissa, exponent) = match text.find('e') {
+        Some(i) => text.split_at(i),
+        None => (text, ""),
+    };
+    let mantissa = if mantissa.contains('.') {
+        mantissa.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        mantissa
+    };
+    format!("{}{}", mantissa, exponent)
+}
+
+fn group_digits(body: &str, separator: char) -> String {
+    let end = body
+        .find(|ch: char| !ch.is_ascii_digit())
+        .unwrap_or(body.len());
+    let (integer, rest) = body.split_at(end);
+    let mut grouped = String::with_capacity(body.len() + integer.len() / 3);
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped.push_str(rest);
+    grouped
+}
+
+// `str.format` with automatic, numbered and named fields, `!r`/`!s` conversions, format specs
+// and `.attribute` or `[index]` lookups on the field.
+fn format_string(
+    template: &str,
+    args: &[Value],
+    keywords: &[(String, Value)],
+) -> EvalResult<String> {
+    let mut result = String::with_capacity(template.len());
+    let mut next_index = 0;
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        result.push_str(&rest[..i]);
+        let brace = &rest[i..i + 1];
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix(brace) {
+            result.push_str(brace);
+            rest = after;
+            continue;
+        }
+        if brace == "}" {
+            return Err(EvalError::Value(
+                "Single '}' encountered in format string".to_string(),
+            ));
+        }
+        let end = rest
+            .find('}')
+            .ok_or_else(|| EvalError::Value("expected '}' before end of string".to_string()))?;
+        let field = &rest[..end];
+        rest = &rest[end + 1..];
+
+        let (field, spec) = field.split_once(':').unwrap_or((field, ""));
+        let (field, conversion) = match field.split_once('!') {
+            Some((field, conversion)) => (field, Some(conversion)),
+            None => (field, None),
+        };
+        let name_end = field.find(['.', '[']).unwrap_or(field.len());
+        let (name, mut accessors) = field.split_at(name_end);
+        let mut value = if name.is_empty() {
+            next_index += 1;
+            positional_field(args, next_index - 1)?
+        } else if let Ok(index) = name.parse::<usize>() {
+            positional_field(args, index)?
+        } else {
+            keywords
+                .iter()
+                .find(|(keyword, _)| keyword == name)
+                .map(|(_, value)| value.clone())
+                .ok_or_else(|| EvalError::Key(quote(name)))?
+        };
+        while !accessors.is_empty() {
+            if let Some(after) = accessors.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                value = attribute(value, &after[..end])?;
+                accessors = &after[end..];
+            } else {
+                let end = accessors
+                    .find(']')
+                    .ok_or_else(|| EvalError::Value("Missing ']' in format string".to_string()))?;
+                let key = &accessors[1..end];
+                let index = match key.parse::<i64>() {
+                    Ok(index) => Value::Int(index),
+                    Err(_) => Value::Str(key.to_string()),
+                };
+                value = index_value(&value, &index)?;
+                accessors = &accessors[end + 1..];
+            }
+        }
+        let value = match conversion {
+            None => value,
+            Some("s") => Value::Str(value.to_str()),
+            Some("r" | "a") => Value::Str(value.repr()),
+            Some(conversion) => {
+                return Err(EvalError::Value(format!(
+                    "Unknown conversion specifier {}",
+                    conversion
+                )))
+            }
+        };
+        result.push_str(&format_value(&value, spec)?);
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn positional_field(args: &[Value], index: usize) -> EvalResult<Value> {
+    args.get(index).cloned().ok_or_else(|| {
+        EvalError::Index(format!(
+            "Replacement index {} out of range for positional args tuple",
+            index
+        ))
+    })
+}
+
+// printf-style `'%s_link' % name` and `'%(name)s' % values`.
+fn printf_format(template: &str, args: &Value) -> EvalResult<String> {
+    let (positional, mapping) = match args {
+        Value::Tuple(items) => (items.as_slice(), None),
+        Value::Dict(values) => (std::slice::from_ref(args), Some(values)),
+        args => (std::slice::from_ref(args), None),
+    };
+    let mut positional = positional.iter();
+    let mut used_mapping = false;
+    let mut result = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            result.push(ch);
+            continue;
+        }
+        let mut key = None;
+        if chars.peek() == Some(&'(') {
+            chars.next();
+            let name = chars
+                .by_ref()
+                .take_while(|ch| *ch != ')')
+                .collect::<String>();
+            key = Some(name);
+        }
+        let mut flags = String::new();
+        while let Some(flag) = chars.next_if(|ch| "-+ 0#".contains(*ch)) {
+            flags.push(flag);
+        }
+        let mut width = String::new();
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            width.push(digit);
+        }
+        let mut precision = None;
+        if chars.next_if_eq(&'.').is_some() {
+            let mut digits = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                digits.push(digit);
+            }
+            precision = Some(if digits.is_empty() {
+                "0".to_string()
+            } else {
+                digits
+            });
+        }
+        let conversion = chars
+            .next()
+            .ok_or_else(|| EvalError::Value("incomplete format".to_string()))?;
+        if conversion == '%' {
+            result.push('%');
+            continue;
+        }
+
+        let value = match (&key, mapping) {
+            (Some(key), Some(values)) => {
+                used_mapping = true;
+                values
+                    .get(key)
+                    .cloned()
+                    .ok_or_else(|| EvalError::Key(quote(key)))?
+            }
+            (Some(_), None) => {
+                return Err(EvalError::Type("format requires a mapping".to_string()))
+            }
+            (None, _) => positional.next().cloned().ok_or_else(|| {
+                EvalError::Type("not enough arguments for format string".to_string())
+            })?,
+        };
+        let (value, kind) = match conversion {
+            's' => (Value::Str(value.to_str()), 's'),
+            'r' | 'a' => (Value::Str(value.repr()), 's'),
+            'd' | 'i' | 'u' => match number(&value) {
+                Some(Number::Float(number)) => (Value::Int(float_to_int(number)?), 'd'),
+                Some(_) => (value, 'd'),
+                None => {
+                    return Err(EvalError::Type(format!(
+                        "%{} format: a real number is required, not {}",
+                        conversion,
+                        value.type_name()
+                    )))
+                }
+            },
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => match number(&value) {
+                Some(number) => (Value::Float(number.to_f64()), conversion),
+                None => {
+                    return Err(EvalError::Type(format!(
+                        "must be real number, not {}",
+                        value.type_name()
+                    )))
+                }
+            },
+            'x' | 'X' | 'o' | 'c' => (value, conversion),
+            conversion => {
+                return Err(EvalError::Value(format!(
+                    "unsupported format character '{}'",
+                    conversion
+                )))
+            }
+        };
+        let align = if flags.contains('-') { "<" } else { ">" };
+        let sign = if flags.contains('+') {
+            "+"
+        } else if flags.contains(' ') {
+            " "
+        } else {
+            ""
+        };
+        let alternate = if flags.contains('#') { "#" } else { "" };
+        let zero = if flags.contains('0') && !flags.contains('-') && kind != 's' {
+            "0"
+        } else {
+            ""
+        };
+        let precision = precision
+            .map(|digits| format!(".{}", digits))
+            .unwrap_or_default();
+        let spec = if zero.is_empty() {
+            format!(
+                "{}{}{}{}{}{}",
+                align, sign, alternate, width, precision, kind
+            )
+        } else {
+            format!(
+                "{}{}{}{}{}{}",
+                sign, alternate, zero, width, precision, kind
+            )
+        };
+        result.push_str(&format_value(&value, &spec)?);
+    }
+    if !used_mapping && positional.next().is_some() {
+        return Err(EvalError::Type(
+            "not all arguments converted during string formatting".to_string(),
+        ));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repr(source: &str) -> String {
+        let names = |name: &str| match name {
+            "n" => Some(Value::Int(3)),
+            "x" => Some(Value::Float(1.5)),
+            "cfg" => Some(Value::Dict(IndexMap::from([
+                ("name".to_string(), Value::Str("arm".to_string())),
+                (
+                    "size".to_string(),
+                    Value::List(vec![Value::Int(1), Value::Int(2)]),
+                ),
+            ]))),
+            _ => None,
+        };
+        match eval(source, &names) {
+            Ok(value) => value.repr(),
+            Err(err) => format!("error: {}", err),
+        }
+    }
+
+    #[test]
+    fn evaluates_like_python() {
+        // Expected values are `repr(eval(source))` in CPython.
+        let cases = [
+            ("1 + 2 * 3 ** 2", "19"),
+            ("-2 ** 2", "-4"),
+            ("2 ** -1", "0.5"),
+            ("7 / 2", "3.5"),
+            ("-7 // 2", "-4"),
+            ("-7 % 3", "2"),
+            ("7.5 // -2", "-4.0"),
+            ("7.5 % -2", "-0.5"),
+            ("1 << 4 | 3 & 6 ^ 1", "19"),
+            ("True + 1", "2"),
+            ("1 < n <= 3 != 4", "True"),
+            ("0 or [] or 'a'", "'a'"),
+            ("n and x", "1.5"),
+            ("not n", "False"),
+            ("x is None or 'is' not in 'this'", "False"),
+            ("'a' if n > 5 else 'b'", "'b'"),
+            ("(1, 'a', [None, 2.0])", "(1, 'a', [None, 2.0])"),
+            ("(1,)", "(1,)"),
+            ("1, 2", "(1, 2)"),
+            ("'abc'[::-1] + r'\\n' 'x'", "'cba\\\\nx'"),
+            ("[1, 2, 3, 4][1:-1]", "[2, 3]"),
+            ("[i * n for i in range(4) if i % 2]", "[3, 9]"),
+            ("{k: v for k, v in zip('ab', [1, 2])}", "{'a': 1, 'b': 2}"),
+            ("[a + b for a, b in [(1, 2), (3, 4)]]", "[3, 7]"),
+            ("sum(i for i in range(5))", "10"),
+            ("(lambda a, b: a * b)(n, x)", "4.5"),
+            ("list(map(lambda v: v + n, [1, 2]))", "[4, 5]"),
+            (
+                "sorted(['bb', 'a', 'ccc'], key=len, reverse=True)",
+                "['ccc', 'bb', 'a']",
+            ),
+            ("max([3, 1, 2], key=lambda v: -v)", "1"),
+            ("min([], default=0)", "0"),
+            ("cfg.name.upper() + cfg['size'][-1:][0] * '!'", "'ARM!!'"),
+            ("cfg.name.upper() + str(cfg['size'][-1])", "'ARM2'"),
+            ("cfg.get('missing', 'none')", "'none'"),
+            ("list(cfg.keys())", "['name', 'size']"),
+            ("' a b  c '.split()", "['a', 'b', 'c']"),
+            ("'a,b,c'.rsplit(',', 1)", "['a,b', 'c']"),
+            ("'-'.join(['a', 'b'])", "'a-b'"),
+            ("'{0}_{name!r:>6}'.format(n, name='x')", "\"3_   'x'\""),
+            (
+                "'%05.1f|%-4d|%x|%s' % (x, n, 255, None)",
+                "'001.5|3   |ff|None'",
+            ),
+            ("'%(a)s' % {'a': 1}", "'1'"),
+            ("format(1234567.891, ',.2f')", "'1,234,567.89'"),
+            ("format(0.000123, 'g')", "'0.000123'"),
+            ("format(12345678.0, '.3g')", "'1.23e+07'"),
+            ("int('0x1f', 16) + int(' 7 ') + int(-2.9)", "36"),
+            ("float('1e3') + float(n)", "1003.0"),
+            ("round(2.5) + round(3.5)", "6"),
+            ("round(1.25, 1)", "1.2"),
+            ("round(-25, -1)", "-20"),
+            ("abs(-n) + len('héllo')", "8"),
+            ("any([0, '']) or all([])", "True"),
+            ("divmod(-7, 2)", "(-4, 1)"),
+            ("dict([('a', 1)], b=2)", "{'a': 1, 'b': 2}"),
+            ("list(enumerate('ab', 1))", "[(1, 'a'), (2, 'b')]"),
+            ("1 / 0", "error: ZeroDivisionError: division by zero"),
+            (
+                "missing + 1",
+                "error: NameError: name 'missing' is not defined",
+            ),
+            ("[1][2]", "error: IndexError: list index out of range"),
+            ("cfg['other']", "error: KeyError: 'other'"),
+            (
+                "2 ** 64",
+                "error: OverflowError: integer result does not fit in 64 bits",
+            ),
+            (
+                "'a' < 1",
+                "error: TypeError: '<' not supported between instances of 'str' and 'int'",
+            ),
+            ("1 +", "error: invalid syntax: unexpected end of expression"),
+            (
+                "(1",
+                "error: invalid syntax: expected `)` but found end of expression",
+            ),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(repr(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn bounds_size_and_depth() {
+        assert_eq!(
+            repr("'ab' * 10 ** 9"),
+            "error: MemoryError: result is too large"
+        );
+        assert_eq!(
+            repr("len(range(10 ** 12))"),
+            "error: MemoryError: result is too large"
+        );
+        assert_eq!(
+            repr(&format!("{}1{}", "(".repeat(500), ")".repeat(500))),
+            "error: invalid syntax: expression is nested too deeply"
+        );
+        assert_eq!(
+            repr(&vec!["1"; 500].join(" + ")),
+            "error: invalid syntax: expression is nested too deeply"
+        );
+        assert_eq!(
+            repr("(lambda f: f(f))(lambda f: f(f))"),
+            "error: RecursionError: maximum recursion depth exceeded"
+        );
+    }
+}
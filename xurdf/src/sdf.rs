@@ -0,0 +1,1314 @@
+extern crate nalgebra as na;
+
+use super::edit::{lump_fixed_joints_except, WORLD_LINK};
+use super::import::*;
+use super::model::*;
+use super::xml_writer::*;
+use anyhow::{anyhow, bail, Context, Result};
+use na::{Isometry3, Matrix3, Quaternion, Translation3, UnitQuaternion, Vector3, Vector4};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct SdfOptions {
+    /// Merge links attached by fixed joints into their parents, as Gazebo's URDF importer does.
+    pub lump_fixed_joints: bool,
+}
+
+impl SdfOptions {
+    pub fn with_lump_fixed_joints(mut self, lump_fixed_joints: bool) -> Self {
+        self.lump_fixed_joints = lump_fixed_joints;
+        self
+    }
+}
+
+pub fn write_sdf_to_string(robot: &Robot) -> Result<String> {
+    write_sdf_to_string_with_options(robot, SdfOptions::default())
+}
+
+/// Writes `robot` as an SDFormat 1.9 model. Each child link is placed relative to its parent
+/// joint and each joint relative to its parent link, so URDF origins are carried over as-is.
+///
+/// `<gazebo>` blocks are folded in the way Gazebo's own URDF importer does: friction and contact
+/// tags of a link go to the `<surface>` of each of its collisions, a `material` script to each
+/// visual, and joint physics tags to the joint. Anything else in a block, such as sensors and
+/// plugins, is copied into its link, joint or the model as written. Fixed joints whose block sets
+/// `preserveFixedJoint` or `disableFixedJointLumping` are never lumped.
+pub fn write_sdf_to_string_with_options(robot: &Robot, options: SdfOptions) -> Result<String> {
+    let lumped;
+    let model = if options.lump_fixed_joints {
+        lumped = lump_fixed_joints_except(robot, &preserved_joints(robot)?)?;
+        &lumped.robot
+    } else {
+        robot
+    };
+
+    let sources = model
+        .extensions
+        .iter()
+        .map(extension_source)
+        .collect::<Vec<_>>();
+    let documents = sources
+        .iter()
+        .map(|source| parse_extension(source))
+        .collect::<Result<Vec<_>>>()?;
+    let mut model_extensions = Vec::new();
+    let mut extensions = HashMap::<&str, Vec<SdfNode>>::new();
+    for (extension, document) in model.extensions.iter().zip(documents.iter()) {
+        let nodes = element_children(document.root_element());
+        match extension.reference.as_deref() {
+            None => model_extensions.extend(nodes),
+            Some(name) if model.link(name).is_some() || model.joint(name).is_some() => {
+                extensions.entry(name).or_default().extend(nodes)
+            }
+            // The joint was lumped away together with its child link.
+            Some(name) if robot.joint(name).is_some() => {}
+            Some(name) => bail!(
+                "<gazebo> block refers to `{}`, which is neither a link nor a joint",
+                name
+            ),
+        }
+    }
+    let extensions_of = |name: &str| extensions.get(name).map(Vec::as_slice).unwrap_or(&[]);
+
+    let mut writer = XmlWriter::new();
+    writer.start("sdf", &[("version", "1.9")]);
+    writer.start("model", &[("name", &model.name)]);
+    for link in model.links.iter().filter(|link| link.name != WORLD_LINK) {
+        write_link(&mut writer, model, link, extensions_of(&link.name));
+    }
+    for joint in model.joints.iter() {
+        write_joint(&mut writer, joint, extensions_of(&joint.name))?;
+    }
+    for node in model_extensions {
+        write_verbatim(&mut writer, node);
+    }
+    writer.end("model");
+    writer.end("sdf");
+    Ok(writer.finish())
+}
+
+// `<gazebo reference="link">` tags and where they go in each collision.
+const COLLISION_EXTENSION_TAGS: &[(&str, &[&str])] = &[
+    ("maxContacts", &["max_contacts"]),
+    ("mu1", &["surface", "friction", "ode", "mu"]),
+    ("mu2", &["surface", "friction", "ode", "mu2"]),
+    ("fdir1", &["surface", "friction", "ode", "fdir1"]),
+    ("kp", &["surface", "contact", "ode", "kp"]),
+    ("kd", &["surface", "contact", "ode", "kd"]),
+    ("minDepth", &["surface", "contact", "ode", "min_depth"]),
+    ("maxVel", &["surface", "contact", "ode", "max_vel"]),
+];
+
+// `<gazebo reference="link">` tags that are renamed in the link itself.
+const LINK_EXTENSION_TAGS: &[(&str, &[&str])] = &[
+    ("selfCollide", &["self_collide"]),
+    ("enableWind", &["enable_wind"]),
+];
+
+// `<gazebo reference="joint">` tags and where they go in the joint.
+const JOINT_EXTENSION_TAGS: &[(&str, &[&str])] = &[
+    ("springReference", &["axis", "dynamics", "spring_reference"]),
+    ("springStiffness", &["axis", "dynamics", "spring_stiffness"]),
+    ("provideFeedback", &["physics", "provide_feedback"]),
+    (
+        "implicitSpringDamper",
+        &["physics", "ode", "implicit_spring_damper"],
+    ),
+    ("stopCfm", &["physics", "ode", "limit", "cfm"]),
+    ("stopErp", &["physics", "ode", "limit", "erp"]),
+];
+
+const LUMPING_EXTENSION_TAGS: &[&str] = &["preserveFixedJoint", "disableFixedJointLumping"];
+
+fn extension_source(extension: &Extension) -> String {
+    format!("<gazebo>{}</gazebo>", extension.xml)
+}
+
+fn parse_extension(source: &str) -> Result<roxmltree::Document<'_>> {
+    roxmltree::Document::parse(source).context("failed to parse <gazebo> block")
+}
+
+// Fixed joints whose `<gazebo>` block asks for them to be kept when lumping.
+fn preserved_joints(robot: &Robot) -> Result<HashSet<&str>> {
+    let mut preserved = HashSet::new();
+    for extension in robot.extensions.iter() {
+        let Some(reference) = extension.reference.as_deref() else {
+            continue;
+        };
+        let source = extension_source(extension);
+        let document = parse_extension(&source)?;
+        let nodes = element_children(document.root_element()).collect::<Vec<_>>();
+        if LUMPING_EXTENSION_TAGS
+            .iter()
+            .any(|tag| extension_text(&nodes, tag) == Some("true"))
+        {
+            preserved.insert(reference);
+        }
+    }
+    Ok(preserved)
+}
+
+// The text of the last `tag` among `nodes`, so later blocks override earlier ones.
+fn extension_text<'a>(nodes: &[SdfNode<'a, '_>], tag: &str) -> Option<&'a str> {
+    nodes
+        .iter()
+        .rev()
+        .find(|node| node.tag_name().name() == tag)
+        .map(|node| node.text().unwrap_or("").trim())
+}
+
+fn extension_values(
+    nodes: &[SdfNode],
+    tags: &[(&str, &'static [&'static str])],
+) -> Vec<(&'static [&'static str], String)> {
+    tags.iter()
+        .filter_map(|(tag, path)| extension_text(nodes, tag).map(|text| (*path, text.to_string())))
+        .collect()
+}
+
+fn is_mapped_tag(node: &SdfNode, tags: &[(&str, &[&str])]) -> bool {
+    let name = node.tag_name().name();
+    tags.iter().any(|(tag, _)| *tag == name)
+}
+
+// Writes each value as the last element of its path, sharing the elements of common prefixes.
+// Values with a common prefix must be adjacent.
+fn write_paths(writer: &mut XmlWriter, values: &[(&[&str], String)]) {
+    let mut index = 0;
+    while index < values.len() {
+        let (path, value) = &values[index];
+        if let [tag] = path {
+            writer.text(tag, &[], value);
+            index += 1;
+            continue;
+        }
+        let group = values[index..]
+            .iter()
+            .take_while(|(other, _)| other.len() > 1 && other[0] == path[0])
+            .map(|(other, value)| (&other[1..], value.clone()))
+            .collect::<Vec<_>>();
+        writer.start(path[0], &[]);
+        write_paths(writer, &group);
+        writer.end(path[0]);
+        index += group.len();
+    }
+}
+
+fn write_verbatim(writer: &mut XmlWriter, node: SdfNode) {
+    let tag = node.tag_name().name();
+    let attributes = node
+        .attributes()
+        .iter()
+        .map(|attribute| (attribute.name(), attribute.value()))
+        .collect::<Vec<_>>();
+    let children = element_children(node).collect::<Vec<_>>();
+    if !children.is_empty() {
+        writer.start(tag, &attributes);
+        for child in children {
+            write_verbatim(writer, child);
+        }
+        writer.end(tag);
+    } else {
+        match node.text().map(str::trim).filter(|text| !text.is_empty()) {
+            Some(text) => writer.text(tag, &attributes, text),
+            None => writer.empty(tag, &attributes),
+        }
+    }
+}
+
+fn write_link(writer: &mut XmlWriter, robot: &Robot, link: &Link, extensions: &[SdfNode]) {
+    writer.start("link", &[("name", &link.name)]);
+    match robot.parent_joint(&link.name) {
+        // Floating joints have no SDF counterpart; the child becomes a free link that
+        // starts at the joint origin.
+        Some(joint) if joint.joint_type == "floating" => {
+            write_pose(writer, &joint.origin, Some(pose_frame(&joint.parent)))
+        }
+        Some(joint) => writer.text("pose", &[("relative_to", &joint.name)], "0 0 0 0 0 0"),
+        None => {}
+    }
+
+    if link.inertial.mass > 0.0 {
+        let inertial = &link.inertial;
+        writer.start("inertial", &[]);
+        write_pose(writer, &inertial.origin, None);
+        writer.text("mass", &[], &format_float(inertial.mass));
+        writer.start("inertia", &[]);
+        for (name, (row, column)) in [
+            ("ixx", (0, 0)),
+            ("ixy", (0, 1)),
+            ("ixz", (0, 2)),
+            ("iyy", (1, 1)),
+            ("iyz", (1, 2)),
+            ("izz", (2, 2)),
+        ] {
+            writer.text(name, &[], &format_float(inertial.inertia[(row, column)]));
+        }
+        writer.end("inertia");
+        writer.end("inertial");
+    }
+
+    let collision_values = extension_values(extensions, COLLISION_EXTENSION_TAGS);
+    let script = extension_text(extensions, "material");
+    let mut names = HashSet::new();
+    for (index, collision) in link.collisions.iter().enumerate() {
+        let name = element_name(&mut names, &collision.name, &link.name, "collision", index);
+        writer.start("collision", &[("name", &name)]);
+        write_pose(writer, &collision.origin, None);
+        write_geometry(writer, &collision.geometry);
+        write_paths(writer, &collision_values);
+        writer.end("collision");
+    }
+    for (index, visual) in link.visuals.iter().enumerate() {
+        let name = element_name(&mut names, &visual.name, &link.name, "visual", index);
+        writer.start("visual", &[("name", &name)]);
+        write_pose(writer, &visual.origin, None);
+        write_geometry(writer, &visual.geometry);
+        let mut material: Vec<(&[&str], String)> = Vec::new();
+        if let Some(color) = visual.material.as_ref().and_then(|material| material.color) {
+            let rgba = format_floats(color.as_slice());
+            material.push((&["material", "ambient"], rgba.clone()));
+            material.push((&["material", "diffuse"], rgba));
+        }
+        if let Some(script) = script {
+            material.push((
+                &["material", "script", "uri"],
+                "file://media/materials/scripts/gazebo.material".to_string(),
+            ));
+            material.push((&["material", "script", "name"], script.to_string()));
+        }
+        write_paths(writer, &material);
+        writer.end("visual");
+    }
+
+    let mut values = extension_values(extensions, LINK_EXTENSION_TAGS);
+    if let Some(off) = extension_text(extensions, "turnGravityOff") {
+        let gravity = if off == "true" { "false" } else { "true" };
+        values.push((&["gravity"], gravity.to_string()));
+    }
+    write_paths(writer, &values);
+    for node in extensions.iter().filter(|node| {
+        !is_mapped_tag(node, COLLISION_EXTENSION_TAGS)
+            && !is_mapped_tag(node, LINK_EXTENSION_TAGS)
+            && !matches!(node.tag_name().name(), "material" | "turnGravityOff")
+    }) {
+        write_verbatim(writer, *node);
+    }
+    writer.end("link");
+}
+
+fn write_joint(writer: &mut XmlWriter, joint: &Joint, extensions: &[SdfNode]) -> Result<()> {
+    let joint_type = match joint.joint_type.as_str() {
+        "revolute" | "continuous" | "prismatic" | "fixed" => joint.joint_type.as_str(),
+        "floating" => return Ok(()),
+        other => bail!(
+            "joint `{}` has type `{}`, which SDFormat cannot represent",
+            joint.name,
+            other
+        ),
+    };
+    writer.start("joint", &[("name", &joint.name), ("type", joint_type)]);
+    // A joint pose is relative to its child link by default, which is in turn placed
+    // relative to the joint, so the parent frame has to be explicit.
+    writer.text(
+        "pose",
+        &[("relative_to", pose_frame(&joint.parent))],
+        &pose_text(&joint.origin),
+    );
+    writer.text("parent", &[], &joint.parent);
+    writer.text("child", &[], &joint.child);
+    let mut values: Vec<(&[&str], String)> = Vec::new();
+    if joint_type != "fixed" {
+        values.push((&["axis", "xyz"], format_vector(&joint.axis)));
+        if joint_type != "continuous" {
+            values.push((&["axis", "limit", "lower"], format_float(joint.limit.lower)));
+            values.push((&["axis", "limit", "upper"], format_float(joint.limit.upper)));
+        }
+        values.push((
+            &["axis", "limit", "effort"],
+            format_float(joint.limit.effort),
+        ));
+        values.push((
+            &["axis", "limit", "velocity"],
+            format_float(joint.limit.velocity),
+        ));
+    }
+    values.extend(extension_values(extensions, JOINT_EXTENSION_TAGS));
+    write_paths(writer, &values);
+    for node in extensions.iter().filter(|node| {
+        !is_mapped_tag(node, JOINT_EXTENSION_TAGS)
+            && !LUMPING_EXTENSION_TAGS.contains(&node.tag_name().name())
+    }) {
+        write_verbatim(writer, *node);
+    }
+    writer.end("joint");
+    Ok(())
+}
+
+fn write_geometry(writer: &mut XmlWriter, geometry: &Geometry) {
+    writer.start("geometry", &[]);
+    match geometry {
+        Geometry::Box { size } => {
+            writer.start("box", &[]);
+            writer.text("size", &[], &format_vector(size));
+            writer.end("box");
+        }
+        Geometry::Cylinder { radius, length } => {
+            writer.start("cylinder", &[]);
+            writer.text("radius", &[], &format_float(*radius));
+            writer.text("length", &[], &format_float(*length));
+            writer.end("cylinder");
+        }
+        Geometry::Sphere { radius } => {
+            writer.start("sphere", &[]);
+            writer.text("radius", &[], &format_float(*radius));
+            writer.end("sphere");
+        }
+        Geometry::Mesh { filename, scale } => {
+            writer.start("mesh", &[]);
+            writer.text("uri", &[], filename);
+            if let Some(scale) = scale {
+                writer.text("scale", &[], &format_vector(scale));
+            }
+            writer.end("mesh");
+        }
+    }
+    writer.end("geometry");
+}
+
+fn write_pose(writer: &mut XmlWriter, pose: &Pose, relative_to: Option<&str>) {
+    if relative_to.is_none() && pose.xyz.iter().chain(pose.rpy.iter()).all(|v| *v == 0.0) {
+        return;
+    }
+    let attributes = relative_to
+        .map(|frame| vec![("relative_to", frame)])
+        .unwrap_or_default();
+    writer.text("pose", &attributes, &pose_text(pose));
+}
+
+fn pose_text(pose: &Pose) -> String {
+    format!("{} {}", format_vector(&pose.xyz), format_vector(&pose.rpy))
+}
+
+// `world` is not a frame inside a model; the model frame stands in for it.
+fn pose_frame(link: &str) -> &str {
+    if link == WORLD_LINK {
+        "__model__"
+    } else {
+        link
+    }
+}
+
+fn element_name(
+    names: &mut HashSet<String>,
+    name: &Option<String>,
+    link: &str,
+    kind: &str,
+    index: usize,
+) -> String {
+    let name = match name {
+        Some(name) if !names.contains(name) => name.clone(),
+        Some(name) => format!("{}_{}", name, index),
+        None => format!("{}_{}_{}", link, kind, index),
+    };
+    names.insert(name.clone());
+    name
+}
+
+pub fn parse_sdf_from_string(xml: &str) -> Result<ImportedRobot> {
+    let doc = roxmltree::Document::parse(xml)?;
+    let root = doc.root_element();
+    let mut notes = ImportNotes::default();
+    let model = match root.tag_name().name() {
+        "model" => root,
+        "sdf" => {
+            let mut models = elements(root, "model").collect::<Vec<_>>();
+            if models.is_empty() {
+                for world in elements(root, "world") {
+                    models.extend(elements(world, "model"));
+                    if element_children(world).any(|child| child.tag_name().name() != "model") {
+                        notes.note("world elements outside the model were dropped".to_string());
+                    }
+                }
+            }
+            match models.as_slice() {
+                [model] => *model,
+                _ => bail!("expected a single `<model>`, found {}", models.len()),
+            }
+        }
+        other => bail!(
+            "expected an `<sdf>` or `<model>` element, found `<{}>`",
+            other
+        ),
+    };
+    SdfImporter {
+        frames: HashMap::new(),
+        notes,
+    }
+    .read_model(model)
+}
+
+pub fn parse_sdf_from_file<P: AsRef<Path>>(path: P) -> Result<ImportedRobot> {
+    parse_sdf_from_string(&std::fs::read_to_string(path)?)
+}
+
+type SdfNode<'a, 'input> = roxmltree::Node<'a, 'input>;
+
+// A named pose from the model's frame graph: a link, joint or `<frame>`.
+struct SdfFrame {
+    pose: Isometry3<f64>,
+    relative_to: String,
+}
+
+struct SdfImporter {
+    frames: HashMap<String, SdfFrame>,
+    notes: ImportNotes,
+}
+
+const MODEL_FRAME: &str = "__model__";
+
+fn element_children<'a, 'input: 'a>(
+    node: SdfNode<'a, 'input>,
+) -> impl Iterator<Item = SdfNode<'a, 'input>> + 'a {
+    node.children().filter(roxmltree::Node::is_element)
+}
+
+fn elements<'a, 'input: 'a>(
+    node: SdfNode<'a, 'input>,
+    tag: &'a str,
+) -> impl Iterator<Item = SdfNode<'a, 'input>> + 'a {
+    element_children(node).filter(move |child| child.tag_name().name() == tag)
+}
+
+fn element<'a, 'input: 'a>(node: SdfNode<'a, 'input>, tag: &'a str) -> Option<SdfNode<'a, 'input>> {
+    elements(node, tag).next()
+}
+
+fn element_text<'a>(node: SdfNode<'a, '_>, tag: &str) -> Option<&'a str> {
+    element_children(node)
+        .find(|child| child.tag_name().name() == tag)
+        .map(|child| child.text().unwrap_or("").trim())
+}
+
+fn element_number(node: SdfNode, tag: &str) -> Result<Option<f64>> {
+    element_text(node, tag)
+        .map(|text| parse_exact_floats::<1>(text, &format!("`<{}>`", tag)).map(|[value]| value))
+        .transpose()
+}
+
+fn element_vector(node: SdfNode, tag: &str) -> Result<Option<Vector3<f64>>> {
+    element_text(node, tag)
+        .map(|text| parse_exact_floats::<3>(text, &format!("`<{}>`", tag)).map(Vector3::from))
+        .transpose()
+}
+
+fn required_name(node: SdfNode) -> Result<String> {
+    node.attribute("name")
+        .map(String::from)
+        .ok_or_else(|| anyhow!("`<{}>` without a name", node.tag_name().name()))
+}
+
+// Reads the `<pose>` child of `node` with the frame it is relative to, if one is given.
+fn read_pose(node: SdfNode) -> Result<(Isometry3<f64>, Option<String>)> {
+    let Some(pose) = element(node, "pose") else {
+        return Ok((Isometry3::identity(), None));
+    };
+    let relative_to = pose
+        .attribute("relative_to")
+        .or(pose.attribute("frame"))
+        .filter(|frame| !frame.is_empty())
+        .map(String::from);
+    let text = pose.text().unwrap_or("").trim();
+    if text.is_empty() {
+        return Ok((Isometry3::identity(), relative_to));
+    }
+    let isometry = if pose.attribute("rotation_format") == Some("quat_xyzw") {
+        let [x, y, z, qx, qy, qz, qw] = parse_exact_floats::<7>(text, "`<pose>`")?;
+        Isometry3::from_parts(
+            Translation3::new(x, y, z),
+            UnitQuaternion::from_quaternion(Quaternion::new(qw, qx, qy, qz)),
+        )
+    } else {
+        let [x, y, z, mut roll, mut pitch, mut yaw] = parse_exact_floats::<6>(text, "`<pose>`")?;
+        if pose.attribute("degrees") == Some("true") {
+            (roll, pitch, yaw) = (roll.to_radians(), pitch.to_radians(), yaw.to_radians());
+        }
+        Isometry3::from_parts(
+            Translation3::new(x, y, z),
+            UnitQuaternion::from_euler_angles(roll, pitch, yaw),
+        )
+    };
+    Ok((isometry, relative_to))
+}
+
+impl SdfImporter {
+    fn read_model(mut self, model: SdfNode) -> Result<ImportedRobot> {
+        let mut robot = Robot {
+            name: required_name(model)?,
+            ..Default::default()
+        };
+        for child in element_children(model) {
+            let tag = child.tag_name().name();
+            let default_frame = match tag {
+                "link" => MODEL_FRAME.to_string(),
+                "joint" => element_text(child, "child").unwrap_or_default().to_string(),
+                "frame" => child
+                    .attribute("attached_to")
+                    .unwrap_or(MODEL_FRAME)
+                    .to_string(),
+                "pose" | "static" | "self_collide" | "allow_auto_disable" | "enable_wind" => {
+                    continue
+                }
+                other => {
+                    self.notes
+                        .note(format!("`<{}>` elements of the model were dropped", other));
+                    continue;
+                }
+            };
+            let (pose, relative_to) = read_pose(child)?;
+            self.frames.insert(
+                required_name(child)?,
+                SdfFrame {
+                    pose,
+                    relative_to: relative_to.unwrap_or(default_frame),
+                },
+            );
+        }
+
+        // URDF puts each child link frame at its joint, so link contents are re-expressed
+        // relative to that joint; root links keep their own frame.
+        let joints = elements(model, "joint").collect::<Vec<_>>();
+        let mut link_frames = HashMap::new();
+        for link in elements(model, "link") {
+            let name = required_name(link)?;
+            let parent_joint = joints
+                .iter()
+                .find(|joint| element_text(**joint, "child") == Some(name.as_str()));
+            let frame = match parent_joint {
+                Some(joint) => self.resolve(&required_name(*joint)?)?,
+                None => self.resolve(&name)?,
+            };
+            link_frames.insert(name, frame);
+        }
+        if joints
+            .iter()
+            .any(|joint| element_text(*joint, "parent") == Some(WORLD_LINK))
+        {
+            robot.links.push(Link {
+                name: WORLD_LINK.to_string(),
+                ..Default::default()
+            });
+            link_frames.insert(WORLD_LINK.to_string(), Isometry3::identity());
+        }
+
+        for link in elements(model, "link") {
+            let name = required_name(link)?;
+            let frame = link_frames[&name];
+            robot.links.push(self.read_link(link, name, &frame)?);
+        }
+        for joint in joints {
+            robot.joints.push(self.read_joint(joint, &link_frames)?);
+        }
+        Ok(self.notes.finish(robot))
+    }
+
+    fn resolve(&self, name: &str) -> Result<Isometry3<f64>> {
+        let mut pose = Isometry3::identity();
+        let mut visited = HashSet::new();
+        let mut current = name;
+        while current != MODEL_FRAME && current != WORLD_LINK {
+            if !visited.insert(current) {
+                bail!("the pose of frame `{}` is defined relative to itself", name);
+            }
+            let frame = self
+                .frames
+                .get(current)
+                .ok_or_else(|| anyhow!("unknown frame `{}`", current))?;
+            pose = frame.pose * pose;
+            current = &frame.relative_to;
+        }
+        Ok(pose)
+    }
+
+    // Places a pose given in `node` relative to `default_frame` into `link_frame`.
+    fn read_local_pose(
+        &self,
+        node: SdfNode,
+        default_frame: &str,
+        link_frame: &Isometry3<f64>,
+    ) -> Result<Pose> {
+        let (pose, relative_to) = read_pose(node)?;
+        let frame = self.resolve(relative_to.as_deref().unwrap_or(default_frame))?;
+        Ok(Pose::from_isometry(&(link_frame.inverse() * frame * pose)))
+    }
+
+    fn read_link(&mut self, node: SdfNode, name: String, frame: &Isometry3<f64>) -> Result<Link> {
+        // SDFormat gives links without an `<inertial>` unit mass and inertia.
+        let mut link = Link {
+            name,
+            inertial: Inertial {
+                origin: Pose::from_isometry(
+                    &(frame.inverse() * self.resolve(required_name(node)?.as_str())?),
+                ),
+                mass: 1.0,
+                inertia: Matrix3::identity(),
+            },
+            ..Default::default()
+        };
+        for child in element_children(node) {
+            match child.tag_name().name() {
+                "inertial" => {
+                    let mut inertia = Matrix3::identity();
+                    if let Some(moments) = element(child, "inertia") {
+                        for (tag, (row, column)) in [
+                            ("ixx", (0, 0)),
+                            ("ixy", (0, 1)),
+                            ("ixz", (0, 2)),
+                            ("iyy", (1, 1)),
+                            ("iyz", (1, 2)),
+                            ("izz", (2, 2)),
+                        ] {
+                            if let Some(value) = element_number(moments, tag)? {
+                                inertia[(row, column)] = value;
+                                inertia[(column, row)] = value;
+                            }
+                        }
+                    }
+                    link.inertial = Inertial {
+                        origin: self.read_local_pose(child, &link.name, frame)?,
+                        mass: element_number(child, "mass")?.unwrap_or(1.0),
+                        inertia,
+                    };
+                }
+                "visual" => {
+                    let Some(geometry) = self.read_geometry(child)? else {
+                        continue;
+                    };
+                    self.note_dropped(child, &["pose", "geometry", "material"]);
+                    link.visuals.push(Visual {
+                        name: child.attribute("name").map(String::from),
+                        origin: self.read_local_pose(child, &link.name, frame)?,
+                        geometry,
+                        material: element(child, "material")
+                            .map(|material| self.read_material(material))
+                            .transpose()?,
+                    });
+                }
+                "collision" => {
+                    let Some(geometry) = self.read_geometry(child)? else {
+                        continue;
+                    };
+                    self.note_dropped(child, &["pose", "geometry"]);
+                    link.collisions.push(Collision {
+                        name: child.attribute("name").map(String::from),
+                        origin: self.read_local_pose(child, &link.name, frame)?,
+                        geometry,
+                    });
+                }
+                "pose" => {}
+                other => self
+                    .notes
+                    .note(format!("`<{}>` elements of links were dropped", other)),
+            }
+        }
+        Ok(link)
+    }
+
+    fn note_dropped(&mut self, node: SdfNode, kept: &[&str]) {
+        for child in element_children(node) {
+            if !kept.contains(&child.tag_name().name()) {
+                self.notes.note(format!(
+                    "`<{}>` elements of `<{}>` were dropped",
+                    child.tag_name().name(),
+                    node.tag_name().name()
+                ));
+            }
+        }
+    }
+
+    fn read_geometry(&mut self, node: SdfNode) -> Result<Option<Geometry>> {
+        let shape = element(node, "geometry")
+            .and_then(|geometry| element_children(geometry).next())
+            .ok_or_else(|| anyhow!("`<{}>` without a geometry", node.tag_name().name()))?;
+        let number = |tag| {
+            element_number(shape, tag)?
+                .ok_or_else(|| anyhow!("`<{}>` without `<{}>`", shape.tag_name().name(), tag))
+        };
+        let geometry = match shape.tag_name().name() {
+            "box" => Geometry::Box {
+                size: element_vector(shape, "size")?
+                    .ok_or_else(|| anyhow!("`<box>` without `<size>`"))?,
+            },
+            "sphere" => Geometry::Sphere {
+                radius: number("radius")?,
+            },
+            "cylinder" => Geometry::Cylinder {
+                radius: number("radius")?,
+                length: number("length")?,
+            },
+            "capsule" => {
+                self.notes.note(
+                    "`capsule` geometries were approximated as cylinders without the end caps"
+                        .to_string(),
+                );
+                Geometry::Cylinder {
+                    radius: number("radius")?,
+                    length: number("length")?,
+                }
+            }
+            "ellipsoid" => {
+                self.notes.note(
+                    "`ellipsoid` geometries were approximated as spheres of the largest radius"
+                        .to_string(),
+                );
+                let radii = element_vector(shape, "radii")?
+                    .ok_or_else(|| anyhow!("`<ellipsoid>` without `<radii>`"))?;
+                Geometry::Sphere {
+                    radius: radii.max(),
+                }
+            }
+            "mesh" => {
+                if element(shape, "submesh").is_some() {
+                    self.notes.note(
+                        "`<submesh>` selections were dropped; the whole mesh is used".to_string(),
+                    );
+                }
+                Geometry::Mesh {
+                    filename: element_text(shape, "uri")
+                        .ok_or_else(|| anyhow!("`<mesh>` without `<uri>`"))?
+                        .to_string(),
+                    scale: element_vector(shape, "scale")?,
+                }
+            }
+            other => {
+                self.notes.note(format!(
+                    "`{}` geometries have no URDF equivalent and were dropped",
+                    other
+                ));
+                return Ok(None);
+            }
+        };
+        Ok(Some(geometry))
+    }
+
+    fn read_material(&mut self, node: SdfNode) -> Result<Material> {
+        let color = element_text(node, "diffuse")
+            .or(element_text(node, "ambient"))
+            .map(|text| {
+                let values = parse_floats(text, "a material color")?;
+                match values.as_slice() {
+                    [r, g, b] => Ok(Vector4::new(*r, *g, *b, 1.0)),
+                    [r, g, b, a] => Ok(Vector4::new(*r, *g, *b, *a)),
+                    _ => bail!(
+                        "expected 3 or 4 numbers in a material color, found {}",
+                        values.len()
+                    ),
+                }
+            })
+            .transpose()?;
+        let name = element(node, "script").and_then(|script| element_text(script, "name"));
+        if element(node, "pbr").is_some() {
+            self.notes
+                .note("`<pbr>` material properties were dropped".to_string());
+        }
+        Ok(Material {
+            name: name.map(String::from),
+            color,
+        })
+    }
+
+    fn read_joint(
+        &mut self,
+        node: SdfNode,
+        link_frames: &HashMap<String, Isometry3<f64>>,
+    ) -> Result<Joint> {
+        let name = required_name(node)?;
+        let parent = element_text(node, "parent")
+            .ok_or_else(|| anyhow!("joint `{}` without a parent", name))?
+            .to_string();
+        let child = element_text(node, "child")
+            .ok_or_else(|| anyhow!("joint `{}` without a child", name))?
+            .to_string();
+        let parent_frame = link_frames
+            .get(&parent)
+            .ok_or_else(|| anyhow!("joint `{}` has unknown parent `{}`", name, parent))?;
+        let frame = self.resolve(&name)?;
+
+        let mut axis = Vector3::z();
+        let mut limit = JointLimit::default();
+        let mut limited = false;
+        if let Some(axis_node) = element(node, "axis") {
+            if let Some(xyz) = element(axis_node, "xyz") {
+                let direction = Vector3::from(parse_exact_floats::<3>(
+                    xyz.text().unwrap_or("").trim(),
+                    "`<xyz>`",
+                )?);
+                let expressed_in = match xyz.attribute("expressed_in") {
+                    Some(frame) => self.resolve(frame)?,
+                    None if element_text(axis_node, "use_parent_model_frame") == Some("true") => {
+                        Isometry3::identity()
+                    }
+                    None => frame,
+                };
+                axis = frame.rotation.inverse() * expressed_in.rotation * direction;
+            }
+            if let Some(limit_node) = element(axis_node, "limit") {
+                let lower = element_number(limit_node, "lower")?;
+                let upper = element_number(limit_node, "upper")?;
+                if let (Some(lower), Some(upper)) = (lower, upper) {
+                    // SDFormat marks unlimited revolute joints with +/-1e16.
+                    limited = lower > -1e16 && upper < 1e16;
+                    limit.lower = lower;
+                    limit.upper = upper;
+                }
+                limit.effort = element_number(limit_node, "effort")?.unwrap_or(0.0);
+                limit.velocity = element_number(limit_node, "velocity")?.unwrap_or(0.0);
+            }
+            if element(axis_node, "dynamics").is_some() {
+                self.notes
+                    .note("joint `<dynamics>` were dropped".to_string());
+            }
+        }
+
+        let joint_type = match node.attribute("type").unwrap_or("") {
+            "revolute" if !limited => {
+                limit.lower = 0.0;
+                limit.upper = 0.0;
+                "continuous"
+            }
+            joint_type @ ("revolute" | "continuous" | "prismatic" | "fixed") => joint_type,
+            joint_type @ ("ball" | "universal" | "revolute2" | "gearbox" | "screw") => {
+                self.notes.note(format!(
+                    "{} joint `{}` cannot be represented and was imported as fixed",
+                    joint_type, name
+                ));
+                "fixed"
+            }
+            other => bail!("joint `{}` has unknown type `{}`", name, other),
+        };
+        Ok(Joint {
+            name,
+            joint_type: joint_type.to_string(),
+            origin: Pose::from_isometry(&(parent_frame.inverse() * frame)),
+            parent,
+            child,
+            axis,
+            limit,
+            mimic: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_urdf_from_string;
+
+    const URDF: &str = r#"
+        <robot name="arm">
+          <material name="red"><color rgba="1 0 0 1"/></material>
+          <link name="base">
+            <inertial>
+              <origin xyz="0 0 0.1"/>
+              <mass value="2"/>
+              <inertia ixx="0.1" ixy="0" ixz="0" iyy="0.1" iyz="0" izz="0.2"/>
+            </inertial>
+            <visual>
+              <geometry><box size="0.2 0.2 0.2"/></geometry>
+              <material name="red"/>
+            </visual>
+            <collision><geometry><cylinder radius="0.1" length="0.2"/></geometry></collision>
+          </link>
+          <link name="upper">
+            <inertial>
+              <mass value="1"/>
+              <inertia ixx="0.01" ixy="0" ixz="0" iyy="0.01" iyz="0" izz="0.01"/>
+            </inertial>
+            <visual name="shell">
+              <origin xyz="0 0 0.25"/>
+              <geometry><mesh filename="package://arm/upper.dae" scale="1 1 2"/></geometry>
+            </visual>
+          </link>
+          <link name="tool">
+            <inertial>
+              <mass value="1"/>
+              <inertia ixx="0.01" ixy="0" ixz="0" iyy="0.01" iyz="0" izz="0.01"/>
+            </inertial>
+            <visual><geometry><sphere radius="0.05"/></geometry></visual>
+          </link>
+          <joint name="shoulder" type="revolute">
+            <parent link="base"/>
+            <child link="upper"/>
+            <origin xyz="0 0 0.2" rpy="0 0 1.5"/>
+            <axis xyz="0 1 0"/>
+            <limit lower="-1" upper="1" effort="10" velocity="2"/>
+          </joint>
+          <joint name="tool_mount" type="fixed">
+            <parent link="upper"/>
+            <child link="tool"/>
+            <origin xyz="0 0 0.5"/>
+          </joint>
+        </robot>
+    "#;
+
+    #[test]
+    fn writes_links_joints_and_geometry() {
+        let robot = parse_urdf_from_string(URDF).unwrap();
+
+        let sdf = write_sdf_to_string(&robot).unwrap();
+
+        assert_eq!(
+            sdf,
+            r#"<?xml version="1.0"?>
+<sdf version="1.9">
+  <model name="arm">
+    <link name="base">
+      <inertial>
+        <pose>0 0 0.1 0 0 0</pose>
+        <mass>2</mass>
+        <inertia>
+          <ixx>0.1</ixx>
+          <ixy>0</ixy>
+          <ixz>0</ixz>
+          <iyy>0.1</iyy>
+          <iyz>0</iyz>
+          <izz>0.2</izz>
+        </inertia>
+      </inertial>
+      <collision name="base_collision_0">
+        <geometry>
+          <cylinder>
+            <radius>0.1</radius>
+            <length>0.2</length>
+          </cylinder>
+        </geometry>
+      </collision>
+      <visual name="base_visual_0">
+        <geometry>
+          <box>
+            <size>0.2 0.2 0.2</size>
+          </box>
+        </geometry>
+        <material>
+          <ambient>1 0 0 1</ambient>
+          <diffuse>1 0 0 1</diffuse>
+        </material>
+      </visual>
+    </link>
+    <link name="upper">
+      <pose relative_to="shoulder">0 0 0 0 0 0</pose>
+      <inertial>
+        <mass>1</mass>
+        <inertia>
+          <ixx>0.01</ixx>
+          <ixy>0</ixy>
+          <ixz>0</ixz>
+          <iyy>0.01</iyy>
+          <iyz>0</iyz>
+          <izz>0.01</izz>
+        </inertia>
+      </inertial>
+      <visual name="shell">
+        <pose>0 0 0.25 0 0 0</pose>
+        <geometry>
+          <mesh>
+            <uri>package://arm/upper.dae</uri>
+            <scale>1 1 2</scale>
+          </mesh>
+        </geometry>
+      </visual>
+    </link>
+    <link name="tool">
+      <pose relative_to="tool_mount">0 0 0 0 0 0</pose>
+      <inertial>
+        <mass>1</mass>
+        <inertia>
+          <ixx>0.01</ixx>
+          <ixy>0</ixy>
+          <ixz>0</ixz>
+          <iyy>0.01</iyy>
+          <iyz>0</iyz>
+          <izz>0.01</izz>
+        </inertia>
+      </inertial>
+      <visual name="tool_visual_0">
+        <geometry>
+          <sphere>
+            <radius>0.05</radius>
+          </sphere>
+        </geometry>
+      </visual>
+    </link>
+    <joint name="shoulder" type="revolute">
+      <pose relative_to="base">0 0 0.2 0 0 1.5</pose>
+      <parent>base</parent>
+      <child>upper</child>
+      <axis>
+        <xyz>0 1 0</xyz>
+        <limit>
+          <lower>-1</lower>
+          <upper>1</upper>
+          <effort>10</effort>
+          <velocity>2</velocity>
+        </limit>
+      </axis>
+    </joint>
+    <joint name="tool_mount" type="fixed">
+      <pose relative_to="upper">0 0 0.5 0 0 0</pose>
+      <parent>upper</parent>
+      <child>tool</child>
+    </joint>
+  </model>
+</sdf>
+"#
+        );
+    }
+
+    #[test]
+    fn lumps_fixed_joints_when_requested() {
+        let robot = parse_urdf_from_string(URDF).unwrap();
+
+        let sdf = write_sdf_to_string_with_options(
+            &robot,
+            SdfOptions::default().with_lump_fixed_joints(true),
+        )
+        .unwrap();
+
+        assert!(!sdf.contains("tool_mount"));
+        assert!(!sdf.contains(r#"<link name="tool">"#));
+        assert!(sdf.contains("<mass>2</mass>"));
+        assert!(sdf.contains(
+            r#"<visual name="upper_visual_1">
+        <pose>0 0 0.5 0 0 0</pose>"#
+        ));
+        // Two unit masses at z = 0 and z = 0.5 combine around z = 0.25.
+        assert!(sdf.contains("<pose>0 0 0.25 0 0 0</pose>\n        <mass>2</mass>"));
+        assert!(sdf.contains("<ixx>0.145</ixx>"));
+    }
+
+    #[test]
+    fn folds_gazebo_extensions_into_sdf_elements() {
+        let robot = parse_urdf_from_string(
+            r#"
+            <robot name="bot">
+              <link name="base">
+                <collision><geometry><sphere radius="0.1"/></geometry></collision>
+                <visual><geometry><sphere radius="0.1"/></geometry></visual>
+              </link>
+              <link name="camera"/>
+              <link name="wheel"/>
+              <joint name="camera_mount" type="fixed">
+                <parent link="base"/>
+                <child link="camera"/>
+              </joint>
+              <joint name="axle" type="continuous">
+                <parent link="base"/>
+                <child link="wheel"/>
+              </joint>
+              <gazebo reference="base">
+                <mu1>0.8</mu1>
+                <kp>1e6</kp>
+                <material>Gazebo/Red</material>
+                <turnGravityOff>true</turnGravityOff>
+              </gazebo>
+              <gazebo reference="base"><mu1>0.9</mu1></gazebo>
+              <gazebo reference="camera">
+                <sensor name="cam" type="camera"><update_rate>30</update_rate></sensor>
+              </gazebo>
+              <gazebo reference="axle"><provideFeedback>true</provideFeedback></gazebo>
+              <gazebo><plugin filename="libdiff.so" name="diff"><wheel>axle</wheel></plugin></gazebo>
+            </robot>
+            "#,
+        )
+        .unwrap();
+
+        let sdf = write_sdf_to_string(&robot).unwrap();
+
+        assert!(sdf.contains(
+            r#"          </sphere>
+        </geometry>
+        <surface>
+          <friction>
+            <ode>
+              <mu>0.9</mu>
+            </ode>
+          </friction>
+          <contact>
+            <ode>
+              <kp>1e6</kp>
+            </ode>
+          </contact>
+        </surface>
+      </collision>"#
+        ));
+        assert!(sdf.contains(
+            r#"        <material>
+          <script>
+            <uri>file://media/materials/scripts/gazebo.material</uri>
+            <name>Gazebo/Red</name>
+          </script>
+        </material>
+      </visual>
+      <gravity>false</gravity>
+    </link>"#
+        ));
+        assert!(sdf.contains(
+            r#"      <sensor name="cam" type="camera">
+        <update_rate>30</update_rate>
+      </sensor>
+    </link>"#
+        ));
+        assert!(sdf.contains(
+            r#"      </axis>
+      <physics>
+        <provide_feedback>true</provide_feedback>
+      </physics>
+    </joint>"#
+        ));
+        assert!(sdf.contains(
+            r#"    <plugin filename="libdiff.so" name="diff">
+      <wheel>axle</wheel>
+    </plugin>
+  </model>"#
+        ));
+
+        // Lumping moves the sensor to the parent link, unless the joint is preserved.
+        let options = SdfOptions::default().with_lump_fixed_joints(true);
+        let lumped = write_sdf_to_string_with_options(&robot, options.clone()).unwrap();
+        assert!(!lumped.contains(r#"<link name="camera">"#));
+        assert!(lumped.contains(r#"<sensor name="cam" type="camera">"#));
+        let mut preserved = robot.clone();
+        preserved.extensions.push(Extension {
+            reference: Some("camera_mount".to_string()),
+            xml: "<preserveFixedJoint>true</preserveFixedJoint>".to_string(),
+        });
+        let sdf = write_sdf_to_string_with_options(&preserved, options).unwrap();
+        assert!(sdf.contains(r#"<joint name="camera_mount" type="fixed">"#));
+        assert!(!sdf.contains("preserveFixedJoint"));
+
+        preserved.extensions[0].reference = Some("missing".to_string());
+        assert_eq!(
+            write_sdf_to_string(&preserved).unwrap_err().to_string(),
+            "<gazebo> block refers to `missing`, which is neither a link nor a joint"
+        );
+    }
+
+    #[test]
+    fn rejects_joint_types_without_sdf_equivalent() {
+        let mut robot = parse_urdf_from_string(URDF).unwrap();
+        robot.joints[0].joint_type = "planar".to_string();
+
+        let err = write_sdf_to_string(&robot).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "joint `shoulder` has type `planar`, which SDFormat cannot represent"
+        );
+    }
+
+    fn assert_pose(pose: &Pose, xyz: [f64; 3], rpy: [f64; 3]) {
+        let expected = Pose {
+            xyz: Vector3::from(xyz),
+            rpy: Vector3::from(rpy),
+        };
+        assert!(
+            (pose.to_isometry().to_homogeneous() - expected.to_isometry().to_homogeneous()).norm()
+                < 1e-9,
+            "{:?} != {:?}",
+            pose,
+            expected
+        );
+    }
+
+    #[test]
+    fn imports_exported_model() {
+        let robot = parse_urdf_from_string(URDF).unwrap();
+        let sdf = write_sdf_to_string(&robot).unwrap();
+
+        let imported = parse_sdf_from_string(&sdf).unwrap();
+
+        assert!(imported.warnings.is_empty(), "{:?}", imported.warnings);
+        let robot = imported.robot;
+        assert_eq!(robot.links.len(), 3);
+        let shoulder = robot.joint("shoulder").unwrap();
+        assert_pose(&shoulder.origin, [0.0, 0.0, 0.2], [0.0, 0.0, 1.5]);
+        assert_eq!(shoulder.axis, Vector3::y());
+        assert_eq!((shoulder.limit.lower, shoulder.limit.upper), (-1.0, 1.0));
+        assert_pose(
+            &robot.joint("tool_mount").unwrap().origin,
+            [0.0, 0.0, 0.5],
+            [0.0; 3],
+        );
+        let base = robot.link("base").unwrap();
+        assert_pose(&base.inertial.origin, [0.0, 0.0, 0.1], [0.0; 3]);
+        assert_eq!(base.inertial.inertia[(2, 2)], 0.2);
+        assert_eq!(
+            base.visuals[0].material.as_ref().unwrap().color,
+            Some(Vector4::new(1.0, 0.0, 0.0, 1.0))
+        );
+        let upper = robot.link("upper").unwrap();
+        assert_pose(&upper.visuals[0].origin, [0.0, 0.0, 0.25], [0.0; 3]);
+    }
+
+    #[test]
+    fn resolves_frame_graph_into_joint_origins() {
+        let sdf = r#"
+            <sdf version="1.9">
+              <model name="arm">
+                <link name="base"/>
+                <link name="upper">
+                  <pose>0 0 1 0 0 90</pose>
+                  <visual name="shell">
+                    <pose relative_to="base">0 0 1.5 0 0 0</pose>
+                    <geometry><capsule><radius>0.1</radius><length>1</length></capsule></geometry>
+                  </visual>
+                  <sensor name="imu" type="imu"/>
+                </link>
+                <link name="tip">
+                  <pose relative_to="mount">0.5 0 0 0 0 0</pose>
+                </link>
+                <frame name="mount" attached_to="upper">
+                  <pose relative_to="upper">0 0 1 0 0 0</pose>
+                </frame>
+                <joint name="shoulder" type="revolute">
+                  <pose>0 0 -0.5 0 0 0</pose>
+                  <parent>base</parent>
+                  <child>upper</child>
+                  <axis><xyz expressed_in="__model__">1 0 0</xyz></axis>
+                </joint>
+                <joint name="wrist" type="ball">
+                  <parent>upper</parent>
+                  <child>tip</child>
+                </joint>
+              </model>
+            </sdf>
+        "#;
+
+        let imported = parse_sdf_from_string(sdf).unwrap();
+
+        let robot = &imported.robot;
+        // `<pose>` has no `degrees` attribute, so 90 is in radians.
+        let yaw = 90.0_f64;
+        let shoulder = robot.joint("shoulder").unwrap();
+        assert_eq!(shoulder.joint_type, "continuous");
+        assert_pose(&shoulder.origin, [0.0, 0.0, 0.5], [0.0, 0.0, yaw]);
+        assert!((shoulder.axis - Vector3::new(yaw.cos(), -yaw.sin(), 0.0)).norm() < 1e-9);
+        let upper = robot.link("upper").unwrap();
+        assert_pose(&upper.inertial.origin, [0.0, 0.0, 0.5], [0.0; 3]);
+        assert_pose(&upper.visuals[0].origin, [0.0, 0.0, 1.0], [0.0, 0.0, -yaw]);
+        let wrist = robot.joint("wrist").unwrap();
+        assert_eq!(wrist.joint_type, "fixed");
+        // The wrist sits in the rotated frame of the shoulder joint.
+        assert_pose(&wrist.origin, [0.5, 0.0, 1.5], [0.0; 3]);
+        assert_eq!(
+            imported.warnings,
+            [
+                "`capsule` geometries were approximated as cylinders without the end caps",
+                "`<sensor>` elements of links were dropped",
+                "ball joint `wrist` cannot be represented and was imported as fixed",
+            ]
+        );
+    }
+}
@@ -5,6 +5,100 @@ use anyhow::{Context, Result};
 use na::{Matrix3, Vector3, Vector4};
 use std::collections::HashMap;
 use std::path::Path;
+use xmltree::{Element, EmitterConfig, XMLNode};
+
+trait XmlNode<'a>: Copy {
+    fn tag_name(&self) -> &'a str;
+    fn attribute(&self, name: &str) -> Option<&'a str>;
+    fn children(&self) -> impl Iterator<Item = Self>;
+    fn text(&self) -> &'a str;
+    /// The content between the start and end tags, as XML.
+    fn inner_xml(&self) -> Result<String>;
+}
+
+impl<'a, 'input: 'a> XmlNode<'a> for roxmltree::Node<'a, 'input> {
+    fn tag_name(&self) -> &'a str {
+        roxmltree::Node::tag_name(self).name()
+    }
+
+    fn attribute(&self, name: &str) -> Option<&'a str> {
+        roxmltree::Node::attribute(self, name)
+    }
+
+    fn children(&self) -> impl Iterator<Item = Self> {
+        roxmltree::Node::children(self).filter(roxmltree::Node::is_element)
+    }
+
+    fn text(&self) -> &'a str {
+        roxmltree::Node::text(self).unwrap_or("")
+    }
+
+    fn inner_xml(&self) -> Result<String> {
+        let (Some(first), Some(last)) = (self.first_child(), self.last_child()) else {
+            return Ok(String::new());
+        };
+        let range = first.range().start..last.range().end;
+        Ok(self.document().input_text()[range].trim().to_string())
+    }
+}
+
+impl<'a> XmlNode<'a> for &'a Element {
+    fn tag_name(&self) -> &'a str {
+        &self.name
+    }
+
+    fn attribute(&self, name: &str) -> Option<&'a str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+
+    fn children(&self) -> impl Iterator<Item = Self> {
+        self.children.iter().filter_map(XMLNode::as_element)
+    }
+
+    fn text(&self) -> &'a str {
+        self.children
+            .iter()
+            .find_map(|child| match child {
+                XMLNode::Text(text) | XMLNode::CData(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .unwrap_or("")
+    }
+
+    fn inner_xml(&self) -> Result<String> {
+        let mut xml = Vec::new();
+        for child in self.children.iter() {
+            match child {
+                XMLNode::Element(elem) => {
+                    // Namespaces are declared on the document root, not inside the block.
+                    let mut elem = elem.clone();
+                    clear_namespaces(&mut elem);
+                    let config = EmitterConfig::new().write_document_declaration(false);
+                    elem.write_with_config(&mut xml, config)?;
+                }
+                XMLNode::Text(text) => xml.extend(escape_text(text).as_bytes()),
+                XMLNode::CData(text) => xml.extend(format!("<![CDATA[{}]]>", text).as_bytes()),
+                XMLNode::Comment(_) | XMLNode::ProcessingInstruction(..) => {}
+            }
+        }
+        Ok(String::from_utf8(xml)?.trim().to_string())
+    }
+}
+
+fn clear_namespaces(elem: &mut Element) {
+    elem.namespaces = None;
+    for child in elem.children.iter_mut() {
+        if let XMLNode::Element(child) = child {
+            clear_namespaces(child);
+        }
+    }
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
 
 fn parse_string_to_vector3(s: &str) -> Result<Vector3<f64>> {
     let vec = s
@@ -38,7 +132,7 @@ fn parse_string_to_vector4(s: &str) -> Result<Vector4<f64>> {
     }
 }
 
-fn parse_pose(node: roxmltree::Node) -> Result<Pose> {
+fn parse_pose<'a, N: XmlNode<'a>>(node: N) -> Result<Pose> {
     let xyz_str = node.attribute("xyz").unwrap_or("");
     let rpy_str = node.attribute("rpy").unwrap_or("");
     Ok(Pose {
@@ -47,7 +141,7 @@ fn parse_pose(node: roxmltree::Node) -> Result<Pose> {
     })
 }
 
-fn parse_inertia(node: roxmltree::Node) -> Result<Matrix3<f64>> {
+fn parse_inertia<'a, N: XmlNode<'a>>(node: N) -> Result<Matrix3<f64>> {
     let mut inertia = Matrix3::<f64>::identity();
     inertia[(0, 0)] = node
         .attribute("ixx")
@@ -79,12 +173,12 @@ fn parse_inertia(node: roxmltree::Node) -> Result<Matrix3<f64>> {
     Ok(inertia)
 }
 
-fn parse_inertial(node: roxmltree::Node) -> Result<Inertial> {
+fn parse_inertial<'a, N: XmlNode<'a>>(node: N) -> Result<Inertial> {
     let mut origin = Pose::default();
     let mut mass = 1.0f64;
     let mut inertia = Matrix3::<f64>::identity();
     for child in node.children() {
-        match child.tag_name().name() {
+        match child.tag_name() {
             "origin" => origin = parse_pose(child)?,
             "mass" => {
                 mass = child
@@ -97,13 +191,13 @@ fn parse_inertial(node: roxmltree::Node) -> Result<Inertial> {
         }
     }
     Ok(Inertial {
-        origin: origin,
-        mass: mass,
-        inertia: inertia,
+        origin,
+        mass,
+        inertia,
     })
 }
 
-fn parse_limit(node: roxmltree::Node) -> Result<JointLimit> {
+fn parse_limit<'a, N: XmlNode<'a>>(node: N) -> Result<JointLimit> {
     let lower = node.attribute("lower").unwrap_or("0").parse()?;
     let upper = node.attribute("upper").unwrap_or("0").parse()?;
     let effort = node
@@ -115,23 +209,34 @@ fn parse_limit(node: roxmltree::Node) -> Result<JointLimit> {
         .ok_or(anyhow::anyhow!("Failed to parse limit velocity"))?
         .parse()?;
     Ok(JointLimit {
-        lower: lower,
-        upper: upper,
-        effort: effort,
-        velocity: velocity,
+        lower,
+        upper,
+        effort,
+        velocity,
     })
 }
 
-fn parse_geometry(node: roxmltree::Node) -> Result<Geometry> {
+fn parse_mimic<'a, N: XmlNode<'a>>(node: N) -> Result<Mimic> {
+    let joint = node
+        .attribute("joint")
+        .ok_or(anyhow::anyhow!("Failed to parse mimic joint"))?;
+    Ok(Mimic {
+        joint: joint.to_string(),
+        multiplier: node.attribute("multiplier").unwrap_or("1").parse()?,
+        offset: node.attribute("offset").unwrap_or("0").parse()?,
+    })
+}
+
+fn parse_geometry<'a, N: XmlNode<'a>>(node: N) -> Result<Geometry> {
     for child in node.children() {
-        match child.tag_name().name() {
+        match child.tag_name() {
             "box" => {
                 let size = parse_string_to_vector3(
                     child
                         .attribute("size")
                         .ok_or(anyhow::anyhow!("Failed to parse box size"))?,
                 )?;
-                return Ok(Geometry::Box { size: size });
+                return Ok(Geometry::Box { size });
             }
             "cylinder" => {
                 let radius = child
@@ -142,17 +247,14 @@ fn parse_geometry(node: roxmltree::Node) -> Result<Geometry> {
                     .attribute("length")
                     .ok_or(anyhow::anyhow!("Failed to parse cylinder length"))?
                     .parse()?;
-                return Ok(Geometry::Cylinder {
-                    radius: radius,
-                    length: length,
-                });
+                return Ok(Geometry::Cylinder { radius, length });
             }
             "sphere" => {
                 let radius = child
                     .attribute("radius")
                     .ok_or(anyhow::anyhow!("Failed to parse sphere radius"))?
                     .parse()?;
-                return Ok(Geometry::Sphere { radius: radius });
+                return Ok(Geometry::Sphere { radius });
             }
             "mesh" => {
                 let filename = child
@@ -172,21 +274,18 @@ fn parse_geometry(node: roxmltree::Node) -> Result<Geometry> {
     Err(anyhow::anyhow!("Failed to parse geometry"))
 }
 
-fn parse_material(
-    node: roxmltree::Node,
+fn parse_material<'a, N: XmlNode<'a>>(
+    node: N,
     material_library: &HashMap<String, Material>,
 ) -> Result<Material> {
     let name = node.attribute("name").map(String::from);
     let mut color = None;
     for child in node.children() {
-        match child.tag_name().name() {
-            "color" => {
-                color =
-                    Some(parse_string_to_vector4(child.attribute("rgba").ok_or(
-                        anyhow::anyhow!("Failed to parse material color rgba"),
-                    )?)?);
-            }
-            &_ => (),
+        if child.tag_name() == "color" {
+            color =
+                Some(parse_string_to_vector4(child.attribute("rgba").ok_or(
+                    anyhow::anyhow!("Failed to parse material color rgba"),
+                )?)?);
         }
     }
 
@@ -201,13 +300,15 @@ fn parse_material(
     Ok(Material { name, color })
 }
 
-fn parse_materials(node: roxmltree::Node) -> Result<(Vec<Material>, HashMap<String, Material>)> {
+fn parse_materials<'a, N: XmlNode<'a>>(
+    node: N,
+) -> Result<(Vec<Material>, HashMap<String, Material>)> {
     let mut materials = Vec::new();
     let mut material_library = HashMap::new();
     let empty_material_library = HashMap::new();
 
     for child in node.children() {
-        if child.tag_name().name() != "material" {
+        if child.tag_name() != "material" {
             continue;
         }
         let material = parse_material(child, &empty_material_library)?;
@@ -220,8 +321,8 @@ fn parse_materials(node: roxmltree::Node) -> Result<(Vec<Material>, HashMap<Stri
     Ok((materials, material_library))
 }
 
-fn parse_visual(
-    node: roxmltree::Node,
+fn parse_visual<'a, N: XmlNode<'a>>(
+    node: N,
     material_library: &HashMap<String, Material>,
 ) -> Result<Visual> {
     let name = node.attribute("name").map(String::from);
@@ -231,7 +332,7 @@ fn parse_visual(
     };
     let mut material = None;
     for child in node.children() {
-        match child.tag_name().name() {
+        match child.tag_name() {
             "origin" => origin = parse_pose(child)?,
             "geometry" => geometry = parse_geometry(child)?,
             "material" => material = Some(parse_material(child, material_library)?),
@@ -239,34 +340,37 @@ fn parse_visual(
         }
     }
     Ok(Visual {
-        name: name,
-        origin: origin,
-        geometry: geometry,
-        material: material,
+        name,
+        origin,
+        geometry,
+        material,
     })
 }
 
-fn parse_collision(node: roxmltree::Node) -> Result<Collision> {
+fn parse_collision<'a, N: XmlNode<'a>>(node: N) -> Result<Collision> {
     let name = node.attribute("name").map(String::from);
     let mut origin = Pose::default();
     let mut geometry = Geometry::Box {
         size: Vector3::<f64>::zeros(),
     };
     for child in node.children() {
-        match child.tag_name().name() {
+        match child.tag_name() {
             "origin" => origin = parse_pose(child)?,
             "geometry" => geometry = parse_geometry(child)?,
             &_ => (),
         }
     }
     Ok(Collision {
-        name: name,
-        origin: origin,
-        geometry: geometry,
+        name,
+        origin,
+        geometry,
     })
 }
 
-fn parse_link(node: roxmltree::Node, material_library: &HashMap<String, Material>) -> Result<Link> {
+fn parse_link<'a, N: XmlNode<'a>>(
+    node: N,
+    material_library: &HashMap<String, Material>,
+) -> Result<Link> {
     let name = String::from(
         node.attribute("name")
             .ok_or(anyhow::anyhow!("Failed to parse link name"))?,
@@ -275,7 +379,7 @@ fn parse_link(node: roxmltree::Node, material_library: &HashMap<String, Material
     let mut visuals: Vec<Visual> = Vec::new();
     let mut collisions: Vec<Collision> = Vec::new();
     for child in node.children() {
-        match child.tag_name().name() {
+        match child.tag_name() {
             "inertial" => inertial = parse_inertial(child)?,
             "visual" => visuals.push(parse_visual(child, material_library)?),
             "collision" => collisions.push(parse_collision(child)?),
@@ -283,14 +387,14 @@ fn parse_link(node: roxmltree::Node, material_library: &HashMap<String, Material
         }
     }
     Ok(Link {
-        name: name,
-        inertial: inertial,
-        visuals: visuals,
-        collisions: collisions,
+        name,
+        inertial,
+        visuals,
+        collisions,
     })
 }
 
-fn parse_joint(node: roxmltree::Node) -> Result<Joint> {
+fn parse_joint<'a, N: XmlNode<'a>>(node: N) -> Result<Joint> {
     let name = String::from(
         node.attribute("name")
             .ok_or(anyhow::anyhow!("Failed to parse joint name"))?,
@@ -304,43 +408,115 @@ fn parse_joint(node: roxmltree::Node) -> Result<Joint> {
     let mut jchild = None;
     let mut axis = Vector3::new(1.0, 0.0, 0.0);
     let mut limit = JointLimit::default();
+    let mut mimic = None;
     for child in node.children() {
-        match child.tag_name().name() {
+        match child.tag_name() {
             "origin" => origin = parse_pose(child)?,
             "parent" => jparent = child.attribute("link"),
             "child" => jchild = child.attribute("link"),
             "axis" => axis = parse_pose(child)?.xyz,
             "limit" => limit = parse_limit(child)?,
+            "mimic" => mimic = Some(parse_mimic(child)?),
             &_ => (),
         }
     }
     Ok(Joint {
-        name: name,
-        joint_type: joint_type,
-        origin: origin,
+        name,
+        joint_type,
+        origin,
         parent: String::from(jparent.ok_or(anyhow::anyhow!("Failed to parse joint parent"))?),
         child: String::from(jchild.ok_or(anyhow::anyhow!("Failed to parse joint child"))?),
-        axis: axis,
-        limit: limit,
+        axis,
+        limit,
+        mimic,
+    })
+}
+
+fn parse_transmission<'a, N: XmlNode<'a>>(node: N) -> Result<Transmission> {
+    let name = String::from(
+        node.attribute("name")
+            .ok_or(anyhow::anyhow!("Failed to parse transmission name"))?,
+    );
+    // The ROS 1 format before `<type>` gave the type as an attribute.
+    let mut transmission_type = node.attribute("type").unwrap_or("").to_string();
+    let mut joints = Vec::new();
+    let mut actuators = Vec::new();
+    for child in node.children() {
+        match child.tag_name() {
+            "type" => transmission_type = child.text().trim().to_string(),
+            "joint" => joints.push(TransmissionJoint {
+                name: String::from(
+                    child
+                        .attribute("name")
+                        .ok_or(anyhow::anyhow!("Failed to parse transmission joint name"))?,
+                ),
+                hardware_interfaces: hardware_interfaces(child),
+            }),
+            "actuator" => {
+                let mechanical_reduction = child
+                    .children()
+                    .find(|n| n.tag_name() == "mechanicalReduction")
+                    .map(|n| n.text().trim().parse())
+                    .transpose()?;
+                actuators.push(Actuator {
+                    name: String::from(
+                        child
+                            .attribute("name")
+                            .ok_or(anyhow::anyhow!("Failed to parse actuator name"))?,
+                    ),
+                    mechanical_reduction,
+                    hardware_interfaces: hardware_interfaces(child),
+                });
+            }
+            &_ => (),
+        }
+    }
+    Ok(Transmission {
+        name,
+        transmission_type,
+        joints,
+        actuators,
+    })
+}
+
+fn hardware_interfaces<'a, N: XmlNode<'a>>(node: N) -> Vec<String> {
+    node.children()
+        .filter(|n| n.tag_name() == "hardwareInterface")
+        .map(|n| n.text().trim().to_string())
+        .collect()
+}
+
+fn parse_extension<'a, N: XmlNode<'a>>(node: N) -> Result<Extension> {
+    Ok(Extension {
+        reference: node.attribute("reference").map(String::from),
+        xml: node
+            .inner_xml()
+            .context("failed to read gazebo extension")?,
     })
 }
 
 pub fn parse_urdf_from_string(xml: &str) -> Result<Robot> {
     let doc = roxmltree::Document::parse(xml)?;
-    let node = doc.root_element();
+    parse_robot(doc.root_element())
+}
+
+pub fn parse_urdf_from_element(elem: &Element) -> Result<Robot> {
+    parse_robot(elem)
+}
+
+fn parse_robot<'a, N: XmlNode<'a>>(node: N) -> Result<Robot> {
     let (materials, material_library) = parse_materials(node)?;
     let links = node
         .children()
-        .filter(|n| n.tag_name().name() == "link")
-        .map(|n| {
+        .filter(|n| n.tag_name() == "link")
+        .flat_map(|n| {
             let link = parse_link(n, &material_library)?;
             Result::<Link>::Ok(link)
         })
-        .flatten()
         .collect();
     let joints = node
         .children()
-        .filter(|n| n.tag_name().name() == "joint")
+        .filter(|n| n.tag_name() == "joint")
         .map(|n| {
             parse_joint(n).with_context(|| {
                 format!(
@@ -350,14 +526,33 @@ pub fn parse_urdf_from_string(xml: &str) -> Result<Robot> {
             })
         })
         .collect::<Result<Vec<Joint>>>()?;
+    let transmissions = node
+        .children()
+        .filter(|n| n.tag_name() == "transmission")
+        .map(|n| {
+            parse_transmission(n).with_context(|| {
+                format!(
+                    "failed to parse transmission `{}`",
+                    n.attribute("name").unwrap_or("<unnamed>")
+                )
+            })
+        })
+        .collect::<Result<Vec<Transmission>>>()?;
+    let extensions = node
+        .children()
+        .filter(|n| n.tag_name() == "gazebo")
+        .map(parse_extension)
+        .collect::<Result<Vec<Extension>>>()?;
     Ok(Robot {
         name: String::from(
             node.attribute("name")
                 .ok_or(anyhow::anyhow!("Failed to parse robot name"))?,
         ),
-        materials: materials,
-        links: links,
-        joints: joints,
+        materials,
+        links,
+        joints,
+        transmissions,
+        extensions,
     })
 }
 
@@ -396,6 +591,39 @@ mod tests {
         assert_eq!(robot.joints[0].axis, Vector3::new(1.0, 0.0, 0.0));
     }
 
+    #[test]
+    fn parses_mimic_with_defaults() {
+        let robot = parse_urdf_from_string(
+            r#"
+            <robot name="gripper">
+              <link name="palm"/>
+              <link name="left"/>
+              <link name="right"/>
+              <joint name="left_finger" type="prismatic">
+                <parent link="palm"/>
+                <child link="left"/>
+              </joint>
+              <joint name="right_finger" type="prismatic">
+                <parent link="palm"/>
+                <child link="right"/>
+                <mimic joint="left_finger" multiplier="-1"/>
+              </joint>
+            </robot>
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(robot.joints[0].mimic, None);
+        assert_eq!(
+            robot.joints[1].mimic,
+            Some(Mimic {
+                joint: "left_finger".to_string(),
+                multiplier: -1.0,
+                offset: 0.0,
+            })
+        );
+    }
+
     #[test]
     fn invalid_joint_returns_error_instead_of_being_ignored() {
         let err = parse_urdf_from_string(
@@ -416,6 +644,70 @@ mod tests {
         assert!(message.contains("Failed to parse joint child"));
     }
 
+    #[test]
+    fn parses_xmltree_element_like_string() {
+        let xml = r#"
+            <robot name="tree">
+              <material name="blue"><color rgba="0 0 1 1"/></material>
+              <link name="base">
+                <inertial><mass value="2"/><inertia ixx="1" ixy="0" ixz="0" iyy="1" iyz="0" izz="1"/></inertial>
+                <visual><geometry><box size="1 2 3"/></geometry><material name="blue"/></visual>
+              </link>
+              <link name="child"/>
+              <joint name="joint" type="prismatic">
+                <parent link="base"/>
+                <child link="child"/>
+                <origin xyz="1 0 0" rpy="0 0 1"/>
+                <limit lower="-1" upper="1" effort="5" velocity="2"/>
+              </joint>
+            </robot>
+        "#;
+        let from_string = parse_urdf_from_string(xml).unwrap();
+        let elem = Element::parse(xml.as_bytes()).unwrap();
+
+        let from_element = parse_urdf_from_element(&elem).unwrap();
+
+        assert_eq!(format!("{:?}", from_element), format!("{:?}", from_string));
+    }
+
+    #[test]
+    fn keeps_gazebo_extensions_as_xml() {
+        let xml = r#"
+            <robot name="gazebo" xmlns:xacro="http://www.ros.org/wiki/xacro">
+              <link name="base"/>
+              <gazebo reference="base">
+                <mu1>0.5</mu1>
+                <sensor name="cam" type="camera"><update_rate>30</update_rate></sensor>
+              </gazebo>
+              <gazebo><plugin filename="libp.so">a &amp; b</plugin></gazebo>
+            </robot>
+        "#;
+        let from_string = parse_urdf_from_string(xml).unwrap();
+        let from_element =
+            parse_urdf_from_element(&Element::parse(xml.as_bytes()).unwrap()).unwrap();
+
+        assert_eq!(
+            from_string.extensions,
+            vec![
+                Extension {
+                    reference: Some("base".to_string()),
+                    xml: r#"<mu1>0.5</mu1>
+                <sensor name="cam" type="camera"><update_rate>30</update_rate></sensor>"#
+                        .to_string(),
+                },
+                Extension {
+                    reference: None,
+                    xml: r#"<plugin filename="libp.so">a &amp; b</plugin>"#.to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            from_element.extensions[1].xml,
+            r#"<plugin filename="libp.so">a &amp; b</plugin>"#
+        );
+        assert!(!from_element.extensions[0].xml.contains("xmlns"));
+    }
+
     #[test]
     fn test_parse_visual_material_color() {
         let robot = parse_urdf_from_string(
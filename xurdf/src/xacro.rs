@@ -1,1442 +1,3782 @@
 use super::eval::*;
+use super::json_writer::json_string;
+use super::model::Robot;
+use super::urdf::parse_urdf_from_element;
 use anyhow::{bail, Context as AnyhowContext, Result};
 use std::collections::{BTreeMap, HashMap, HashSet};
-use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use xmltree::{Element, XMLNode};
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use xml::common::Position;
+use xml::reader::{EventReader, ParserConfig, XmlEvent};
+use xmltree::{Element, EmitterConfig, XMLNode};
 
 const XACRO_PREFIX: &str = "xacro";
 const UNSUPPORTED_XACRO_TAGS: &[&str] = &["element", "attribute"];
+const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="UTF-8"?>"#;
 
-#[derive(Clone, Debug)]
-struct Macro {
-    params: Vec<MacroParam>,
-    body: Element,
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
 }
 
-#[derive(Clone, Debug)]
-struct MacroParam {
-    name: String,
-    kind: MacroParamKind,
-    default: Option<MacroDefault>,
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}:{}", file.display(), self.line, self.column),
+            None => write!(f, "<string>:{}:{}", self.line, self.column),
+        }
+    }
 }
 
-impl MacroParam {
-    fn symbol_key(&self) -> String {
-        match self.kind {
-            MacroParamKind::Value => self.name.clone(),
-            MacroParamKind::SingleBlock => format!("*{}", self.name),
-            MacroParamKind::ContentBlock => format!("**{}", self.name),
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MacroCall {
+    pub name: String,
+    pub location: Option<SourceLocation>,
+}
+
+#[derive(Debug)]
+pub struct XacroDiagnostic {
+    error: anyhow::Error,
+    location: SourceLocation,
+    source_line: Option<String>,
+    macro_stack: Vec<MacroCall>,
+}
+
+impl XacroDiagnostic {
+    pub fn error(&self) -> &anyhow::Error {
+        &self.error
+    }
+
+    pub fn location(&self) -> &SourceLocation {
+        &self.location
+    }
+
+    pub fn macro_stack(&self) -> &[MacroCall] {
+        &self.macro_stack
+    }
+
+    pub fn render(&self) -> String {
+        let line_number = self.location.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        let mut rendered = format!(
+            "error: {:#}\n{} --> {}\n",
+            self.error, gutter, self.location
+        );
+        if let Some(source_line) = &self.source_line {
+            let caret_indent = source_line
+                .chars()
+                .take(self.location.column.saturating_sub(1))
+                .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+                .collect::<String>();
+            rendered.push_str(&format!("{} |\n", gutter));
+            rendered.push_str(&format!("{} | {}\n", line_number, source_line));
+            rendered.push_str(&format!("{} | {}^\n", gutter, caret_indent));
+        }
+        // A runaway recursion repeats the same calls, so stop at the first repeat.
+        let mut seen = HashSet::new();
+        for (i, call) in self.macro_stack.iter().rev().enumerate() {
+            match &call.location {
+                Some(location) => rendered.push_str(&format!(
+                    "{} = note: in expansion of macro `xacro:{}` at {}\n",
+                    gutter, call.name, location
+                )),
+                None => rendered.push_str(&format!(
+                    "{} = note: in expansion of macro `xacro:{}`\n",
+                    gutter, call.name
+                )),
+            }
+            if !seen.insert(call) {
+                let remaining = self.macro_stack.len() - i - 1;
+                if remaining > 0 {
+                    rendered.push_str(&format!(
+                        "{} = note: ... and {} more expansions\n",
+                        gutter, remaining
+                    ));
+                }
+                break;
+            }
         }
+        rendered
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-enum MacroParamKind {
-    Value,
-    SingleBlock,
-    ContentBlock,
+impl fmt::Display for XacroDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.error)
+    }
 }
 
-#[derive(Clone, Debug)]
-struct MacroDefault {
-    forward: bool,
-    value: Option<String>,
+impl std::error::Error for XacroDiagnostic {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
 }
 
-#[derive(Clone, Debug)]
-struct BlockValue {
-    nodes: Vec<XMLNode>,
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct XacroDependencies {
+    pub includes: Vec<PathBuf>,
+    pub yaml_files: Vec<PathBuf>,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-enum PropertyScope {
-    Local,
-    Parent,
-    Global,
+impl XacroDependencies {
+    pub fn all(&self) -> impl Iterator<Item = &PathBuf> {
+        self.includes.iter().chain(self.yaml_files.iter())
+    }
+
+    fn add_include(&mut self, path: PathBuf) {
+        if !self.includes.contains(&path) {
+            self.includes.push(path);
+        }
+    }
+
+    fn add_yaml_file(&mut self, path: PathBuf) {
+        if !self.yaml_files.contains(&path) {
+            self.yaml_files.push(path);
+        }
+    }
 }
 
-#[derive(Clone, Debug, Default)]
-struct XacroContext {
-    properties: HashMap<String, XacroValue>,
-    blocks: HashMap<String, BlockValue>,
-    macros: HashMap<String, Macro>,
-    args: HashMap<String, String>,
+/// A [`XacroSandbox`] limit that processing ran into.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum XacroSandboxError {
+    PathNotAllowed(PathBuf),
+    EnvNotAllowed(String),
+    IncludeDepthExceeded(usize),
+    /// The limit and the names of the macros being expanded, outermost first.
+    MacroDepthExceeded(usize, Vec<String>),
+    OutputTooLarge(usize),
+    StepBudgetExceeded(u64),
+    TimeBudgetExceeded(Duration),
 }
 
-pub trait XacroSubstitutionResolver: std::fmt::Debug + Send + Sync {
-    fn resolve_arg(&self, _name: &str) -> Result<Option<String>> {
-        Ok(None)
+impl fmt::Display for XacroSandboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PathNotAllowed(path) => write!(
+                f,
+                "sandbox: `{}` is outside the allowed directories",
+                path.display()
+            ),
+            Self::EnvNotAllowed(name) => {
+                write!(f, "sandbox: environment variable `{}` is not allowed", name)
+            }
+            Self::IncludeDepthExceeded(limit) => {
+                write!(f, "sandbox: include depth limit of {} exceeded", limit)
+            }
+            Self::MacroDepthExceeded(limit, chain) => {
+                write!(f, "sandbox: macro call depth limit of {} exceeded: ", limit)?;
+                // A runaway recursion repeats the same calls, so stop at the first repeat.
+                let mut seen = HashSet::new();
+                for (i, name) in chain.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" -> ")?;
+                    }
+                    write!(f, "xacro:{}", name)?;
+                    if !seen.insert(name) {
+                        if i + 1 < chain.len() {
+                            f.write_str(" -> ...")?;
+                        }
+                        break;
+                    }
+                }
+                Ok(())
+            }
+            Self::OutputTooLarge(limit) => {
+                write!(f, "sandbox: output size limit of {} bytes exceeded", limit)
+            }
+            Self::StepBudgetExceeded(limit) => {
+                write!(f, "sandbox: step budget of {} exceeded", limit)
+            }
+            Self::TimeBudgetExceeded(limit) => {
+                write!(f, "sandbox: time budget of {:?} exceeded", limit)
+            }
+        }
     }
+}
 
-    fn resolve_find(&self, _package: &str) -> Result<Option<PathBuf>> {
-        Ok(None)
-    }
+impl std::error::Error for XacroSandboxError {}
+
+/// Finds the sandbox violation behind a processing error, including one reported with a
+/// source location.
+pub fn xacro_sandbox_error(err: &anyhow::Error) -> Option<&XacroSandboxError> {
+    err.chain().find_map(|err| {
+        err.downcast_ref::<XacroSandboxError>().or_else(|| {
+            err.downcast_ref::<XacroDiagnostic>()
+                .and_then(|diagnostic| xacro_sandbox_error(diagnostic.error()))
+        })
+    })
 }
 
-#[derive(Debug, Default)]
-struct EmptyXacroSubstitutionResolver;
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum XacroEnvAccess {
+    Allow,
+    #[default]
+    Deny,
+    AllowList(Vec<String>),
+}
 
-impl XacroSubstitutionResolver for EmptyXacroSubstitutionResolver {}
+impl XacroEnvAccess {
+    fn allows(&self, name: &str) -> bool {
+        match self {
+            Self::Allow => true,
+            Self::Deny => false,
+            Self::AllowList(names) => names.iter().any(|allowed| allowed == name),
+        }
+    }
+}
 
+/// Restrictions for expanding untrusted Xacro. A new sandbox denies all file and environment
+/// access until directories and variables are allowed; its other limits are off until set.
 #[derive(Clone, Debug)]
 #[non_exhaustive]
-pub struct XacroOptions {
-    pub require_macro_params: bool,
-    pub args: HashMap<String, String>,
-    pub package_paths: HashMap<String, PathBuf>,
-    substitution_resolver: Arc<dyn XacroSubstitutionResolver>,
+pub struct XacroSandbox {
+    /// Files, including the current directory, may only be read below these directories.
+    pub allowed_roots: Vec<PathBuf>,
+    pub env_access: XacroEnvAccess,
+    pub max_include_depth: Option<usize>,
+    pub max_output_bytes: Option<usize>,
+    /// Elements and macro calls processed.
+    pub max_steps: Option<u64>,
+    pub time_budget: Option<Duration>,
 }
 
-impl Default for XacroOptions {
+impl Default for XacroSandbox {
     fn default() -> Self {
         Self {
-            require_macro_params: true,
-            args: HashMap::new(),
-            package_paths: HashMap::new(),
-            substitution_resolver: Arc::new(EmptyXacroSubstitutionResolver),
+            allowed_roots: Vec::new(),
+            env_access: XacroEnvAccess::Deny,
+            max_include_depth: None,
+            max_output_bytes: None,
+            max_steps: None,
+            time_budget: None,
         }
     }
 }
 
-impl XacroOptions {
-    pub fn with_arg(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
-        self.args.insert(name.into(), value.into());
+impl XacroSandbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_allowed_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.allowed_roots.push(root.into());
         self
     }
 
-    pub fn with_package_path(
-        mut self,
-        package: impl Into<String>,
-        path: impl Into<PathBuf>,
-    ) -> Self {
-        self.package_paths.insert(package.into(), path.into());
+    pub fn with_env_access(mut self, env_access: XacroEnvAccess) -> Self {
+        self.env_access = env_access;
         self
     }
 
-    pub fn with_substitution_resolver<R>(mut self, resolver: R) -> Self
-    where
-        R: XacroSubstitutionResolver + 'static,
-    {
-        self.substitution_resolver = Arc::new(resolver);
+    pub fn with_max_include_depth(mut self, depth: usize) -> Self {
+        self.max_include_depth = Some(depth);
         self
     }
-}
 
-#[derive(Debug)]
-pub struct XacroProcessor {
-    context: XacroContext,
-    options: XacroOptions,
-    include_stack: Vec<PathBuf>,
-    parent_property_exports: HashMap<String, XacroValue>,
-    global_property_exports: HashMap<String, XacroValue>,
-    parent_block_exports: HashMap<String, BlockValue>,
-    global_block_exports: HashMap<String, BlockValue>,
-}
+    pub fn with_max_output_bytes(mut self, bytes: usize) -> Self {
+        self.max_output_bytes = Some(bytes);
+        self
+    }
 
-impl Default for XacroProcessor {
-    fn default() -> Self {
-        Self::new()
+    pub fn with_max_steps(mut self, steps: u64) -> Self {
+        self.max_steps = Some(steps);
+        self
+    }
+
+    pub fn with_time_budget(mut self, budget: Duration) -> Self {
+        self.time_budget = Some(budget);
+        self
     }
 }
 
-impl XacroProcessor {
-    pub fn new() -> Self {
-        Self::with_options(XacroOptions::default())
+// Wraps the configured provider so every read, including package manifests, stays inside
+// the sandbox roots.
+#[derive(Debug)]
+struct SandboxedFileProvider {
+    files: Arc<dyn XacroFileProvider>,
+    roots: Vec<PathBuf>,
+}
+
+impl SandboxedFileProvider {
+    fn new(files: Arc<dyn XacroFileProvider>, roots: &[PathBuf]) -> Self {
+        let roots = roots
+            .iter()
+            .map(|root| files.canonicalize(root).unwrap_or_else(|_| root.clone()))
+            .collect();
+        Self { files, roots }
     }
 
-    pub fn with_options(options: XacroOptions) -> Self {
-        Self {
-            context: context_from_options(&options),
-            options,
-            include_stack: Vec::new(),
-            parent_property_exports: HashMap::new(),
-            global_property_exports: HashMap::new(),
-            parent_block_exports: HashMap::new(),
-            global_block_exports: HashMap::new(),
+    fn check(&self, path: &Path) -> Result<PathBuf> {
+        let canonical = self.files.canonicalize(path)?;
+        if !self.roots.iter().any(|root| canonical.starts_with(root)) {
+            return Err(XacroSandboxError::PathNotAllowed(canonical).into());
         }
+        Ok(canonical)
     }
+}
 
-    pub fn current_file(&self) -> Option<&Path> {
-        self.include_stack.last().map(PathBuf::as_path)
+impl XacroFileProvider for SandboxedFileProvider {
+    fn read(&self, path: &Path) -> Result<String> {
+        self.files.read(&self.check(path)?)
     }
 
-    pub fn current_dir(&self) -> Option<&Path> {
-        self.current_file().and_then(Path::parent)
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        self.check(path)
     }
 
-    pub fn process_string(&mut self, xml: &str) -> Result<String> {
-        self.reset_context();
-        self.process_source_string(xml)
+    fn exists(&self, path: &Path) -> bool {
+        self.check(path).is_ok_and(|path| self.files.exists(&path))
     }
 
-    pub fn process_file<P: AsRef<Path>>(&mut self, path: P) -> Result<String> {
-        self.reset_context();
-        self.process_file_inner(path.as_ref())
+    fn is_dir(&self, path: &Path) -> bool {
+        self.check(path).is_ok_and(|path| self.files.is_dir(&path))
     }
 
-    pub fn process_document(&mut self, elem: &Element) -> Result<Element> {
-        self.reset_context();
-        self.process_element(elem)
+    fn current_dir(&self) -> Result<PathBuf> {
+        self.check(&self.files.current_dir()?)
     }
 
-    fn reset_context(&mut self) {
-        self.context = context_from_options(&self.options);
-        self.include_stack.clear();
-        self.parent_property_exports.clear();
-        self.global_property_exports.clear();
-        self.parent_block_exports.clear();
-        self.global_block_exports.clear();
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        self.files.modified(&self.check(path).ok()?)
     }
 
-    fn process_file_inner(&mut self, path: &Path) -> Result<String> {
-        let new_elem = self.process_file_to_element_inner(path)?;
-        write_element_to_string(&new_elem)
+    fn size(&self, path: &Path) -> Option<u64> {
+        self.files.size(&self.check(path).ok()?)
     }
+}
 
-    fn process_file_to_element_inner(&mut self, path: &Path) -> Result<Element> {
-        let canonical_path = std::fs::canonicalize(path)
-            .with_context(|| format!("failed to resolve xacro file `{}`", path.display()))?;
+// Step, time and output accounting shared by a processor and its macro calls.
+#[derive(Debug)]
+struct XacroBudget {
+    started: Instant,
+    steps: u64,
+    output_bytes: usize,
+}
 
-        if self.include_stack.iter().any(|p| p == &canonical_path) {
-            bail!(
-                "cyclic xacro include detected: {}",
-                format_include_stack(&self.include_stack, &canonical_path)
-            );
+impl Default for XacroBudget {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            steps: 0,
+            output_bytes: 0,
         }
+    }
+}
 
-        let xml = std::fs::read_to_string(&canonical_path)
-            .with_context(|| format!("failed to read xacro file `{}`", canonical_path.display()))?;
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeclaredArg {
+    pub name: String,
+    pub default: String,
+}
 
-        self.include_stack.push(canonical_path.clone());
-        let result = self
-            .process_source_element(&xml)
-            .with_context(|| format!("while processing xacro file `{}`", canonical_path.display()));
-        self.include_stack.pop();
-        result
-    }
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct XacroArgReport {
+    pub declared: Vec<DeclaredArg>,
+    pub used: Vec<String>,
+    pub provided: Vec<String>,
+}
 
-    fn process_source_string(&mut self, xml: &str) -> Result<String> {
-        let new_elem = self.process_source_element(xml)?;
-        write_element_to_string(&new_elem)
+impl XacroArgReport {
+    pub fn undeclared(&self) -> impl Iterator<Item = &str> {
+        self.provided
+            .iter()
+            .filter(|name| !self.is_declared(name))
+            .map(String::as_str)
     }
 
-    fn process_source_element(&mut self, xml: &str) -> Result<Element> {
-        let elem = Element::parse(xml.as_bytes()).context("failed to parse xacro XML")?;
-        self.process_element(&elem)
+    pub fn unused(&self) -> impl Iterator<Item = &DeclaredArg> {
+        self.declared
+            .iter()
+            .filter(|arg| !self.used.contains(&arg.name))
     }
 
-    fn process_element(&mut self, elem: &Element) -> Result<Element> {
-        let mut new_elem = elem.clone();
-        new_elem.children.clear();
+    fn is_declared(&self, name: &str) -> bool {
+        self.declared.iter().any(|arg| arg.name == name)
+    }
 
-        if elem.prefix.is_none() {
-            if element_attributes_use_args(elem) {
-                self.handle_direct_args(elem)?;
-            }
-            for (name, val) in elem.attributes.iter() {
-                let new_value = self.eval_text(val)?;
-                new_elem.attributes.insert(name.clone(), new_value);
-            }
+    fn declare(&mut self, name: &str, default: &str) {
+        if !self.is_declared(name) {
+            self.declared.push(DeclaredArg {
+                name: name.to_string(),
+                default: default.to_string(),
+            });
         }
+    }
 
-        for child in elem.children.iter() {
-            let Some(node) = child.as_element() else {
-                continue;
-            };
-
-            match xacro_tag_name(node) {
-                Some("property") => self.handle_property(node)?,
-                Some("arg") => self.handle_arg(node)?,
-                Some("macro") => self.handle_macro_definition(node)?,
-                Some("include") => {
-                    let new_nodes = self.handle_include(node)?;
-                    new_elem.children.extend(new_nodes);
-                }
-                Some("insert_block") => {
-                    let new_nodes = self.handle_insert_block(node)?;
-                    new_elem.children.extend(new_nodes);
-                }
-                Some("if") => {
-                    if self.evaluate_condition(node, "if")? {
-                        let new_node = self.process_element(node)?;
-                        new_elem.children.extend(new_node.children);
-                    }
-                }
-                Some("unless") => {
-                    if !self.evaluate_condition(node, "unless")? {
-                        let new_node = self.process_element(node)?;
-                        new_elem.children.extend(new_node.children);
-                    }
-                }
-                Some(name) => {
-                    let new_nodes = self.handle_macro_call(node, name)?;
-                    new_elem.children.extend(new_nodes);
-                }
-                None => {
-                    let new_node = self.process_element(node)?;
-                    new_elem.children.push(XMLNode::Element(new_node));
-                }
-            }
+    fn mark_used(&mut self, name: &str) {
+        if !self.used.iter().any(|used| used == name) {
+            self.used.push(name.to_string());
         }
-
-        Ok(new_elem)
     }
+}
 
-    fn handle_direct_args(&mut self, elem: &Element) -> Result<()> {
-        for child in elem.children.iter() {
-            let Some(node) = child.as_element() else {
-                continue;
-            };
-            if xacro_tag_name(node) == Some("arg") {
-                self.handle_arg(node)?;
-            }
-        }
-        Ok(())
+pub fn render_xacro_error(err: &anyhow::Error) -> String {
+    match err
+        .chain()
+        .find_map(|err| err.downcast_ref::<XacroDiagnostic>())
+    {
+        Some(diagnostic) => diagnostic.render(),
+        None => format!("error: {:#}\n", err),
     }
+}
 
-    fn handle_property(&mut self, node: &Element) -> Result<()> {
-        let name = self.eval_text(required_attr(node, "name")?)?;
-        let value = node.attributes.get("value");
-        let default = node.attributes.get("default");
-        let scope = self.property_scope(node)?;
-        let has_element_children = node
-            .children
-            .iter()
-            .any(|child| child.as_element().is_some());
+#[derive(Clone, Debug)]
+struct Macro {
+    params: Vec<MacroParam>,
+    body: NodeRef,
+}
 
-        if value.is_some() && default.is_some() {
-            bail!("xacro:property attributes `value` and `default` are mutually exclusive");
-        }
-        if (value.is_some() || default.is_some()) && has_element_children {
-            bail!("xacro:property cannot mix `value`/`default` attributes with block content");
-        }
+#[derive(Clone, Debug)]
+struct MacroParam {
+    name: String,
+    kind: MacroParamKind,
+    default: Option<MacroDefault>,
+}
 
-        if let Some(value) = value {
-            let value = self.eval_value(value)?;
-            self.set_property(name, value, scope);
-        } else if let Some(default) = default {
-            if !self.context.properties.contains_key(&name) {
-                let value = self.eval_value(default)?;
-                self.set_property(name, value, scope);
-            }
+impl MacroParam {
+    fn symbol_key(&self) -> String {
+        match self.kind {
+            MacroParamKind::Value => self.name.clone(),
+            MacroParamKind::SingleBlock => format!("*{}", self.name),
+            MacroParamKind::ContentBlock => format!("**{}", self.name),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MacroParamKind {
+    Value,
+    SingleBlock,
+    ContentBlock,
+}
+
+#[derive(Clone, Debug)]
+struct MacroDefault {
+    forward: bool,
+    value: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+struct BlockValue {
+    node: NodeRef,
+    // Whether the block is the element's content rather than the element itself.
+    content: bool,
+}
+
+impl BlockValue {
+    fn nodes(&self) -> &[XMLNode] {
+        if self.content {
+            &self.node.element().children
         } else {
-            let name = format!("**{}", name);
-            let block = BlockValue {
-                nodes: element_child_nodes(&node.children),
-            };
-            self.set_block(name, block, scope);
+            self.node.as_nodes()
         }
-        Ok(())
     }
+}
 
-    fn handle_arg(&mut self, node: &Element) -> Result<()> {
-        let name = required_attr(node, "name")?.to_string();
-        let value = node
-            .attributes
-            .get("default")
-            .or_else(|| node.attributes.get("value"))
-            .with_context(|| format!("xacro:arg `{}` requires `default` attribute", name))?;
+/// Where each parsed element sits and starts, keyed by element address. Shared nodes never
+/// move, so an address identifies an element for as long as the nodes it was recorded for
+/// are alive.
+type NodeIndex = Arc<HashMap<usize, IndexedNode>>;
 
-        if !self.context.args.contains_key(&name) {
-            let value = self.eval_text(value)?;
-            self.context.args.insert(name, value);
+#[derive(Clone, Debug)]
+struct IndexedNode {
+    location: Option<SourceLocation>,
+    parent: Option<usize>,
+    position: usize,
+}
+
+// Parsed nodes that macro bodies and blocks point into instead of copying them.
+#[derive(Clone, Debug)]
+struct SharedNodes {
+    nodes: Arc<[XMLNode]>,
+    index: NodeIndex,
+}
+
+impl SharedNodes {
+    /// `locations` are those of the elements in `nodes`, in document order.
+    fn new(nodes: Vec<XMLNode>, locations: impl IntoIterator<Item = SourceLocation>) -> Self {
+        let nodes = Arc::<[XMLNode]>::from(nodes);
+        let mut index = HashMap::new();
+        index_nodes(&nodes, None, &mut locations.into_iter(), &mut index);
+        Self {
+            nodes,
+            index: Arc::new(index),
         }
-        Ok(())
     }
 
-    fn handle_macro_definition(&mut self, node: &Element) -> Result<()> {
-        let name = required_attr(node, "name")?.to_string();
-        let params = node
-            .attributes
-            .get("params")
-            .map(String::as_str)
-            .unwrap_or("");
-        self.context.macros.insert(
-            name,
-            Macro {
-                params: parse_macro_args(params)?,
-                body: node.clone(),
+    /// The child positions leading to `elem`, if it is one of these nodes.
+    fn path(&self, elem: &Element) -> Option<Vec<usize>> {
+        let mut path = Vec::new();
+        let mut address = Some(element_address(elem));
+        while let Some(current) = address {
+            let node = self.index.get(&current)?;
+            path.push(node.position);
+            address = node.parent;
+        }
+        path.reverse();
+        Some(path)
+    }
+}
+
+fn index_nodes(
+    nodes: &[XMLNode],
+    parent: Option<usize>,
+    locations: &mut impl Iterator<Item = SourceLocation>,
+    index: &mut HashMap<usize, IndexedNode>,
+) {
+    for (position, node) in nodes.iter().enumerate() {
+        let XMLNode::Element(elem) = node else {
+            continue;
+        };
+        let address = element_address(elem);
+        index.insert(
+            address,
+            IndexedNode {
+                location: locations.next(),
+                parent,
+                position,
             },
         );
-        Ok(())
+        index_nodes(&elem.children, Some(address), locations, index);
     }
+}
 
-    fn handle_include(&mut self, node: &Element) -> Result<Vec<XMLNode>> {
-        let filename = self.eval_text(required_attr(node, "filename")?)?;
-        if filename.is_empty() {
-            bail!("xacro:include requires a non-empty `filename` attribute");
-        }
+// An element inside shared nodes, found again through its child positions.
+#[derive(Clone, Debug)]
+struct NodeRef {
+    tree: SharedNodes,
+    path: Arc<[usize]>,
+}
 
-        let include_path = self.resolve_include_path(&filename);
-        let included = self
-            .process_file_to_element_inner(&include_path)
-            .with_context(|| format!("while including xacro file `{}`", filename))?;
-        Ok(included.children)
+impl NodeRef {
+    // Copies an element that is not part of any shared nodes.
+    fn copy(elem: &Element) -> Self {
+        Self {
+            tree: SharedNodes::new(vec![XMLNode::Element(elem.clone())], []),
+            path: Arc::from([0]),
+        }
     }
 
-    fn handle_insert_block(&mut self, node: &Element) -> Result<Vec<XMLNode>> {
-        let name = self.eval_text(required_attr(node, "name")?)?;
-        let block = self
-            .context
-            .blocks
-            .get(&format!("**{}", name))
-            .or_else(|| self.context.blocks.get(&format!("*{}", name)))
-            .cloned()
-            .with_context(|| format!("undefined xacro block `{}`", name))?;
-        self.expand_block_nodes(&block.nodes)
+    /// The element alone, as a slice of its siblings.
+    fn as_nodes(&self) -> &[XMLNode] {
+        let (&position, parents) = self.path.split_last().expect("paths are never empty");
+        let mut nodes = &*self.tree.nodes;
+        for &parent in parents {
+            nodes = &nodes[parent]
+                .as_element()
+                .expect("paths only pass through elements")
+                .children;
+        }
+        &nodes[position..=position]
     }
 
-    fn evaluate_condition(&self, node: &Element, tag_name: &str) -> Result<bool> {
-        self.eval_bool(required_attr(node, "value")?)
-            .with_context(|| format!("failed to evaluate xacro:{} condition", tag_name))
+    fn element(&self) -> &Element {
+        self.as_nodes()[0]
+            .as_element()
+            .expect("paths lead to elements")
     }
+}
 
-    fn handle_macro_call(&mut self, node: &Element, name: &str) -> Result<Vec<XMLNode>> {
-        let Some(this_macro) = self.context.macros.get(name).cloned() else {
-            if UNSUPPORTED_XACRO_TAGS.contains(&name) {
-                bail!("unsupported xacro tag `xacro:{}`", name);
-            }
-            bail!("undefined xacro macro `xacro:{}`", name);
-        };
-
-        let value_param_names = this_macro
-            .params
-            .iter()
-            .filter(|param| param.kind == MacroParamKind::Value)
-            .map(|param| param.name.as_str())
-            .collect::<HashSet<_>>();
-        for attr_name in node.attributes.keys() {
-            if !value_param_names.contains(attr_name.as_str()) {
-                bail!(
-                    "invalid parameter `{}` for macro `xacro:{}`",
-                    attr_name,
-                    name
-                );
-            }
-        }
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PropertyScope {
+    Local,
+    Parent,
+    Global,
+}
 
-        let mut local_processor = XacroProcessor {
-            context: self.context.clone(),
-            options: self.options.clone(),
-            include_stack: self.include_stack.clone(),
-            parent_property_exports: HashMap::new(),
-            global_property_exports: HashMap::new(),
-            parent_block_exports: HashMap::new(),
-            global_block_exports: HashMap::new(),
-        };
+// A macro call sees everything defined by its caller and shadows it with its own
+// definitions. Each call pushes a scope that shares the caller's bindings instead of copying
+// them; the caller's own bindings are only copied if it writes while a callee still holds them.
+#[derive(Debug)]
+struct Scope<T> {
+    values: Arc<HashMap<String, T>>,
+    parent: Option<Arc<Scope<T>>>,
+}
 
-        for param in this_macro
-            .params
-            .iter()
-            .filter(|param| param.kind == MacroParamKind::Value)
-        {
-            if let Some(attr_value) = node.attributes.get(&param.name) {
-                local_processor
-                    .context
-                    .properties
-                    .insert(param.name.clone(), self.eval_value(attr_value)?);
-            } else if let Some(default) = &param.default {
-                local_processor
-                    .context
-                    .properties
-                    .insert(param.name.clone(), self.eval_macro_default(param, default)?);
-            } else if self.options.require_macro_params {
-                bail!(
-                    "missing required parameter `{}` for macro `xacro:{}`",
-                    param.name,
-                    name
-                );
-            }
+impl<T> Clone for Scope<T> {
+    fn clone(&self) -> Self {
+        Self {
+            values: self.values.clone(),
+            parent: self.parent.clone(),
         }
+    }
+}
 
-        let mut child_blocks = node.children.iter().filter_map(XMLNode::as_element);
-        for param in this_macro
-            .params
-            .iter()
-            .filter(|param| param.kind != MacroParamKind::Value)
-        {
-            let Some(block) = child_blocks.next() else {
-                bail!(
-                    "not enough blocks when instantiating macro `xacro:{}`; missing `{}`",
-                    name,
-                    param.symbol_key()
-                );
-            };
-
-            let nodes = match param.kind {
-                MacroParamKind::Value => unreachable!(),
-                MacroParamKind::SingleBlock => vec![XMLNode::Element(block.clone())],
-                MacroParamKind::ContentBlock => element_child_nodes(&block.children),
-            };
-            local_processor
-                .context
-                .blocks
-                .insert(param.symbol_key(), BlockValue { nodes });
+impl<T> Default for Scope<T> {
+    fn default() -> Self {
+        Self {
+            values: Arc::default(),
+            parent: None,
         }
+    }
+}
 
-        if let Some(block) = child_blocks.next() {
-            bail!(
-                "unused block `{}` when instantiating macro `xacro:{}`",
-                block.name,
-                name
-            );
+impl<T> From<HashMap<String, T>> for Scope<T> {
+    fn from(values: HashMap<String, T>) -> Self {
+        Self {
+            values: Arc::new(values),
+            parent: None,
         }
-
-        let new_elem = local_processor.process_element(&this_macro.body)?;
-        self.apply_scope_exports(&local_processor);
-        Ok(new_elem.children)
     }
+}
 
-    fn property_scope(&self, node: &Element) -> Result<PropertyScope> {
-        let Some(scope) = node.attributes.get("scope") else {
-            return Ok(PropertyScope::Local);
+impl<T: Clone> Scope<T> {
+    fn child(&self) -> Self {
+        let parent = if self.values.is_empty() {
+            self.parent.clone()
+        } else {
+            Some(Arc::new(self.clone()))
         };
-        match self.eval_text(scope)?.as_str() {
-            "local" => Ok(PropertyScope::Local),
-            "parent" => Ok(PropertyScope::Parent),
-            "global" => Ok(PropertyScope::Global),
-            scope => bail!("unsupported xacro:property scope `{}`", scope),
+        Self {
+            values: Arc::default(),
+            parent,
         }
     }
 
-    fn set_property(&mut self, name: String, value: XacroValue, scope: PropertyScope) {
-        self.context.properties.insert(name.clone(), value.clone());
-        match scope {
-            PropertyScope::Local => {}
-            PropertyScope::Parent => {
-                self.parent_property_exports.insert(name, value);
-            }
-            PropertyScope::Global => {
-                self.global_property_exports.insert(name, value);
+    fn get(&self, name: &str) -> Option<&T> {
+        let mut scope = self;
+        loop {
+            if let Some(value) = scope.values.get(name) {
+                return Some(value);
             }
+            scope = scope.parent.as_deref()?;
         }
     }
 
-    fn set_block(&mut self, name: String, block: BlockValue, scope: PropertyScope) {
-        self.context.blocks.insert(name.clone(), block.clone());
-        match scope {
-            PropertyScope::Local => {}
-            PropertyScope::Parent => {
-                self.parent_block_exports.insert(name, block);
-            }
-            PropertyScope::Global => {
-                self.global_block_exports.insert(name, block);
-            }
-        }
+    fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
     }
 
-    fn apply_scope_exports(&mut self, child: &XacroProcessor) {
-        for (name, value) in child.parent_property_exports.iter() {
-            self.context.properties.insert(name.clone(), value.clone());
-        }
-        for (name, block) in child.parent_block_exports.iter() {
-            self.context.blocks.insert(name.clone(), block.clone());
-        }
-        for (name, value) in child.global_property_exports.iter() {
-            self.context.properties.insert(name.clone(), value.clone());
-            self.global_property_exports
-                .insert(name.clone(), value.clone());
-        }
-        for (name, block) in child.global_block_exports.iter() {
-            self.context.blocks.insert(name.clone(), block.clone());
-            self.global_block_exports
-                .insert(name.clone(), block.clone());
-        }
+    fn insert(&mut self, name: String, value: T) {
+        Arc::make_mut(&mut self.values).insert(name, value);
     }
+}
 
-    fn eval_macro_default(&self, param: &MacroParam, default: &MacroDefault) -> Result<XacroValue> {
-        if default.forward {
-            if let Some(value) = self.context.properties.get(&param.name) {
-                return Ok(value.clone());
-            }
-        }
+impl XacroSymbols for Scope<XacroValue> {
+    fn get(&self, name: &str) -> Option<&XacroValue> {
+        Scope::get(self, name)
+    }
 
-        if let Some(value) = &default.value {
-            let value = self.eval_value(value)?;
-            return Ok(match value {
-                XacroValue::String(value) => {
-                    XacroValue::String(strip_balanced_quotes(&value).to_string())
-                }
-                value => value,
-            });
+    fn names(&self) -> Vec<&str> {
+        let mut names = HashSet::new();
+        let mut scope = Some(self);
+        while let Some(current) = scope {
+            names.extend(current.values.keys().map(String::as_str));
+            scope = current.parent.as_deref();
         }
-
-        bail!("undefined property to forward: {}", param.name)
+        names.into_iter().collect()
     }
+}
 
-    fn expand_block_nodes(&mut self, nodes: &[XMLNode]) -> Result<Vec<XMLNode>> {
-        let mut expanded = Vec::new();
-        for node in nodes {
-            match node {
-                XMLNode::Element(element) => {
-                    expanded.push(XMLNode::Element(self.process_element(element)?));
-                }
-                other => expanded.push(other.clone()),
-            }
+#[derive(Clone, Debug, Default)]
+struct XacroContext {
+    properties: Scope<XacroValue>,
+    blocks: Scope<BlockValue>,
+    macros: Scope<Arc<Macro>>,
+    args: Scope<XacroValue>,
+}
+
+impl XacroContext {
+    fn child(&self) -> Self {
+        Self {
+            properties: self.properties.child(),
+            blocks: self.blocks.child(),
+            macros: self.macros.child(),
+            args: self.args.child(),
         }
-        Ok(expanded)
     }
+}
 
-    fn eval_text(&self, text: &str) -> Result<String> {
-        try_eval_text_with_values(
-            text,
-            &self.context.properties,
-            &|expr| self.resolve_substitution(expr),
-            &|expr| self.resolve_value_expression(expr),
-        )
+pub trait XacroSubstitutionResolver: std::fmt::Debug + Send + Sync {
+    fn resolve_arg(&self, _name: &str) -> Result<Option<String>> {
+        Ok(None)
     }
 
-    fn eval_value(&self, text: &str) -> Result<XacroValue> {
-        try_eval_value_with_values(
-            text,
-            &self.context.properties,
-            &|expr| self.resolve_substitution(expr),
-            &|expr| self.resolve_value_expression(expr),
-        )
+    fn resolve_find(&self, _package: &str) -> Result<Option<PathBuf>> {
+        Ok(None)
     }
+}
 
-    fn eval_bool(&self, text: &str) -> Result<bool> {
-        try_get_boolean_value_with_values(
-            text,
-            &self.context.properties,
-            &|expr| self.resolve_substitution(expr),
-            &|expr| self.resolve_value_expression(expr),
-        )
-    }
+#[derive(Debug, Default)]
+struct EmptyXacroSubstitutionResolver;
 
-    fn resolve_value_expression(&self, expr: &str) -> Result<Option<XacroValue>> {
-        let Some(arg) = xacro_function_arg(expr, "xacro.load_yaml")
-            .or_else(|| xacro_function_arg(expr, "load_yaml"))
-        else {
-            return Ok(None);
-        };
+impl XacroSubstitutionResolver for EmptyXacroSubstitutionResolver {}
 
-        let filename = self.eval_value(&format!("${{{}}}", arg))?.raw_value();
-        if filename.is_empty() {
-            bail!("xacro.load_yaml requires a non-empty filename");
-        }
+/// Where includes, YAML files and package manifests are read from.
+pub trait XacroFileProvider: std::fmt::Debug + Send + Sync {
+    fn read(&self, path: &Path) -> Result<String>;
 
-        let path = self.resolve_include_path(&filename);
-        let yaml = std::fs::read_to_string(&path)
-            .with_context(|| format!("failed to read YAML file `{}`", path.display()))?;
-        parse_simple_yaml(&yaml)
-            .with_context(|| format!("failed to parse YAML file `{}`", path.display()))
-            .map(Some)
-    }
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf>;
 
-    fn resolve_include_path(&self, filename: &str) -> PathBuf {
-        let path = Path::new(filename);
-        if path.is_absolute() {
-            path.to_path_buf()
-        } else {
-            self.current_dir()
-                .unwrap_or_else(|| Path::new("."))
-                .join(path)
-        }
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Defaults to [`exists`](Self::exists), which also accepts plain files; providers that
+    /// can tell the two apart should override it.
+    fn is_dir(&self, path: &Path) -> bool {
+        self.exists(path)
     }
 
-    fn resolve_substitution(&self, expr: &str) -> Result<String> {
-        let mut parts = expr.split_whitespace();
-        let Some(command) = parts.next() else {
-            bail!("empty xacro substitution command");
-        };
+    /// The directory `$(cwd)` and relative package paths resolve against.
+    fn current_dir(&self) -> Result<PathBuf> {
+        Ok(std::env::current_dir()?)
+    }
 
-        match command {
-            "cwd" => Ok(self
-                .current_dir()
-                .map(Path::to_path_buf)
-                .unwrap_or(std::env::current_dir()?)
-                .to_string_lossy()
-                .into_owned()),
-            "env" => {
-                let name = required_substitution_arg(command, parts.next())?;
-                ensure_no_extra_args(command, &mut parts)?;
-                std::env::var(name)
-                    .with_context(|| format!("environment variable `{}` is not set", name))
-            }
-            "optenv" => {
-                let name = required_substitution_arg(command, parts.next())?;
-                match std::env::var(name) {
-                    Ok(value) => Ok(value),
-                    Err(_) => Ok(parts.collect::<Vec<_>>().join(" ")),
-                }
-            }
-            "arg" | "var" => {
-                let name = required_substitution_arg(command, parts.next())?;
-                ensure_no_extra_args(command, &mut parts)?;
-                self.resolve_arg(name)
-            }
-            "find" | "find-pkg-share" => {
-                let package = required_substitution_arg(command, parts.next())?;
-                ensure_no_extra_args(command, &mut parts)?;
-                self.resolve_find(package)
-            }
-            _ => bail!("unsupported xacro substitution command `$({})`", expr),
-        }
+    /// Used to invalidate [`XacroCache`] entries; files without one are never cached.
+    fn modified(&self, _path: &Path) -> Option<SystemTime> {
+        None
     }
 
-    fn resolve_arg(&self, name: &str) -> Result<String> {
-        if let Some(value) = self.context.args.get(name) {
-            return Ok(value.clone());
-        }
+    /// Also checked by [`XacroCache`], so an edit within the resolution of
+    /// [`modified`](Self::modified) is still noticed when it changes the size.
+    fn size(&self, _path: &Path) -> Option<u64> {
+        None
+    }
+}
 
-        if let Some(value) = self.options.substitution_resolver.resolve_arg(name)? {
-            return Ok(value);
-        }
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsFileProvider;
 
-        bail!("undefined xacro argument `{}`", name)
+impl XacroFileProvider for OsFileProvider {
+    fn read(&self, path: &Path) -> Result<String> {
+        Ok(std::fs::read_to_string(path)?)
     }
 
-    fn resolve_find(&self, package: &str) -> Result<String> {
-        if let Some(path) = self.options.package_paths.get(package) {
-            return Ok(normalize_package_path(path).to_string_lossy().into_owned());
-        }
-
-        if let Some(path) = self.options.substitution_resolver.resolve_find(package)? {
-            return Ok(normalize_package_path(&path).to_string_lossy().into_owned());
-        }
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        Ok(std::fs::canonicalize(path)?)
+    }
 
-        if let Some(path) = self.discover_package_path(package)? {
-            return Ok(path.to_string_lossy().into_owned());
-        }
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
 
-        bail!(
-            "unable to resolve package `{}` for `$(find {})`",
-            package,
-            package
-        )
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
     }
 
-    fn discover_package_path(&self, package: &str) -> Result<Option<PathBuf>> {
-        if let Some(path) = self.discover_current_package_path(package)? {
-            return Ok(Some(path));
-        }
-        if let Some(path) = discover_package_from_env(package)? {
-            return Ok(Some(path));
-        }
-        Ok(None)
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
     }
 
-    fn discover_current_package_path(&self, package: &str) -> Result<Option<PathBuf>> {
-        let mut dir = self.current_dir();
-        while let Some(path) = dir {
-            let package_xml = path.join("package.xml");
-            if package_xml.is_file() && package_xml_name(&package_xml)?.as_deref() == Some(package)
-            {
-                return Ok(Some(normalize_package_path(path)));
-            }
-            dir = path.parent();
-        }
-        Ok(None)
+    fn size(&self, path: &Path) -> Option<u64> {
+        std::fs::metadata(path).map(|metadata| metadata.len()).ok()
     }
 }
 
-fn normalize_package_path(path: &Path) -> PathBuf {
-    let path = if path.is_absolute() {
-        path.to_path_buf()
-    } else {
-        std::env::current_dir()
-            .unwrap_or_else(|_| PathBuf::from("."))
-            .join(path)
-    };
-    std::fs::canonicalize(&path).unwrap_or(path)
+/// Files held in memory. Relative paths are resolved against `/`, and a directory exists
+/// when it contains at least one file.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryFileProvider {
+    files: HashMap<PathBuf, String>,
 }
 
-fn discover_package_from_env(package: &str) -> Result<Option<PathBuf>> {
-    for var in ["AMENT_PREFIX_PATH", "COLCON_PREFIX_PATH"] {
-        if let Some(path) = discover_package_share_from_env_var(var, package) {
-            return Ok(Some(path));
-        }
+impl MemoryFileProvider {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    let Some(paths) = std::env::var_os("ROS_PACKAGE_PATH") else {
-        return Ok(None);
-    };
-    for path in std::env::split_paths(&paths) {
-        if path.file_name().and_then(|name| name.to_str()) == Some(package) {
-            let package_xml = path.join("package.xml");
-            if package_xml.is_file() && package_xml_name(&package_xml)?.as_deref() == Some(package)
-            {
-                return Ok(Some(normalize_package_path(&path)));
-            }
-        }
+    pub fn with_file(mut self, path: impl AsRef<Path>, contents: impl Into<String>) -> Self {
+        self.insert(path, contents);
+        self
+    }
 
-        let candidate = path.join(package);
-        let package_xml = candidate.join("package.xml");
-        if package_xml.is_file() && package_xml_name(&package_xml)?.as_deref() == Some(package) {
-            return Ok(Some(normalize_package_path(&candidate)));
-        }
+    pub fn insert(&mut self, path: impl AsRef<Path>, contents: impl Into<String>) {
+        self.files
+            .insert(normalize_memory_path(path.as_ref()), contents.into());
     }
-    Ok(None)
 }
 
-fn discover_package_share_from_env_var(var: &str, package: &str) -> Option<PathBuf> {
-    let paths = std::env::var_os(var)?;
-    for prefix in std::env::split_paths(&paths) {
-        let candidate = prefix.join("share").join(package);
-        if candidate.is_dir() {
-            return Some(normalize_package_path(&candidate));
-        }
+impl XacroFileProvider for MemoryFileProvider {
+    fn read(&self, path: &Path) -> Result<String> {
+        self.files
+            .get(&normalize_memory_path(path))
+            .cloned()
+            .with_context(|| format!("no such in-memory file `{}`", path.display()))
     }
-    None
-}
 
-fn package_xml_name(path: &Path) -> Result<Option<String>> {
-    let xml = std::fs::read_to_string(path)
-        .with_context(|| format!("failed to read package manifest `{}`", path.display()))?;
-    let elem = Element::parse(xml.as_bytes())
-        .with_context(|| format!("failed to parse package manifest `{}`", path.display()))?;
-    Ok(elem.children.iter().find_map(|node| {
-        let child = node.as_element()?;
-        if child.name == "name" {
-            child.get_text().map(|value| value.trim().to_string())
-        } else {
-            None
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        let normalized = normalize_memory_path(path);
+        if !self.exists(&normalized) {
+            bail!("no such in-memory file or directory `{}`", path.display());
         }
-    }))
+        Ok(normalized)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(&normalize_memory_path(path)) || self.is_dir(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        let path = normalize_memory_path(path);
+        // `Path::starts_with` compares whole components, so `/pkg/a` is not inside `/pkg/ab`.
+        self.files
+            .keys()
+            .any(|file| file != &path && file.starts_with(&path))
+    }
+
+    fn current_dir(&self) -> Result<PathBuf> {
+        Ok(PathBuf::from("/"))
+    }
 }
 
-fn xacro_function_arg<'a>(expr: &'a str, name: &str) -> Option<&'a str> {
-    let expr = expr.trim();
-    let rest = expr.strip_prefix(name)?.trim_start();
-    if !rest.starts_with('(') || !rest.ends_with(')') {
-        return None;
+fn normalize_memory_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+        }
     }
-    Some(rest[1..rest.len() - 1].trim())
+    normalized
 }
 
+pub const DEFAULT_MAX_MACRO_DEPTH: usize = 100;
+
 #[derive(Clone, Debug)]
-struct YamlLine {
-    line_number: usize,
-    indent: usize,
-    key: String,
-    value: Option<String>,
+#[non_exhaustive]
+pub struct XacroOptions {
+    pub require_macro_params: bool,
+    pub args: HashMap<String, XacroValue>,
+    pub package_paths: HashMap<String, PathBuf>,
+    pub number_format: NumberFormat,
+    pub strict: bool,
+    pub pretty_print: bool,
+    pub generated_banner: bool,
+    pub functions: XacroFunctions,
+    pub constants: HashMap<String, XacroValue>,
+    pub cache: Option<XacroCache>,
+    /// Macro calls nest at most this deep, so runaway recursion fails instead of overflowing
+    /// the stack.
+    pub max_macro_depth: Option<usize>,
+    /// Without a sandbox, files and environment variables are read freely.
+    pub sandbox: Option<XacroSandbox>,
+    substitution_resolver: Arc<dyn XacroSubstitutionResolver>,
+    file_provider: Arc<dyn XacroFileProvider>,
 }
 
-fn parse_simple_yaml(input: &str) -> Result<XacroValue> {
-    let mut lines = Vec::new();
-    for (idx, raw_line) in input.lines().enumerate() {
-        let line = strip_yaml_comment(raw_line).trim_end().to_string();
-        if line.trim().is_empty() || line.trim_start().starts_with("---") {
-            continue;
+impl Default for XacroOptions {
+    fn default() -> Self {
+        Self {
+            require_macro_params: true,
+            args: HashMap::new(),
+            package_paths: HashMap::new(),
+            number_format: NumberFormat::default(),
+            strict: false,
+            pretty_print: false,
+            generated_banner: false,
+            functions: XacroFunctions::default(),
+            constants: HashMap::new(),
+            cache: None,
+            max_macro_depth: Some(DEFAULT_MAX_MACRO_DEPTH),
+            sandbox: None,
+            substitution_resolver: Arc::new(EmptyXacroSubstitutionResolver),
+            file_provider: Arc::new(OsFileProvider),
         }
+    }
+}
 
-        let indent = line.chars().take_while(|ch| *ch == ' ').count();
-        let trimmed = line.trim_start();
-        let (key, value) = split_yaml_key_value(trimmed)
-            .with_context(|| format!("invalid YAML mapping on line {}", idx + 1))?;
-        lines.push(YamlLine {
-            line_number: idx + 1,
-            indent,
-            key: strip_balanced_quotes(key.trim()).to_string(),
-            value: value.map(|value| value.trim().to_string()),
-        });
+impl XacroOptions {
+    pub fn with_arg(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.args
+            .insert(name.into(), XacroValue::String(value.into()));
+        self
     }
 
-    if lines.is_empty() {
-        return Ok(XacroValue::Null);
+    pub fn with_typed_arg(mut self, name: impl Into<String>, value: XacroValue) -> Self {
+        self.args.insert(name.into(), value);
+        self
     }
 
-    let mut index = 0;
-    let root_indent = lines[0].indent;
-    let value = parse_yaml_map(&lines, &mut index, root_indent)?;
-    if index != lines.len() {
-        bail!(
-            "unexpected YAML content on line {}",
-            lines[index].line_number
-        );
+    pub fn with_package_path(
+        mut self,
+        package: impl Into<String>,
+        path: impl Into<PathBuf>,
+    ) -> Self {
+        self.package_paths.insert(package.into(), path.into());
+        self
     }
-    Ok(value)
-}
 
-fn parse_yaml_map(lines: &[YamlLine], index: &mut usize, indent: usize) -> Result<XacroValue> {
-    let mut map = BTreeMap::new();
+    pub fn with_number_format(mut self, number_format: NumberFormat) -> Self {
+        self.number_format = number_format;
+        self
+    }
 
-    while *index < lines.len() {
-        let line = &lines[*index];
-        if line.indent < indent {
-            break;
-        }
-        if line.indent > indent {
-            bail!("unexpected YAML indentation on line {}", line.line_number);
-        }
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
 
-        *index += 1;
-        let value = if let Some(value) = &line.value {
-            parse_yaml_scalar(value)
-                .with_context(|| format!("invalid YAML scalar on line {}", line.line_number))?
-        } else if *index < lines.len() && lines[*index].indent > indent {
-            parse_yaml_map(lines, index, lines[*index].indent)?
-        } else {
-            XacroValue::Map(BTreeMap::new())
-        };
-        map.insert(line.key.clone(), value);
+    pub fn with_pretty_print(mut self, pretty_print: bool) -> Self {
+        self.pretty_print = pretty_print;
+        self
     }
 
-    Ok(XacroValue::Map(map))
-}
+    pub fn with_generated_banner(mut self, generated_banner: bool) -> Self {
+        self.generated_banner = generated_banner;
+        self
+    }
 
-fn parse_yaml_scalar(value: &str) -> Result<XacroValue> {
-    let value = value.trim();
-    if value.is_empty() {
-        return Ok(XacroValue::String(String::new()));
+    pub fn with_function<F>(mut self, name: impl Into<String>, function: F) -> Self
+    where
+        F: Fn(&[XacroValue]) -> Result<XacroValue> + Send + Sync + 'static,
+    {
+        self.functions.insert(name, function);
+        self
     }
 
-    if let Some(rest) = value.strip_prefix("!degrees") {
-        let degrees = parse_yaml_number(rest.trim())?;
-        return Ok(XacroValue::Number(degrees.to_radians()));
+    pub fn with_constant(mut self, name: impl Into<String>, value: XacroValue) -> Self {
+        self.constants.insert(name.into(), value);
+        self
     }
-    if let Some(rest) = value.strip_prefix("!radians") {
-        return Ok(XacroValue::Number(parse_yaml_number(rest.trim())?));
+
+    pub fn with_cache(mut self, cache: XacroCache) -> Self {
+        self.cache = Some(cache);
+        self
     }
-    if value.starts_with('[') && value.ends_with(']') {
-        return split_inline_yaml_list(&value[1..value.len() - 1])
-            .into_iter()
-            .map(|item| parse_yaml_scalar(&item))
-            .collect::<Result<Vec<_>>>()
-            .map(XacroValue::List);
+
+    pub fn with_sandbox(mut self, sandbox: XacroSandbox) -> Self {
+        self.sandbox = Some(sandbox);
+        self
     }
 
-    let unquoted = strip_balanced_quotes(value);
-    if unquoted != value {
-        return Ok(XacroValue::String(unquoted.to_string()));
+    pub fn with_max_macro_depth(mut self, depth: usize) -> Self {
+        self.max_macro_depth = Some(depth);
+        self
     }
 
-    match value.to_ascii_lowercase().as_str() {
-        "true" => Ok(XacroValue::Bool(true)),
-        "false" => Ok(XacroValue::Bool(false)),
-        "null" | "~" => Ok(XacroValue::Null),
-        _ => match value.parse::<f64>() {
-            Ok(number) => Ok(XacroValue::Number(number)),
-            Err(_) => Ok(XacroValue::String(value.to_string())),
-        },
+    pub fn with_substitution_resolver<R>(mut self, resolver: R) -> Self
+    where
+        R: XacroSubstitutionResolver + 'static,
+    {
+        self.substitution_resolver = Arc::new(resolver);
+        self
+    }
+
+    pub fn with_file_provider<P>(mut self, provider: P) -> Self
+    where
+        P: XacroFileProvider + 'static,
+    {
+        self.file_provider = Arc::new(provider);
+        self
     }
 }
 
-fn parse_yaml_number(value: &str) -> Result<f64> {
-    let XacroValue::Number(value) = parse_yaml_scalar(value)? else {
-        bail!("expected numeric YAML tag argument");
-    };
-    Ok(value)
+#[derive(Clone, Debug)]
+struct ParsedDocument {
+    source: Arc<str>,
+    tree: SharedNodes,
 }
 
-fn strip_yaml_comment(line: &str) -> String {
-    let mut quote = None;
-    let mut escape = false;
-    for (idx, ch) in line.char_indices() {
-        if escape {
-            escape = false;
-            continue;
-        }
-        if ch == '\\' {
-            escape = true;
-            continue;
-        }
-        if let Some(q) = quote {
-            if ch == q {
-                quote = None;
-            }
-            continue;
-        }
-        match ch {
-            '\'' | '"' => quote = Some(ch),
-            '#' => return line[..idx].to_string(),
-            _ => {}
-        }
-    }
-    line.to_string()
+// What a cached file is compared against to tell whether it changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FileStamp {
+    modified: SystemTime,
+    size: Option<u64>,
 }
 
-fn split_yaml_key_value(line: &str) -> Option<(&str, Option<&str>)> {
-    let mut quote = None;
-    let mut escape = false;
-    for (idx, ch) in line.char_indices() {
-        if escape {
-            escape = false;
-            continue;
-        }
-        if ch == '\\' {
-            escape = true;
-            continue;
-        }
-        if let Some(q) = quote {
-            if ch == q {
-                quote = None;
-            }
-            continue;
-        }
-        match ch {
-            '\'' | '"' => quote = Some(ch),
-            ':' => {
-                let key = line[..idx].trim();
-                if key.is_empty() {
-                    return None;
-                }
-                let value = line[idx + ch.len_utf8()..].trim();
-                return Some((key, (!value.is_empty()).then_some(value)));
-            }
-            _ => {}
-        }
-    }
-    None
+#[derive(Debug, Default)]
+struct XacroCacheEntries {
+    documents: HashMap<PathBuf, (FileStamp, ParsedDocument)>,
+    yaml_values: HashMap<PathBuf, (FileStamp, XacroValue)>,
 }
 
-fn split_inline_yaml_list(value: &str) -> Vec<String> {
-    let mut values = Vec::new();
-    let mut start = 0usize;
-    let mut quote = None;
-    let mut escape = false;
-    let mut bracket_depth = 0usize;
+/// Parsed include files and YAML values keyed by canonical path, modification time and size.
+/// Clones share the same entries, so one cache can serve many processors and threads.
+#[derive(Clone, Debug, Default)]
+pub struct XacroCache {
+    entries: Arc<Mutex<XacroCacheEntries>>,
+}
 
-    for (idx, ch) in value.char_indices() {
-        if escape {
-            escape = false;
-            continue;
-        }
-        if ch == '\\' {
-            escape = true;
-            continue;
-        }
-        if let Some(q) = quote {
-            if ch == q {
-                quote = None;
-            }
-            continue;
-        }
-        match ch {
-            '\'' | '"' => quote = Some(ch),
-            '[' => bracket_depth += 1,
-            ']' => bracket_depth = bracket_depth.saturating_sub(1),
-            ',' if bracket_depth == 0 => {
-                let item = value[start..idx].trim();
-                if !item.is_empty() {
-                    values.push(item.to_string());
-                }
-                start = idx + ch.len_utf8();
-            }
-            _ => {}
+impl XacroCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        let entries = self.lock();
+        entries.documents.len() + entries.yaml_values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.lock();
+        entries.documents.clear();
+        entries.yaml_values.clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, XacroCacheEntries> {
+        self.entries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn document(&self, path: &Path, stamp: FileStamp) -> Option<ParsedDocument> {
+        match self.lock().documents.get(path) {
+            Some((cached, document)) if *cached == stamp => Some(document.clone()),
+            _ => None,
         }
     }
 
-    let item = value[start..].trim();
-    if !item.is_empty() {
-        values.push(item.to_string());
+    fn insert_document(&self, path: &Path, stamp: FileStamp, document: ParsedDocument) {
+        self.lock()
+            .documents
+            .insert(path.to_path_buf(), (stamp, document));
     }
-    values
-}
 
-fn context_from_options(options: &XacroOptions) -> XacroContext {
-    XacroContext {
-        args: options.args.clone(),
-        ..XacroContext::default()
+    fn yaml_value(&self, path: &Path, stamp: FileStamp) -> Option<XacroValue> {
+        match self.lock().yaml_values.get(path) {
+            Some((cached, value)) if *cached == stamp => Some(value.clone()),
+            _ => None,
+        }
     }
-}
 
-fn xacro_tag_name(node: &Element) -> Option<&str> {
-    match node.prefix.as_deref() {
-        Some(XACRO_PREFIX) => Some(node.name.as_str()),
-        _ => None,
+    fn insert_yaml_value(&self, path: &Path, stamp: FileStamp, value: XacroValue) {
+        self.lock()
+            .yaml_values
+            .insert(path.to_path_buf(), (stamp, value));
     }
 }
 
-fn element_attributes_use_args(elem: &Element) -> bool {
-    elem.attributes
-        .values()
-        .any(|value| value.contains("$(arg") || value.contains("$(var"))
+// A stack whose frames are shared with the processors of nested macro calls, so entering a
+// macro pushes one frame instead of copying the whole stack.
+#[derive(Debug)]
+struct SharedStack<T> {
+    top: Option<Arc<StackFrame<T>>>,
+    len: usize,
 }
 
-fn required_attr<'a>(node: &'a Element, attr: &str) -> Result<&'a str> {
-    node.attributes
-        .get(attr)
-        .map(String::as_str)
-        .with_context(|| format!("xacro:{} requires `{}` attribute", node.name, attr))
+#[derive(Debug)]
+struct StackFrame<T> {
+    value: T,
+    parent: Option<Arc<StackFrame<T>>>,
 }
 
-fn required_substitution_arg<'a>(command: &str, value: Option<&'a str>) -> Result<&'a str> {
-    value.with_context(|| format!("$({}) requires an argument", command))
+impl<T> Default for SharedStack<T> {
+    fn default() -> Self {
+        Self { top: None, len: 0 }
+    }
 }
 
-fn ensure_no_extra_args<'a>(
-    command: &str,
-    parts: &mut impl Iterator<Item = &'a str>,
-) -> Result<()> {
-    if let Some(extra) = parts.next() {
-        bail!("$({}) received unexpected argument `{}`", command, extra);
+impl<T> Clone for SharedStack<T> {
+    fn clone(&self) -> Self {
+        Self {
+            top: self.top.clone(),
+            len: self.len,
+        }
     }
-    Ok(())
 }
 
-fn parse_macro_args(s: &str) -> Result<Vec<MacroParam>> {
-    let tokens = tokenize_macro_params(s)?;
-    let mut params = Vec::new();
-    let mut symbols = HashSet::new();
+impl<T> SharedStack<T> {
+    fn len(&self) -> usize {
+        self.len
+    }
 
-    for token in tokens {
-        let param = parse_macro_param(&token)?;
-        let key = param.symbol_key();
-        if !symbols.insert(key.clone()) {
-            bail!("duplicate macro parameter `{}`", key);
-        }
-        params.push(param);
+    fn is_empty(&self) -> bool {
+        self.len == 0
     }
 
-    Ok(params)
-}
+    fn last(&self) -> Option<&T> {
+        self.top.as_ref().map(|frame| &frame.value)
+    }
 
-fn parse_macro_param(token: &str) -> Result<MacroParam> {
-    let (raw_name, default) = match token.split_once(":=") {
-        Some((name, default)) => (name.trim(), Some(parse_macro_default(default.trim()))),
-        None => match token.split_once('=') {
-            Some((name, default)) => (name.trim(), Some(parse_macro_default(default.trim()))),
-            None => (token.trim(), None),
-        },
-    };
+    fn push(&mut self, value: T) {
+        let parent = self.top.take();
+        self.top = Some(Arc::new(StackFrame { value, parent }));
+        self.len += 1;
+    }
 
-    let (kind, name) = if let Some(name) = raw_name.strip_prefix("**") {
-        (MacroParamKind::ContentBlock, name)
-    } else if let Some(name) = raw_name.strip_prefix('*') {
-        (MacroParamKind::SingleBlock, name)
-    } else {
-        (MacroParamKind::Value, raw_name)
-    };
+    fn pop(&mut self) {
+        if let Some(frame) = self.top.take() {
+            self.top = frame.parent.clone();
+            self.len -= 1;
+        }
+    }
 
-    if name.is_empty() {
-        bail!("macro parameter name cannot be empty");
+    fn clear(&mut self) {
+        *self = Self::default();
     }
-    if kind != MacroParamKind::Value && default.is_some() {
-        bail!(
-            "block parameter `{}` cannot define a default value",
-            raw_name
-        );
+
+    /// Iterates from the most recently pushed value down.
+    fn iter(&self) -> impl Iterator<Item = &T> {
+        std::iter::successors(self.top.as_deref(), |frame| frame.parent.as_deref())
+            .map(|frame| &frame.value)
     }
 
-    Ok(MacroParam {
-        name: name.to_string(),
-        kind,
-        default,
-    })
+    /// The values with the first pushed one first.
+    fn to_vec(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        let mut values = self.iter().cloned().collect::<Vec<_>>();
+        values.reverse();
+        values
+    }
 }
 
-fn parse_macro_default(default: &str) -> MacroDefault {
-    if let Some(value) = default.strip_prefix("^|") {
-        MacroDefault {
-            forward: true,
-            value: Some(value.to_string()),
+#[derive(Debug)]
+pub struct XacroProcessor {
+    context: XacroContext,
+    options: Arc<XacroOptions>,
+    include_stack: SharedStack<PathBuf>,
+    parent_property_exports: HashMap<String, XacroValue>,
+    global_property_exports: HashMap<String, XacroValue>,
+    parent_block_exports: HashMap<String, BlockValue>,
+    global_block_exports: HashMap<String, BlockValue>,
+    macro_stack: SharedStack<MacroCall>,
+    // The parsed nodes of the documents, macro bodies and blocks being expanded.
+    trees: SharedStack<SharedNodes>,
+    sources: Arc<Mutex<HashMap<String, Arc<str>>>>,
+    dependencies: Arc<Mutex<XacroDependencies>>,
+    arg_report: Arc<Mutex<XacroArgReport>>,
+    file_provider: Arc<dyn XacroFileProvider>,
+    include_depth: usize,
+    budget: Arc<Mutex<XacroBudget>>,
+}
+
+impl Default for XacroProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl XacroProcessor {
+    pub fn new() -> Self {
+        Self::with_options(XacroOptions::default())
+    }
+
+    pub fn with_options(options: XacroOptions) -> Self {
+        let file_provider: Arc<dyn XacroFileProvider> = match &options.sandbox {
+            Some(sandbox) => Arc::new(SandboxedFileProvider::new(
+                options.file_provider.clone(),
+                &sandbox.allowed_roots,
+            )),
+            None => options.file_provider.clone(),
+        };
+        Self {
+            context: context_from_options(&options),
+            options: Arc::new(options),
+            include_stack: SharedStack::default(),
+            parent_property_exports: HashMap::new(),
+            global_property_exports: HashMap::new(),
+            parent_block_exports: HashMap::new(),
+            global_block_exports: HashMap::new(),
+            macro_stack: SharedStack::default(),
+            trees: SharedStack::default(),
+            sources: Arc::default(),
+            dependencies: Arc::default(),
+            arg_report: Arc::default(),
+            file_provider,
+            include_depth: 0,
+            budget: Arc::default(),
         }
-    } else if let Some(value) = default.strip_prefix('^') {
-        MacroDefault {
-            forward: true,
-            value: if value.is_empty() {
-                None
-            } else {
-                Some(value.to_string())
-            },
+    }
+
+    pub fn current_file(&self) -> Option<&Path> {
+        self.include_stack.last().map(PathBuf::as_path)
+    }
+
+    pub fn current_dir(&self) -> Option<&Path> {
+        self.current_file().and_then(Path::parent)
+    }
+
+    pub fn dependencies(&self) -> XacroDependencies {
+        self.dependencies
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    pub fn arg_report(&self) -> XacroArgReport {
+        self.arg_report
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    pub fn process_string(&mut self, xml: &str) -> Result<String> {
+        self.reset_context();
+        self.process_source_string(xml)
+    }
+
+    pub fn process_file<P: AsRef<Path>>(&mut self, path: P) -> Result<String> {
+        self.reset_context();
+        self.process_file_inner(path.as_ref())
+    }
+
+    pub fn process_string_to_robot(&mut self, xml: &str) -> Result<Robot> {
+        self.reset_context();
+        let new_elem = self.process_source_element(xml)?;
+        parse_urdf_from_element(&new_elem)
+    }
+
+    pub fn process_file_to_robot<P: AsRef<Path>>(&mut self, path: P) -> Result<Robot> {
+        self.reset_context();
+        let new_elem = self.process_file_to_element_inner(path.as_ref())?;
+        parse_urdf_from_element(&new_elem)
+    }
+
+    pub fn process_document(&mut self, elem: &Element) -> Result<Element> {
+        self.reset_context();
+        self.process_element(elem)
+    }
+
+    fn reset_context(&mut self) {
+        self.context = context_from_options(&self.options);
+        self.include_stack.clear();
+        self.parent_property_exports.clear();
+        self.global_property_exports.clear();
+        self.parent_block_exports.clear();
+        self.global_block_exports.clear();
+        self.macro_stack.clear();
+        self.trees.clear();
+        self.sources = Arc::default();
+        self.dependencies = Arc::default();
+        self.include_depth = 0;
+        self.budget = Arc::default();
+        let mut provided = self.options.args.keys().cloned().collect::<Vec<_>>();
+        provided.sort();
+        self.arg_report = Arc::new(Mutex::new(XacroArgReport {
+            provided,
+            ..XacroArgReport::default()
+        }));
+    }
+
+    fn process_file_inner(&mut self, path: &Path) -> Result<String> {
+        let nodes = self.process_file_to_nodes_inner(path)?;
+        self.write_output(&nodes, &path.display().to_string())
+    }
+
+    fn process_file_to_element_inner(&mut self, path: &Path) -> Result<Element> {
+        let nodes = self.process_file_to_nodes_inner(path)?;
+        root_element(nodes)
+    }
+
+    fn process_file_to_nodes_inner(&mut self, path: &Path) -> Result<Vec<XMLNode>> {
+        let canonical_path = self
+            .files()
+            .canonicalize(path)
+            .with_context(|| format!("failed to resolve xacro file `{}`", path.display()))?;
+
+        if self.include_stack.iter().any(|p| p == &canonical_path) {
+            bail!(
+                "cyclic xacro include detected: {}",
+                format_include_stack(&self.include_stack, &canonical_path)
+            );
         }
-    } else {
-        MacroDefault {
-            forward: false,
-            value: Some(default.to_string()),
+
+        if !self.include_stack.is_empty() {
+            self.dependencies
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .add_include(canonical_path.clone());
         }
+
+        self.include_stack.push(canonical_path.clone());
+        let result = self
+            .load_document(&canonical_path)
+            .and_then(|document| self.process_document_nodes(&document))
+            .with_context(|| format!("while processing xacro file `{}`", canonical_path.display()));
+        self.include_stack.pop();
+        result
     }
-}
 
-fn tokenize_macro_params(s: &str) -> Result<Vec<String>> {
-    let mut tokens = Vec::new();
-    let mut chars = s.char_indices().peekable();
+    fn process_source_string(&mut self, xml: &str) -> Result<String> {
+        let nodes = self.process_source_nodes(xml)?;
+        self.write_output(&nodes, "<string>")
+    }
+
+    fn process_source_element(&mut self, xml: &str) -> Result<Element> {
+        let nodes = self.process_source_nodes(xml)?;
+        root_element(nodes)
+    }
+
+    fn process_source_nodes(&mut self, xml: &str) -> Result<Vec<XMLNode>> {
+        let file = self
+            .current_file()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        let document = parse_document(xml, &file)?;
+        self.process_document_nodes(&document)
+    }
+
+    fn load_document(&self, path: &Path) -> Result<ParsedDocument> {
+        let files = self.files();
+        let stamp = self.file_stamp(path);
+        if let Some(document) = self
+            .options
+            .cache
+            .as_ref()
+            .zip(stamp)
+            .and_then(|(cache, stamp)| cache.document(path, stamp))
+        {
+            return Ok(document);
+        }
+
+        let xml = files
+            .read(path)
+            .with_context(|| format!("failed to read xacro file `{}`", path.display()))?;
+        let document = parse_document(&xml, &path.display().to_string())?;
+        if let Some((cache, stamp)) = self.options.cache.as_ref().zip(stamp) {
+            cache.insert_document(path, stamp, document.clone());
+        }
+        Ok(document)
+    }
+
+    fn file_stamp(&self, path: &Path) -> Option<FileStamp> {
+        self.options.cache.as_ref()?;
+        Some(FileStamp {
+            modified: self.files().modified(path)?,
+            size: self.files().size(path),
+        })
+    }
+
+    fn process_document_nodes(&mut self, document: &ParsedDocument) -> Result<Vec<XMLNode>> {
+        let file = self
+            .current_file()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        self.sources
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(file, document.source.clone());
+
+        self.trees.push(document.tree.clone());
+        let new_nodes = document
+            .tree
+            .nodes
+            .iter()
+            .filter_map(|node| match node {
+                XMLNode::Element(elem) => Some(self.process_element(elem).map(XMLNode::Element)),
+                XMLNode::Comment(_) | XMLNode::ProcessingInstruction(..) => Some(Ok(node.clone())),
+                XMLNode::CData(_) | XMLNode::Text(_) => None,
+            })
+            .collect();
+        self.trees.pop();
+        new_nodes
+    }
+
+    fn write_output(&self, nodes: &[XMLNode], source: &str) -> Result<String> {
+        let separator = if self.options.pretty_print { "\n" } else { "" };
+        let mut output = String::from(XML_DECLARATION);
+        if self.options.generated_banner {
+            output.push_str(separator);
+            output.push_str(&self.generated_banner(source));
+        }
+        for node in nodes {
+            output.push_str(separator);
+            match node {
+                XMLNode::Element(elem) => {
+                    output.push_str(&write_element_to_string(elem, self.options.pretty_print)?)
+                }
+                XMLNode::Comment(comment) => output.push_str(&format!("<!--{}-->", comment)),
+                XMLNode::ProcessingInstruction(name, Some(data)) => {
+                    output.push_str(&format!("<?{} {}?>", name, data))
+                }
+                XMLNode::ProcessingInstruction(name, None) => {
+                    output.push_str(&format!("<?{}?>", name))
+                }
+                XMLNode::CData(_) | XMLNode::Text(_) => {}
+            }
+        }
+        if self.options.pretty_print {
+            output.push('\n');
+        }
+        if let Some(limit) = self
+            .sandbox()
+            .and_then(|sandbox| sandbox.max_output_bytes)
+            .filter(|limit| output.len() > *limit)
+        {
+            return Err(XacroSandboxError::OutputTooLarge(limit).into());
+        }
+        Ok(output)
+    }
+
+    fn generated_banner(&self, source: &str) -> String {
+        let mut lines = vec![format!("Generated by xurdf from {}", source)];
+        let dependencies = self.dependencies();
+        if dependencies.all().next().is_some() {
+            lines.push("Source files:".to_string());
+            lines.extend(
+                dependencies
+                    .all()
+                    .map(|path| format!("  {}", path.display())),
+            );
+        }
+        if !self.options.args.is_empty() {
+            let mut args = self.options.args.iter().collect::<Vec<_>>();
+            args.sort_by_key(|(name, _)| *name);
+            lines.push("Args:".to_string());
+            lines.extend(args.into_iter().map(|(name, value)| match value {
+                XacroValue::List(_) | XacroValue::Map(_) => {
+                    format!("  {}:={}", name, format_json_value(value))
+                }
+                value => format!(
+                    "  {}:={}",
+                    name,
+                    value.raw_value_with_format(self.options.number_format)
+                ),
+            }));
+        }
+        lines.push("Editing this file by hand is not recommended.".to_string());
+
+        let body = lines
+            .iter()
+            .map(|line| format!("  {}\n", line.replace("--", "- -")))
+            .collect::<String>();
+        format!("<!--\n{}-->", body)
+    }
+
+    fn process_element(&mut self, elem: &Element) -> Result<Element> {
+        self.step()?;
+        if xacro_tag_name(elem).is_none() {
+            let attributes = elem
+                .attributes
+                .iter()
+                .map(|(name, value)| name.len() + value.len() + 4)
+                .sum::<usize>();
+            self.charge_output(elem.name.len() * 2 + attributes + 5)?;
+        }
+        let mut new_elem = Element {
+            prefix: elem.prefix.clone(),
+            namespace: elem.namespace.clone(),
+            namespaces: elem.namespaces.clone(),
+            name: elem.name.clone(),
+            attributes: Default::default(),
+            children: Vec::with_capacity(elem.children.len()),
+        };
+
+        if elem.prefix.is_none() {
+            self.process_element_attributes(elem, &mut new_elem)
+                .map_err(|err| self.locate_error(err, elem))?;
+        } else {
+            new_elem.attributes = elem.attributes.clone();
+        }
+
+        for child in elem.children.iter() {
+            match child {
+                XMLNode::Element(node) => self
+                    .process_child_element(node, &mut new_elem)
+                    .map_err(|err| self.locate_error(err, node))?,
+                XMLNode::Text(text) if !self.keeps_text(text) => {}
+                XMLNode::Text(text) => {
+                    let new_text = self
+                        .eval_text(text)
+                        .map_err(|err| self.locate_error(err, elem))?;
+                    self.charge_output(new_text.len())?;
+                    new_elem.children.push(XMLNode::Text(new_text));
+                }
+                other => new_elem.children.push(other.clone()),
+            }
+        }
+
+        Ok(new_elem)
+    }
+
+    fn process_element_attributes(&mut self, elem: &Element, new_elem: &mut Element) -> Result<()> {
+        if element_attributes_use_args(elem) {
+            self.handle_direct_args(elem)?;
+        }
+        for (name, val) in elem.attributes.iter() {
+            let new_value = self.eval_attribute_text(elem, name, val)?;
+            new_elem.attributes.insert(name.clone(), new_value);
+        }
+        Ok(())
+    }
+
+    fn process_child_element(&mut self, node: &Element, new_elem: &mut Element) -> Result<()> {
+        match xacro_tag_name(node) {
+            Some("property") => self.handle_property(node)?,
+            Some("arg") => self.handle_arg(node)?,
+            Some("macro") => self.handle_macro_definition(node)?,
+            Some("include") => {
+                let new_nodes = self.handle_include(node)?;
+                new_elem.children.extend(new_nodes);
+            }
+            Some("insert_block") => {
+                let new_nodes = self.handle_insert_block(node)?;
+                new_elem.children.extend(new_nodes);
+            }
+            Some("if") => {
+                if self.evaluate_condition(node, "if")? {
+                    let new_node = self.process_element(node)?;
+                    new_elem.children.extend(new_node.children);
+                }
+            }
+            Some("unless") => {
+                if !self.evaluate_condition(node, "unless")? {
+                    let new_node = self.process_element(node)?;
+                    new_elem.children.extend(new_node.children);
+                }
+            }
+            Some(name) => {
+                let new_nodes = self.handle_macro_call(node, name)?;
+                new_elem.children.extend(new_nodes);
+            }
+            None => {
+                let new_node = self.process_element(node)?;
+                new_elem.children.push(XMLNode::Element(new_node));
+            }
+        }
+        Ok(())
+    }
+
+    // Pretty-printing indents the output itself, so the source's indentation is dropped.
+    fn keeps_text(&self, text: &str) -> bool {
+        !self.options.pretty_print || !text.trim().is_empty()
+    }
+
+    fn locate_error(&self, err: anyhow::Error, node: &Element) -> anyhow::Error {
+        if err.chain().any(|err| err.is::<XacroDiagnostic>()) {
+            return err;
+        }
+        let Some(location) = self.source_location(node) else {
+            return err;
+        };
+        let source_line = self.source_line(&location);
+        XacroDiagnostic {
+            error: err,
+            location,
+            source_line,
+            macro_stack: self.macro_stack.to_vec(),
+        }
+        .into()
+    }
+
+    fn source_location(&self, node: &Element) -> Option<SourceLocation> {
+        let address = element_address(node);
+        self.trees
+            .iter()
+            .find_map(|tree| tree.index.get(&address)?.location.clone())
+    }
+
+    // Refers to `elem` in the nodes being expanded, copying it only if it is not among them.
+    fn node_ref(&self, elem: &Element) -> NodeRef {
+        self.trees
+            .iter()
+            .find_map(|tree| {
+                Some(NodeRef {
+                    path: tree.path(elem)?.into(),
+                    tree: tree.clone(),
+                })
+            })
+            .unwrap_or_else(|| NodeRef::copy(elem))
+    }
+
+    fn source_line(&self, location: &SourceLocation) -> Option<String> {
+        let file = location
+            .file
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        let sources = self.sources.lock().unwrap_or_else(|err| err.into_inner());
+        sources
+            .get(&file)?
+            .lines()
+            .nth(location.line.checked_sub(1)?)
+            .map(str::to_string)
+    }
+
+    fn handle_direct_args(&mut self, elem: &Element) -> Result<()> {
+        for child in elem.children.iter() {
+            let Some(node) = child.as_element() else {
+                continue;
+            };
+            if xacro_tag_name(node) == Some("arg") {
+                self.handle_arg(node)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_property(&mut self, node: &Element) -> Result<()> {
+        let name = self.eval_attribute_text(node, "name", required_attr(node, "name")?)?;
+        let value = node.attributes.get("value");
+        let default = node.attributes.get("default");
+        let scope = self.property_scope(node)?;
+        let has_element_children = node
+            .children
+            .iter()
+            .any(|child| child.as_element().is_some());
+
+        if value.is_some() && default.is_some() {
+            bail!("xacro:property attributes `value` and `default` are mutually exclusive");
+        }
+        if (value.is_some() || default.is_some()) && has_element_children {
+            bail!("xacro:property cannot mix `value`/`default` attributes with block content");
+        }
+
+        if let Some(value) = value {
+            let value = self.eval_attribute_value(node, "value", value)?;
+            self.set_property(name, value, scope);
+        } else if let Some(default) = default {
+            if !self.context.properties.contains_key(&name) {
+                let value = self.eval_attribute_value(node, "default", default)?;
+                self.set_property(name, value, scope);
+            }
+        } else {
+            let name = format!("**{}", name);
+            let block = BlockValue {
+                node: self.node_ref(node),
+                content: true,
+            };
+            self.set_block(name, block, scope);
+        }
+        Ok(())
+    }
+
+    fn handle_arg(&mut self, node: &Element) -> Result<()> {
+        let name = required_attr(node, "name")?.to_string();
+        let (attr, value) = node
+            .attributes
+            .get_key_value("default")
+            .or_else(|| node.attributes.get_key_value("value"))
+            .with_context(|| format!("xacro:arg `{}` requires `default` attribute", name))?;
+        self.arg_report
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .declare(&name, value);
+
+        if !self.context.args.contains_key(&name) {
+            let value = self.eval_attribute_text(node, attr, value)?;
+            self.context.args.insert(name, XacroValue::String(value));
+        }
+        Ok(())
+    }
+
+    fn handle_macro_definition(&mut self, node: &Element) -> Result<()> {
+        let name = required_attr(node, "name")?.to_string();
+        let params = node
+            .attributes
+            .get("params")
+            .map(String::as_str)
+            .unwrap_or("");
+        self.context.macros.insert(
+            name,
+            Arc::new(Macro {
+                params: parse_macro_args(params)?,
+                body: self.node_ref(node),
+            }),
+        );
+        Ok(())
+    }
+
+    fn handle_include(&mut self, node: &Element) -> Result<Vec<XMLNode>> {
+        let filename =
+            self.eval_attribute_text(node, "filename", required_attr(node, "filename")?)?;
+        if filename.is_empty() {
+            bail!("xacro:include requires a non-empty `filename` attribute");
+        }
+
+        if let Some(limit) = self
+            .sandbox()
+            .and_then(|sandbox| sandbox.max_include_depth)
+            .filter(|limit| self.include_depth >= *limit)
+        {
+            return Err(XacroSandboxError::IncludeDepthExceeded(limit).into());
+        }
+
+        let include_path = self.resolve_include_path(&filename);
+        self.include_depth += 1;
+        let included = self
+            .process_file_to_element_inner(&include_path)
+            .with_context(|| format!("while including xacro file `{}`", filename));
+        self.include_depth -= 1;
+        Ok(included?.children)
+    }
+
+    fn handle_insert_block(&mut self, node: &Element) -> Result<Vec<XMLNode>> {
+        let name = self.eval_attribute_text(node, "name", required_attr(node, "name")?)?;
+        let block = self
+            .context
+            .blocks
+            .get(&format!("**{}", name))
+            .or_else(|| self.context.blocks.get(&format!("*{}", name)))
+            .cloned()
+            .with_context(|| format!("undefined xacro block `{}`", name))?;
+        self.trees.push(block.node.tree.clone());
+        let expanded = self.expand_block_nodes(block.nodes());
+        self.trees.pop();
+        expanded
+    }
+
+    fn evaluate_condition(&self, node: &Element, tag_name: &str) -> Result<bool> {
+        self.eval_bool(required_attr(node, "value")?)
+            .with_context(|| self.attribute_context(node, "value"))
+            .with_context(|| format!("failed to evaluate xacro:{} condition", tag_name))
+    }
+
+    fn handle_macro_call(&mut self, node: &Element, name: &str) -> Result<Vec<XMLNode>> {
+        let Some(this_macro) = self.context.macros.get(name).cloned() else {
+            if UNSUPPORTED_XACRO_TAGS.contains(&name) {
+                bail!("unsupported xacro tag `xacro:{}`", name);
+            }
+            bail!("undefined xacro macro `xacro:{}`", name);
+        };
+        if let Some(limit) = self
+            .options
+            .max_macro_depth
+            .filter(|limit| self.macro_stack.len() >= *limit)
+        {
+            let chain = self
+                .macro_stack
+                .to_vec()
+                .into_iter()
+                .map(|call| call.name)
+                .chain([name.to_string()])
+                .collect();
+            return Err(XacroSandboxError::MacroDepthExceeded(limit, chain).into());
+        }
+
+        let value_param_names = this_macro
+            .params
+            .iter()
+            .filter(|param| param.kind == MacroParamKind::Value)
+            .map(|param| param.name.as_str())
+            .collect::<HashSet<_>>();
+        for attr_name in node.attributes.keys() {
+            if !value_param_names.contains(attr_name.as_str()) {
+                bail!(
+                    "invalid parameter `{}` for macro `xacro:{}`",
+                    attr_name,
+                    name
+                );
+            }
+        }
+
+        let mut local_processor = XacroProcessor {
+            context: self.context.child(),
+            options: self.options.clone(),
+            include_stack: self.include_stack.clone(),
+            parent_property_exports: HashMap::new(),
+            global_property_exports: HashMap::new(),
+            parent_block_exports: HashMap::new(),
+            global_block_exports: HashMap::new(),
+            macro_stack: self.macro_stack.clone(),
+            trees: self.trees.clone(),
+            sources: self.sources.clone(),
+            dependencies: self.dependencies.clone(),
+            arg_report: self.arg_report.clone(),
+            file_provider: self.file_provider.clone(),
+            include_depth: self.include_depth,
+            budget: self.budget.clone(),
+        };
+        local_processor.macro_stack.push(MacroCall {
+            name: name.to_string(),
+            location: self.source_location(node),
+        });
+        local_processor.trees.push(this_macro.body.tree.clone());
+
+        for param in this_macro
+            .params
+            .iter()
+            .filter(|param| param.kind == MacroParamKind::Value)
+        {
+            if let Some(attr_value) = node.attributes.get(&param.name) {
+                local_processor.context.properties.insert(
+                    param.name.clone(),
+                    self.eval_attribute_value(node, &param.name, attr_value)?,
+                );
+            } else if let Some(default) = &param.default {
+                local_processor
+                    .context
+                    .properties
+                    .insert(param.name.clone(), self.eval_macro_default(param, default)?);
+            } else if self.options.require_macro_params {
+                bail!(
+                    "missing required parameter `{}` for macro `xacro:{}`",
+                    param.name,
+                    name
+                );
+            }
+        }
+
+        let mut child_blocks = node.children.iter().filter_map(XMLNode::as_element);
+        for param in this_macro
+            .params
+            .iter()
+            .filter(|param| param.kind != MacroParamKind::Value)
+        {
+            let Some(block) = child_blocks.next() else {
+                bail!(
+                    "not enough blocks when instantiating macro `xacro:{}`; missing `{}`",
+                    name,
+                    param.symbol_key()
+                );
+            };
+
+            let value = match param.kind {
+                MacroParamKind::Value => unreachable!(),
+                MacroParamKind::SingleBlock => BlockValue {
+                    node: self.node_ref(block),
+                    content: false,
+                },
+                MacroParamKind::ContentBlock => BlockValue {
+                    node: self.node_ref(block),
+                    content: true,
+                },
+            };
+            local_processor
+                .context
+                .blocks
+                .insert(param.symbol_key(), value);
+        }
+
+        if let Some(block) = child_blocks.next() {
+            bail!(
+                "unused block `{}` when instantiating macro `xacro:{}`",
+                block.name,
+                name
+            );
+        }
+
+        let new_elem = local_processor.process_element(this_macro.body.element())?;
+        // Drop the callee's scope first so the exports below write to an unshared scope.
+        local_processor.context = XacroContext::default();
+        self.apply_scope_exports(&local_processor);
+        Ok(new_elem.children)
+    }
+
+    fn property_scope(&self, node: &Element) -> Result<PropertyScope> {
+        let Some(scope) = node.attributes.get("scope") else {
+            return Ok(PropertyScope::Local);
+        };
+        match self.eval_text(scope)?.as_str() {
+            "local" => Ok(PropertyScope::Local),
+            "parent" => Ok(PropertyScope::Parent),
+            "global" => Ok(PropertyScope::Global),
+            scope => bail!("unsupported xacro:property scope `{}`", scope),
+        }
+    }
+
+    fn set_property(&mut self, name: String, value: XacroValue, scope: PropertyScope) {
+        self.context.properties.insert(name.clone(), value.clone());
+        match scope {
+            PropertyScope::Local => {}
+            PropertyScope::Parent => {
+                self.parent_property_exports.insert(name, value);
+            }
+            PropertyScope::Global => {
+                self.global_property_exports.insert(name, value);
+            }
+        }
+    }
+
+    fn set_block(&mut self, name: String, block: BlockValue, scope: PropertyScope) {
+        self.context.blocks.insert(name.clone(), block.clone());
+        match scope {
+            PropertyScope::Local => {}
+            PropertyScope::Parent => {
+                self.parent_block_exports.insert(name, block);
+            }
+            PropertyScope::Global => {
+                self.global_block_exports.insert(name, block);
+            }
+        }
+    }
+
+    fn apply_scope_exports(&mut self, child: &XacroProcessor) {
+        for (name, value) in child.parent_property_exports.iter() {
+            self.context.properties.insert(name.clone(), value.clone());
+        }
+        for (name, block) in child.parent_block_exports.iter() {
+            self.context.blocks.insert(name.clone(), block.clone());
+        }
+        for (name, value) in child.global_property_exports.iter() {
+            self.context.properties.insert(name.clone(), value.clone());
+            self.global_property_exports
+                .insert(name.clone(), value.clone());
+        }
+        for (name, block) in child.global_block_exports.iter() {
+            self.context.blocks.insert(name.clone(), block.clone());
+            self.global_block_exports
+                .insert(name.clone(), block.clone());
+        }
+    }
+
+    fn eval_macro_default(&self, param: &MacroParam, default: &MacroDefault) -> Result<XacroValue> {
+        if default.forward {
+            if let Some(value) = self.context.properties.get(&param.name) {
+                return Ok(value.clone());
+            }
+        }
+
+        if let Some(value) = &default.value {
+            let value = self.eval_value(value)?;
+            return Ok(match value {
+                XacroValue::String(value) => {
+                    XacroValue::String(strip_balanced_quotes(&value).to_string())
+                }
+                value => value,
+            });
+        }
+
+        bail!("undefined property to forward: {}", param.name)
+    }
+
+    fn expand_block_nodes(&mut self, nodes: &[XMLNode]) -> Result<Vec<XMLNode>> {
+        let mut expanded = Vec::new();
+        for node in nodes {
+            match node {
+                XMLNode::Element(element) => {
+                    expanded.push(XMLNode::Element(self.process_element(element)?));
+                }
+                XMLNode::Text(text) if !self.keeps_text(text) => {}
+                XMLNode::Text(text) => expanded.push(XMLNode::Text(self.eval_text(text)?)),
+                other => expanded.push(other.clone()),
+            }
+        }
+        Ok(expanded)
+    }
+
+    fn eval_options(&self) -> EvalOptions {
+        let options = EvalOptions::default()
+            .with_number_format(self.options.number_format)
+            .with_strict(self.options.strict)
+            .with_functions(self.options.functions.clone());
+        match self.step_hook() {
+            Some(hook) => options.with_step_hook(hook),
+            None => options,
+        }
+    }
+
+    fn eval_attribute_text(&self, node: &Element, attr: &str, text: &str) -> Result<String> {
+        self.eval_text(text)
+            .with_context(|| self.attribute_context(node, attr))
+    }
+
+    fn eval_attribute_value(&self, node: &Element, attr: &str, text: &str) -> Result<XacroValue> {
+        self.eval_value(text)
+            .with_context(|| self.attribute_context(node, attr))
+    }
+
+    fn attribute_context(&self, node: &Element, attr: &str) -> String {
+        let tag = match &node.prefix {
+            Some(prefix) => format!("{}:{}", prefix, node.name),
+            None => node.name.clone(),
+        };
+        let mut files = self.include_stack.iter();
+        let Some(current) = files.next() else {
+            return format!("in attribute `{}` of `<{}>`", attr, tag);
+        };
+        let mut context = format!(
+            "in attribute `{}` of `<{}>` in `{}`",
+            attr,
+            tag,
+            current.display()
+        );
+        for parent in files {
+            context.push_str(&format!(", included from `{}`", parent.display()));
+        }
+        context
+    }
+
+    fn eval_text(&self, text: &str) -> Result<String> {
+        try_eval_text_with_options(
+            text,
+            &self.context.properties,
+            &self.eval_options(),
+            &|expr| self.resolve_substitution(expr),
+            &|expr| self.resolve_value_expression(expr),
+        )
+    }
+
+    fn eval_value(&self, text: &str) -> Result<XacroValue> {
+        try_eval_value_with_options(
+            text,
+            &self.context.properties,
+            &self.eval_options(),
+            &|expr| self.resolve_substitution(expr),
+            &|expr| self.resolve_value_expression(expr),
+        )
+    }
+
+    fn eval_bool(&self, text: &str) -> Result<bool> {
+        try_get_boolean_value_with_options(
+            text,
+            &self.context.properties,
+            &self.eval_options(),
+            &|expr| self.resolve_substitution(expr),
+            &|expr| self.resolve_value_expression(expr),
+        )
+    }
+
+    fn resolve_value_expression(&self, expr: &str) -> Result<Option<XacroValue>> {
+        if let Some(arg) = xacro_function_arg(expr, "xacro.arg") {
+            let name = self.eval_value(&format!("${{{}}}", arg))?.raw_value();
+            return self.resolve_typed_arg(&name).map(Some);
+        }
+
+        let Some(arg) = xacro_function_arg(expr, "xacro.load_yaml")
+            .or_else(|| xacro_function_arg(expr, "load_yaml"))
+        else {
+            return Ok(None);
+        };
+
+        let filename = self.eval_value(&format!("${{{}}}", arg))?.raw_value();
+        if filename.is_empty() {
+            bail!("xacro.load_yaml requires a non-empty filename");
+        }
+
+        let path = self.resolve_include_path(&filename);
+        let files = self.files();
+        let canonical_path = files.canonicalize(&path).unwrap_or(path.clone());
+        let stamp = self.file_stamp(&canonical_path);
+        if let Some(value) = self
+            .options
+            .cache
+            .as_ref()
+            .zip(stamp)
+            .and_then(|(cache, stamp)| cache.yaml_value(&canonical_path, stamp))
+        {
+            self.record_yaml_dependency(canonical_path);
+            return Ok(Some(value));
+        }
+
+        let yaml = files
+            .read(&path)
+            .with_context(|| format!("failed to read YAML file `{}`", path.display()))?;
+        let value = parse_simple_yaml(&yaml)
+            .with_context(|| format!("failed to parse YAML file `{}`", path.display()))?;
+        if let Some((cache, stamp)) = self.options.cache.as_ref().zip(stamp) {
+            cache.insert_yaml_value(&canonical_path, stamp, value.clone());
+        }
+        self.record_yaml_dependency(canonical_path);
+        Ok(Some(value))
+    }
+
+    fn record_yaml_dependency(&self, path: PathBuf) {
+        self.dependencies
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .add_yaml_file(path);
+    }
+
+    fn resolve_include_path(&self, filename: &str) -> PathBuf {
+        let path = Path::new(filename);
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.current_dir()
+                .unwrap_or_else(|| Path::new("."))
+                .join(path)
+        }
+    }
+
+    fn resolve_substitution(&self, expr: &str) -> Result<String> {
+        let mut parts = expr.split_whitespace();
+        let Some(command) = parts.next() else {
+            bail!("empty xacro substitution command");
+        };
+
+        match command {
+            "cwd" => {
+                let dir = match self.current_dir() {
+                    Some(dir) => dir.to_path_buf(),
+                    None => self.files().current_dir()?,
+                };
+                Ok(dir.to_string_lossy().into_owned())
+            }
+            "env" => {
+                let name = required_substitution_arg(command, parts.next())?;
+                ensure_no_extra_args(command, &mut parts)?;
+                self.env_var(name)?
+                    .with_context(|| format!("environment variable `{}` is not set", name))
+            }
+            "optenv" => {
+                let name = required_substitution_arg(command, parts.next())?;
+                match self.env_var(name)? {
+                    Some(value) => Ok(value),
+                    None => Ok(parts.collect::<Vec<_>>().join(" ")),
+                }
+            }
+            "arg" | "var" => {
+                let name = required_substitution_arg(command, parts.next())?;
+                ensure_no_extra_args(command, &mut parts)?;
+                self.resolve_arg(name)
+            }
+            "find" | "find-pkg-share" => {
+                let package = required_substitution_arg(command, parts.next())?;
+                ensure_no_extra_args(command, &mut parts)?;
+                self.resolve_find(package)
+            }
+            _ => bail!("unsupported xacro substitution command `$({})`", expr),
+        }
+    }
+
+    fn resolve_arg(&self, name: &str) -> Result<String> {
+        Ok(self
+            .resolve_typed_arg(name)?
+            .raw_value_with_format(self.options.number_format))
+    }
+
+    fn resolve_typed_arg(&self, name: &str) -> Result<XacroValue> {
+        self.arg_report
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .mark_used(name);
+        if let Some(value) = self.context.args.get(name) {
+            return Ok(value.clone());
+        }
+
+        if let Some(value) = self.options.substitution_resolver.resolve_arg(name)? {
+            return Ok(XacroValue::String(value));
+        }
+
+        bail!("undefined xacro argument `{}`", name)
+    }
+
+    fn resolve_find(&self, package: &str) -> Result<String> {
+        if let Some(path) = self.options.package_paths.get(package) {
+            return Ok(normalize_package_path(self.files(), path)
+                .to_string_lossy()
+                .into_owned());
+        }
+
+        if let Some(path) = self.options.substitution_resolver.resolve_find(package)? {
+            return Ok(normalize_package_path(self.files(), &path)
+                .to_string_lossy()
+                .into_owned());
+        }
+
+        if let Some(path) = self.discover_package_path(package)? {
+            return Ok(path.to_string_lossy().into_owned());
+        }
+
+        bail!(
+            "unable to resolve package `{}` for `$(find {})`",
+            package,
+            package
+        )
+    }
+
+    fn discover_package_path(&self, package: &str) -> Result<Option<PathBuf>> {
+        if let Some(path) = self.discover_current_package_path(package)? {
+            return Ok(Some(path));
+        }
+        let env = |name: &str| {
+            self.env_allows(name)
+                .then(|| std::env::var_os(name))
+                .flatten()
+        };
+        if let Some(path) = discover_package_from_env(self.files(), &env, package)? {
+            return Ok(Some(path));
+        }
+        Ok(None)
+    }
+
+    fn discover_current_package_path(&self, package: &str) -> Result<Option<PathBuf>> {
+        let mut dir = self.current_dir();
+        while let Some(path) = dir {
+            let package_xml = path.join("package.xml");
+            if is_package_manifest(self.files(), &package_xml, package)? {
+                return Ok(Some(normalize_package_path(self.files(), path)));
+            }
+            dir = path.parent();
+        }
+        Ok(None)
+    }
+
+    fn files(&self) -> &dyn XacroFileProvider {
+        self.file_provider.as_ref()
+    }
+
+    fn env_var(&self, name: &str) -> Result<Option<String>> {
+        if !self.env_allows(name) {
+            return Err(XacroSandboxError::EnvNotAllowed(name.to_string()).into());
+        }
+        Ok(std::env::var(name).ok())
+    }
+
+    fn sandbox(&self) -> Option<&XacroSandbox> {
+        self.options.sandbox.as_ref()
+    }
+
+    fn env_allows(&self, name: &str) -> bool {
+        self.sandbox()
+            .is_none_or(|sandbox| sandbox.env_access.allows(name))
+    }
+
+    fn step(&self) -> Result<()> {
+        match self.sandbox() {
+            Some(sandbox) => charge_step(sandbox, &self.budget),
+            None => Ok(()),
+        }
+    }
+
+    // Expressions charge a step per sub-expression, so a comprehension cannot run unbounded
+    // inside a single element.
+    fn step_hook(&self) -> Option<StepHook> {
+        self.sandbox()
+            .filter(|sandbox| sandbox.max_steps.is_some() || sandbox.time_budget.is_some())?;
+        let options = self.options.clone();
+        let budget = self.budget.clone();
+        Some(StepHook(Arc::new(move || match &options.sandbox {
+            Some(sandbox) => charge_step(sandbox, &budget),
+            None => Ok(()),
+        })))
+    }
+
+    fn charge_output(&self, bytes: usize) -> Result<()> {
+        let Some(limit) = self.sandbox().and_then(|sandbox| sandbox.max_output_bytes) else {
+            return Ok(());
+        };
+        let mut budget = self.budget.lock().unwrap_or_else(|err| err.into_inner());
+        budget.output_bytes += bytes;
+        if budget.output_bytes > limit {
+            return Err(XacroSandboxError::OutputTooLarge(limit).into());
+        }
+        Ok(())
+    }
+}
+
+fn charge_step(sandbox: &XacroSandbox, budget: &Mutex<XacroBudget>) -> Result<()> {
+    if sandbox.max_steps.is_none() && sandbox.time_budget.is_none() {
+        return Ok(());
+    }
+    let mut budget = budget.lock().unwrap_or_else(|err| err.into_inner());
+    budget.steps += 1;
+    if let Some(limit) = sandbox.max_steps.filter(|limit| budget.steps > *limit) {
+        return Err(XacroSandboxError::StepBudgetExceeded(limit).into());
+    }
+    if let Some(limit) = sandbox
+        .time_budget
+        .filter(|limit| budget.started.elapsed() > *limit)
+    {
+        return Err(XacroSandboxError::TimeBudgetExceeded(limit).into());
+    }
+    Ok(())
+}
+
+fn normalize_package_path(files: &dyn XacroFileProvider, path: &Path) -> PathBuf {
+    files.canonicalize(path).unwrap_or_else(|_| {
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            files
+                .current_dir()
+                .unwrap_or_else(|_| PathBuf::from("."))
+                .join(path)
+        }
+    })
+}
+
+fn discover_package_from_env(
+    files: &dyn XacroFileProvider,
+    env: &dyn Fn(&str) -> Option<OsString>,
+    package: &str,
+) -> Result<Option<PathBuf>> {
+    for var in ["AMENT_PREFIX_PATH", "COLCON_PREFIX_PATH"] {
+        if let Some(path) = discover_package_share_from_env_var(files, env(var), package) {
+            return Ok(Some(path));
+        }
+    }
+
+    let Some(paths) = env("ROS_PACKAGE_PATH") else {
+        return Ok(None);
+    };
+    for path in std::env::split_paths(&paths) {
+        if path.file_name().and_then(|name| name.to_str()) == Some(package)
+            && is_package_manifest(files, &path.join("package.xml"), package)?
+        {
+            return Ok(Some(normalize_package_path(files, &path)));
+        }
+
+        let candidate = path.join(package);
+        if is_package_manifest(files, &candidate.join("package.xml"), package)? {
+            return Ok(Some(normalize_package_path(files, &candidate)));
+        }
+    }
+    Ok(None)
+}
+
+fn discover_package_share_from_env_var(
+    files: &dyn XacroFileProvider,
+    paths: Option<OsString>,
+    package: &str,
+) -> Option<PathBuf> {
+    let paths = paths?;
+    for prefix in std::env::split_paths(&paths) {
+        let candidate = prefix.join("share").join(package);
+        if files.is_dir(&candidate) {
+            return Some(normalize_package_path(files, &candidate));
+        }
+    }
+    None
+}
+
+fn is_package_manifest(files: &dyn XacroFileProvider, path: &Path, package: &str) -> Result<bool> {
+    Ok(files.exists(path) && package_xml_name(files, path)?.as_deref() == Some(package))
+}
+
+fn package_xml_name(files: &dyn XacroFileProvider, path: &Path) -> Result<Option<String>> {
+    let xml = files
+        .read(path)
+        .with_context(|| format!("failed to read package manifest `{}`", path.display()))?;
+    let elem = Element::parse(xml.as_bytes())
+        .with_context(|| format!("failed to parse package manifest `{}`", path.display()))?;
+    Ok(elem.children.iter().find_map(|node| {
+        let child = node.as_element()?;
+        if child.name == "name" {
+            child.get_text().map(|value| value.trim().to_string())
+        } else {
+            None
+        }
+    }))
+}
+
+fn xacro_function_arg<'a>(expr: &'a str, name: &str) -> Option<&'a str> {
+    let (args, rest) = split_call_args(expr.trim().strip_prefix(name)?)?;
+    match args.as_slice() {
+        [arg] if rest.trim().is_empty() => Some(arg),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug)]
+struct YamlLine {
+    line_number: usize,
+    indent: usize,
+    key: String,
+    value: Option<String>,
+}
+
+fn parse_simple_yaml(input: &str) -> Result<XacroValue> {
+    let mut lines = Vec::new();
+    for (idx, raw_line) in input.lines().enumerate() {
+        let line = strip_yaml_comment(raw_line).trim_end().to_string();
+        if line.trim().is_empty() || line.trim_start().starts_with("---") {
+            continue;
+        }
+
+        let indent = line.chars().take_while(|ch| *ch == ' ').count();
+        let trimmed = line.trim_start();
+        let (key, value) = split_yaml_key_value(trimmed)
+            .with_context(|| format!("invalid YAML mapping on line {}", idx + 1))?;
+        lines.push(YamlLine {
+            line_number: idx + 1,
+            indent,
+            key: strip_balanced_quotes(key.trim()).to_string(),
+            value: value.map(|value| value.trim().to_string()),
+        });
+    }
+
+    if lines.is_empty() {
+        return Ok(XacroValue::Null);
+    }
+
+    let mut index = 0;
+    let root_indent = lines[0].indent;
+    let value = parse_yaml_map(&lines, &mut index, root_indent)?;
+    if index != lines.len() {
+        bail!(
+            "unexpected YAML content on line {}",
+            lines[index].line_number
+        );
+    }
+    Ok(value)
+}
+
+fn parse_yaml_map(lines: &[YamlLine], index: &mut usize, indent: usize) -> Result<XacroValue> {
+    let mut map = BTreeMap::new();
+
+    while *index < lines.len() {
+        let line = &lines[*index];
+        if line.indent < indent {
+            break;
+        }
+        if line.indent > indent {
+            bail!("unexpected YAML indentation on line {}", line.line_number);
+        }
+
+        *index += 1;
+        let value = if let Some(value) = &line.value {
+            parse_yaml_scalar(value)
+                .with_context(|| format!("invalid YAML scalar on line {}", line.line_number))?
+        } else if *index < lines.len() && lines[*index].indent > indent {
+            parse_yaml_map(lines, index, lines[*index].indent)?
+        } else {
+            XacroValue::Map(BTreeMap::new())
+        };
+        map.insert(line.key.clone(), value);
+    }
+
+    Ok(XacroValue::Map(map))
+}
+
+fn parse_yaml_scalar(value: &str) -> Result<XacroValue> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(XacroValue::String(String::new()));
+    }
+
+    if let Some(rest) = value.strip_prefix("!degrees") {
+        let degrees = parse_yaml_number(rest.trim())?;
+        return Ok(XacroValue::Number(degrees.to_radians()));
+    }
+    if let Some(rest) = value.strip_prefix("!radians") {
+        return Ok(XacroValue::Number(parse_yaml_number(rest.trim())?));
+    }
+    if value.starts_with('[') && value.ends_with(']') {
+        return split_inline_yaml_list(&value[1..value.len() - 1])
+            .into_iter()
+            .map(|item| parse_yaml_scalar(&item))
+            .collect::<Result<Vec<_>>>()
+            .map(XacroValue::List);
+    }
+    if value.starts_with('{') && value.ends_with('}') {
+        return split_inline_yaml_list(&value[1..value.len() - 1])
+            .iter()
+            .map(|entry| {
+                let (key, value) = split_yaml_flow_entry(entry)?;
+                Ok((key.to_string(), parse_yaml_scalar(value)?))
+            })
+            .collect::<Result<BTreeMap<_, _>>>()
+            .map(XacroValue::Map);
+    }
+
+    let unquoted = strip_balanced_quotes(value);
+    if unquoted != value {
+        return Ok(XacroValue::String(unquoted.to_string()));
+    }
+
+    match value.to_ascii_lowercase().as_str() {
+        "true" => Ok(XacroValue::Bool(true)),
+        "false" => Ok(XacroValue::Bool(false)),
+        "null" | "~" => Ok(XacroValue::Null),
+        _ => Ok(XacroValue::from_raw(value)),
+    }
+}
+
+fn parse_yaml_number(value: &str) -> Result<f64> {
+    match parse_yaml_scalar(value)? {
+        XacroValue::Integer(value) => Ok(value as f64),
+        XacroValue::Number(value) => Ok(value),
+        _ => bail!("expected numeric YAML tag argument"),
+    }
+}
+
+fn strip_yaml_comment(line: &str) -> String {
+    let mut quote = None;
+    let mut escape = false;
+    for (idx, ch) in line.char_indices() {
+        if escape {
+            escape = false;
+            continue;
+        }
+        if ch == '\\' {
+            escape = true;
+            continue;
+        }
+        if let Some(q) = quote {
+            if ch == q {
+                quote = None;
+            }
+            continue;
+        }
+        match ch {
+            '\'' | '"' => quote = Some(ch),
+            '#' => return line[..idx].to_string(),
+            _ => {}
+        }
+    }
+    line.to_string()
+}
+
+fn split_yaml_key_value(line: &str) -> Option<(&str, Option<&str>)> {
+    let mut quote = None;
+    let mut escape = false;
+    for (idx, ch) in line.char_indices() {
+        if escape {
+            escape = false;
+            continue;
+        }
+        if ch == '\\' {
+            escape = true;
+            continue;
+        }
+        if let Some(q) = quote {
+            if ch == q {
+                quote = None;
+            }
+            continue;
+        }
+        match ch {
+            '\'' | '"' => quote = Some(ch),
+            ':' => {
+                let key = line[..idx].trim();
+                if key.is_empty() {
+                    return None;
+                }
+                let value = line[idx + ch.len_utf8()..].trim();
+                return Some((key, (!value.is_empty()).then_some(value)));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Splits `key: value` inside a flow mapping; quoted keys may omit the space after `:`.
+fn split_yaml_flow_entry(entry: &str) -> Result<(&str, &str)> {
+    let (key, value) = match entry.chars().next() {
+        Some(quote @ ('\'' | '"')) => entry[1..].find(quote).and_then(|end| {
+            let (key, rest) = entry.split_at(end + 2);
+            Some((key, rest.trim_start().strip_prefix(':')?))
+        }),
+        _ => entry.split_once(':'),
+    }
+    .with_context(|| {
+        format!(
+            "expected `key: value` in YAML flow mapping, found `{}`",
+            entry
+        )
+    })?;
+    Ok((strip_balanced_quotes(key.trim()), value))
+}
+
+fn split_inline_yaml_list(value: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut start = 0usize;
+    let mut quote = None;
+    let mut escape = false;
+    let mut bracket_depth = 0usize;
+
+    for (idx, ch) in value.char_indices() {
+        if escape {
+            escape = false;
+            continue;
+        }
+        if ch == '\\' {
+            escape = true;
+            continue;
+        }
+        if let Some(q) = quote {
+            if ch == q {
+                quote = None;
+            }
+            continue;
+        }
+        match ch {
+            '\'' | '"' => quote = Some(ch),
+            '[' | '{' => bracket_depth += 1,
+            ']' | '}' => bracket_depth = bracket_depth.saturating_sub(1),
+            ',' if bracket_depth == 0 => {
+                let item = value[start..idx].trim();
+                if !item.is_empty() {
+                    values.push(item.to_string());
+                }
+                start = idx + ch.len_utf8();
+            }
+            _ => {}
+        }
+    }
+
+    let item = value[start..].trim();
+    if !item.is_empty() {
+        values.push(item.to_string());
+    }
+    values
+}
+
+pub fn parse_yaml_value(input: &str) -> Result<XacroValue> {
+    let trimmed = input.trim();
+    let is_mapping = trimmed.lines().count() > 1
+        || (!trimmed.starts_with(['[', '{', '\'', '"'])
+            && (trimmed.contains(": ") || trimmed.ends_with(':')));
+    if is_mapping {
+        parse_simple_yaml(input)
+    } else {
+        parse_yaml_scalar(trimmed)
+    }
+}
+
+fn format_json_value(value: &XacroValue) -> String {
+    match value {
+        XacroValue::Null => "null".to_string(),
+        XacroValue::Bool(value) => value.to_string(),
+        XacroValue::Integer(value) => value.to_string(),
+        XacroValue::Number(value) if value.is_finite() => {
+            NumberFormat::PythonRepr.format_number(*value)
+        }
+        XacroValue::Number(_) => "null".to_string(),
+        XacroValue::String(value) => json_string(value),
+        XacroValue::List(values) => format!(
+            "[{}]",
+            values
+                .iter()
+                .map(format_json_value)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        XacroValue::Map(values) => format!(
+            "{{{}}}",
+            values
+                .iter()
+                .map(|(key, value)| format!("{}: {}", json_string(key), format_json_value(value)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn context_from_options(options: &XacroOptions) -> XacroContext {
+    XacroContext {
+        properties: options.constants.clone().into(),
+        args: options.args.clone().into(),
+        ..XacroContext::default()
+    }
+}
+
+fn element_address(elem: &Element) -> usize {
+    elem as *const Element as usize
+}
+
+fn xacro_tag_name(node: &Element) -> Option<&str> {
+    match node.prefix.as_deref() {
+        Some(XACRO_PREFIX) => Some(node.name.as_str()),
+        _ => None,
+    }
+}
+
+fn element_attributes_use_args(elem: &Element) -> bool {
+    elem.attributes
+        .values()
+        .any(|value| value.contains("$(arg") || value.contains("$(var"))
+}
+
+fn required_attr<'a>(node: &'a Element, attr: &str) -> Result<&'a str> {
+    node.attributes
+        .get(attr)
+        .map(String::as_str)
+        .with_context(|| format!("xacro:{} requires `{}` attribute", node.name, attr))
+}
+
+fn required_substitution_arg<'a>(command: &str, value: Option<&'a str>) -> Result<&'a str> {
+    value.with_context(|| format!("$({}) requires an argument", command))
+}
+
+fn ensure_no_extra_args<'a>(
+    command: &str,
+    parts: &mut impl Iterator<Item = &'a str>,
+) -> Result<()> {
+    if let Some(extra) = parts.next() {
+        bail!("$({}) received unexpected argument `{}`", command, extra);
+    }
+    Ok(())
+}
+
+fn parse_macro_args(s: &str) -> Result<Vec<MacroParam>> {
+    let tokens = tokenize_macro_params(s)?;
+    let mut params = Vec::new();
+    let mut symbols = HashSet::new();
+
+    for token in tokens {
+        let param = parse_macro_param(&token)?;
+        let key = param.symbol_key();
+        if !symbols.insert(key.clone()) {
+            bail!("duplicate macro parameter `{}`", key);
+        }
+        params.push(param);
+    }
+
+    Ok(params)
+}
+
+fn parse_macro_param(token: &str) -> Result<MacroParam> {
+    let (raw_name, default) = match token.split_once(":=") {
+        Some((name, default)) => (name.trim(), Some(parse_macro_default(default.trim()))),
+        None => match token.split_once('=') {
+            Some((name, default)) => (name.trim(), Some(parse_macro_default(default.trim()))),
+            None => (token.trim(), None),
+        },
+    };
+
+    let (kind, name) = if let Some(name) = raw_name.strip_prefix("**") {
+        (MacroParamKind::ContentBlock, name)
+    } else if let Some(name) = raw_name.strip_prefix('*') {
+        (MacroParamKind::SingleBlock, name)
+    } else {
+        (MacroParamKind::Value, raw_name)
+    };
+
+    if name.is_empty() {
+        bail!("macro parameter name cannot be empty");
+    }
+    if kind != MacroParamKind::Value && default.is_some() {
+        bail!(
+            "block parameter `{}` cannot define a default value",
+            raw_name
+        );
+    }
+
+    Ok(MacroParam {
+        name: name.to_string(),
+        kind,
+        default,
+    })
+}
+
+fn parse_macro_default(default: &str) -> MacroDefault {
+    if let Some(value) = default.strip_prefix("^|") {
+        MacroDefault {
+            forward: true,
+            value: Some(value.to_string()),
+        }
+    } else if let Some(value) = default.strip_prefix('^') {
+        MacroDefault {
+            forward: true,
+            value: if value.is_empty() {
+                None
+            } else {
+                Some(value.to_string())
+            },
+        }
+    } else {
+        MacroDefault {
+            forward: false,
+            value: Some(default.to_string()),
+        }
+    }
+}
+
+fn tokenize_macro_params(s: &str) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+
+    while let Some((start, ch)) = chars.next() {
+        if ch.is_whitespace() {
+            continue;
+        }
+
+        let mut end = start + ch.len_utf8();
+        let mut quote = None;
+        let mut brace_depth = 0usize;
+        let mut paren_depth = 0usize;
+
+        while let Some(&(idx, ch)) = chars.peek() {
+            if let Some(q) = quote {
+                chars.next();
+                end = idx + ch.len_utf8();
+                if ch == q {
+                    quote = None;
+                }
+                continue;
+            }
+
+            match ch {
+                '\'' | '"' => {
+                    chars.next();
+                    quote = Some(ch);
+                    end = idx + ch.len_utf8();
+                }
+                '{' => {
+                    chars.next();
+                    brace_depth += 1;
+                    end = idx + ch.len_utf8();
+                }
+                '}' => {
+                    chars.next();
+                    brace_depth = brace_depth.saturating_sub(1);
+                    end = idx + ch.len_utf8();
+                }
+                '(' => {
+                    chars.next();
+                    paren_depth += 1;
+                    end = idx + ch.len_utf8();
+                }
+                ')' => {
+                    chars.next();
+                    paren_depth = paren_depth.saturating_sub(1);
+                    end = idx + ch.len_utf8();
+                }
+                _ if ch.is_whitespace() && brace_depth == 0 && paren_depth == 0 => break,
+                _ => {
+                    chars.next();
+                    end = idx + ch.len_utf8();
+                }
+            }
+        }
+
+        let token = s[start..end].trim();
+        if !token.is_empty() {
+            tokens.push(token.to_string());
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn strip_balanced_quotes(value: &str) -> &str {
+    if value.len() >= 2
+        && ((value.starts_with('\'') && value.ends_with('\''))
+            || (value.starts_with('"') && value.ends_with('"')))
+    {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    }
+}
+
+// Like `Element::parse_all`, but keeps CDATA sections instead of turning them into text and
+// also returns where each element starts in `file`, in document order.
+fn parse_xml_nodes(xml: &str, file: &str) -> Result<(Vec<XMLNode>, Vec<SourceLocation>)> {
+    let config = ParserConfig::new()
+        .ignore_comments(false)
+        .cdata_to_characters(false);
+    let mut reader = EventReader::new_with_config(xml.as_bytes(), config);
+    let mut open_elements: Vec<Element> = Vec::new();
+    let mut nodes = Vec::new();
+    let mut locations = Vec::new();
+    let file = (!file.is_empty()).then(|| PathBuf::from(file));
+    loop {
+        let node = match reader.next()? {
+            XmlEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            } => {
+                let attributes = attributes
+                    .into_iter()
+                    .map(|attr| (attr.name.local_name, attr.value))
+                    .collect::<HashMap<_, _>>();
+                let position = reader.position();
+                locations.push(SourceLocation {
+                    file: file.clone(),
+                    line: position.row as usize + 1,
+                    column: position.column as usize + 1,
+                });
+                open_elements.push(Element {
+                    prefix: name.prefix,
+                    namespace: name.namespace,
+                    namespaces: (!namespace.is_essentially_empty()).then_some(namespace),
+                    name: name.local_name,
+                    attributes,
+                    children: Vec::new(),
+                });
+                continue;
+            }
+            XmlEvent::EndElement { .. } => XMLNode::Element(
+                open_elements
+                    .pop()
+                    .context("unbalanced end element in XML")?,
+            ),
+            XmlEvent::Characters(text) | XmlEvent::Whitespace(text) => XMLNode::Text(text),
+            XmlEvent::CData(text) => XMLNode::CData(text),
+            XmlEvent::Comment(comment) => XMLNode::Comment(comment),
+            XmlEvent::ProcessingInstruction { name, data } => {
+                XMLNode::ProcessingInstruction(name, data)
+            }
+            XmlEvent::StartDocument { .. } => continue,
+            XmlEvent::EndDocument => return Ok((nodes, locations)),
+        };
+        match open_elements.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => nodes.push(node),
+        }
+    }
+}
+
+fn parse_document(xml: &str, file: &str) -> Result<ParsedDocument> {
+    let (nodes, locations) = parse_xml_nodes(xml, file).context("failed to parse xacro XML")?;
+    Ok(ParsedDocument {
+        source: Arc::from(xml),
+        tree: SharedNodes::new(nodes, locations),
+    })
+}
+
+fn root_element(nodes: Vec<XMLNode>) -> Result<Element> {
+    nodes
+        .into_iter()
+        .find_map(|node| match node {
+            XMLNode::Element(elem) => Some(elem),
+            _ => None,
+        })
+        .context("xacro document has no root element")
+}
+
+fn write_element_to_string(elem: &Element, pretty_print: bool) -> Result<String> {
+    let config = EmitterConfig::new()
+        .perform_indent(pretty_print)
+        .write_document_declaration(false);
+    let mut w = Vec::new();
+    elem.write_with_config(&mut w, config)
+        .context("failed to serialize expanded xacro XML")?;
+    String::from_utf8(w).map_err(|e| e.into())
+}
+
+fn format_include_stack(stack: &SharedStack<PathBuf>, repeated: &Path) -> String {
+    let mut paths = stack
+        .to_vec()
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>();
+    paths.push(repeated.display().to_string());
+    paths.join(" -> ")
+}
+
+pub fn parse_xacro_from_string(xml: &str) -> Result<String> {
+    XacroProcessor::new().process_string(xml)
+}
+
+pub fn parse_xacro_from_string_with_options(xml: &str, options: XacroOptions) -> Result<String> {
+    XacroProcessor::with_options(options).process_string(xml)
+}
+
+pub fn parse_xacro_from_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    XacroProcessor::new().process_file(path)
+}
+
+pub fn parse_xacro_from_file_with_options<P: AsRef<Path>>(
+    path: P,
+    options: XacroOptions,
+) -> Result<String> {
+    XacroProcessor::with_options(options).process_file(path)
+}
+
+pub fn parse_xacro_to_robot<P: AsRef<Path>>(path: P) -> Result<Robot> {
+    XacroProcessor::new().process_file_to_robot(path)
+}
+
+pub fn parse_xacro_to_robot_with_options<P: AsRef<Path>>(
+    path: P,
+    options: XacroOptions,
+) -> Result<Robot> {
+    XacroProcessor::with_options(options).process_file_to_robot(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Geometry;
+    use std::fs;
+
+    const NS: &str = "http://www.ros.org/wiki/xacro";
+
+    #[test]
+    fn expands_macro_with_property_and_condition() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:property name="side" value="left"/>
+  <xacro:macro name="link_macro" params="name suffix:=_link">
+    <xacro:if value="${{side == 'left'}}">
+      <link name="${{name}}${{suffix}}"/>
+    </xacro:if>
+  </xacro:macro>
+  <xacro:link_macro name="base"/>
+</robot>"#
+        );
+
+        let result = parse_xacro_from_string(&xml).unwrap();
+
+        assert!(result.contains(r#"<link name="base_link" />"#));
+        assert!(!result.contains("xacro:macro"));
+    }
+
+    #[test]
+    fn expands_no_arg_macro_without_params_attribute() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:macro name="default_inertial">
+    <inertial>
+      <mass value="0.01"/>
+    </inertial>
+  </xacro:macro>
+  <xacro:default_inertial/>
+</robot>"#
+        );
+
+        let result = parse_xacro_from_string(&xml).unwrap();
+
+        assert!(result.contains(r#"<mass value="0.01" />"#));
+        assert!(!result.contains("xacro:macro"));
+    }
+
+    #[test]
+    fn expands_single_block_macro_parameter() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:macro name="default_inertial" params="mass *origin">
+    <inertial>
+      <xacro:insert_block name="origin"/>
+      <mass value="${{mass}}"/>
+    </inertial>
+  </xacro:macro>
+  <xacro:default_inertial mass="1.0">
+    <origin xyz="0 0 0" rpy="0 0 0"/>
+  </xacro:default_inertial>
+</robot>"#
+        );
+
+        let result = parse_xacro_from_string(&xml).unwrap();
+
+        assert!(result.contains("<origin "));
+        assert!(result.contains(r#"xyz="0 0 0""#));
+        assert!(result.contains(r#"rpy="0 0 0""#));
+        assert!(result.contains(r#"<mass value="1" />"#));
+    }
+
+    #[test]
+    fn expands_content_block_macro_parameter() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:macro name="group" params="**links">
+    <wrapper>
+      <xacro:insert_block name="links"/>
+    </wrapper>
+  </xacro:macro>
+  <xacro:group>
+    <contents>
+      <link name="a"/>
+      <link name="b"/>
+    </contents>
+  </xacro:group>
+</robot>"#
+        );
+
+        let result = parse_xacro_from_string(&xml).unwrap();
+
+        assert!(without_indentation(&result)
+            .contains(r#"<wrapper><link name="a" /><link name="b" /></wrapper>"#));
+        assert!(!result.contains("contents"));
+    }
+
+    #[test]
+    fn expands_property_block() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:property name="origin_block">
+    <origin xyz="1 2 3"/>
+  </xacro:property>
+  <joint name="j">
+    <xacro:insert_block name="origin_block"/>
+  </joint>
+</robot>"#
+        );
+
+        let result = parse_xacro_from_string(&xml).unwrap();
+
+        assert!(without_indentation(&result)
+            .contains(r#"<joint name="j"><origin xyz="1 2 3" /></joint>"#));
+    }
+
+    #[test]
+    fn supports_forwarded_macro_default() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:property name="prefix" value="outer"/>
+  <xacro:macro name="make_link" params="prefix:=^ suffix:=_link">
+    <link name="${{prefix}}${{suffix}}"/>
+  </xacro:macro>
+  <xacro:make_link/>
+</robot>"#
+        );
+
+        let result = parse_xacro_from_string(&xml).unwrap();
+
+        assert!(result.contains(r#"<link name="outer_link" />"#));
+    }
+
+    #[test]
+    fn expands_radians_helper_expression() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <joint name="j">
+    <origin rpy="0 0 ${{radians(90)}}"/>
+    <limit lower="0" upper="${{radians(180)}}"/>
+  </joint>
+</robot>"#
+        );
+
+        let result = parse_xacro_from_string(&xml).unwrap();
+
+        assert!(result.contains(r#"rpy="0 0 1.5707963267948966""#));
+        assert!(result.contains(r#"upper="3.141592653589793""#));
+        assert!(!result.contains("radians("));
+    }
+
+    #[test]
+    fn strips_quotes_from_macro_default_literals() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:macro name="make_link" params="prefix:='' suffix:=_link">
+    <link name="${{prefix}}${{suffix}}"/>
+  </xacro:macro>
+  <xacro:make_link/>
+</robot>"#
+        );
+
+        let result = parse_xacro_from_string(&xml).unwrap();
+
+        assert!(result.contains(r#"<link name="_link" />"#));
+    }
+
+    #[test]
+    fn includes_relative_file_and_reuses_its_macro() {
+        let dir = temp_fixture_dir("include-relative");
+        let include_dir = dir.join("common");
+        fs::create_dir_all(&include_dir).unwrap();
+        fs::write(
+            include_dir.join("links.xacro"),
+            format!(
+                r#"<robot xmlns:xacro="{NS}">
+  <xacro:property name="included_suffix" value="_from_include"/>
+  <xacro:macro name="make_link" params="name">
+    <link name="${{name}}${{included_suffix}}"/>
+  </xacro:macro>
+</robot>"#
+            ),
+        )
+        .unwrap();
+        let main = dir.join("main.xacro");
+        fs::write(
+            &main,
+            format!(
+                r#"<robot xmlns:xacro="{NS}">
+  <xacro:include filename="common/links.xacro"/>
+  <xacro:make_link name="base"/>
+</robot>"#
+            ),
+        )
+        .unwrap();
+
+        let result = parse_xacro_from_file(&main).unwrap();
+
+        assert!(result.contains(r#"<link name="base_from_include" />"#));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn include_filename_can_use_xacro_arg() {
+        let dir = temp_fixture_dir("include-arg");
+        let include_dir = dir.join("common");
+        fs::create_dir_all(&include_dir).unwrap();
+        fs::write(
+            include_dir.join("links.xacro"),
+            format!(
+                r#"<robot xmlns:xacro="{NS}"><xacro:macro name="make_link" params="name"><link name="${{name}}"/></xacro:macro></robot>"#
+            ),
+        )
+        .unwrap();
+        let main = dir.join("main.xacro");
+        fs::write(
+            &main,
+            format!(
+                r#"<robot xmlns:xacro="{NS}">
+  <xacro:arg name="include_file" default="common/links.xacro"/>
+  <xacro:include filename="$(arg include_file)"/>
+  <xacro:make_link name="base"/>
+</robot>"#
+            ),
+        )
+        .unwrap();
+
+        let result = parse_xacro_from_file(&main).unwrap();
+
+        assert!(result.contains(r#"<link name="base" />"#));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn root_attribute_can_use_child_arg_default() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}" name="$(arg name)">
+  <xacro:arg name="name" default="demo"/>
+  <link name="base"/>
+</robot>"#
+        );
+
+        let result = parse_xacro_from_string(&xml).unwrap();
+
+        assert!(result.contains(r#"name="demo""#));
+        assert!(result.contains(r#"<link name="base" />"#));
+    }
+
+    #[test]
+    fn option_arg_overrides_child_arg_default_in_root_attribute() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}" name="$(arg name)">
+  <xacro:arg name="name" default="demo"/>
+  <link name="base"/>
+</robot>"#
+        );
+        let options = XacroOptions::default().with_arg("name", "provided");
+
+        let result = parse_xacro_from_string_with_options(&xml, options).unwrap();
+
+        assert!(result.contains(r#"name="provided""#));
+    }
+
+    #[test]
+    fn arg_default_can_use_preceding_property_when_parent_attribute_does_not_need_args() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:property name="default_name" value="demo"/>
+  <xacro:arg name="name" default="${{default_name}}"/>
+  <link name="$(arg name)"/>
+</robot>"#
+        );
+
+        let result = parse_xacro_from_string(&xml).unwrap();
+
+        assert!(result.contains(r#"<link name="demo" />"#));
+    }
+
+    #[test]
+    fn find_substitution_accepts_relative_option_package_path() {
+        let dir = PathBuf::from(format!(
+            "target/xurdf-xacro-relative-package-{}",
+            std::process::id()
+        ));
+        let package = dir.join("fixture_pkg");
+        let include_dir = package.join("common");
+        fs::create_dir_all(&include_dir).unwrap();
+        fs::write(
+            package.join("package.xml"),
+            r#"<package><name>fixture_pkg</name></package>"#,
+        )
+        .unwrap();
+        fs::write(
+            include_dir.join("links.xacro"),
+            format!(
+                r#"<robot xmlns:xacro="{NS}"><xacro:macro name="make_link" params="name"><link name="${{name}}"/></xacro:macro></robot>"#
+            ),
+        )
+        .unwrap();
+        let main = package.join("main.xacro");
+        fs::write(
+            &main,
+            format!(
+                r#"<robot xmlns:xacro="{NS}">
+  <xacro:include filename="$(find fixture_pkg)/common/links.xacro"/>
+  <xacro:make_link name="base"/>
+</robot>"#
+            ),
+        )
+        .unwrap();
+        let relative_package = PathBuf::from(format!(
+            "target/xurdf-xacro-relative-package-{}/fixture_pkg",
+            std::process::id()
+        ));
+        let options = XacroOptions::default().with_package_path("fixture_pkg", relative_package);
+
+        let result = parse_xacro_from_file_with_options(&main, options).unwrap();
+
+        assert!(result.contains(r#"<link name="base" />"#));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn find_substitution_discovers_current_package_manifest() {
+        let dir = temp_fixture_dir("find-current-package");
+        fs::write(
+            dir.join("package.xml"),
+            r#"<package><name>fixture_pkg</name></package>"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("links.xacro"),
+            format!(
+                r#"<robot xmlns:xacro="{NS}"><xacro:macro name="make_link" params="name"><link name="${{name}}"/></xacro:macro></robot>"#
+            ),
+        )
+        .unwrap();
+        let main = dir.join("main.xacro");
+        fs::write(
+            &main,
+            format!(
+                r#"<robot xmlns:xacro="{NS}">
+  <xacro:include filename="$(find-pkg-share fixture_pkg)/links.xacro"/>
+  <xacro:arg name="link_name" default="base"/>
+  <xacro:make_link name="$(var link_name)"/>
+</robot>"#
+            ),
+        )
+        .unwrap();
+
+        let result = parse_xacro_from_file(&main).unwrap();
+
+        assert!(result.contains(r#"<link name="base" />"#));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn find_substitution_discovers_ament_prefix_share_package() {
+        let dir = temp_fixture_dir("find-ament-prefix");
+        let package = dir.join("share").join("ament_fixture_pkg");
+        fs::create_dir_all(&package).unwrap();
+        fs::write(
+            package.join("links.xacro"),
+            format!(
+                r#"<robot xmlns:xacro="{NS}"><xacro:macro name="make_link" params="name"><link name="${{name}}"/></xacro:macro></robot>"#
+            ),
+        )
+        .unwrap();
+        let main = dir.join("main.xacro");
+        fs::write(
+            &main,
+            format!(
+                r#"<robot xmlns:xacro="{NS}">
+  <xacro:include filename="$(find-pkg-share ament_fixture_pkg)/links.xacro"/>
+  <xacro:make_link name="base"/>
+</robot>"#
+            ),
+        )
+        .unwrap();
+        let _guard = EnvVarGuard::set("AMENT_PREFIX_PATH", &dir);
+
+        let result = parse_xacro_from_file(&main).unwrap();
+
+        assert!(result.contains(r#"<link name="base" />"#));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn find_substitution_discovers_ros_package_path_manifest() {
+        let dir = temp_fixture_dir("find-ros-package-path");
+        let package = dir.join("ros_fixture_pkg");
+        fs::create_dir_all(&package).unwrap();
+        fs::write(
+            package.join("package.xml"),
+            r#"<package><name>ros_fixture_pkg</name></package>"#,
+        )
+        .unwrap();
+        fs::write(
+            package.join("links.xacro"),
+            format!(
+                r#"<robot xmlns:xacro="{NS}"><xacro:macro name="make_link" params="name"><link name="${{name}}"/></xacro:macro></robot>"#
+            ),
+        )
+        .unwrap();
+        let main = dir.join("main.xacro");
+        fs::write(
+            &main,
+            format!(
+                r#"<robot xmlns:xacro="{NS}">
+  <xacro:include filename="$(find ros_fixture_pkg)/links.xacro"/>
+  <xacro:make_link name="base"/>
+</robot>"#
+            ),
+        )
+        .unwrap();
+        let _guard = EnvVarGuard::set("ROS_PACKAGE_PATH", &dir);
+
+        let result = parse_xacro_from_file(&main).unwrap();
+
+        assert!(result.contains(r#"<link name="base" />"#));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn substitution_args_support_arg_optenv_and_find() {
+        let dir = temp_fixture_dir("substitution-args");
+        let missing_env = format!("XURDF_MISSING_OPTENV_{}", std::process::id());
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:arg name="prefix" default="default"/>
+  <link name="$(arg prefix)_$(optenv {missing_env} fallback)" mesh="$(find fixture_pkg)/meshes/base.stl"/>
+</robot>"#
+        );
+        let options = XacroOptions::default()
+            .with_arg("prefix", "provided")
+            .with_package_path("fixture_pkg", &dir);
+
+        let result = parse_xacro_from_string_with_options(&xml, options).unwrap();
+
+        assert!(result.contains(r#"name="provided_fallback""#));
+        assert!(result.contains(&format!(
+            r#"mesh="{}/meshes/base.stl""#,
+            dir.to_string_lossy()
+        )));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn errors_on_cyclic_include() {
+        let dir = temp_fixture_dir("include-cycle");
+        let a = dir.join("a.xacro");
+        let b = dir.join("b.xacro");
+        fs::write(
+            &a,
+            format!(r#"<robot xmlns:xacro="{NS}"><xacro:include filename="b.xacro"/></robot>"#),
+        )
+        .unwrap();
+        fs::write(
+            &b,
+            format!(r#"<robot xmlns:xacro="{NS}"><xacro:include filename="a.xacro"/></robot>"#),
+        )
+        .unwrap();
+
+        let err = parse_xacro_from_file(&a).unwrap_err();
+        let err = format!("{:#}", err);
+
+        assert!(err.contains("cyclic xacro include detected"));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn errors_on_runaway_macro_recursion() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:macro name="ping" params=""><xacro:pong/></xacro:macro>
+  <xacro:macro name="pong" params=""><xacro:ping/></xacro:macro>
+  <xacro:macro name="start" params=""><xacro:ping/></xacro:macro>
+  <xacro:start/>
+</robot>"#
+        );
+
+        let err = parse_xacro_from_string(&xml).unwrap_err();
 
-    while let Some((start, ch)) = chars.next() {
-        if ch.is_whitespace() {
-            continue;
-        }
+        assert!(matches!(
+            xacro_sandbox_error(&err),
+            Some(XacroSandboxError::MacroDepthExceeded(DEFAULT_MAX_MACRO_DEPTH, chain))
+                if chain.len() == DEFAULT_MAX_MACRO_DEPTH + 1
+        ));
+        assert!(render_xacro_error(&err).starts_with(
+            "error: sandbox: macro call depth limit of 100 exceeded: \
+             xacro:start -> xacro:ping -> xacro:pong -> xacro:ping -> ...\n"
+        ));
 
-        let mut end = start + ch.len_utf8();
-        let mut quote = None;
-        let mut brace_depth = 0usize;
-        let mut paren_depth = 0usize;
+        let options = XacroOptions::default().with_max_macro_depth(3);
+        let err = parse_xacro_from_string_with_options(&xml, options).unwrap_err();
+        assert_eq!(
+            xacro_sandbox_error(&err).unwrap().to_string(),
+            "sandbox: macro call depth limit of 3 exceeded: xacro:start -> xacro:ping -> xacro:pong -> \
+             xacro:ping"
+        );
+    }
 
-        while let Some(&(idx, ch)) = chars.peek() {
-            if let Some(q) = quote {
-                chars.next();
-                end = idx + ch.len_utf8();
-                if ch == q {
-                    quote = None;
-                }
-                continue;
-            }
+    #[test]
+    fn errors_on_invalid_macro_parameter() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:macro name="make_link" params="name"><link name="${{name}}"/></xacro:macro>
+  <xacro:make_link name="base" extra="bad"/>
+</robot>"#
+        );
 
-            match ch {
-                '\'' | '"' => {
-                    chars.next();
-                    quote = Some(ch);
-                    end = idx + ch.len_utf8();
-                }
-                '{' => {
-                    chars.next();
-                    brace_depth += 1;
-                    end = idx + ch.len_utf8();
-                }
-                '}' => {
-                    chars.next();
-                    brace_depth = brace_depth.saturating_sub(1);
-                    end = idx + ch.len_utf8();
-                }
-                '(' => {
-                    chars.next();
-                    paren_depth += 1;
-                    end = idx + ch.len_utf8();
-                }
-                ')' => {
-                    chars.next();
-                    paren_depth = paren_depth.saturating_sub(1);
-                    end = idx + ch.len_utf8();
-                }
-                _ if ch.is_whitespace() && brace_depth == 0 && paren_depth == 0 => break,
-                _ => {
-                    chars.next();
-                    end = idx + ch.len_utf8();
-                }
-            }
-        }
+        let err = parse_xacro_from_string(&xml).unwrap_err().to_string();
 
-        let token = s[start..end].trim();
-        if !token.is_empty() {
-            tokens.push(token.to_string());
-        }
+        assert!(err.contains("invalid parameter `extra` for macro `xacro:make_link`"));
     }
 
-    Ok(tokens)
-}
+    #[test]
+    fn errors_on_unused_macro_block() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:macro name="make_link" params="name"><link name="${{name}}"/></xacro:macro>
+  <xacro:make_link name="base"><origin/></xacro:make_link>
+</robot>"#
+        );
 
-fn element_child_nodes(nodes: &[XMLNode]) -> Vec<XMLNode> {
-    nodes
-        .iter()
-        .filter_map(|node| node.as_element().cloned().map(XMLNode::Element))
-        .collect()
-}
+        let err = parse_xacro_from_string(&xml).unwrap_err().to_string();
 
-fn strip_balanced_quotes(value: &str) -> &str {
-    if value.len() >= 2
-        && ((value.starts_with('\'') && value.ends_with('\''))
-            || (value.starts_with('"') && value.ends_with('"')))
-    {
-        &value[1..value.len() - 1]
-    } else {
-        value
+        assert!(err.contains("unused block `origin` when instantiating macro `xacro:make_link`"));
     }
-}
 
-fn write_element_to_string(elem: &Element) -> Result<String> {
-    let mut w = Vec::new();
-    elem.write(&mut w)
-        .context("failed to serialize expanded xacro XML")?;
-    String::from_utf8(w).map_err(|e| e.into())
-}
+    #[test]
+    fn allows_empty_property_block() {
+        let xml = format!(r#"<robot xmlns:xacro="{NS}"><xacro:property name="foo"/></robot>"#);
 
-fn format_include_stack(stack: &[PathBuf], repeated: &Path) -> String {
-    let mut paths = stack
-        .iter()
-        .map(|path| path.display().to_string())
-        .collect::<Vec<_>>();
-    paths.push(repeated.display().to_string());
-    paths.join(" -> ")
-}
+        let result = parse_xacro_from_string(&xml).unwrap();
 
-pub fn parse_xacro_from_string(xml: &str) -> Result<String> {
-    XacroProcessor::new().process_string(xml)
-}
+        assert!(!result.contains("xacro:property"));
+    }
 
-pub fn parse_xacro_from_string_with_options(xml: &str, options: XacroOptions) -> Result<String> {
-    XacroProcessor::with_options(options).process_string(xml)
-}
+    #[test]
+    fn errors_on_undefined_macro() {
+        let xml = format!(r#"<robot xmlns:xacro="{NS}"><xacro:missing/></robot>"#);
 
-pub fn parse_xacro_from_file<P: AsRef<Path>>(path: P) -> Result<String> {
-    XacroProcessor::new().process_file(path)
-}
+        let err = parse_xacro_from_string(&xml).unwrap_err().to_string();
 
-pub fn parse_xacro_from_file_with_options<P: AsRef<Path>>(
-    path: P,
-    options: XacroOptions,
-) -> Result<String> {
-    XacroProcessor::with_options(options).process_file(path)
-}
+        assert!(err.contains("undefined xacro macro `xacro:missing`"));
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
+    #[test]
+    fn errors_on_missing_required_macro_parameter() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:macro name="link_macro" params="name"><link name="${{name}}"/></xacro:macro>
+  <xacro:link_macro/>
+</robot>"#
+        );
 
-    const NS: &str = "http://www.ros.org/wiki/xacro";
+        let err = parse_xacro_from_string(&xml).unwrap_err().to_string();
+
+        assert!(err.contains("missing required parameter `name` for macro `xacro:link_macro`"));
+    }
 
     #[test]
-    fn expands_macro_with_property_and_condition() {
+    fn macro_parent_scope_exports_property_to_caller() {
         let xml = format!(
             r#"<robot xmlns:xacro="{NS}">
-  <xacro:property name="side" value="left"/>
-  <xacro:macro name="link_macro" params="name suffix:=_link">
-    <xacro:if value="${{side == 'left'}}">
-      <link name="${{name}}${{suffix}}"/>
-    </xacro:if>
+  <xacro:macro name="set_values" params="value">
+    <xacro:property name="local_only" value="hidden"/>
+    <xacro:property name="exported" value="${{value}}" scope="parent"/>
   </xacro:macro>
-  <xacro:link_macro name="base"/>
+  <xacro:set_values value="ok"/>
+  <link name="${{exported}}" local="${{local_only}}"/>
 </robot>"#
         );
 
         let result = parse_xacro_from_string(&xml).unwrap();
 
-        assert!(result.contains(r#"<link name="base_link" />"#));
-        assert!(!result.contains("xacro:macro"));
+        assert!(result.contains(r#"name="ok""#));
+        assert!(result.contains(r#"local="${local_only}""#));
     }
 
     #[test]
-    fn expands_no_arg_macro_without_params_attribute() {
+    fn nested_macro_scopes_shadow_without_leaking() {
         let xml = format!(
             r#"<robot xmlns:xacro="{NS}">
-  <xacro:macro name="default_inertial">
-    <inertial>
-      <mass value="0.01"/>
-    </inertial>
+  <xacro:property name="name" value="base"/>
+  <xacro:property name="color" value="red"/>
+  <xacro:macro name="inner" params="name">
+    <link name="${{name}}" color="${{color}}"/>
   </xacro:macro>
-  <xacro:default_inertial/>
+  <xacro:macro name="outer" params="name">
+    <xacro:property name="color" value="blue"/>
+    <xacro:inner name="${{name}}_child"/>
+    <link name="${{name}}" color="${{color}}"/>
+  </xacro:macro>
+  <xacro:outer name="arm"/>
+  <link name="${{name}}" color="${{color}}"/>
 </robot>"#
         );
 
         let result = parse_xacro_from_string(&xml).unwrap();
 
-        assert!(result.contains(r#"<mass value="0.01" />"#));
-        assert!(!result.contains("xacro:macro"));
+        let links = result.split("<link ").skip(1).collect::<Vec<_>>();
+        assert_eq!(links.len(), 3);
+        assert!(links[0].contains(r#"name="arm_child""#) && links[0].contains(r#"color="blue""#));
+        assert!(links[1].contains(r#"name="arm""#) && links[1].contains(r#"color="blue""#));
+        assert!(links[2].contains(r#"name="base""#) && links[2].contains(r#"color="red""#));
     }
 
     #[test]
-    fn expands_single_block_macro_parameter() {
+    fn macro_global_scope_exports_through_nested_callers() {
         let xml = format!(
             r#"<robot xmlns:xacro="{NS}">
-  <xacro:macro name="default_inertial" params="mass *origin">
-    <inertial>
-      <xacro:insert_block name="origin"/>
-      <mass value="${{mass}}"/>
-    </inertial>
+  <xacro:macro name="inner" params="">
+    <xacro:property name="global_name" value="visible" scope="global"/>
   </xacro:macro>
-  <xacro:default_inertial mass="1.0">
-    <origin xyz="0 0 0" rpy="0 0 0"/>
-  </xacro:default_inertial>
+  <xacro:macro name="outer" params="">
+    <xacro:inner/>
+    <link name="inside_${{global_name}}"/>
+  </xacro:macro>
+  <xacro:outer/>
+  <link name="outside_${{global_name}}"/>
 </robot>"#
         );
 
         let result = parse_xacro_from_string(&xml).unwrap();
 
-        assert!(result.contains("<origin "));
-        assert!(result.contains(r#"xyz="0 0 0""#));
-        assert!(result.contains(r#"rpy="0 0 0""#));
-        assert!(result.contains(r#"<mass value="1" />"#));
+        assert!(result.contains(r#"name="inside_visible""#));
+        assert!(result.contains(r#"name="outside_visible""#));
     }
 
     #[test]
-    fn expands_content_block_macro_parameter() {
+    fn scope_exports_reach_only_their_target_scope() {
         let xml = format!(
             r#"<robot xmlns:xacro="{NS}">
-  <xacro:macro name="group" params="**links">
-    <wrapper>
-      <xacro:insert_block name="links"/>
-    </wrapper>
+  <xacro:property name="color" value="red"/>
+  <xacro:macro name="inner" params="">
+    <xacro:property name="color" value="blue" scope="parent"/>
+    <xacro:property name="size" value="large" scope="global"/>
+    <xacro:property name="shape" scope="parent"><box size="${{size}}"/></xacro:property>
   </xacro:macro>
-  <xacro:group>
-    <contents>
-      <link name="a"/>
-      <link name="b"/>
-    </contents>
-  </xacro:group>
+  <xacro:macro name="outer" params="">
+    <link name="before" color="${{color}}"/>
+    <xacro:inner/>
+    <link name="after" color="${{color}}" size="${{size}}"><xacro:insert_block name="shape"/></link>
+  </xacro:macro>
+  <xacro:outer/>
+  <link name="top" color="${{color}}" size="${{size}}"/>
 </robot>"#
         );
 
         let result = parse_xacro_from_string(&xml).unwrap();
 
-        assert!(result.contains(r#"<wrapper><link name="a" /><link name="b" /></wrapper>"#));
-        assert!(!result.contains("contents"));
+        let links = result.split("<link ").skip(1).collect::<Vec<_>>();
+        assert_eq!(links.len(), 3);
+        assert!(links[0].contains(r#"color="red""#));
+        assert!(links[1].contains(r#"color="blue""#) && links[1].contains(r#"size="large""#));
+        assert!(links[1].contains(r#"<box size="large" />"#));
+        assert!(links[2].contains(r#"color="red""#) && links[2].contains(r#"size="large""#));
     }
 
     #[test]
-    fn expands_property_block() {
+    fn macro_redefined_inside_a_macro_is_local_to_the_call() {
         let xml = format!(
             r#"<robot xmlns:xacro="{NS}">
-  <xacro:property name="origin_block">
-    <origin xyz="1 2 3"/>
-  </xacro:property>
-  <joint name="j">
-    <xacro:insert_block name="origin_block"/>
-  </joint>
+  <xacro:macro name="part" params="name"><link name="${{name}}"/></xacro:macro>
+  <xacro:macro name="assembly" params="prefix">
+    <xacro:macro name="part" params="name"><joint name="${{prefix}}${{name}}"/></xacro:macro>
+    <xacro:part name="a"/>
+  </xacro:macro>
+  <xacro:assembly prefix="left_"/>
+  <xacro:assembly prefix="right_"/>
+  <xacro:part name="b"/>
 </robot>"#
         );
 
         let result = parse_xacro_from_string(&xml).unwrap();
 
-        assert!(result.contains(r#"<joint name="j"><origin xyz="1 2 3" /></joint>"#));
+        assert!(result.contains(r#"<joint name="left_a" />"#));
+        assert!(result.contains(r#"<joint name="right_a" />"#));
+        assert!(result.contains(r#"<link name="b" />"#));
+        assert!(!result.contains(r#"<joint name="left_b""#));
+    }
+
+    #[test]
+    fn errors_on_invalid_property_scope() {
+        let xml = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:property name="bad" value="1" scope="sideways"/>
+</robot>"#
+        );
+
+        let err = parse_xacro_from_string(&xml).unwrap_err().to_string();
+
+        assert!(err.contains("unsupported xacro:property scope `sideways`"));
+    }
+
+    #[test]
+    fn processor_does_not_leak_context_between_documents() {
+        let first = format!(
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:macro name="link_macro" params="name"><link name="${{name}}"/></xacro:macro>
+  <xacro:link_macro name="first"/>
+</robot>"#
+        );
+        let second =
+            format!(r#"<robot xmlns:xacro="{NS}"><xacro:link_macro name="second"/></robot>"#);
+        let mut processor = XacroProcessor::new();
+
+        processor.process_string(&first).unwrap();
+        let err = processor.process_string(&second).unwrap_err().to_string();
+
+        assert!(err.contains("undefined xacro macro `xacro:link_macro`"));
     }
 
     #[test]
-    fn supports_forwarded_macro_default() {
-        let xml = format!(
-            r#"<robot xmlns:xacro="{NS}">
-  <xacro:property name="prefix" value="outer"/>
-  <xacro:macro name="make_link" params="prefix:=^ suffix:=_link">
-    <link name="${{prefix}}${{suffix}}"/>
-  </xacro:macro>
-  <xacro:make_link/>
+    fn loads_yaml_and_accesses_nested_values() {
+        let dir = temp_fixture_dir("load-yaml");
+        fs::write(
+            dir.join("config.yaml"),
+            r#"
+robot:
+  name: arm
+joints:
+  shoulder:
+    enabled: true
+    max_effort: 150.0
+    min_position: !degrees -180
+values:
+  offsets: [1, 2, foo]
+"#,
+        )
+        .unwrap();
+        let main = dir.join("main.xacro");
+        fs::write(
+            &main,
+            format!(
+                r#"<robot xmlns:xacro="{NS}">
+  <xacro:property name="cfg_path" value="config.yaml"/>
+  <xacro:property name="cfg" value="${{xacro.load_yaml(cfg_path)}}"/>
+  <xacro:property name="shoulder" value="${{cfg['joints']['shoulder']}}"/>
+  <xacro:if value="${{shoulder['enabled']}}">
+    <link name="${{cfg.robot.name}}" effort="${{shoulder['max_effort']}}" lower="${{shoulder['min_position']}}" offset="${{cfg['values']['offsets'][2]}}"/>
+  </xacro:if>
 </robot>"#
-        );
+            ),
+        )
+        .unwrap();
 
-        let result = parse_xacro_from_string(&xml).unwrap();
+        let result = parse_xacro_from_file(&main).unwrap();
 
-        assert!(result.contains(r#"<link name="outer_link" />"#));
+        assert!(result.contains(r#"name="arm""#));
+        assert!(result.contains(r#"effort="150""#));
+        assert!(result.contains(r#"lower="-3.141592653589793""#));
+        assert!(result.contains(r#"offset="foo""#));
+        let _ = fs::remove_dir_all(dir);
     }
 
     #[test]
-    fn expands_radians_helper_expression() {
-        let xml = format!(
-            r#"<robot xmlns:xacro="{NS}">
-  <joint name="j">
-    <origin rpy="0 0 ${{radians(90)}}"/>
-    <limit lower="0" upper="${{radians(180)}}"/>
-  </joint>
+    fn yaml_boolean_false_skips_if_body() {
+        let dir = temp_fixture_dir("load-yaml-false");
+        fs::write(
+            dir.join("config.yaml"),
+            r#"
+feature:
+  enabled: false
+"#,
+        )
+        .unwrap();
+        let main = dir.join("main.xacro");
+        fs::write(
+            &main,
+            format!(
+                r#"<robot xmlns:xacro="{NS}">
+  <xacro:property name="cfg" value="${{load_yaml('config.yaml')}}"/>
+  <xacro:if value="${{cfg.feature.enabled}}">
+    <link name="enabled"/>
+  </xacro:if>
 </robot>"#
-        );
+            ),
+        )
+        .unwrap();
 
-        let result = parse_xacro_from_string(&xml).unwrap();
+        let result = parse_xacro_from_file(&main).unwrap();
 
-        assert!(result.contains(r#"rpy="0 0 1.5707963267948966""#));
-        assert!(result.contains(r#"upper="3.141592653589793""#));
-        assert!(!result.contains("radians("));
+        assert!(!result.contains(r#"name="enabled""#));
+        let _ = fs::remove_dir_all(dir);
     }
 
     #[test]
-    fn strips_quotes_from_macro_default_literals() {
+    fn python_number_format_matches_upstream_xacro() {
         let xml = format!(
             r#"<robot xmlns:xacro="{NS}">
-  <xacro:macro name="make_link" params="prefix:='' suffix:=_link">
-    <link name="${{prefix}}${{suffix}}"/>
-  </xacro:macro>
-  <xacro:make_link/>
+  <xacro:property name="count" value="3"/>
+  <xacro:property name="width" value="0.10"/>
+  <link name="base" angle="${{degrees(pi)}}" count="${{count}}" doubled="${{count * 2}}" half="${{count / 2}}" width="${{width}}" tiny="${{width * 1e-4}}"/>
 </robot>"#
         );
+        let options = XacroOptions::default().with_number_format(NumberFormat::PythonRepr);
+
+        let result = parse_xacro_from_string_with_options(&xml, options).unwrap();
+
+        assert!(result.contains(r#"angle="180.0""#));
+        assert!(result.contains(r#"count="3""#));
+        assert!(result.contains(r#"doubled="6""#));
+        assert!(result.contains(r#"half="1.5""#));
+        assert!(result.contains(r#"width="0.1""#));
+        assert!(result.contains(r#"tiny="1e-05""#));
 
         let result = parse_xacro_from_string(&xml).unwrap();
 
-        assert!(result.contains(r#"<link name="_link" />"#));
+        assert!(result.contains(r#"angle="180""#));
+        assert!(result.contains(r#"tiny="0.00001""#));
     }
 
     #[test]
-    fn includes_relative_file_and_reuses_its_macro() {
-        let dir = temp_fixture_dir("include-relative");
-        let include_dir = dir.join("common");
-        fs::create_dir_all(&include_dir).unwrap();
+    fn strict_mode_reports_undefined_property_with_location() {
+        let dir = temp_fixture_dir("strict-undefined");
         fs::write(
-            include_dir.join("links.xacro"),
+            dir.join("wheel.xacro"),
             format!(
                 r#"<robot xmlns:xacro="{NS}">
-  <xacro:property name="included_suffix" value="_from_include"/>
-  <xacro:macro name="make_link" params="name">
-    <link name="${{name}}${{included_suffix}}"/>
-  </xacro:macro>
+  <xacro:property name="wheel_radius" value="0.1"/>
+  <link name="wheel" radius="${{wheel_radus}}"/>
 </robot>"#
             ),
         )
@@ -1444,30 +3784,40 @@ mod tests {
         let main = dir.join("main.xacro");
         fs::write(
             &main,
-            format!(
-                r#"<robot xmlns:xacro="{NS}">
-  <xacro:include filename="common/links.xacro"/>
-  <xacro:make_link name="base"/>
-</robot>"#
-            ),
+            format!(r#"<robot xmlns:xacro="{NS}"><xacro:include filename="wheel.xacro"/></robot>"#),
         )
         .unwrap();
 
         let result = parse_xacro_from_file(&main).unwrap();
+        assert!(result.contains(r#"radius="${wheel_radus}""#));
 
-        assert!(result.contains(r#"<link name="base_from_include" />"#));
+        let options = XacroOptions::default().with_strict(true);
+        let err = parse_xacro_from_file_with_options(&main, options).unwrap_err();
+        let err = format!("{:#}", err);
+
+        assert!(err.contains("undefined name `wheel_radus`"));
+        assert!(err.contains("did you mean `wheel_radius`?"));
+        assert!(err.contains(&format!(
+            "in attribute `radius` of `<link>` in `{}`, included from `{}`",
+            fs::canonicalize(dir.join("wheel.xacro")).unwrap().display(),
+            fs::canonicalize(&main).unwrap().display()
+        )));
         let _ = fs::remove_dir_all(dir);
     }
 
     #[test]
-    fn include_filename_can_use_xacro_arg() {
-        let dir = temp_fixture_dir("include-arg");
-        let include_dir = dir.join("common");
-        fs::create_dir_all(&include_dir).unwrap();
+    fn errors_report_source_location_and_macro_stack() {
+        let dir = temp_fixture_dir("diagnostic-location");
+        let library = dir.join("arm.xacro");
         fs::write(
-            include_dir.join("links.xacro"),
+            &library,
             format!(
-                r#"<robot xmlns:xacro="{NS}"><xacro:macro name="make_link" params="name"><link name="${{name}}"/></xacro:macro></robot>"#
+                r#"<robot xmlns:xacro="{NS}">
+  <xacro:macro name="arm" params="prefix">
+    <link name="${{prefix}}_base"/>
+    <xacro:gripper prefix="${{prefix}}"/>
+  </xacro:macro>
+</robot>"#
             ),
         )
         .unwrap();
@@ -1476,152 +3826,298 @@ mod tests {
             &main,
             format!(
                 r#"<robot xmlns:xacro="{NS}">
-  <xacro:arg name="include_file" default="common/links.xacro"/>
-  <xacro:include filename="$(arg include_file)"/>
-  <xacro:make_link name="base"/>
+  <xacro:include filename="arm.xacro"/>
+  <xacro:arm prefix="left"/>
 </robot>"#
             ),
         )
         .unwrap();
-
-        let result = parse_xacro_from_file(&main).unwrap();
-
-        assert!(result.contains(r#"<link name="base" />"#));
+        let library = fs::canonicalize(library).unwrap();
+        let main = fs::canonicalize(main).unwrap();
+
+        let err = parse_xacro_from_file(&main).unwrap_err();
+        let diagnostic = err
+            .chain()
+            .find_map(|err| err.downcast_ref::<XacroDiagnostic>())
+            .unwrap();
+
+        assert_eq!(
+            diagnostic.location(),
+            &SourceLocation {
+                file: Some(library.clone()),
+                line: 4,
+                column: 5,
+            }
+        );
+        assert_eq!(
+            diagnostic.macro_stack(),
+            &[MacroCall {
+                name: "arm".to_string(),
+                location: Some(SourceLocation {
+                    file: Some(main.clone()),
+                    line: 3,
+                    column: 3,
+                }),
+            }]
+        );
+        assert_eq!(
+            render_xacro_error(&err),
+            format!(
+                "error: undefined xacro macro `xacro:gripper`
+  --> {}:4:5
+  |
+4 |     <xacro:gripper prefix=\"${{prefix}}\"/>
+  |     ^
+  = note: in expansion of macro `xacro:arm` at {}:3:3
+",
+                library.display(),
+                main.display()
+            )
+        );
         let _ = fs::remove_dir_all(dir);
     }
 
     #[test]
-    fn root_attribute_can_use_child_arg_default() {
+    fn errors_collapse_repeated_macro_frames() {
         let xml = format!(
-            r#"<robot xmlns:xacro="{NS}" name="$(arg name)">
-  <xacro:arg name="name" default="demo"/>
-  <link name="base"/>
+            r#"<robot xmlns:xacro="{NS}">
+  <xacro:macro name="loop" params="">
+    <xacro:loop/>
+  </xacro:macro>
+  <xacro:loop/>
 </robot>"#
         );
-
-        let result = parse_xacro_from_string(&xml).unwrap();
-
-        assert!(result.contains(r#"name="demo""#));
-        assert!(result.contains(r#"<link name="base" />"#));
+        let options = XacroOptions::default().with_max_macro_depth(4);
+        let err = parse_xacro_from_string_with_options(&xml, options).unwrap_err();
+
+        assert_eq!(
+            render_xacro_error(&err),
+            "error: sandbox: macro call depth limit of 4 exceeded: xacro:loop -> xacro:loop -> ...
+  --> <string>:3:5
+  |
+3 |     <xacro:loop/>
+  |     ^
+  = note: in expansion of macro `xacro:loop` at <string>:3:5
+  = note: in expansion of macro `xacro:loop` at <string>:3:5
+  = note: ... and 2 more expansions
+"
+        );
     }
 
     #[test]
-    fn option_arg_overrides_child_arg_default_in_root_attribute() {
+    fn string_errors_report_line_and_column() {
         let xml = format!(
-            r#"<robot xmlns:xacro="{NS}" name="$(arg name)">
-  <xacro:arg name="name" default="demo"/>
+            r#"<robot xmlns:xacro="{NS}">
   <link name="base"/>
+  <xacro:missing/>
 </robot>"#
         );
-        let options = XacroOptions::default().with_arg("name", "provided");
 
-        let result = parse_xacro_from_string_with_options(&xml, options).unwrap();
+        let err = parse_xacro_from_string(&xml).unwrap_err();
 
-        assert!(result.contains(r#"name="provided""#));
+        assert_eq!(
+            err.to_string(),
+            "<string>:3:3: undefined xacro macro `xacro:missing`"
+        );
     }
 
     #[test]
-    fn arg_default_can_use_preceding_property_when_parent_attribute_does_not_need_args() {
+    fn error_columns_count_characters() {
         let xml = format!(
-            r#"<robot xmlns:xacro="{NS}">
-  <xacro:property name="default_name" value="demo"/>
-  <xacro:arg name="name" default="${{default_name}}"/>
-  <link name="$(arg name)"/>
-</robot>"#
+            "<robot xmlns:xacro=\"{NS}\">\r\n  <link name=\"über\"/><xacro:missing/>\r\n</robot>"
         );
 
-        let result = parse_xacro_from_string(&xml).unwrap();
+        let err = parse_xacro_from_string(&xml).unwrap_err();
 
-        assert!(result.contains(r#"<link name="demo" />"#));
+        assert_eq!(
+            err.to_string(),
+            "<string>:2:22: undefined xacro macro `xacro:missing`"
+        );
     }
 
     #[test]
-    fn find_substitution_accepts_relative_option_package_path() {
-        let dir = PathBuf::from(format!(
-            "target/xurdf-xacro-relative-package-{}",
-            std::process::id()
-        ));
-        let package = dir.join("fixture_pkg");
-        let include_dir = package.join("common");
-        fs::create_dir_all(&include_dir).unwrap();
-        fs::write(
-            package.join("package.xml"),
-            r#"<package><name>fixture_pkg</name></package>"#,
-        )
-        .unwrap();
-        fs::write(
-            include_dir.join("links.xacro"),
-            format!(
-                r#"<robot xmlns:xacro="{NS}"><xacro:macro name="make_link" params="name"><link name="${{name}}"/></xacro:macro></robot>"#
-            ),
-        )
-        .unwrap();
-        let main = package.join("main.xacro");
-        fs::write(
-            &main,
-            format!(
-                r#"<robot xmlns:xacro="{NS}">
-  <xacro:include filename="$(find fixture_pkg)/common/links.xacro"/>
-  <xacro:make_link name="base"/>
+    fn processes_files_from_memory_file_provider() {
+        let files = MemoryFileProvider::new()
+            .with_file(
+                "/ws/robot/package.xml",
+                "<package><name>robot</name></package>",
+            )
+            .with_file("/ws/robot/config/arm.yaml", "name: arm\n")
+            .with_file(
+                "/ws/robot/urdf/parts/link.xacro",
+                format!(
+                    r#"<robot xmlns:xacro="{NS}">
+  <xacro:property name="cfg" value="${{load_yaml('../../config/arm.yaml')}}"/>
+  <link name="${{cfg.name}}"/>
 </robot>"#
-            ),
-        )
-        .unwrap();
-        let relative_package = PathBuf::from(format!(
-            "target/xurdf-xacro-relative-package-{}/fixture_pkg",
-            std::process::id()
-        ));
-        let options = XacroOptions::default().with_package_path("fixture_pkg", relative_package);
+                ),
+            )
+            .with_file(
+                "/ws/robot/urdf/main.xacro",
+                format!(
+                    r#"<robot name="robot" xmlns:xacro="{NS}">
+  <xacro:include filename="$(find robot)/urdf/parts/link.xacro"/>
+</robot>"#
+                ),
+            );
+        let options = XacroOptions::default().with_file_provider(files);
+        let mut processor = XacroProcessor::with_options(options);
 
-        let result = parse_xacro_from_file_with_options(&main, options).unwrap();
+        let robot = processor
+            .process_file_to_robot("ws/robot/urdf/./main.xacro")
+            .unwrap();
 
-        assert!(result.contains(r#"<link name="base" />"#));
-        let _ = fs::remove_dir_all(dir);
+        assert_eq!(robot.links[0].name, "arm");
+        assert_eq!(
+            processor.dependencies().includes,
+            vec![PathBuf::from("/ws/robot/urdf/parts/link.xacro")]
+        );
+        let err = processor.process_file("/ws/missing.xacro").unwrap_err();
+        assert!(format!("{:#}", err).contains("no such in-memory file or directory"));
+        assert!(processor
+            .process_string(r#"<robot><link name="$(cwd)"/></robot>"#)
+            .unwrap()
+            .contains(r#"<link name="/" />"#));
+
+        let files = MemoryFileProvider::new()
+            .with_file("/opt/a/share/robot", "not a package")
+            .with_file("/opt/b/share/robot/package.xml", "<package/>");
+        assert!(files.exists(Path::new("/opt/b/share/robot")));
+        assert!(!files.exists(Path::new("/opt/b/share/rob")));
+        assert!(!files.is_dir(Path::new("/opt/a/share/robot")));
+        assert!(!files.is_dir(Path::new("/opt/b/share/robot/package.xml")));
+        assert_eq!(
+            discover_package_share_from_env_var(
+                &files,
+                Some(std::env::join_paths(["/opt/a", "/opt/b"]).unwrap()),
+                "robot"
+            ),
+            Some(PathBuf::from("/opt/b/share/robot"))
+        );
     }
 
     #[test]
-    fn find_substitution_discovers_current_package_manifest() {
-        let dir = temp_fixture_dir("find-current-package");
+    fn sandbox_violations_are_distinct_errors() {
+        let dir = temp_fixture_dir("sandbox");
+        let allowed = dir.join("allowed");
+        fs::create_dir_all(&allowed).unwrap();
+        fs::write(dir.join("secret.xacro"), "<robot/>").unwrap();
         fs::write(
-            dir.join("package.xml"),
-            r#"<package><name>fixture_pkg</name></package>"#,
+            allowed.join("nested.xacro"),
+            format!(r#"<robot xmlns:xacro="{NS}"><xacro:include filename="leaf.xacro"/></robot>"#),
         )
         .unwrap();
         fs::write(
-            dir.join("links.xacro"),
-            format!(
-                r#"<robot xmlns:xacro="{NS}"><xacro:macro name="make_link" params="name"><link name="${{name}}"/></xacro:macro></robot>"#
-            ),
+            allowed.join("leaf.xacro"),
+            "<robot><link name=\"leaf\"/></robot>",
         )
         .unwrap();
-        let main = dir.join("main.xacro");
-        fs::write(
-            &main,
-            format!(
-                r#"<robot xmlns:xacro="{NS}">
-  <xacro:include filename="$(find-pkg-share fixture_pkg)/links.xacro"/>
-  <xacro:arg name="link_name" default="base"/>
-  <xacro:make_link name="$(var link_name)"/>
-</robot>"#
+        let main = allowed.join("main.xacro");
+        let process = |sandbox: XacroSandbox, body: &str| {
+            fs::write(
+                &main,
+                format!(r#"<robot xmlns:xacro="{NS}">{body}</robot>"#),
+            )
+            .unwrap();
+            let options = XacroOptions::default().with_sandbox(sandbox.with_allowed_root(&allowed));
+            XacroProcessor::with_options(options).process_file(&main)
+        };
+        let violation = |sandbox: XacroSandbox, body: &str| {
+            let err = process(sandbox, body).unwrap_err();
+            xacro_sandbox_error(&err)
+                .unwrap_or_else(|| panic!("not a sandbox error: {:#}", err))
+                .clone()
+        };
+        let recursive = r#"<xacro:macro name="loop" params="">
+  <xacro:loop/></xacro:macro><xacro:loop/>"#;
+
+        assert!(process(
+            XacroSandbox::new(),
+            r#"<xacro:include filename="nested.xacro"/>"#
+        )
+        .unwrap()
+        .contains("leaf"));
+        assert_eq!(
+            violation(
+                XacroSandbox::new(),
+                r#"<xacro:include filename="../secret.xacro"/>"#
+            ),
+            XacroSandboxError::PathNotAllowed(fs::canonicalize(dir.join("secret.xacro")).unwrap())
+        );
+        let err =
+            XacroProcessor::with_options(XacroOptions::default().with_sandbox(XacroSandbox::new()))
+                .process_file(&main)
+                .unwrap_err();
+        assert_eq!(
+            xacro_sandbox_error(&err),
+            Some(&XacroSandboxError::PathNotAllowed(
+                fs::canonicalize(&main).unwrap()
+            ))
+        );
+        assert_eq!(
+            violation(
+                XacroSandbox::new(),
+                r#"<link name="$(optenv HOME robot)"/>"#
             ),
+            XacroSandboxError::EnvNotAllowed("HOME".to_string())
+        );
+        assert!(process(
+            XacroSandbox::new().with_env_access(XacroEnvAccess::Allow),
+            r#"<link name="$(optenv XURDF_UNSET robot)"/>"#
         )
-        .unwrap();
-
-        let result = parse_xacro_from_file(&main).unwrap();
-
-        assert!(result.contains(r#"<link name="base" />"#));
-        let _ = fs::remove_dir_all(dir);
+        .unwrap()
+        .contains(r#"name="robot""#));
+        assert!(process(
+            XacroSandbox::new()
+                .with_env_access(XacroEnvAccess::AllowList(vec!["XURDF_UNSET".to_string()])),
+            r#"<link name="$(optenv XURDF_UNSET robot)"/>"#
+        )
+        .unwrap()
+        .contains(r#"name="robot""#));
+        assert_eq!(
+            violation(
+                XacroSandbox::new().with_max_include_depth(1),
+                r#"<xacro:include filename="nested.xacro"/>"#
+            ),
+            XacroSandboxError::IncludeDepthExceeded(1)
+        );
+        assert_eq!(
+            violation(
+                XacroSandbox::new().with_max_output_bytes(100),
+                &r#"<link name="a_long_link_name"/>"#.repeat(10)
+            ),
+            XacroSandboxError::OutputTooLarge(100)
+        );
+        assert_eq!(
+            violation(XacroSandbox::new().with_max_steps(50), recursive),
+            XacroSandboxError::StepBudgetExceeded(50)
+        );
+        assert_eq!(
+            violation(
+                XacroSandbox::new().with_max_steps(50),
+                r#"<link name="${len([x for x in range(1000)])}"/>"#
+            ),
+            XacroSandboxError::StepBudgetExceeded(50)
+        );
+        assert_eq!(
+            violation(
+                XacroSandbox::new().with_time_budget(Duration::ZERO),
+                recursive
+            ),
+            XacroSandboxError::TimeBudgetExceeded(Duration::ZERO)
+        );
     }
 
     #[test]
-    fn find_substitution_discovers_ament_prefix_share_package() {
-        let dir = temp_fixture_dir("find-ament-prefix");
-        let package = dir.join("share").join("ament_fixture_pkg");
-        fs::create_dir_all(&package).unwrap();
+    fn shares_cached_includes_and_yaml_between_processors() {
+        let dir = temp_fixture_dir("cache");
+        let config = dir.join("config.yaml");
+        let links = dir.join("links.xacro");
+        fs::write(&config, "name: arm\n").unwrap();
         fs::write(
-            package.join("links.xacro"),
+            &links,
             format!(
-                r#"<robot xmlns:xacro="{NS}"><xacro:macro name="make_link" params="name"><link name="${{name}}"/></xacro:macro></robot>"#
+                r#"<robot xmlns:xacro="{NS}"><xacro:property name="suffix" value="a"/></robot>"#
             ),
         )
         .unwrap();
@@ -1629,35 +4125,76 @@ mod tests {
         fs::write(
             &main,
             format!(
-                r#"<robot xmlns:xacro="{NS}">
-  <xacro:include filename="$(find-pkg-share ament_fixture_pkg)/links.xacro"/>
-  <xacro:make_link name="base"/>
+                r#"<robot xmlns:xacro="{NS}">
+  <xacro:include filename="links.xacro"/>
+  <xacro:property name="cfg" value="${{load_yaml('config.yaml')}}"/>
+  <link name="${{cfg.name}}_${{suffix}}"/>
 </robot>"#
             ),
         )
         .unwrap();
-        let _guard = EnvVarGuard::set("AMENT_PREFIX_PATH", &dir);
-
-        let result = parse_xacro_from_file(&main).unwrap();
+        let cache = XacroCache::new();
+        let process = || {
+            let options = XacroOptions::default().with_cache(cache.clone());
+            XacroProcessor::with_options(options)
+                .process_file(&main)
+                .unwrap()
+        };
 
-        assert!(result.contains(r#"<link name="base" />"#));
-        let _ = fs::remove_dir_all(dir);
-    }
+        assert!(process().contains(r#"<link name="arm_a" />"#));
+        assert_eq!(cache.len(), 3);
 
-    #[test]
-    fn find_substitution_discovers_ros_package_path_manifest() {
-        let dir = temp_fixture_dir("find-ros-package-path");
-        let package = dir.join("ros_fixture_pkg");
-        fs::create_dir_all(&package).unwrap();
+        let times = |path: &Path| fs::metadata(path).unwrap().modified().unwrap();
+        let (config_time, links_time) = (times(&config), times(&links));
+        fs::write(&config, "name: leg\n").unwrap();
         fs::write(
-            package.join("package.xml"),
-            r#"<package><name>ros_fixture_pkg</name></package>"#,
+            &links,
+            format!(
+                r#"<robot xmlns:xacro="{NS}"><xacro:property name="suffix" value="b"/></robot>"#
+            ),
         )
         .unwrap();
+        let set_time = |path: &Path, time: SystemTime| {
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        };
+        set_time(&config, config_time);
+        set_time(&links, links_time);
+        let output = std::thread::scope(|scope| scope.spawn(process).join().unwrap());
+        assert!(output.contains(r#"<link name="arm_a" />"#));
+
+        let later = config_time + std::time::Duration::from_secs(5);
+        set_time(&config, later);
+        set_time(&links, later);
+        assert!(process().contains(r#"<link name="leg_b" />"#));
+        assert_eq!(cache.len(), 3);
+
+        // An edit that keeps the timestamp is still noticed when it changes the size.
+        fs::write(&config, "name: shoulder\n").unwrap();
+        set_time(&config, later);
+        assert!(process().contains(r#"<link name="shoulder_b" />"#));
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn tracks_included_and_yaml_dependencies() {
+        let dir = temp_fixture_dir("dependencies");
+        fs::write(dir.join("config.yaml"), "name: arm\n").unwrap();
         fs::write(
-            package.join("links.xacro"),
+            dir.join("links.xacro"),
             format!(
-                r#"<robot xmlns:xacro="{NS}"><xacro:macro name="make_link" params="name"><link name="${{name}}"/></xacro:macro></robot>"#
+                r#"<robot xmlns:xacro="{NS}">
+  <xacro:macro name="make_link" params="">
+    <xacro:property name="cfg" value="${{load_yaml('config.yaml')}}"/>
+    <link name="${{cfg.name}}"/>
+  </xacro:macro>
+</robot>"#
             ),
         )
         .unwrap();
@@ -1666,271 +4203,340 @@ mod tests {
             &main,
             format!(
                 r#"<robot xmlns:xacro="{NS}">
-  <xacro:include filename="$(find ros_fixture_pkg)/links.xacro"/>
-  <xacro:make_link name="base"/>
+  <xacro:include filename="links.xacro"/>
+  <xacro:make_link/>
+  <xacro:make_link/>
 </robot>"#
             ),
         )
         .unwrap();
-        let _guard = EnvVarGuard::set("ROS_PACKAGE_PATH", &dir);
-
-        let result = parse_xacro_from_file(&main).unwrap();
+        let mut processor = XacroProcessor::new();
 
-        assert!(result.contains(r#"<link name="base" />"#));
-        let _ = fs::remove_dir_all(dir);
-    }
+        processor.process_file(&main).unwrap();
+        let dependencies = processor.dependencies();
 
-    #[test]
-    fn substitution_args_support_arg_optenv_and_find() {
-        let dir = temp_fixture_dir("substitution-args");
-        let missing_env = format!("XURDF_MISSING_OPTENV_{}", std::process::id());
-        let xml = format!(
-            r#"<robot xmlns:xacro="{NS}">
-  <xacro:arg name="prefix" default="default"/>
-  <link name="$(arg prefix)_$(optenv {missing_env} fallback)" mesh="$(find fixture_pkg)/meshes/base.stl"/>
-</robot>"#
+        assert_eq!(
+            dependencies.includes,
+            vec![fs::canonicalize(dir.join("links.xacro")).unwrap()]
         );
-        let options = XacroOptions::default()
-            .with_arg("prefix", "provided")
-            .with_package_path("fixture_pkg", &dir);
-
-        let result = parse_xacro_from_string_with_options(&xml, options).unwrap();
-
-        assert!(result.contains(r#"name="provided_fallback""#));
-        assert!(result.contains(&format!(
-            r#"mesh="{}/meshes/base.stl""#,
-            dir.to_string_lossy()
-        )));
+        assert_eq!(
+            dependencies.yaml_files,
+            vec![fs::canonicalize(dir.join("config.yaml")).unwrap()]
+        );
+        assert_eq!(dependencies.all().count(), 2);
         let _ = fs::remove_dir_all(dir);
     }
 
     #[test]
-    fn errors_on_cyclic_include() {
-        let dir = temp_fixture_dir("include-cycle");
-        let a = dir.join("a.xacro");
-        let b = dir.join("b.xacro");
+    fn processes_xacro_directly_into_robot() {
+        let dir = temp_fixture_dir("to-robot");
+        let main = dir.join("robot.xacro");
         fs::write(
-            &a,
-            format!(r#"<robot xmlns:xacro="{NS}"><xacro:include filename="b.xacro"/></robot>"#),
-        )
-        .unwrap();
-        fs::write(
-            &b,
-            format!(r#"<robot xmlns:xacro="{NS}"><xacro:include filename="a.xacro"/></robot>"#),
+            &main,
+            format!(
+                r#"<robot xmlns:xacro="{NS}" name="$(arg name)">
+  <xacro:arg name="name" default="demo"/>
+  <xacro:property name="length" value="0.5"/>
+  <xacro:macro name="segment" params="name parent">
+    <link name="${{name}}">
+      <visual><geometry><cylinder radius="0.1" length="${{length}}"/></geometry></visual>
+    </link>
+    <joint name="${{parent}}_to_${{name}}" type="revolute">
+      <parent link="${{parent}}"/>
+      <child link="${{name}}"/>
+      <origin xyz="0 0 ${{length}}"/>
+      <axis xyz="0 0 1"/>
+      <limit lower="${{-pi/2}}" upper="${{pi/2}}" effort="10" velocity="1"/>
+    </joint>
+  </xacro:macro>
+  <link name="base"/>
+  <xacro:segment name="upper" parent="base"/>
+</robot>"#
+            ),
         )
         .unwrap();
-
-        let err = parse_xacro_from_file(&a).unwrap_err();
-        let err = format!("{:#}", err);
-
-        assert!(err.contains("cyclic xacro include detected"));
+        let options = XacroOptions::default().with_arg("name", "arm");
+
+        let robot = parse_xacro_to_robot_with_options(&main, options).unwrap();
+
+        assert_eq!(robot.name, "arm");
+        assert_eq!(robot.links.len(), 2);
+        assert_eq!(robot.joints.len(), 1);
+        assert_eq!(robot.joints[0].name, "base_to_upper");
+        assert_eq!(robot.joints[0].origin.xyz[2], 0.5);
+        assert_eq!(robot.joints[0].limit.upper, std::f64::consts::FRAC_PI_2);
+        let Geometry::Cylinder { length, .. } = robot.links[1].visuals[0].geometry else {
+            panic!("expected cylinder geometry");
+        };
+        assert_eq!(length, 0.5);
         let _ = fs::remove_dir_all(dir);
     }
 
     #[test]
-    fn errors_on_invalid_macro_parameter() {
+    fn registered_functions_and_constants_are_visible_in_expressions() {
         let xml = format!(
-            r#"<robot xmlns:xacro="{NS}">
-  <xacro:macro name="make_link" params="name"><link name="${{name}}"/></xacro:macro>
-  <xacro:make_link name="base" extra="bad"/>
+            r#"<robot name="r" xmlns:xacro="{NS}">
+  <xacro:property name="spec" value="${{motor_spec(motor)}}"/>
+  <link name="${{prefix}}base">
+    <inertial><mass value="${{spec.mass}}"/></inertial>
+  </link>
+  <joint name="j" type="revolute">
+    <limit effort="${{motor_spec('xm430')['torque']}}" lower="0" upper="${{mm(12.5)}}" velocity="1"/>
+  </joint>
 </robot>"#
         );
+        let options = XacroOptions::default()
+            .with_constant("prefix", XacroValue::String("left_".to_string()))
+            .with_constant("motor", XacroValue::String("xm430".to_string()))
+            .with_function("mm", |args| match args {
+                [value] => Ok(XacroValue::Number(
+                    value.raw_value().parse::<f64>()? / 1000.0,
+                )),
+                _ => bail!("mm() takes one argument"),
+            })
+            .with_function("motor_spec", |args| match args {
+                [XacroValue::String(name)] if name == "xm430" => Ok(XacroValue::Map(
+                    [
+                        ("mass".to_string(), XacroValue::Number(0.082)),
+                        ("torque".to_string(), XacroValue::Number(4.1)),
+                    ]
+                    .into_iter()
+                    .collect(),
+                )),
+                _ => bail!("unknown motor {:?}", args),
+            });
 
-        let err = parse_xacro_from_string(&xml).unwrap_err().to_string();
+        let result = parse_xacro_from_string_with_options(&xml, options).unwrap();
 
-        assert!(err.contains("invalid parameter `extra` for macro `xacro:make_link`"));
+        assert!(result.contains(r#"<link name="left_base">"#), "{}", result);
+        assert!(result.contains(r#"<mass value="0.082" />"#), "{}", result);
+        assert!(result.contains(r#"effort="4.1""#), "{}", result);
+        assert!(result.contains(r#"upper="0.0125""#), "{}", result);
     }
 
     #[test]
-    fn errors_on_unused_macro_block() {
+    fn typed_args_are_available_through_xacro_arg() {
         let xml = format!(
-            r#"<robot xmlns:xacro="{NS}">
-  <xacro:macro name="make_link" params="name"><link name="${{name}}"/></xacro:macro>
-  <xacro:make_link name="base"><origin/></xacro:make_link>
+            r#"<robot name="r" xmlns:xacro="{NS}">
+  <xacro:arg name="prefix" default="left_"/>
+  <xacro:property name="offsets" value="${{xacro.arg('offsets')}}"/>
+  <link name="${{xacro.arg('prefix')}}base">
+    <inertial><mass value="${{xacro.arg('mass') * 2}}"/></inertial>
+  </link>
+  <joint name="j" type="fixed">
+    <origin xyz="$(arg offsets)" rpy="${{offsets[0]}} 0 ${{xacro.arg('limits')['upper']}}"/>
+  </joint>
 </robot>"#
         );
+        let options = XacroOptions::default()
+            .with_typed_arg("mass", XacroValue::Number(1.5))
+            .with_typed_arg("offsets", parse_yaml_value("[0.5, 0, 1]").unwrap())
+            .with_typed_arg("limits", parse_yaml_value("lower: -1\nupper: 2").unwrap());
 
-        let err = parse_xacro_from_string(&xml).unwrap_err().to_string();
-
-        assert!(err.contains("unused block `origin` when instantiating macro `xacro:make_link`"));
-    }
-
-    #[test]
-    fn allows_empty_property_block() {
-        let xml = format!(r#"<robot xmlns:xacro="{NS}"><xacro:property name="foo"/></robot>"#);
-
-        let result = parse_xacro_from_string(&xml).unwrap();
-
-        assert!(!result.contains("xacro:property"));
-    }
-
-    #[test]
-    fn errors_on_undefined_macro() {
-        let xml = format!(r#"<robot xmlns:xacro="{NS}"><xacro:missing/></robot>"#);
-
-        let err = parse_xacro_from_string(&xml).unwrap_err().to_string();
+        let result = parse_xacro_from_string_with_options(&xml, options).unwrap();
 
-        assert!(err.contains("undefined xacro macro `xacro:missing`"));
+        assert!(result.contains(r#"<link name="left_base">"#), "{}", result);
+        assert!(result.contains(r#"<mass value="3" />"#), "{}", result);
+        assert!(result.contains(r#"xyz="0.5 0 1""#), "{}", result);
+        assert!(result.contains(r#"rpy="0.5 0 2""#), "{}", result);
     }
 
     #[test]
-    fn errors_on_missing_required_macro_parameter() {
-        let xml = format!(
-            r#"<robot xmlns:xacro="{NS}">
-  <xacro:macro name="link_macro" params="name"><link name="${{name}}"/></xacro:macro>
-  <xacro:link_macro/>
-</robot>"#
+    fn parses_yaml_arg_values() {
+        assert_eq!(parse_yaml_value("3").unwrap(), XacroValue::Integer(3));
+        assert_eq!(
+            parse_yaml_value("[1, two]").unwrap(),
+            XacroValue::List(vec![
+                XacroValue::Integer(1),
+                XacroValue::String("two".to_string())
+            ])
+        );
+        assert_eq!(
+            parse_yaml_value(r#"{a: [1, {b: x}], "c":2.5}"#).unwrap(),
+            XacroValue::Map(BTreeMap::from([
+                (
+                    "a".to_string(),
+                    XacroValue::List(vec![
+                        XacroValue::Integer(1),
+                        XacroValue::Map(BTreeMap::from([(
+                            "b".to_string(),
+                            XacroValue::String("x".to_string())
+                        )])),
+                    ])
+                ),
+                ("c".to_string(), XacroValue::Number(2.5)),
+            ]))
+        );
+        assert!(parse_yaml_value("{a}").is_err());
+        assert_eq!(
+            parse_yaml_value("http://example.com").unwrap(),
+            XacroValue::String("http://example.com".to_string())
+        );
+        assert_eq!(
+            parse_yaml_value("arm:\n  dof: 6").unwrap(),
+            XacroValue::Map(BTreeMap::from([(
+                "arm".to_string(),
+                XacroValue::Map(BTreeMap::from([(
+                    "dof".to_string(),
+                    XacroValue::Integer(6)
+                )]))
+            )]))
         );
-
-        let err = parse_xacro_from_string(&xml).unwrap_err().to_string();
-
-        assert!(err.contains("missing required parameter `name` for macro `xacro:link_macro`"));
     }
 
     #[test]
-    fn macro_parent_scope_exports_property_to_caller() {
+    fn reports_declared_used_and_provided_args() {
         let xml = format!(
-            r#"<robot xmlns:xacro="{NS}">
-  <xacro:macro name="set_values" params="value">
-    <xacro:property name="local_only" value="hidden"/>
-    <xacro:property name="exported" value="${{value}}" scope="parent"/>
-  </xacro:macro>
-  <xacro:set_values value="ok"/>
-  <link name="${{exported}}" local="${{local_only}}"/>
+            r#"<robot name="$(arg name)" xmlns:xacro="{NS}">
+  <xacro:arg name="name" default="robot"/>
+  <xacro:arg name="prefix" default=""/>
+  <xacro:arg name="use_gripper" default="false"/>
+  <xacro:if value="${{xacro.arg('use_gripper')}}">
+    <link name="gripper"/>
+  </xacro:if>
 </robot>"#
         );
-
-        let result = parse_xacro_from_string(&xml).unwrap();
-
-        assert!(result.contains(r#"name="ok""#));
-        assert!(result.contains(r#"local="${local_only}""#));
+        let options = XacroOptions::default()
+            .with_arg("name", "arm")
+            .with_arg("typo", "1");
+        let mut processor = XacroProcessor::with_options(options);
+
+        processor.process_string(&xml).unwrap();
+        let report = processor.arg_report();
+
+        assert_eq!(
+            report.declared,
+            vec![
+                DeclaredArg {
+                    name: "name".to_string(),
+                    default: "robot".to_string()
+                },
+                DeclaredArg {
+                    name: "prefix".to_string(),
+                    default: String::new()
+                },
+                DeclaredArg {
+                    name: "use_gripper".to_string(),
+                    default: "false".to_string()
+                },
+            ]
+        );
+        assert_eq!(report.used, vec!["name", "use_gripper"]);
+        assert_eq!(report.provided, vec!["name", "typo"]);
+        assert_eq!(report.undeclared().collect::<Vec<_>>(), vec!["typo"]);
+        assert_eq!(
+            report
+                .unused()
+                .map(|arg| arg.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["prefix"]
+        );
     }
 
     #[test]
-    fn macro_global_scope_exports_through_nested_callers() {
+    fn preserves_comments_text_cdata_and_processing_instructions() {
         let xml = format!(
-            r#"<robot xmlns:xacro="{NS}">
-  <xacro:macro name="inner" params="">
-    <xacro:property name="global_name" value="visible" scope="global"/>
-  </xacro:macro>
-  <xacro:macro name="outer" params="">
-    <xacro:inner/>
-    <link name="inside_${{global_name}}"/>
+            r#"<?xml version="1.0"?>
+<!-- generated robot -->
+<robot name="r" xmlns:xacro="{NS}">
+  <!-- base -->
+  <xacro:property name="mu" value="0.2"/>
+  <xacro:macro name="friction" params="*body">
+    <!-- friction -->
+    <xacro:insert_block name="body"/>
   </xacro:macro>
-  <xacro:outer/>
-  <link name="outside_${{global_name}}"/>
+  <gazebo>
+    <xacro:friction><mu1>${{mu}}</mu1></xacro:friction>
+    <![CDATA[${{mu}}]]>
+    <?pi data?>
+  </gazebo>
 </robot>"#
         );
 
         let result = parse_xacro_from_string(&xml).unwrap();
 
-        assert!(result.contains(r#"name="inside_visible""#));
-        assert!(result.contains(r#"name="outside_visible""#));
-    }
-
-    #[test]
-    fn errors_on_invalid_property_scope() {
-        let xml = format!(
-            r#"<robot xmlns:xacro="{NS}">
-  <xacro:property name="bad" value="1" scope="sideways"/>
-</robot>"#
-        );
-
-        let err = parse_xacro_from_string(&xml).unwrap_err().to_string();
-
-        assert!(err.contains("unsupported xacro:property scope `sideways`"));
-    }
-
-    #[test]
-    fn processor_does_not_leak_context_between_documents() {
-        let first = format!(
-            r#"<robot xmlns:xacro="{NS}">
-  <xacro:macro name="link_macro" params="name"><link name="${{name}}"/></xacro:macro>
-  <xacro:link_macro name="first"/>
+        assert_eq!(
+            result,
+            r#"<?xml version="1.0" encoding="UTF-8"?><!-- generated robot --><robot xmlns:xacro="http://www.ros.org/wiki/xacro" name="r">
+  <!-- base -->
+  
+  
+  <gazebo>
+    
+    <!-- friction -->
+    <mu1>0.2</mu1>
+  
+    <![CDATA[${mu}]]>
+    <?pi data?>
+  </gazebo>
 </robot>"#
         );
-        let second =
-            format!(r#"<robot xmlns:xacro="{NS}"><xacro:link_macro name="second"/></robot>"#);
-        let mut processor = XacroProcessor::new();
-
-        processor.process_string(&first).unwrap();
-        let err = processor.process_string(&second).unwrap_err().to_string();
-
-        assert!(err.contains("undefined xacro macro `xacro:link_macro`"));
+        let pretty = parse_xacro_from_string_with_options(
+            &xml,
+            XacroOptions::default().with_pretty_print(true),
+        )
+        .unwrap();
+        assert!(pretty.contains("  <!-- base -->\n  <gazebo>\n    <!-- friction -->\n"));
     }
 
     #[test]
-    fn loads_yaml_and_accesses_nested_values() {
-        let dir = temp_fixture_dir("load-yaml");
+    fn pretty_prints_output_with_generated_banner() {
+        let dir = temp_fixture_dir("banner");
         fs::write(
-            dir.join("config.yaml"),
-            r#"
-robot:
-  name: arm
-joints:
-  shoulder:
-    enabled: true
-    max_effort: 150.0
-    min_position: !degrees -180
-values:
-  offsets: [1, 2, foo]
-"#,
+            dir.join("links.xacro"),
+            format!(r#"<robot xmlns:xacro="{NS}"><link name="$(arg name)"/></robot>"#),
         )
         .unwrap();
         let main = dir.join("main.xacro");
         fs::write(
             &main,
             format!(
-                r#"<robot xmlns:xacro="{NS}">
-  <xacro:property name="cfg_path" value="config.yaml"/>
-  <xacro:property name="cfg" value="${{xacro.load_yaml(cfg_path)}}"/>
-  <xacro:property name="shoulder" value="${{cfg['joints']['shoulder']}}"/>
-  <xacro:if value="${{shoulder['enabled']}}">
-    <link name="${{cfg.robot.name}}" effort="${{shoulder['max_effort']}}" lower="${{shoulder['min_position']}}" offset="${{cfg['values']['offsets'][2]}}"/>
-  </xacro:if>
-</robot>"#
+                r#"<robot name="r" xmlns:xacro="{NS}"><xacro:include filename="links.xacro"/></robot>"#
             ),
         )
         .unwrap();
+        let options = XacroOptions::default()
+            .with_arg("name", "base")
+            .with_pretty_print(true)
+            .with_generated_banner(true);
 
-        let result = parse_xacro_from_file(&main).unwrap();
+        let result = parse_xacro_from_file_with_options(&main, options).unwrap();
 
-        assert!(result.contains(r#"name="arm""#));
-        assert!(result.contains(r#"effort="150""#));
-        assert!(result.contains(r#"lower="-3.141592653589793""#));
-        assert!(result.contains(r#"offset="foo""#));
+        let expected = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!--
+  Generated by xurdf from {}
+  Source files:
+    {}
+  Args:
+    name:=base
+  Editing this file by hand is not recommended.
+-->
+<robot xmlns:xacro="http://www.ros.org/wiki/xacro" name="r">
+  <link name="base" />
+</robot>
+"#,
+            main.display(),
+            fs::canonicalize(dir.join("links.xacro")).unwrap().display()
+        );
+        assert_eq!(result, expected);
         let _ = fs::remove_dir_all(dir);
     }
 
     #[test]
-    fn yaml_boolean_false_skips_if_body() {
-        let dir = temp_fixture_dir("load-yaml-false");
-        fs::write(
-            dir.join("config.yaml"),
-            r#"
-feature:
-  enabled: false
-"#,
-        )
-        .unwrap();
-        let main = dir.join("main.xacro");
-        fs::write(
-            &main,
-            format!(
-                r#"<robot xmlns:xacro="{NS}">
-  <xacro:property name="cfg" value="${{load_yaml('config.yaml')}}"/>
-  <xacro:if value="${{cfg.feature.enabled}}">
-    <link name="enabled"/>
-  </xacro:if>
-</robot>"#
+    fn formats_typed_banner_args_as_json() {
+        let value = XacroValue::Map(BTreeMap::from([
+            (
+                "a\"b".to_string(),
+                XacroValue::List(vec![XacroValue::Integer(1), XacroValue::Number(0.5)]),
             ),
-        )
-        .unwrap();
-
-        let result = parse_xacro_from_file(&main).unwrap();
-
-        assert!(!result.contains(r#"name="enabled""#));
-        let _ = fs::remove_dir_all(dir);
+            ("c".to_string(), XacroValue::String("x\u{1}\\".to_string())),
+            ("d".to_string(), XacroValue::Number(f64::NAN)),
+        ]));
+        assert_eq!(
+            format_json_value(&value),
+            r#"{"a\"b": [1, 0.5], "c": "x\u0001\\", "d": null}"#
+        );
     }
 
     struct EnvVarGuard {
@@ -1956,6 +4562,13 @@ feature:
         }
     }
 
+    fn without_indentation(xml: &str) -> String {
+        regex::Regex::new(r">\s+<")
+            .unwrap()
+            .replace_all(xml, "><")
+            .into_owned()
+    }
+
     fn temp_fixture_dir(name: &str) -> PathBuf {
         let dir = std::env::temp_dir().join(format!("xurdf-xacro-{}-{}", name, std::process::id()));
         let _ = fs::remove_dir_all(&dir);
@@ -0,0 +1,88 @@
+extern crate nalgebra as na;
+
+use na::Vector3;
+
+// Writes indented XML with attributes in the order given, so exported files are stable
+// enough to diff.
+pub(crate) struct XmlWriter {
+    output: String,
+    depth: usize,
+}
+
+impl XmlWriter {
+    pub(crate) fn new() -> Self {
+        Self {
+            output: String::from("<?xml version=\"1.0\"?>\n"),
+            depth: 0,
+        }
+    }
+
+    pub(crate) fn start(&mut self, tag: &str, attributes: &[(&str, &str)]) {
+        self.open_tag(tag, attributes);
+        self.output.push_str(">\n");
+        self.depth += 1;
+    }
+
+    pub(crate) fn end(&mut self, tag: &str) {
+        self.depth -= 1;
+        self.indent();
+        self.output.push_str(&format!("</{}>\n", tag));
+    }
+
+    pub(crate) fn empty(&mut self, tag: &str, attributes: &[(&str, &str)]) {
+        self.open_tag(tag, attributes);
+        self.output.push_str("/>\n");
+    }
+
+    pub(crate) fn text(&mut self, tag: &str, attributes: &[(&str, &str)], text: &str) {
+        self.open_tag(tag, attributes);
+        self.output
+            .push_str(&format!(">{}</{}>\n", escape(text), tag));
+    }
+
+    pub(crate) fn finish(self) -> String {
+        self.output
+    }
+
+    fn open_tag(&mut self, tag: &str, attributes: &[(&str, &str)]) {
+        self.indent();
+        self.output.push('<');
+        self.output.push_str(tag);
+        for (name, value) in attributes {
+            self.output
+                .push_str(&format!(" {}=\"{}\"", name, escape(value)));
+        }
+    }
+
+    fn indent(&mut self) {
+        self.output.push_str(&"  ".repeat(self.depth));
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub(crate) fn format_float(value: f64) -> String {
+    // Avoid `-0` from rounding noise in computed poses.
+    if value == 0.0 {
+        "0".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+pub(crate) fn format_floats(values: &[f64]) -> String {
+    values
+        .iter()
+        .map(|value| format_float(*value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub(crate) fn format_vector(vector: &Vector3<f64>) -> String {
+    format_floats(vector.as_slice())
+}